@@ -71,6 +71,7 @@ pub async fn connect_client(
     let client_config = quinn::ClientConfig {
         crypto: Arc::new(crypto),
         transport: Arc::new(transport_config(&opt)),
+        path_cache: None,
     };
 
     let quinn::NewConnection { connection, .. } = endpoint
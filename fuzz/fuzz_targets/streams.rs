@@ -8,7 +8,7 @@ use proto::fuzzing::{
     ConnectionState, ResetStream, Retransmits, SendStream, Streams, StreamsState,
     TransportParameters,
 };
-use proto::{Dir, Side, StreamId, VarInt};
+use proto::{Dir, SchedulerKind, Side, StreamId, VarInt};
 
 #[derive(Arbitrary, Debug)]
 struct StreamParams {
@@ -41,7 +41,11 @@ fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
         params.max_remote_bi.into(),
         params.send_window.into(),
         params.receive_window.into(),
+        None,
         params.stream_receive_window.into(),
+        None,
+        None,
+        SchedulerKind::RoundRobin,
     );
 
     for operation in operations {
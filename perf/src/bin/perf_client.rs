@@ -121,6 +121,7 @@ async fn run(opt: Opt) -> Result<()> {
     let cfg = quinn::ClientConfig {
         crypto: Arc::new(crypto),
         transport,
+        path_cache: None,
     };
 
     let stream_stats = OpenStreamStats::default();
@@ -1,11 +1,20 @@
+#[cfg(feature = "ring")]
+use std::fmt;
 use std::time::Duration;
 
 use rand::RngCore;
+#[cfg(feature = "ring")]
+use ring::aead::{self, quic::HeaderProtectionKey};
 
+#[cfg(feature = "ring")]
+use crate::config::ConfigError;
 use crate::shared::ConnectionId;
 use crate::MAX_CID_SIZE;
 
 /// Generates connection IDs for incoming connections
+///
+/// Configured with [`EndpointConfig::cid_generator()`](crate::EndpointConfig::cid_generator),
+/// allowing connection IDs to carry routing information for load balancers.
 pub trait ConnectionIdGenerator: Send {
     /// Generates a new CID
     ///
@@ -16,12 +25,25 @@ pub trait ConnectionIdGenerator: Send {
     fn generate_cid(&mut self) -> ConnectionId;
     /// Returns the length of a CID for connections created by this generator
     fn cid_len(&self) -> usize;
+    /// Quickly determine whether `cid` could have been generated by this generator
+    ///
+    /// Packets for unknown connections whose destination CID fails validation are dropped
+    /// without a stateless reset being sent. False positives are fine, but false negatives
+    /// prevent peers of lost connections from learning that they are gone. Defaults to accepting
+    /// every CID.
+    fn validate(&self, _cid: &ConnectionId) -> Result<(), InvalidCid> {
+        Ok(())
+    }
     /// Returns the lifetime of generated Connection IDs
     ///
     /// Connection IDs will be retired after the returned `Duration`, if any. Assumed to be constant.
     fn cid_lifetime(&self) -> Option<Duration>;
 }
 
+/// The connection ID was not recognized by the [`ConnectionIdGenerator`]
+#[derive(Debug, Copy, Clone)]
+pub struct InvalidCid;
+
 /// Generates purely random connection IDs of a certain length
 #[derive(Debug, Clone, Copy)]
 pub struct RandomConnectionIdGenerator {
@@ -74,3 +96,398 @@ impl ConnectionIdGenerator for RandomConnectionIdGenerator {
         self.lifetime
     }
 }
+
+/// Generates random connection IDs that identify one of several shards of an endpoint
+///
+/// The byte at `offset` of every generated CID is congruent to `shard` modulo `shards`, allowing
+/// a load balancer or a kernel steering program to route packets to the shard owning the
+/// connection without keeping per-connection state. This leaks the shard index to observers, but
+/// nothing else about the connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ShardedConnectionIdGenerator {
+    cid_len: usize,
+    lifetime: Option<Duration>,
+    offset: usize,
+    shard: u8,
+    shards: u8,
+}
+
+impl ShardedConnectionIdGenerator {
+    /// Initialize a generator for shard `shard` out of `shards`
+    ///
+    /// `offset` must be less than `cid_len`, which must be less than or equal to MAX_CID_SIZE.
+    /// `shard` must be less than `shards`.
+    pub fn new(cid_len: usize, offset: usize, shard: u8, shards: u8) -> Self {
+        debug_assert!(cid_len <= MAX_CID_SIZE);
+        debug_assert!(offset < cid_len);
+        debug_assert!(shard < shards);
+        Self {
+            cid_len,
+            lifetime: None,
+            offset,
+            shard,
+            shards,
+        }
+    }
+
+    /// Set the lifetime of CIDs created by this generator
+    pub fn set_lifetime(&mut self, d: Duration) -> &mut Self {
+        self.lifetime = Some(d);
+        self
+    }
+}
+
+impl ConnectionIdGenerator for ShardedConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let mut bytes_arr = [0; MAX_CID_SIZE];
+        rand::thread_rng().fill_bytes(&mut bytes_arr[..self.cid_len]);
+
+        // Round down to a multiple of `shards`, staying within range, and add the shard index
+        let shards = u16::from(self.shards);
+        let mut byte = u16::from(bytes_arr[self.offset]) / shards * shards + u16::from(self.shard);
+        if byte > u16::from(u8::MAX) {
+            byte -= shards;
+        }
+        bytes_arr[self.offset] = byte as u8;
+
+        ConnectionId::new(&bytes_arr[..self.cid_len])
+    }
+
+    fn cid_len(&self) -> usize {
+        self.cid_len
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        if cid.len() != self.cid_len || cid[self.offset] % self.shards != self.shard {
+            return Err(InvalidCid);
+        }
+        Ok(())
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+/// Routing parameters shared by a QUIC-aware load balancer and the servers behind it
+///
+/// Follows the encrypted connection ID format of the QUIC-LB draft
+/// (draft-ietf-quic-load-balancers): the first byte carries a configuration ID in its three most
+/// significant bits and the length of the rest of the CID in its remaining bits, followed by the
+/// server ID and a unique nonce, encrypted together with a four-pass Feistel network keyed by
+/// AES-128. Load balancers holding the key can recover the server ID to route packets statelessly,
+/// while CIDs for the same connection remain unlinkable to observers.
+///
+/// The round function uses the leading bytes of each AES output block, which limits the server ID
+/// and nonce to 10 bytes in total.
+///
+/// Configurations constructed with [`plaintext()`](Self::plaintext) follow the draft's plaintext
+/// CID format instead, in which the server ID is not encrypted and the nonce is random. Such CIDs
+/// reveal which server a connection is bound to, but can be routed by load balancers that don't
+/// implement the cipher.
+#[cfg(feature = "ring")]
+#[derive(Clone)]
+pub struct LoadBalancerConfig {
+    config_id: u8,
+    server_id_len: usize,
+    nonce_len: usize,
+    key: Option<[u8; 16]>,
+}
+
+#[cfg(feature = "ring")]
+impl LoadBalancerConfig {
+    /// Construct a configuration identified by `config_id` for servers with IDs of
+    /// `server_id_len` bytes and CIDs with nonces of `nonce_len` bytes, encrypted with `key`
+    ///
+    /// `config_id` must be less than 7, which is reserved for unroutable CIDs. Nonces must be at
+    /// least 4 bytes long, and server IDs at least 1 byte long.
+    pub fn new(
+        config_id: u8,
+        server_id_len: usize,
+        nonce_len: usize,
+        key: [u8; 16],
+    ) -> Result<Self, ConfigError> {
+        if config_id >= 7
+            || server_id_len == 0
+            || nonce_len < 4
+            || server_id_len + nonce_len > MAX_LB_PLAINTEXT
+        {
+            return Err(ConfigError::OutOfBounds);
+        }
+        Ok(Self {
+            config_id,
+            server_id_len,
+            nonce_len,
+            key: Some(key),
+        })
+    }
+
+    /// Construct a configuration identified by `config_id` for servers with IDs of
+    /// `server_id_len` bytes and CIDs with random nonces of `nonce_len` bytes, left unencrypted
+    ///
+    /// `config_id` must be less than 7, which is reserved for unroutable CIDs. Nonces must be at
+    /// least 4 bytes long, server IDs at least 1 byte long, and the resulting CIDs at most 20
+    /// bytes long.
+    pub fn plaintext(
+        config_id: u8,
+        server_id_len: usize,
+        nonce_len: usize,
+    ) -> Result<Self, ConfigError> {
+        if config_id >= 7
+            || server_id_len == 0
+            || nonce_len < 4
+            || 1 + server_id_len + nonce_len > MAX_CID_SIZE
+        {
+            return Err(ConfigError::OutOfBounds);
+        }
+        Ok(Self {
+            config_id,
+            server_id_len,
+            nonce_len,
+            key: None,
+        })
+    }
+
+    /// Length of the server IDs identified by this configuration
+    pub fn server_id_len(&self) -> usize {
+        self.server_id_len
+    }
+
+    /// Length of the connection IDs produced with this configuration
+    pub fn cid_len(&self) -> usize {
+        1 + self.server_id_len + self.nonce_len
+    }
+
+    /// Recover the server ID from a CID generated with this configuration
+    ///
+    /// Returns `None` if `cid` has the wrong length or was issued under a different configuration
+    /// ID. CIDs encrypted with a different key yield an unrelated server ID.
+    pub fn server_id(&self, cid: &[u8]) -> Option<Vec<u8>> {
+        if cid.len() != self.cid_len() || cid[0] >> 5 != self.config_id {
+            return None;
+        }
+        let mut plaintext = cid[1..].to_vec();
+        if let Some(ref key) = self.key {
+            FeistelCipher::new(key, plaintext.len()).decrypt(&mut plaintext);
+        }
+        plaintext.truncate(self.server_id_len);
+        Some(plaintext)
+    }
+}
+
+#[cfg(feature = "ring")]
+impl fmt::Debug for LoadBalancerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadBalancerConfig")
+            .field("config_id", &self.config_id)
+            .field("server_id_len", &self.server_id_len)
+            .field("nonce_len", &self.nonce_len)
+            .field("key", &self.key.map(|_| "[ elided ]"))
+            .finish()
+    }
+}
+
+/// Generates connection IDs embedding a server ID for a load balancer
+///
+/// See [`LoadBalancerConfig`] for the encrypted and plaintext formats. Encrypted nonces are taken
+/// from a counter starting at a random value, so a generator repeats no CID until its counter
+/// wraps. Generators for the same server ID, such as those of several endpoints built from one
+/// factory, count from independent random values and are only unlikely to issue the same CIDs.
+/// Plaintext configurations use random nonces instead, as a visible counter would link a
+/// connection's CIDs.
+#[cfg(feature = "ring")]
+pub struct LoadBalancerConnectionIdGenerator {
+    config: LoadBalancerConfig,
+    cipher: Option<FeistelCipher>,
+    server_id: Box<[u8]>,
+    counter: u128,
+    lifetime: Option<Duration>,
+}
+
+#[cfg(feature = "ring")]
+impl LoadBalancerConnectionIdGenerator {
+    /// Initialize a generator for the server identified by `server_id` under `config`
+    ///
+    /// Fails if `server_id` isn't exactly as long as the configuration's server IDs.
+    pub fn new(config: LoadBalancerConfig, server_id: &[u8]) -> Result<Self, ConfigError> {
+        if server_id.len() != config.server_id_len {
+            return Err(ConfigError::OutOfBounds);
+        }
+        Ok(Self {
+            cipher: config
+                .key
+                .as_ref()
+                .map(|key| FeistelCipher::new(key, config.server_id_len + config.nonce_len)),
+            config,
+            server_id: server_id.into(),
+            counter: rand::random(),
+            lifetime: None,
+        })
+    }
+
+    /// Set the lifetime of CIDs created by this generator
+    pub fn set_lifetime(&mut self, d: Duration) -> &mut Self {
+        self.lifetime = Some(d);
+        self
+    }
+}
+
+#[cfg(feature = "ring")]
+impl ConnectionIdGenerator for LoadBalancerConnectionIdGenerator {
+    fn generate_cid(&mut self) -> ConnectionId {
+        let config = &self.config;
+        let mut bytes_arr = [0; MAX_CID_SIZE];
+        bytes_arr[0] = config.config_id << 5 | (config.cid_len() - 1) as u8;
+        let plaintext = &mut bytes_arr[1..config.cid_len()];
+        plaintext[..config.server_id_len].copy_from_slice(&self.server_id);
+        let nonce = &mut plaintext[config.server_id_len..];
+        match self.cipher {
+            Some(ref cipher) => {
+                nonce.copy_from_slice(&self.counter.to_be_bytes()[16 - config.nonce_len..]);
+                self.counter = self.counter.wrapping_add(1);
+                cipher.encrypt(plaintext);
+            }
+            None => rand::thread_rng().fill_bytes(nonce),
+        }
+
+        ConnectionId::new(&bytes_arr[..config.cid_len()])
+    }
+
+    fn cid_len(&self) -> usize {
+        self.config.cid_len()
+    }
+
+    fn validate(&self, cid: &ConnectionId) -> Result<(), InvalidCid> {
+        let config = &self.config;
+        if cid.len() != config.cid_len() || cid[0] != config.config_id << 5 | (cid.len() - 1) as u8
+        {
+            return Err(InvalidCid);
+        }
+        Ok(())
+    }
+
+    fn cid_lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+}
+
+#[cfg(feature = "ring")]
+impl fmt::Debug for LoadBalancerConnectionIdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadBalancerConnectionIdGenerator")
+            .field("config", &self.config)
+            .field("lifetime", &self.lifetime)
+            .finish()
+    }
+}
+
+/// Four-pass Feistel network over `len` bytes, splitting odd lengths in the middle of a byte
+#[cfg(feature = "ring")]
+struct FeistelCipher {
+    key: HeaderProtectionKey,
+    len: usize,
+}
+
+#[cfg(feature = "ring")]
+impl FeistelCipher {
+    fn new(key: &[u8; 16], len: usize) -> Self {
+        debug_assert!(len <= MAX_LB_PLAINTEXT);
+        Self {
+            key: HeaderProtectionKey::new(&aead::quic::AES_128, key).unwrap(),
+            len,
+        }
+    }
+
+    fn encrypt(&self, data: &mut [u8]) {
+        let (mut left, mut right) = self.split(data);
+        self.round(&left, 1, &mut right, Half::Right);
+        self.round(&right, 2, &mut left, Half::Left);
+        self.round(&left, 3, &mut right, Half::Right);
+        self.round(&right, 4, &mut left, Half::Left);
+        self.merge(&left, &right, data);
+    }
+
+    fn decrypt(&self, data: &mut [u8]) {
+        let (mut left, mut right) = self.split(data);
+        self.round(&right, 4, &mut left, Half::Left);
+        self.round(&left, 3, &mut right, Half::Right);
+        self.round(&right, 2, &mut left, Half::Left);
+        self.round(&left, 1, &mut right, Half::Right);
+        self.merge(&left, &right, data);
+    }
+
+    fn half_len(&self) -> usize {
+        self.len - self.len / 2
+    }
+
+    /// Split `data` into halves of `half_len()` bytes, sharing the middle byte if `len` is odd
+    fn split(&self, data: &[u8]) -> ([u8; MAX_LB_HALF], [u8; MAX_LB_HALF]) {
+        let half = self.half_len();
+        let (mut left, mut right) = ([0; MAX_LB_HALF], [0; MAX_LB_HALF]);
+        left[..half].copy_from_slice(&data[..half]);
+        right[..half].copy_from_slice(&data[self.len - half..]);
+        self.mask(&mut left, Half::Left);
+        self.mask(&mut right, Half::Right);
+        (left, right)
+    }
+
+    fn merge(&self, left: &[u8; MAX_LB_HALF], right: &[u8; MAX_LB_HALF], data: &mut [u8]) {
+        let half = self.half_len();
+        data[..half].copy_from_slice(&left[..half]);
+        let start = self.len - half;
+        for (i, &x) in right[..half].iter().enumerate() {
+            if i == 0 && start < half {
+                data[start] |= x;
+            } else {
+                data[start + i] = x;
+            }
+        }
+    }
+
+    /// Clear the bits of `half` that belong to the other half when `len` is odd
+    fn mask(&self, half: &mut [u8; MAX_LB_HALF], which: Half) {
+        if self.len & 1 == 0 {
+            return;
+        }
+        match which {
+            Half::Left => half[self.half_len() - 1] &= 0xf0,
+            Half::Right => half[0] &= 0x0f,
+        }
+    }
+
+    /// XOR `target` with the AES-based round function of `input` for pass `pass`
+    fn round(
+        &self,
+        input: &[u8; MAX_LB_HALF],
+        pass: u8,
+        target: &mut [u8; MAX_LB_HALF],
+        which: Half,
+    ) {
+        let half = self.half_len();
+        let mut block = [0; 16];
+        block[..half].copy_from_slice(&input[..half]);
+        block[14] = self.len as u8;
+        block[15] = pass;
+        let mut output = [0; MAX_LB_HALF];
+        output.copy_from_slice(&self.key.new_mask(&block).unwrap()[..MAX_LB_HALF]);
+        self.mask(&mut output, which);
+        for (x, y) in target[..half].iter_mut().zip(&output[..half]) {
+            *x ^= y;
+        }
+    }
+}
+
+#[cfg(feature = "ring")]
+#[derive(Copy, Clone)]
+enum Half {
+    Left,
+    Right,
+}
+
+/// Maximum combined length of the server ID and nonce in a [`LoadBalancerConfig`]
+#[cfg(feature = "ring")]
+const MAX_LB_PLAINTEXT: usize = 2 * MAX_LB_HALF;
+
+/// Number of bytes of AES output available to the round function of [`FeistelCipher`]
+#[cfg(feature = "ring")]
+const MAX_LB_HALF: usize = 5;
@@ -11,7 +11,7 @@ type CidData = (ConnectionId, Option<ResetToken>);
 #[derive(Debug)]
 pub struct CidQueue {
     /// Ring buffer indexed by `self.cursor`
-    buffer: [Option<CidData>; Self::LEN],
+    buffer: Box<[Option<CidData>]>,
     /// Index at which circular buffer addressing is based
     cursor: usize,
     /// Sequence number of `self.buffer[cursor]`
@@ -21,8 +21,14 @@ pub struct CidQueue {
 }
 
 impl CidQueue {
+    #[cfg(test)]
     pub fn new(cid: ConnectionId) -> Self {
-        let mut buffer = [None; Self::LEN];
+        Self::with_capacity(cid, Self::LEN)
+    }
+
+    /// Create a queue able to hold `len` CIDs, including the active one
+    pub fn with_capacity(cid: ConnectionId, len: usize) -> Self {
+        let mut buffer = vec![None; len].into_boxed_slice();
         buffer[0] = Some((cid, None));
         Self {
             buffer,
@@ -40,10 +46,10 @@ impl CidQueue {
             None => return Err(InsertError::Retired),
             Some(x) => x,
         };
-        if index >= Self::LEN as u64 {
+        if index >= self.buffer.len() as u64 {
             return Err(InsertError::ExceedsLimit);
         }
-        let index = (self.cursor + index as usize) % Self::LEN;
+        let index = (self.cursor + index as usize) % self.buffer.len();
         self.buffer[index] = Some((cid.id, Some(cid.reset_token)));
         Ok(())
     }
@@ -52,14 +58,16 @@ impl CidQueue {
     pub fn retire_prior_to(&mut self, sequence: u64) -> Range<u64> {
         let n = match sequence.checked_sub(self.offset) {
             None => return 0..0,
-            Some(n) => n as usize,
+            Some(n) => n,
         };
-        for i in 0..n {
-            self.buffer[(self.cursor + i) % Self::LEN] = None;
+        let len = self.buffer.len();
+        // Retiring a whole window's worth or more empties the buffer
+        for i in 0..n.min(len as u64) as usize {
+            self.buffer[(self.cursor + i) % len] = None;
         }
         let orig_offset = self.offset;
         self.offset = sequence;
-        self.cursor = (self.cursor + n) % Self::LEN;
+        self.cursor = (self.cursor + (n % len as u64) as usize) % len;
         orig_offset..sequence
     }
 
@@ -71,15 +79,15 @@ impl CidQueue {
 
         let orig_offset = self.offset;
         self.offset += i as u64;
-        self.cursor = (self.cursor + i) % Self::LEN;
+        self.cursor = (self.cursor + i) % self.buffer.len();
         let sequence = orig_offset + i as u64;
         Some((cid_data.1.unwrap(), orig_offset..sequence))
     }
 
     /// Iterate inactive CIDs in CidQueue that are not `None`
     fn iter(&self) -> impl Iterator<Item = (usize, CidData)> + '_ {
-        (1..Self::LEN).filter_map(move |step| {
-            let index = (self.cursor + step) % Self::LEN;
+        (1..self.buffer.len()).filter_map(move |step| {
+            let index = (self.cursor + step) % self.buffer.len();
             self.buffer[index].map(|cid_data| (step, cid_data))
         })
     }
@@ -104,6 +112,7 @@ impl CidQueue {
         self.offset
     }
 
+    /// Default number of CIDs that may be held, including the active one
     pub const LEN: usize = 5;
 }
 
@@ -207,6 +216,21 @@ mod tests {
         assert!(!q.is_active_retired());
     }
 
+    #[test]
+    fn retire_beyond_window() {
+        let mut q = CidQueue::new(initial_cid());
+        q.insert(cid(1)).unwrap();
+        assert_eq!(q.retire_prior_to(1_000_000), 0..1_000_000);
+        assert!(q.is_active_retired());
+        assert!(q.next().is_none());
+        q.insert(cid(1_000_000)).unwrap();
+        q.insert(cid(1_000_000 + CidQueue::LEN as u64 - 1)).unwrap();
+        assert_eq!(
+            q.insert(cid(1_000_000 + CidQueue::LEN as u64)),
+            Err(InsertError::ExceedsLimit)
+        );
+    }
+
     #[test]
     fn insert_limit() {
         let mut q = CidQueue::new(initial_cid());
@@ -1,16 +1,32 @@
-use std::{convert::TryInto, fmt, num::TryFromIntError, sync::Arc, time::Duration};
+use std::{
+    convert::TryInto,
+    fmt,
+    net::{SocketAddrV4, SocketAddrV6},
+    num::TryFromIntError,
+    sync::Arc,
+    time::Duration,
+};
 
+use fxhash::FxHashMap;
 use rand::RngCore;
 use thiserror::Error;
 
 #[cfg(feature = "rustls")]
 use crate::crypto::types::{Certificate, CertificateChain, PrivateKey};
+#[cfg(feature = "qlog")]
+use crate::QlogFactory;
+#[cfg(feature = "rustls")]
+use crate::SessionCache;
 use crate::{
     cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator},
+    cid_queue::CidQueue,
     congestion,
     crypto::{self, HandshakeTokenKey, HmacKey},
-    VarInt, VarIntBoundsExceeded, DEFAULT_SUPPORTED_VERSIONS,
+    DatagramDropPolicy, PathCache, RetryPolicy, SchedulerKind, VarInt, VarIntBoundsExceeded,
+    ZeroRttPolicy, DEFAULT_SUPPORTED_VERSIONS, MAX_CID_SIZE,
 };
+#[cfg(feature = "ring")]
+use crate::{LoadBalancerConfig, LoadBalancerConnectionIdGenerator};
 
 /// Parameters governing the core QUIC state machine
 ///
@@ -28,9 +44,17 @@ pub struct TransportConfig {
     pub(crate) max_concurrent_bidi_streams: VarInt,
     pub(crate) max_concurrent_uni_streams: VarInt,
     pub(crate) max_idle_timeout: Option<VarInt>,
+    pub(crate) handshake_timeout: Option<Duration>,
+    pub(crate) closing_period: Option<Duration>,
+    pub(crate) active_connection_id_limit: u32,
     pub(crate) stream_receive_window: VarInt,
+    pub(crate) max_stream_receive_window: Option<VarInt>,
+    pub(crate) stream_reassembly_buffer_size: Option<usize>,
+    pub(crate) stream_scheduler: SchedulerKind,
     pub(crate) receive_window: VarInt,
+    pub(crate) max_receive_window: Option<VarInt>,
     pub(crate) send_window: u64,
+    pub(crate) max_send_window: Option<u64>,
 
     pub(crate) max_tlps: u32,
     pub(crate) packet_threshold: u32,
@@ -38,13 +62,21 @@ pub struct TransportConfig {
     pub(crate) initial_rtt: Duration,
 
     pub(crate) persistent_congestion_threshold: u32,
+    pub(crate) ack_frequency: Option<AckFrequencyConfig>,
+    pub(crate) delivery_rate_threshold: Option<f32>,
     pub(crate) keep_alive_interval: Option<Duration>,
     pub(crate) crypto_buffer_size: usize,
+    pub(crate) undecryptable_packet_buffer_size: usize,
     pub(crate) allow_spin: bool,
+    pub(crate) grease: bool,
     pub(crate) datagram_receive_buffer_size: Option<usize>,
     pub(crate) datagram_send_buffer_size: usize,
+    pub(crate) datagram_drop_policy: DatagramDropPolicy,
 
     pub(crate) congestion_controller_factory: Box<dyn congestion::ControllerFactory + Send + Sync>,
+
+    #[cfg(feature = "qlog")]
+    pub(crate) qlog: Option<Arc<dyn QlogFactory>>,
 }
 
 impl TransportConfig {
@@ -92,6 +124,46 @@ impl TransportConfig {
         self
     }
 
+    /// Maximum duration of the handshake, or `None` for no limit beyond the idle timeout
+    ///
+    /// A connection whose handshake has not completed within this time of the connection being
+    /// created is abandoned with [`ConnectionError::TimedOut`]. Unlike the idle timeout, this is
+    /// purely local and not negotiated with the peer, so servers can drop stalled handshakes quickly
+    /// while keeping a long idle timeout for established connections. Defaults to `None`.
+    ///
+    /// [`ConnectionError::TimedOut`]: crate::ConnectionError::TimedOut
+    pub fn handshake_timeout(&mut self, value: Option<Duration>) -> &mut Self {
+        self.handshake_timeout = value;
+        self
+    }
+
+    /// How long a closed connection lingers, or `None` to use three times the probe timeout
+    ///
+    /// After a connection is closed locally, its state is retained for this long so that stray
+    /// packets from the peer can be answered with another `CONNECTION_CLOSE`, in case the first
+    /// one was lost. The same period applies to draining after the peer closes the connection,
+    /// during which packets still in flight from the peer are discarded. Shortening it speeds up
+    /// shutdown of short-lived clients that wait for their connections to be fully closed before
+    /// exiting, at the risk of the peer not learning of the close and having to wait for its idle
+    /// timeout instead. Defaults to `None`, the period recommended by the specification.
+    pub fn closing_period(&mut self, value: Option<Duration>) -> &mut Self {
+        self.closing_period = value;
+        self
+    }
+
+    /// Maximum number of connection IDs issued by the peer that will be retained
+    ///
+    /// Advertised to the peer as the `active_connection_id_limit` transport parameter, and
+    /// bounds the number of spare connection IDs the peer will issue for use after migration or
+    /// rotation. Lower values reduce per-connection state; higher values let peers behind load
+    /// balancers rotate connection IDs more freely. Values below 2, the protocol minimum, are
+    /// treated as 2. Has no effect if the local endpoint uses zero-length connection IDs, in which
+    /// case the peer has no use for them. Defaults to 5.
+    pub fn active_connection_id_limit(&mut self, value: u32) -> &mut Self {
+        self.active_connection_id_limit = value.max(2);
+        self
+    }
+
     /// Maximum number of bytes the peer may transmit without acknowledgement on any one stream
     /// before becoming blocked.
     ///
@@ -105,6 +177,42 @@ impl TransportConfig {
         self
     }
 
+    /// Upper bound for automatic growth of the per-stream receive window, or `None` to disable
+    ///
+    /// When set, each stream starts out with a window of `stream_receive_window`, which is doubled
+    /// whenever the peer consumes it in less than two round trips, until it reaches this value.
+    /// This lets streams on high bandwidth-delay product paths reach full throughput without
+    /// committing memory for huge windows on every stream up front. Worst-case memory use becomes
+    /// proportional to `max_concurrent_bidi_streams * max_stream_receive_window`. Values smaller
+    /// than `stream_receive_window` have no effect. Defaults to `None`.
+    pub fn max_stream_receive_window(&mut self, value: Option<VarInt>) -> &mut Self {
+        self.max_stream_receive_window = value;
+        self
+    }
+
+    /// Maximum number of bytes to buffer for reassembly across all streams of a connection, or
+    /// `None` for no limit
+    ///
+    /// Flow control bounds the data buffered on each stream, but a peer may spread data over many
+    /// streams, leaving gaps so that none of it can be read. The stream whose data exceeds this
+    /// limit is stopped with error code 0 and its buffered data discarded, so the limit should
+    /// leave room for the data the application is expected to leave unread at any one time.
+    /// Defaults to `None`.
+    pub fn stream_reassembly_buffer_size(&mut self, value: Option<usize>) -> &mut Self {
+        self.stream_reassembly_buffer_size = value;
+        self
+    }
+
+    /// How streams of equal priority share the connection's transmission capacity
+    ///
+    /// Defaults to [`SchedulerKind::RoundRobin`], which suits workloads of many concurrent requests.
+    /// Bulk transfers of several objects may prefer [`SchedulerKind::Sequential`], which avoids
+    /// interleaving their data so that each completes as early as possible.
+    pub fn stream_scheduler(&mut self, value: SchedulerKind) -> &mut Self {
+        self.stream_scheduler = value;
+        self
+    }
+
     /// Maximum number of bytes the peer may transmit across all streams of a connection before
     /// becoming blocked.
     ///
@@ -116,6 +224,20 @@ impl TransportConfig {
         self
     }
 
+    /// Upper bound for automatic growth of the connection-level receive window, or `None` to
+    /// disable
+    ///
+    /// When set, the connection starts out with a window of `receive_window`, which is doubled
+    /// whenever the application consumes it in less than two round trips, until it reaches this
+    /// value. Combined with [`max_stream_receive_window()`](Self::max_stream_receive_window), this
+    /// lets transfers over high bandwidth-delay product paths reach full throughput while bounding
+    /// the memory committed to each connection by this value. Values smaller than `receive_window`
+    /// have no effect. Defaults to `None`.
+    pub fn max_receive_window(&mut self, value: Option<VarInt>) -> &mut Self {
+        self.max_receive_window = value;
+        self
+    }
+
     /// Maximum number of bytes to transmit to a peer without acknowledgment
     ///
     /// Provides an upper bound on memory when communicating with peers that issue large amounts of
@@ -127,6 +249,19 @@ impl TransportConfig {
         self
     }
 
+    /// Upper bound for automatic sizing of the send window, or `None` to disable
+    ///
+    /// When set, the amount of data that may be buffered without acknowledgment tracks twice the
+    /// congestion window, an estimate of the path's bandwidth-delay product, while never falling
+    /// below `send_window` or exceeding this value. Lowering `send_window` alongside this keeps
+    /// memory use of connections on slow or short paths small, while still allowing full
+    /// throughput on fast, long ones. Values smaller than `send_window` have no effect. Defaults to
+    /// `None`.
+    pub fn max_send_window(&mut self, value: Option<u64>) -> &mut Self {
+        self.max_send_window = value;
+        self
+    }
+
     /// Maximum number of tail loss probes before an RTO fires.
     pub fn max_tlps(&mut self, value: u32) -> &mut Self {
         self.max_tlps = value;
@@ -159,9 +294,37 @@ impl TransportConfig {
         self
     }
 
+    /// How often the peer should acknowledge packets, or `None` to leave it to the peer
+    ///
+    /// When set, and the peer supports the ACK frequency extension
+    /// (draft-ietf-quic-ack-frequency), it is asked to acknowledge packets less often once the
+    /// handshake completes. Acknowledging every other packet, as QUIC otherwise requires, can cost
+    /// both endpoints significant CPU time and bandwidth during high-bandwidth transfers. Fewer
+    /// acknowledgements in turn make loss detection and congestion control react more slowly.
+    /// Defaults to `None`.
+    pub fn ack_frequency(&mut self, value: Option<AckFrequencyConfig>) -> &mut Self {
+        self.ack_frequency = value;
+        self
+    }
+
+    /// Relative change in the estimated delivery rate or RTT that is reported as an event, or
+    /// `None` to disable
+    ///
+    /// When set, `Event::DeliveryRateChanged` is emitted whenever the congestion controller's
+    /// bandwidth estimate or the smoothed RTT differs from the values last reported by more than
+    /// this fraction, e.g. `0.1` for 10%, so that adaptive applications such as media encoders can
+    /// react without polling statistics. Defaults to `None`.
+    pub fn delivery_rate_threshold(&mut self, value: Option<f32>) -> &mut Self {
+        self.delivery_rate_threshold = value;
+        self
+    }
+
     /// Period of inactivity before sending a keep-alive packet
     ///
-    /// Keep-alive packets prevent an inactive but otherwise healthy connection from timing out.
+    /// Keep-alive packets prevent an inactive but otherwise healthy connection from timing out,
+    /// whether due to the QUIC idle timeout or to state expiring in NATs and firewalls along the
+    /// path. Each consists of an ack-eliciting PING frame, sent once no packet has been received
+    /// from the peer for `value`, so applications need not generate traffic of their own.
     ///
     /// `None` to disable, which is the default. Only one side of any given connection needs keep-alive
     /// enabled for the connection to be preserved. Must be set lower than the idle_timeout of both
@@ -177,6 +340,18 @@ impl TransportConfig {
         self
     }
 
+    /// Maximum number of bytes of packets to buffer while the connection is not yet able to process
+    /// them
+    ///
+    /// During the handshake, reordering may cause packets to arrive before the keys needed to
+    /// decrypt them, or 1-RTT packets to arrive before the handshake has been confirmed. Such
+    /// packets are held until they can be processed rather than dropped and retransmitted. Once
+    /// this limit is reached, further packets are dropped. Setting this to 0 disables buffering.
+    pub fn undecryptable_packet_buffer_size(&mut self, value: usize) -> &mut Self {
+        self.undecryptable_packet_buffer_size = value;
+        self
+    }
+
     /// Whether the implementation is permitted to set the spin bit on this connection
     ///
     /// This allows passive observers to easily judge the round trip time of a connection, which can
@@ -186,6 +361,17 @@ impl TransportConfig {
         self
     }
 
+    /// Whether to exercise the peer's handling of protocol extension points
+    ///
+    /// When enabled, a transport parameter with a random reserved ID and value is sent during the
+    /// handshake, and PADDING is occasionally placed ahead of other frames in 1-RTT packets, so that
+    /// implementations which mishandle unknown parameters or unusual frame layouts are noticed
+    /// before the protocol ossifies around them. Defaults to `true`.
+    pub fn grease(&mut self, value: bool) -> &mut Self {
+        self.grease = value;
+        self
+    }
+
     /// Maximum number of incoming application datagram bytes to buffer, or None to disable
     /// incoming datagrams
     ///
@@ -201,17 +387,28 @@ impl TransportConfig {
     ///
     /// While datagrams are sent ASAP, it is possible for an application to generate data faster
     /// than the link, or even the underlying hardware, can transmit them. This limits the amount of
-    /// memory that may be consumed in that case. When the send buffer is full and a new datagram is
-    /// sent, older datagrams are dropped until sufficient space is available.
+    /// memory that may be consumed in that case. What happens when the send buffer is full and a
+    /// new datagram is sent is governed by [`datagram_drop_policy()`](Self::datagram_drop_policy).
     pub fn datagram_send_buffer_size(&mut self, value: usize) -> &mut Self {
         self.datagram_send_buffer_size = value;
         self
     }
 
+    /// How to make room for new outgoing datagrams when the send buffer is full
+    ///
+    /// Defaults to [`DatagramDropPolicy::DropOldest`]. Dropped datagrams are counted in
+    /// `ConnectionStats::datagram_drops` and announced by `Event::DatagramsDropped`.
+    pub fn datagram_drop_policy(&mut self, value: DatagramDropPolicy) -> &mut Self {
+        self.datagram_drop_policy = value;
+        self
+    }
+
     /// How to construct new `congestion::Controller`s
     ///
     /// Typically the refcounted configuration of a `congestion::Controller`,
-    /// e.g. a `congestion::NewRenoConfig`.
+    /// e.g. a `congestion::NewRenoConfig`. Background transfers that should yield bandwidth to
+    /// other traffic may use a `congestion::LedbatConfig`, e.g. in the `TransportConfig` of the
+    /// `ClientConfig` used for those connections only.
     ///
     /// # Example
     /// ```
@@ -226,6 +423,18 @@ impl TransportConfig {
         self.congestion_controller_factory = Box::new(factory);
         self
     }
+
+    /// Where to write a qlog trace of each connection, or `None` to disable tracing
+    ///
+    /// Traces record packets sent, received and lost, and updates to RTT and congestion control
+    /// state, in the qlog JSON-SEQ format understood by tools such as qvis. Use a
+    /// [`QlogDir`](crate::QlogDir) to write each connection's trace to its own file. Tracing is
+    /// costly, so it is best enabled only while diagnosing problems. Defaults to `None`.
+    #[cfg(feature = "qlog")]
+    pub fn qlog(&mut self, factory: Option<Arc<dyn QlogFactory>>) -> &mut Self {
+        self.qlog = factory;
+        self
+    }
 }
 
 impl Default for TransportConfig {
@@ -240,9 +449,17 @@ impl Default for TransportConfig {
             max_concurrent_bidi_streams: 100u32.into(),
             max_concurrent_uni_streams: 100u32.into(),
             max_idle_timeout: Some(VarInt(10_000)),
+            handshake_timeout: None,
+            closing_period: None,
+            active_connection_id_limit: CidQueue::LEN as u32,
             stream_receive_window: STREAM_RWND.into(),
+            max_stream_receive_window: None,
+            stream_reassembly_buffer_size: None,
+            stream_scheduler: SchedulerKind::RoundRobin,
             receive_window: VarInt::MAX,
+            max_receive_window: None,
             send_window: (8 * STREAM_RWND).into(),
+            max_send_window: None,
 
             max_tlps: 2,
             packet_threshold: 3,
@@ -250,13 +467,21 @@ impl Default for TransportConfig {
             initial_rtt: Duration::from_millis(333), // per spec, intentionally distinct from EXPECTED_RTT
 
             persistent_congestion_threshold: 3,
+            ack_frequency: None,
+            delivery_rate_threshold: None,
             keep_alive_interval: None,
             crypto_buffer_size: 16 * 1024,
+            undecryptable_packet_buffer_size: 16 * 1024,
             allow_spin: true,
+            grease: true,
             datagram_receive_buffer_size: Some(STREAM_RWND as usize),
             datagram_send_buffer_size: 1024 * 1024,
+            datagram_drop_policy: DatagramDropPolicy::DropOldest,
 
             congestion_controller_factory: Box::new(Arc::new(congestion::CubicConfig::default())),
+
+            #[cfg(feature = "qlog")]
+            qlog: None,
         }
     }
 }
@@ -273,9 +498,23 @@ impl fmt::Debug for TransportConfig {
                 &self.max_concurrent_uni_streams,
             )
             .field("max_idle_timeout", &self.max_idle_timeout)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("closing_period", &self.closing_period)
+            .field(
+                "active_connection_id_limit",
+                &self.active_connection_id_limit,
+            )
             .field("stream_receive_window", &self.stream_receive_window)
+            .field("max_stream_receive_window", &self.max_stream_receive_window)
+            .field(
+                "stream_reassembly_buffer_size",
+                &self.stream_reassembly_buffer_size,
+            )
+            .field("stream_scheduler", &self.stream_scheduler)
             .field("receive_window", &self.receive_window)
+            .field("max_receive_window", &self.max_receive_window)
             .field("send_window", &self.send_window)
+            .field("max_send_window", &self.max_send_window)
             .field("max_tlps", &self.max_tlps)
             .field("packet_threshold", &self.packet_threshold)
             .field("time_threshold", &self.time_threshold)
@@ -284,19 +523,58 @@ impl fmt::Debug for TransportConfig {
                 "persistent_congestion_threshold",
                 &self.persistent_congestion_threshold,
             )
+            .field("ack_frequency", &self.ack_frequency)
+            .field("delivery_rate_threshold", &self.delivery_rate_threshold)
             .field("keep_alive_interval", &self.keep_alive_interval)
             .field("crypto_buffer_size", &self.crypto_buffer_size)
+            .field(
+                "undecryptable_packet_buffer_size",
+                &self.undecryptable_packet_buffer_size,
+            )
             .field("allow_spin", &self.allow_spin)
+            .field("grease", &self.grease)
             .field(
                 "datagram_receive_buffer_size",
                 &self.datagram_receive_buffer_size,
             )
             .field("datagram_send_buffer_size", &self.datagram_send_buffer_size)
+            .field("datagram_drop_policy", &self.datagram_drop_policy)
             .field("congestion_controller_factory", &"[ opaque ]")
             .finish()
     }
 }
 
+/// Acknowledgement behavior to request from the peer
+///
+/// See [`TransportConfig::ack_frequency()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AckFrequencyConfig {
+    /// Number of ack-eliciting packets the peer may receive before it must send an acknowledgement
+    ///
+    /// Defaults to 1, i.e. every other packet is acknowledged as in standard QUIC.
+    pub ack_eliciting_threshold: VarInt,
+    /// Maximum time the peer may delay acknowledgements, or `None` to keep the `max_ack_delay`
+    /// it advertised
+    ///
+    /// Raised to the minimum delay the peer supports if necessary. Defaults to `None`.
+    pub max_ack_delay: Option<Duration>,
+    /// Number of packets by which a missing packet must be overtaken before the peer
+    /// acknowledges immediately, or 0 to never acknowledge immediately due to reordering
+    ///
+    /// Defaults to 2.
+    pub reordering_threshold: VarInt,
+}
+
+impl Default for AckFrequencyConfig {
+    fn default() -> Self {
+        Self {
+            ack_eliciting_threshold: VarInt(1),
+            max_ack_delay: None,
+            reordering_threshold: VarInt(2),
+        }
+    }
+}
+
 /// Global configuration for the endpoint, affecting all connections
 ///
 /// Default values should be suitable for most internet applications.
@@ -311,6 +589,8 @@ pub struct EndpointConfig {
         Arc<dyn Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync>,
     pub(crate) supported_versions: Vec<u32>,
     pub(crate) initial_version: u32,
+    pub(crate) version_negotiation_rate_limit: Option<ResponseRateLimit>,
+    pub(crate) stateless_reset_rate_limit: Option<ResponseRateLimit>,
 }
 
 impl EndpointConfig {
@@ -324,6 +604,8 @@ impl EndpointConfig {
             connection_id_generator_factory: Arc::new(cid_factory),
             initial_version: DEFAULT_SUPPORTED_VERSIONS[0],
             supported_versions: DEFAULT_SUPPORTED_VERSIONS.to_vec(),
+            version_negotiation_rate_limit: Some(ResponseRateLimit::default()),
+            stateless_reset_rate_limit: Some(ResponseRateLimit::default()),
         }
     }
 
@@ -345,6 +627,45 @@ impl EndpointConfig {
         self
     }
 
+    /// Generate connection IDs routable by a QUIC-LB load balancer to the server `server_id`
+    ///
+    /// Shorthand for a [`cid_generator()`](Self::cid_generator) factory constructing an
+    /// [`LoadBalancerConnectionIdGenerator`] from `config`, so that every server of a fleet can be
+    /// configured with just the shared `config` and its own ID. `server_id` must be as long as the
+    /// configuration's server IDs.
+    #[cfg(feature = "ring")]
+    pub fn load_balancer(
+        &mut self,
+        config: LoadBalancerConfig,
+        server_id: &[u8],
+    ) -> Result<&mut Self, ConfigError> {
+        // Validate once up front so the factory can't fail
+        LoadBalancerConnectionIdGenerator::new(config.clone(), server_id)?;
+        let server_id = server_id.to_vec();
+        Ok(self.cid_generator(move || {
+            Box::new(
+                LoadBalancerConnectionIdGenerator::new(config.clone(), &server_id)
+                    .expect("server ID length was checked"),
+            )
+        }))
+    }
+
+    /// Length of the connection IDs generated for incoming packets, in bytes
+    ///
+    /// Replaces any custom generator set with [`cid_generator()`](Self::cid_generator) by a random
+    /// one. Shorter IDs reduce per-packet overhead, while longer ones leave room for routing
+    /// information used by load balancers and make collisions less likely on busy endpoints.
+    /// Zero-length IDs identify connections by their peer's address alone, so connections can't
+    /// migrate, and only one connection per peer address is possible. Must be at most 20.
+    /// Defaults to 8.
+    pub fn cid_len(&mut self, len: usize) -> Result<&mut Self, ConfigError> {
+        if len > MAX_CID_SIZE {
+            return Err(ConfigError::OutOfBounds);
+        }
+        self.cid_generator(move || Box::new(RandomConnectionIdGenerator::new(len)));
+        Ok(self)
+    }
+
     /// Private key used to send authenticated connection resets to peers who were
     /// communicating with a previous instance of this endpoint.
     pub fn reset_key(&mut self, key: Arc<dyn HmacKey>) -> &mut Self {
@@ -375,6 +696,17 @@ impl EndpointConfig {
     }
 
     /// Override supported QUIC versions
+    ///
+    /// `supported_versions` lists the versions accepted from peers, in order of preference, and
+    /// is advertised to clients in Version Negotiation packets. Outgoing connections start out
+    /// with `initial_version`, which must be in the list. If the server responds with Version
+    /// Negotiation, the client restarts the handshake once using the first of
+    /// `supported_versions` that the server offers, discarding any 0-RTT data. Setting
+    /// `initial_version` to a reserved version of the form `0x?a?a?a?a` exercises a server's
+    /// version negotiation.
+    ///
+    /// Defaults to [`DEFAULT_SUPPORTED_VERSIONS`], starting with draft 29. Use `vec![1]` and `1`
+    /// to restrict connections to QUIC version 1.
     pub fn supported_versions(
         &mut self,
         supported_versions: Vec<u32>,
@@ -387,6 +719,51 @@ impl EndpointConfig {
         self.initial_version = initial_version;
         Ok(self)
     }
+
+    /// Limit on the rate of Version Negotiation packets sent in response to packets with
+    /// unsupported versions, or `None` for no limit
+    ///
+    /// Because the source address of such packets is unauthenticated, a spoofed packet can direct
+    /// the response at a third party. Responses in excess of the limit are silently skipped.
+    pub fn version_negotiation_rate_limit(
+        &mut self,
+        limit: Option<ResponseRateLimit>,
+    ) -> &mut Self {
+        self.version_negotiation_rate_limit = limit;
+        self
+    }
+
+    /// Limit on the rate of stateless resets sent in response to packets for unknown connections,
+    /// or `None` for no limit
+    ///
+    /// Like Version Negotiation packets, stateless resets can be directed at a third party by
+    /// spoofed packets. Responses in excess of the limit are silently skipped, so peers of lost
+    /// connections may have to wait for their idle timeout instead.
+    pub fn stateless_reset_rate_limit(&mut self, limit: Option<ResponseRateLimit>) -> &mut Self {
+        self.stateless_reset_rate_limit = limit;
+        self
+    }
+}
+
+/// Limit on the rate at which an endpoint sends responses to unauthenticated packets
+///
+/// Rates are measured in responses per second. The default allows 1000 responses per second in
+/// total, and 10 per second to any one IP address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResponseRateLimit {
+    /// Number of responses that may be sent to all addresses combined
+    pub global: u32,
+    /// Number of responses that may be sent to a single IP address
+    pub per_address: u32,
+}
+
+impl Default for ResponseRateLimit {
+    fn default() -> Self {
+        Self {
+            global: 1000,
+            per_address: 10,
+        }
+    }
 }
 
 impl fmt::Debug for EndpointConfig {
@@ -397,6 +774,14 @@ impl fmt::Debug for EndpointConfig {
             .field("cid_generator_factory", &"[ elided ]")
             .field("supported_versions", &self.supported_versions)
             .field("initial_version", &self.initial_version)
+            .field(
+                "version_negotiation_rate_limit",
+                &self.version_negotiation_rate_limit,
+            )
+            .field(
+                "stateless_reset_rate_limit",
+                &self.stateless_reset_rate_limit,
+            )
             .finish()
     }
 }
@@ -436,15 +821,45 @@ pub struct ServerConfig {
     pub(crate) use_stateless_retry: bool,
     /// Microseconds after a stateless retry token was issued for which it's considered valid.
     pub(crate) retry_token_lifetime: Duration,
+    /// Decides which clients must complete a Retry, superseding `use_stateless_retry`
+    pub(crate) retry_policy: Option<Arc<dyn RetryPolicy>>,
 
     /// Maximum number of concurrent connections
     pub(crate) concurrent_connections: u32,
 
+    /// Maximum number of incoming connections that may be handshaking at once
+    pub(crate) max_incoming_handshakes: u32,
+    /// Maximum number of incoming connections awaiting acceptance by the application
+    pub(crate) accept_buffer: u32,
+
     /// Whether to allow clients to migrate to new addresses
     ///
     /// Improves behavior for clients that move between different internet connections or suffer NAT
     /// rebinding. Enabled by default.
+    ///
+    /// When disabled, the `disable_active_migration` transport parameter is sent to clients, and
+    /// non-probing packets from addresses other than the one the connection was established from
+    /// are ignored. Useful behind load balancers that route by address, where a migrated
+    /// connection could not be reached anyway.
     pub(crate) migration: bool,
+
+    /// IPv4 address clients should move to once the handshake is confirmed
+    pub(crate) preferred_address_v4: Option<SocketAddrV4>,
+    /// IPv6 address clients should move to once the handshake is confirmed
+    pub(crate) preferred_address_v6: Option<SocketAddrV6>,
+
+    /// Governs acceptance of 0-RTT data
+    pub(crate) zero_rtt_policy: Option<Arc<dyn ZeroRttPolicy>>,
+
+    /// Crypto and transport configuration overrides by lowercase server name
+    pub(crate) server_names: FxHashMap<String, ServerNameConfig>,
+}
+
+/// Configuration of connections to a particular server name
+#[derive(Clone)]
+pub(crate) struct ServerNameConfig {
+    pub(crate) crypto: Arc<dyn crypto::ServerConfig>,
+    pub(crate) transport: Arc<TransportConfig>,
 }
 
 impl ServerConfig {
@@ -460,10 +875,20 @@ impl ServerConfig {
             token_key,
             use_stateless_retry: false,
             retry_token_lifetime: Duration::from_secs(15),
+            retry_policy: None,
 
             concurrent_connections: 100_000,
+            max_incoming_handshakes: u32::MAX,
+            accept_buffer: u32::MAX,
 
             migration: true,
+
+            preferred_address_v4: None,
+            preferred_address_v6: None,
+
+            zero_rtt_policy: None,
+
+            server_names: FxHashMap::default(),
         }
     }
 
@@ -490,23 +915,132 @@ impl ServerConfig {
         self
     }
 
+    /// Decide which clients must prove ownership of their address with a Retry
+    ///
+    /// Supersedes [`use_stateless_retry`](Self::use_stateless_retry), allowing e.g. Retry to be
+    /// required only under load, and optionally replaces the built-in token scheme through
+    /// [`RetryPolicy::token_codec()`]. `None` to restore the default behavior.
+    pub fn retry_policy(&mut self, value: Option<Arc<dyn RetryPolicy>>) -> &mut Self {
+        self.retry_policy = value;
+        self
+    }
+
     /// Maximum number of simultaneous connections to accept.
     ///
     /// New incoming connections are only accepted if the total number of incoming or outgoing
-    /// connections is less than this. Outgoing connections are unaffected.
+    /// connections is less than this. Outgoing connections are unaffected. Connection attempts
+    /// beyond the limit are refused with a `CONNECTION_REFUSED` error in response to their first
+    /// packet, before any per-connection state is allocated, and counted by
+    /// [`EndpointStats::connections_refused`](crate::EndpointStats::connections_refused).
     pub fn concurrent_connections(&mut self, value: u32) -> &mut Self {
         self.concurrent_connections = value;
         self
     }
 
+    /// Maximum number of incoming connections that may be handshaking at once
+    ///
+    /// Handshakes are comparatively expensive in CPU and memory, so this bounds the damage a
+    /// flood of connection attempts can do independently of
+    /// [`concurrent_connections`](Self::concurrent_connections). Once the limit is reached,
+    /// further connection attempts are answered with a Retry, as if
+    /// [`use_stateless_retry`](Self::use_stateless_retry) were enabled, and refused outright if
+    /// the client has already proven ownership of its address. Unlimited by default.
+    pub fn max_incoming_handshakes(&mut self, value: u32) -> &mut Self {
+        self.max_incoming_handshakes = value;
+        self
+    }
+
+    /// Maximum number of incoming connections awaiting acceptance by the application
+    ///
+    /// Connections count against this limit from their first packet until passed to
+    /// [`Endpoint::accept_connection()`](crate::Endpoint::accept_connection) or drained, so that
+    /// an application which falls behind on accepting connections doesn't buffer an unbounded
+    /// number of them. Once the limit is reached, further connection attempts are refused.
+    /// Unlimited by default.
+    pub fn accept_buffer(&mut self, value: u32) -> &mut Self {
+        self.accept_buffer = value;
+        self
+    }
+
     /// Whether to allow clients to migrate to new addresses
     ///
     /// Improves behavior for clients that move between different internet connections or suffer NAT
     /// rebinding. Enabled by default.
+    ///
+    /// When disabled, the `disable_active_migration` transport parameter is sent to clients, and
+    /// non-probing packets from addresses other than the one the connection was established from
+    /// are ignored. Useful behind load balancers that route by address, where a migrated
+    /// connection could not be reached anyway.
     pub fn migration(&mut self, value: bool) -> &mut Self {
         self.migration = value;
         self
     }
+
+    /// IPv4 address for clients to move to once the handshake is confirmed
+    ///
+    /// Sent to clients in the `preferred_address` transport parameter, e.g. to move them from an
+    /// anycast address shared by many servers to a unicast address of this server. Clients validate
+    /// the preferred address before migrating to it, and carry on at the original address if that
+    /// fails, so the endpoint must be reachable at both. Only sent if the endpoint uses
+    /// connection IDs of nonzero length. `None` by default.
+    pub fn preferred_address_v4(&mut self, value: Option<SocketAddrV4>) -> &mut Self {
+        self.preferred_address_v4 = value;
+        self
+    }
+
+    /// IPv6 address for clients to move to once the handshake is confirmed
+    ///
+    /// See [`preferred_address_v4()`](Self::preferred_address_v4) for details.
+    pub fn preferred_address_v6(&mut self, value: Option<SocketAddrV6>) -> &mut Self {
+        self.preferred_address_v6 = value;
+        self
+    }
+
+    /// Decide whether to accept 0-RTT data from each resuming client, and how much of it
+    ///
+    /// Allows session tickets to be admitted at most once to guard against replay, and bounds the
+    /// work done for data that isn't yet known to come from a live client. Only takes effect if
+    /// the crypto configuration enables 0-RTT in the first place. If `None`, the default, 0-RTT
+    /// data is accepted whenever the crypto configuration allows it.
+    pub fn zero_rtt_policy(&mut self, value: Option<Arc<dyn ZeroRttPolicy>>) -> &mut Self {
+        self.zero_rtt_policy = value;
+        self
+    }
+
+    /// Use `crypto` and `transport` for incoming connections to the server named `name`
+    ///
+    /// Allows one endpoint to serve several virtual hosts, each with its own certificates and
+    /// transport configuration, by selecting a configuration according to the server name
+    /// indication (SNI) of the client's ClientHello. Names are matched exactly, ignoring case and
+    /// any trailing dot. Connections to other names, or from clients which don't indicate a server
+    /// name, use the [`crypto`](Self::crypto) and [`transport`](Self::transport) configuration of
+    /// `self`, as do endpoint-wide settings such as [`use_stateless_retry`](Self::use_stateless_retry)
+    /// and connection limits.
+    ///
+    /// The server name must be contained in the client's first Initial packet, which is the case
+    /// for ClientHellos of typical size.
+    pub fn server_name(
+        &mut self,
+        name: &str,
+        crypto: Arc<dyn crypto::ServerConfig>,
+        transport: Arc<TransportConfig>,
+    ) -> &mut Self {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        self.server_names
+            .insert(name, ServerNameConfig { crypto, transport });
+        self
+    }
+
+    /// The crypto and transport configuration for connections to `server_name`
+    pub(crate) fn select(
+        &self,
+        server_name: Option<&str>,
+    ) -> (&Arc<dyn crypto::ServerConfig>, &Arc<TransportConfig>) {
+        match server_name.and_then(|name| self.server_names.get(name)) {
+            Some(config) => (&config.crypto, &config.transport),
+            None => (&self.crypto, &self.transport),
+        }
+    }
 }
 
 #[cfg(feature = "rustls")]
@@ -522,6 +1056,19 @@ impl ServerConfig {
         Ok(Self::with_crypto(Arc::new(crypto)))
     }
 
+    /// Create a server config that selects the certificate to present to each client with
+    /// `resolver`
+    ///
+    /// Useful to staple OCSP responses with a [`StapledCertResolver`], or to update certificates
+    /// without replacing the server configuration. Uses a randomized handshake token key.
+    ///
+    /// [`StapledCertResolver`]: crypto::rustls::StapledCertResolver
+    pub fn with_cert_resolver(resolver: Arc<dyn rustls::server::ResolvesServerCert>) -> Self {
+        Self::with_crypto(Arc::new(crypto::rustls::server_config_with_resolver(
+            resolver,
+        )))
+    }
+
     /// Create a server config with the given [`rustls::ServerConfig`]
     ///
     /// Uses a randomized handshake token key.
@@ -543,8 +1090,21 @@ impl fmt::Debug for ServerConfig {
             .field("token_key", &"[ elided ]")
             .field("use_stateless_retry", &self.use_stateless_retry)
             .field("retry_token_lifetime", &self.retry_token_lifetime)
+            .field(
+                "retry_policy",
+                &self.retry_policy.as_ref().map(|_| "[ elided ]"),
+            )
             .field("concurrent_connections", &self.concurrent_connections)
+            .field("max_incoming_handshakes", &self.max_incoming_handshakes)
+            .field("accept_buffer", &self.accept_buffer)
             .field("migration", &self.migration)
+            .field("preferred_address_v4", &self.preferred_address_v4)
+            .field("preferred_address_v6", &self.preferred_address_v6)
+            .field(
+                "zero_rtt_policy",
+                &self.zero_rtt_policy.as_ref().map(|_| "[ elided ]"),
+            )
+            .field("server_names", &self.server_names.keys())
             .finish()
     }
 }
@@ -559,6 +1119,12 @@ pub struct ClientConfig {
 
     /// Cryptographic configuration to use
     pub crypto: Arc<dyn crypto::ClientConfig>,
+
+    /// Where to save and look up the characteristics of the path to each server
+    ///
+    /// When set, connections resume the congestion window of earlier connections to the same
+    /// server, see [`PathCache`]. Defaults to `None`.
+    pub path_cache: Option<Arc<dyn PathCache>>,
 }
 
 #[cfg(feature = "rustls")]
@@ -566,12 +1132,13 @@ impl ClientConfig {
     /// Create a client configuration that trusts the platform's native roots
     #[cfg(feature = "native-certs")]
     pub fn with_native_roots() -> Self {
-        let mut roots = rustls::RootCertStore::empty();
+        let mut roots = Vec::new();
         match rustls_native_certs::load_native_certs() {
             Ok(certs) => {
                 for cert in certs {
-                    if let Err(e) = roots.add(&rustls::Certificate(cert.0)) {
-                        tracing::warn!("failed to parse trust anchor: {}", e);
+                    match webpki::TrustAnchor::try_from_cert_der(&cert.0) {
+                        Ok(_) => roots.push(rustls::Certificate(cert.0)),
+                        Err(e) => tracing::warn!("failed to parse trust anchor: {}", e),
                     }
                 }
             }
@@ -579,6 +1146,8 @@ impl ClientConfig {
                 tracing::warn!("couldn't load any default trust roots: {}", e);
             }
         };
+        let roots =
+            crypto::rustls::WebPkiServerVerifier::new(roots).expect("trust anchors were validated");
 
         Self::new(roots)
     }
@@ -587,18 +1156,36 @@ impl ClientConfig {
     pub fn with_root_certificates(
         certs: impl IntoIterator<Item = Certificate>,
     ) -> Result<Self, webpki::Error> {
-        let mut roots = rustls::RootCertStore::empty();
-        for cert in certs {
-            roots.add(&cert.inner)?;
-        }
+        Ok(Self::builder(certs)?.build())
+    }
 
-        Ok(Self::new(roots))
+    /// Start building a client configuration that trusts specified trust anchors
+    ///
+    /// See [`ClientConfigBuilder`] for the options that can be combined on top.
+    pub fn builder(
+        certs: impl IntoIterator<Item = Certificate>,
+    ) -> Result<ClientConfigBuilder, webpki::Error> {
+        let roots =
+            crypto::rustls::WebPkiServerVerifier::new(certs.into_iter().map(|cert| cert.inner))?;
+
+        Ok(ClientConfigBuilder {
+            roots: Arc::new(roots),
+            pins: None,
+            crls: None,
+            ct_logs: None,
+            cert_selector: None,
+            session_cache: None,
+        })
     }
 
-    fn new(roots: rustls::RootCertStore) -> Self {
+    #[cfg(feature = "native-certs")]
+    fn new(roots: crypto::rustls::WebPkiServerVerifier) -> Self {
+        let mut crypto = crypto::rustls::client_config(rustls::RootCertStore::empty());
+        crypto.dangerous().set_certificate_verifier(Arc::new(roots));
         Self {
             transport: Arc::new(TransportConfig::default()),
-            crypto: Arc::new(crypto::rustls::client_config(roots)),
+            crypto: Arc::new(crypto),
+            path_cache: None,
         }
     }
 }
@@ -608,6 +1195,173 @@ impl fmt::Debug for ClientConfig {
         fmt.debug_struct("ClientConfig<T>")
             .field("transport", &self.transport)
             .field("crypto", &"ClientConfig { elided }")
+            .field(
+                "path_cache",
+                &self.path_cache.as_ref().map(|_| "PathCache { elided }"),
+            )
+            .finish()
+    }
+}
+
+/// Builds a rustls-based [`ClientConfig`] from trust anchors and any combination of further
+/// options
+///
+/// Constructed with [`ClientConfig::builder()`].
+#[cfg(feature = "rustls")]
+pub struct ClientConfigBuilder {
+    roots: Arc<crypto::rustls::WebPkiServerVerifier>,
+    pins: Option<Vec<[u8; 32]>>,
+    crls: Option<Vec<crypto::rustls::CertificateRevocationList>>,
+    ct_logs: Option<(
+        &'static [&'static sct::Log<'static>],
+        crypto::rustls::SctPolicy,
+    )>,
+    cert_selector: Option<Arc<CertSelector>>,
+    session_cache: Option<Arc<dyn SessionCache>>,
+}
+
+#[cfg(feature = "rustls")]
+type CertSelector = dyn Fn(&crypto::rustls::CertificateRequest) -> Option<(CertificateChain, PrivateKey)>
+    + Send
+    + Sync;
+
+#[cfg(feature = "rustls")]
+impl ClientConfigBuilder {
+    /// Additionally require the server's certificate chain to contain one of the public keys
+    /// `pins`
+    ///
+    /// Each pin is the SHA-256 hash of a DER-encoded SubjectPublicKeyInfo, which can be computed
+    /// from a certificate with [`Certificate::spki_sha256()`]. Pinning protects against
+    /// certificates mistakenly or maliciously issued by otherwise trusted authorities. To also pin
+    /// keys on a custom rustls configuration, use [`SpkiPinningVerifier`].
+    ///
+    /// [`SpkiPinningVerifier`]: crypto::rustls::SpkiPinningVerifier
+    pub fn pinned_keys(&mut self, pins: impl IntoIterator<Item = [u8; 32]>) -> &mut Self {
+        self.pins = Some(pins.into_iter().collect());
+        self
+    }
+
+    /// Reject server certificates revoked by any of `crls`
+    ///
+    /// Handshakes with servers presenting a revoked certificate fail with
+    /// [`TransportErrorCode::CERTIFICATE_REVOKED`]. Certificates from an issuer whose list is past
+    /// its `nextUpdate` time are rejected too, so lists must be refreshed as they are reissued. To
+    /// also check OCSP responses, or to check
+    /// revocation on a custom rustls configuration, use [`RevocationVerifier`].
+    ///
+    /// [`TransportErrorCode::CERTIFICATE_REVOKED`]: crate::TransportErrorCode::CERTIFICATE_REVOKED
+    /// [`RevocationVerifier`]: crypto::rustls::RevocationVerifier
+    pub fn crls(
+        &mut self,
+        crls: impl IntoIterator<Item = crypto::rustls::CertificateRevocationList>,
+    ) -> &mut Self {
+        self.crls = Some(crls.into_iter().collect());
+        self
+    }
+
+    /// Verify the server's signed certificate timestamps against the Certificate Transparency
+    /// logs `logs`
+    ///
+    /// With [`SctPolicy::Require`], handshakes fail unless the server presents at least one valid
+    /// SCT issued by one of `logs`, either stapled during the handshake or embedded in its
+    /// certificate. With [`SctPolicy::BestEffort`], servers that present no such SCT are
+    /// accepted, but invalid SCTs claiming to be issued by one of `logs` are still rejected.
+    ///
+    /// [`SctPolicy::Require`]: crypto::rustls::SctPolicy::Require
+    /// [`SctPolicy::BestEffort`]: crypto::rustls::SctPolicy::BestEffort
+    pub fn ct_logs(
+        &mut self,
+        logs: &'static [&'static sct::Log<'static>],
+        policy: crypto::rustls::SctPolicy,
+    ) -> &mut Self {
+        self.ct_logs = Some((logs, policy));
+        self
+    }
+
+    /// Select the certificate to present to servers requesting client authentication with
+    /// `selector`
+    ///
+    /// `selector` is called during each handshake in which the server requests a client
+    /// certificate, and may return `None` to proceed without one. To present a different identity
+    /// per connection, e.g. per tenant, build a configuration whose `selector` captures that
+    /// identity and pass it to the connect call.
+    pub fn client_cert_selector<F>(&mut self, selector: F) -> &mut Self
+    where
+        F: Fn(&crypto::rustls::CertificateRequest) -> Option<(CertificateChain, PrivateKey)>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.cert_selector = Some(Arc::new(selector));
+        self
+    }
+
+    /// Store the state needed to resume sessions in `cache`
+    ///
+    /// Sessions are otherwise only kept in memory for the lifetime of the configuration, so a
+    /// cache that persists its contents allows 0-RTT to be used after the client restarts.
+    pub fn session_cache(&mut self, cache: Arc<dyn SessionCache>) -> &mut Self {
+        self.session_cache = Some(cache);
+        self
+    }
+
+    /// Construct the configuration
+    pub fn build(&self) -> ClientConfig {
+        let mut verifier: Arc<dyn rustls::client::ServerCertVerifier> = self.roots.clone();
+        if let Some(ref crls) = self.crls {
+            verifier = Arc::new(crypto::rustls::RevocationVerifier::new(
+                verifier,
+                crls.iter().cloned(),
+            ));
+        }
+        if let Some((logs, policy)) = self.ct_logs {
+            verifier = Arc::new(crypto::rustls::SctVerifier::new(verifier, logs, policy));
+        }
+        if let Some(ref pins) = self.pins {
+            verifier = Arc::new(crypto::rustls::SpkiPinningVerifier::new(
+                verifier,
+                &self.roots,
+                pins.iter().copied(),
+            ));
+        }
+
+        let mut crypto = match self.cert_selector {
+            Some(ref selector) => {
+                let selector = selector.clone();
+                crypto::rustls::client_config_with_cert_selector(
+                    rustls::RootCertStore::empty(),
+                    move |request| selector(request),
+                )
+            }
+            None => crypto::rustls::client_config(rustls::RootCertStore::empty()),
+        };
+        crypto.dangerous().set_certificate_verifier(verifier);
+        if let Some(ref cache) = self.session_cache {
+            crypto.session_storage = Arc::new(crypto::rustls::SessionStorage::new(cache.clone()));
+        }
+        ClientConfig {
+            transport: Arc::new(TransportConfig::default()),
+            crypto: Arc::new(crypto),
+            path_cache: None,
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl fmt::Debug for ClientConfigBuilder {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ClientConfigBuilder")
+            .field("pins", &self.pins.as_ref().map(|pins| pins.len()))
+            .field("crls", &self.crls.as_ref().map(|crls| crls.len()))
+            .field("ct_logs", &self.ct_logs.map(|(_, policy)| policy))
+            .field(
+                "cert_selector",
+                &self.cert_selector.as_ref().map(|_| "[ elided ]"),
+            )
+            .field(
+                "session_cache",
+                &self.session_cache.as_ref().map(|_| "[ elided ]"),
+            )
             .finish()
     }
 }
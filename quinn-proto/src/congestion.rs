@@ -2,10 +2,16 @@
 
 use std::time::{Duration, Instant};
 
+use crate::connection::RttEstimator;
+
+mod copa;
 mod cubic;
+mod ledbat;
 mod new_reno;
 
+pub use copa::{Copa, CopaConfig};
 pub use cubic::{Cubic, CubicConfig};
+pub use ledbat::{Ledbat, LedbatConfig};
 pub use new_reno::{NewReno, NewRenoConfig};
 
 /// Common interface for different congestion controllers
@@ -16,6 +22,21 @@ pub trait Controller: Send {
     /// application data prior to receiving these acknowledgements.
     fn on_ack(&mut self, now: Instant, sent: Instant, bytes: u64, app_limited: bool, rtt: Duration);
 
+    /// Packet deliveries were confirmed, with RTT statistics beyond the smoothed RTT at hand
+    ///
+    /// This is what connections call, allowing controllers which react to the latest or minimum
+    /// RTT to see them. Defaults to calling `on_ack` with the smoothed RTT.
+    fn on_ack_with_rtt(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.on_ack(now, sent, bytes, app_limited, rtt.get());
+    }
+
     /// Packets were deemed lost or marked congested
     ///
     /// `in_persistent_congestion` indicates whether all packets sent within the persistent
@@ -26,6 +47,15 @@ pub trait Controller: Send {
     /// Number of ack-eliciting bytes that may be in flight
     fn window(&self) -> u64;
 
+    /// Replace the congestion window with `window`, e.g. to resume that of an earlier connection
+    ///
+    /// Implementations should respect their minimum window, and otherwise leave their state, such
+    /// as the slow start threshold, unchanged. Controllers which ignore this, as by default, are
+    /// never jump-started.
+    fn set_window(&mut self, window: u64) {
+        let _ = window;
+    }
+
     /// Duplicate the controller's state
     fn clone_box(&self) -> Box<dyn Controller>;
 
@@ -38,3 +68,23 @@ pub trait ControllerFactory {
     /// Construct a fresh `Controller`
     fn build(&self, now: Instant) -> Box<dyn Controller>;
 }
+
+#[cfg(test)]
+mod test_util {
+    use super::*;
+
+    /// Feed a window's worth of acks with RTT `rtt` to `controller`, one RTT after `now`
+    pub(super) fn round(
+        controller: &mut dyn Controller,
+        estimator: &mut RttEstimator,
+        now: &mut Instant,
+        rtt: Duration,
+    ) {
+        *now += rtt;
+        let packets = (controller.window() / 1232).max(1);
+        for _ in 0..packets {
+            estimator.update(Duration::new(0, 0), rtt);
+            controller.on_ack_with_rtt(*now, *now - rtt, 1232, false, estimator);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Controller, ControllerFactory};
+use crate::connection::RttEstimator;
+
+/// A delay-based congestion controller
+///
+/// Implements Copa as described in "Copa: Practical Delay-Based Congestion Control for the
+/// Internet" (Arun and Balakrishnan, NSDI 2018). Rather than filling bottleneck buffers until
+/// packets are lost, Copa targets a sending rate inversely proportional to the queueing delay it
+/// observes, which keeps queues, and hence latency, short. Losses other than persistent
+/// congestion are not treated as a congestion signal.
+///
+/// The queueing delay is the difference between the standing RTT, the minimum RTT sample over the
+/// last half smoothed RTT, and the minimum RTT sample over the last 10 seconds. Both are tracked
+/// from the individual samples, whether passed to `on_ack` or taken from the latest sample of
+/// the connection's RTT estimator.
+#[derive(Debug, Clone)]
+pub struct Copa {
+    config: Arc<CopaConfig>,
+    /// Maximum number of bytes in flight that may be sent
+    window: u64,
+    /// Whether the window is doubled every RTT rather than adjusted by `velocity`
+    slow_start: bool,
+    /// Multiplier for window adjustments, doubled while the window keeps moving in one direction
+    velocity: u64,
+    /// Direction the window moved in during the last completed RTT
+    direction: Option<Direction>,
+    /// Number of consecutive RTTs the window moved in `direction`
+    same_direction: u32,
+    /// Start of the current RTT and the window at that time
+    epoch: Option<(Instant, u64)>,
+    /// RTT samples over the last half smoothed RTT
+    standing_rtt: MinFilter,
+    /// RTT samples over the last `MIN_RTT_WINDOW`
+    min_rtt: MinFilter,
+}
+
+impl Copa {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<CopaConfig>, _now: Instant) -> Self {
+        Self {
+            window: config.initial_window,
+            slow_start: true,
+            velocity: 1,
+            direction: None,
+            same_direction: 0,
+            epoch: None,
+            standing_rtt: MinFilter::default(),
+            min_rtt: MinFilter::default(),
+            config,
+        }
+    }
+
+    /// React to the acknowledgement of `bytes` with the RTT sample `latest`, given the smoothed
+    /// RTT `smoothed`
+    fn on_sample(
+        &mut self,
+        now: Instant,
+        bytes: u64,
+        app_limited: bool,
+        latest: Duration,
+        smoothed: Duration,
+    ) {
+        let standing = self.standing_rtt.update(now, latest, smoothed / 2);
+        let min = self.min_rtt.update(now, latest, MIN_RTT_WINDOW);
+        if app_limited {
+            return;
+        }
+        let queueing_delay = standing.checked_sub(min).unwrap_or_default();
+
+        // The current rate, window / standing RTT, is below the target rate, max datagram size /
+        // (delta * queueing delay)
+        let below_target = self.window as f64 * self.config.delta * queueing_delay.as_secs_f64()
+            <= self.config.max_datagram_size as f64 * standing.as_secs_f64();
+
+        if self.slow_start {
+            if below_target {
+                self.window += bytes;
+                return;
+            }
+            self.slow_start = false;
+        }
+
+        self.update_velocity(now, smoothed);
+        let change = (self.velocity * self.config.max_datagram_size * bytes) as f64
+            / (self.config.delta * self.window as f64);
+        if below_target {
+            self.window += change as u64;
+        } else {
+            self.window = self
+                .window
+                .saturating_sub(change as u64)
+                .max(self.config.minimum_window);
+        }
+    }
+
+    /// Update `velocity` once per RTT based on the direction the window moved in
+    fn update_velocity(&mut self, now: Instant, rtt: Duration) {
+        let (start, window) = match self.epoch {
+            Some(x) => x,
+            None => {
+                self.epoch = Some((now, self.window));
+                return;
+            }
+        };
+        if now.saturating_duration_since(start) < rtt {
+            return;
+        }
+        self.epoch = Some((now, self.window));
+        let direction = if self.window > window {
+            Direction::Up
+        } else {
+            Direction::Down
+        };
+        if self.direction == Some(direction) {
+            self.same_direction += 1;
+            // Only speed up once the direction has been stable for a few RTTs
+            if self.same_direction >= 3 {
+                self.velocity = (self.velocity * 2).min(MAX_VELOCITY);
+            }
+        } else {
+            self.direction = Some(direction);
+            self.same_direction = 0;
+            self.velocity = 1;
+        }
+    }
+}
+
+impl Controller for Copa {
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        _sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: Duration,
+    ) {
+        self.on_sample(now, bytes, app_limited, rtt, rtt);
+    }
+
+    fn on_ack_with_rtt(
+        &mut self,
+        now: Instant,
+        _sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.on_sample(now, bytes, app_limited, rtt.latest(), rtt.get());
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        _now: Instant,
+        _sent: Instant,
+        is_persistent_congestion: bool,
+    ) {
+        // Losses are left to the delay signal, but there's no sense in further exponential growth
+        self.slow_start = false;
+        if is_persistent_congestion {
+            self.window = self.config.minimum_window;
+            self.velocity = 1;
+            self.direction = None;
+            self.same_direction = 0;
+        }
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn set_window(&mut self, window: u64) {
+        self.window = window.max(self.config.minimum_window);
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Direction {
+    Up,
+    Down,
+}
+
+/// Tracks the minimum of the samples taken over a sliding window
+#[derive(Debug, Clone, Default)]
+struct MinFilter {
+    /// Samples within the window with increasing times and values, so that the first one is the
+    /// minimum
+    samples: VecDeque<(Instant, Duration)>,
+}
+
+impl MinFilter {
+    /// Record `sample`, returning the minimum over the `window` preceding `now`
+    fn update(&mut self, now: Instant, sample: Duration, window: Duration) -> Duration {
+        while matches!(self.samples.back(), Some(&(_, x)) if x >= sample) {
+            self.samples.pop_back();
+        }
+        self.samples.push_back((now, sample));
+        while matches!(self.samples.front(), Some(&(t, _)) if now.saturating_duration_since(t) > window)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.front().unwrap().1
+    }
+}
+
+/// Upper bound on `Copa::velocity`, to keep adjustments from overshooting wildly
+const MAX_VELOCITY: u64 = 1 << 16;
+
+/// Period over which the minimum RTT is taken, as in the paper
+const MIN_RTT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Configuration for the `Copa` congestion controller
+#[derive(Debug, Clone)]
+pub struct CopaConfig {
+    max_datagram_size: u64,
+    initial_window: u64,
+    minimum_window: u64,
+    delta: f64,
+}
+
+impl CopaConfig {
+    /// The sender’s maximum UDP payload size. Does not include UDP or IP overhead.
+    ///
+    /// Used for calculating initial and minimum congestion windows.
+    pub fn max_datagram_size(&mut self, value: u64) -> &mut Self {
+        self.max_datagram_size = value;
+        self
+    }
+
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Default minimum congestion window.
+    ///
+    /// Recommended value: `2 * max_datagram_size`.
+    pub fn minimum_window(&mut self, value: u64) -> &mut Self {
+        self.minimum_window = value;
+        self
+    }
+
+    /// Trade-off between throughput and delay
+    ///
+    /// Copa aims to keep about `1 / delta` packets queued at the bottleneck, so smaller values
+    /// favor throughput and larger values favor low delay. Defaults to 0.5.
+    pub fn delta(&mut self, value: f64) -> &mut Self {
+        self.delta = value;
+        self
+    }
+}
+
+impl Default for CopaConfig {
+    fn default() -> Self {
+        const MAX_DATAGRAM_SIZE: u64 = 1232;
+        Self {
+            max_datagram_size: MAX_DATAGRAM_SIZE,
+            initial_window: 14720.clamp(2 * MAX_DATAGRAM_SIZE, 10 * MAX_DATAGRAM_SIZE),
+            minimum_window: 2 * MAX_DATAGRAM_SIZE,
+            delta: 0.5,
+        }
+    }
+}
+
+impl ControllerFactory for Arc<CopaConfig> {
+    fn build(&self, now: Instant) -> Box<dyn Controller> {
+        Box::new(Copa::new(self.clone(), now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::congestion::test_util::round;
+
+    #[test]
+    fn tracks_queueing_delay() {
+        let base = Duration::from_millis(50);
+        let mut now = Instant::now();
+        let mut estimator = RttEstimator::new(base);
+        let mut copa = Copa::new(Arc::new(CopaConfig::default()), now);
+
+        // Without queueing, the window grows exponentially
+        let initial = copa.window();
+        for _ in 0..4 {
+            round(&mut copa, &mut estimator, &mut now, base);
+        }
+        assert!(copa.slow_start);
+        assert!(copa.window() >= 16 * initial);
+
+        // Growing queues make it back off
+        let grown = copa.window();
+        for _ in 0..20 {
+            round(&mut copa, &mut estimator, &mut now, base * 3);
+        }
+        assert!(!copa.slow_start);
+        assert!(copa.window() < grown / 2, "{} {}", copa.window(), grown);
+
+        // Once queues drain, it grows again
+        let shrunk = copa.window();
+        for _ in 0..10 {
+            round(&mut copa, &mut estimator, &mut now, base);
+        }
+        assert!(copa.window() > shrunk);
+    }
+
+    #[test]
+    fn on_ack_tracks_queueing_delay() {
+        let base = Duration::from_millis(50);
+        let mut now = Instant::now();
+        let mut copa = Copa::new(Arc::new(CopaConfig::default()), now);
+        let mut round = |copa: &mut Copa, rtt: Duration| {
+            now += rtt;
+            for _ in 0..(copa.window() / 1232).max(1) {
+                copa.on_ack(now, now - rtt, 1232, false, rtt);
+            }
+        };
+
+        // RTTs passed directly are compared against the minimum of earlier ones
+        for _ in 0..4 {
+            round(&mut copa, base);
+        }
+        let grown = copa.window();
+        for _ in 0..20 {
+            round(&mut copa, base * 3);
+        }
+        assert!(!copa.slow_start);
+        assert!(copa.window() < grown / 2, "{} {}", copa.window(), grown);
+    }
+
+    #[test]
+    fn min_rtt_expires() {
+        let mut now = Instant::now();
+        let mut copa = Copa::new(Arc::new(CopaConfig::default()), now);
+        copa.on_ack(now, now, 1232, false, Duration::from_millis(20));
+        // After a route change, the old minimum is forgotten
+        now += MIN_RTT_WINDOW + Duration::from_secs(1);
+        copa.on_ack(now, now, 1232, false, Duration::from_millis(80));
+        assert_eq!(
+            copa.min_rtt.samples.front().unwrap().1,
+            Duration::from_millis(80)
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Controller, ControllerFactory};
+use crate::connection::RttEstimator;
+
+/// A scavenger congestion controller for background transfers
+///
+/// Loosely follows LEDBAT++ (draft-irtf-iccrg-ledbat-plus-plus). The window grows only while the
+/// queueing delay, estimated as the difference between recent RTTs and the base delay, stays
+/// below a small target, and shrinks in proportion to how far the delay overshoots it. Since
+/// competing loss-based flows fill queues well beyond the target, a connection using this
+/// controller yields most of the bottleneck to them, while still using links that are otherwise
+/// idle.
+///
+/// As in LEDBAT (RFC 6817), the base delay is the minimum RTT over the last 10 minutes, tracked
+/// per minute, so that a lasting increase in the path's delay, e.g. after a route change, isn't
+/// mistaken for queueing forever.
+#[derive(Debug, Clone)]
+pub struct Ledbat {
+    config: Arc<LedbatConfig>,
+    /// Maximum number of bytes in flight that may be sent
+    window: u64,
+    slow_start: bool,
+    /// The most recent RTT samples, the minimum of which is taken as the current delay
+    samples: [Duration; 4],
+    next_sample: usize,
+    /// Minimum RTT of each of the last `BASE_HISTORY` intervals, most recent last
+    base_history: VecDeque<Duration>,
+    /// Start of the interval tracked by the last entry of `base_history`
+    base_interval_start: Option<Instant>,
+    /// Start of the current loss recovery period, during which further losses are ignored
+    recovery_start_time: Instant,
+    /// Bytes by which the window has been reduced since `reduction_start`
+    reduced: u64,
+    /// Start of the current RTT, and the window at that time
+    reduction_start: Option<(Instant, u64)>,
+}
+
+impl Ledbat {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<LedbatConfig>, now: Instant) -> Self {
+        Self {
+            window: config.initial_window,
+            slow_start: true,
+            samples: [Duration::from_secs(u64::MAX); 4],
+            next_sample: 0,
+            base_history: VecDeque::with_capacity(BASE_HISTORY),
+            base_interval_start: None,
+            recovery_start_time: now,
+            reduced: 0,
+            reduction_start: None,
+            config,
+        }
+    }
+
+    /// Record the RTT sample `latest` in the base delay history, returning the base delay
+    fn update_base(&mut self, now: Instant, latest: Duration) -> Duration {
+        match self.base_interval_start {
+            Some(start) if now.saturating_duration_since(start) < BASE_INTERVAL => {
+                let current = self.base_history.back_mut().unwrap();
+                *current = (*current).min(latest);
+            }
+            _ => {
+                if self.base_history.len() == BASE_HISTORY {
+                    self.base_history.pop_front();
+                }
+                self.base_history.push_back(latest);
+                self.base_interval_start = Some(now);
+            }
+        }
+        *self.base_history.iter().min().unwrap()
+    }
+
+    /// React to the acknowledgement of `bytes` sent at `sent` with the RTT sample `latest`, given
+    /// the smoothed RTT `smoothed`
+    fn on_sample(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        latest: Duration,
+        smoothed: Duration,
+    ) {
+        self.samples[self.next_sample] = latest;
+        self.next_sample = (self.next_sample + 1) % self.samples.len();
+        let base = self.update_base(now, latest);
+        if app_limited || sent <= self.recovery_start_time {
+            return;
+        }
+
+        let current = *self.samples.iter().min().unwrap();
+        let delay = current.checked_sub(base).unwrap_or_default();
+        let target = self.config.target_delay;
+        // Reduce the gain on short paths, so that flows sharing them converge to the same rate
+        let gain = 1.0
+            / (2.0 * target.as_secs_f64() / base.as_secs_f64())
+                .ceil()
+                .clamp(1.0, 16.0);
+        let mss = self.config.max_datagram_size as f64;
+
+        if self.slow_start {
+            if delay < target * 3 / 4 {
+                self.window += (gain * bytes as f64) as u64;
+                return;
+            }
+            self.slow_start = false;
+        }
+
+        // Grow by `gain` datagrams per window acknowledged, and shrink by a fraction of the bytes
+        // acknowledged that's proportional to the overshoot above the target
+        let overshoot = delay.as_secs_f64() / target.as_secs_f64() - 1.0;
+        let change = (gain * mss / self.window as f64 - overshoot.max(0.0)) * bytes as f64;
+        if change >= 0.0 {
+            self.window += change as u64;
+            return;
+        }
+
+        // Don't reduce the window by more than half within a single RTT
+        let window = match self.reduction_start {
+            Some((start, window)) if now.saturating_duration_since(start) < smoothed => window,
+            _ => {
+                self.reduction_start = Some((now, self.window));
+                self.reduced = 0;
+                self.window
+            }
+        };
+        let budget = (window / 2).saturating_sub(self.reduced);
+        let decrease = (-change as u64).min(budget);
+        let new_window = self
+            .window
+            .saturating_sub(decrease)
+            .max(self.config.minimum_window);
+        self.reduced += self.window - new_window;
+        self.window = new_window;
+    }
+}
+
+impl Controller for Ledbat {
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: Duration,
+    ) {
+        self.on_sample(now, sent, bytes, app_limited, rtt, rtt);
+    }
+
+    fn on_ack_with_rtt(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.on_sample(now, sent, bytes, app_limited, rtt.latest(), rtt.get());
+    }
+
+    fn on_congestion_event(&mut self, now: Instant, sent: Instant, is_persistent_congestion: bool) {
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        self.slow_start = false;
+        self.window = (self.window / 2).max(self.config.minimum_window);
+        if is_persistent_congestion {
+            self.window = self.config.minimum_window;
+        }
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+}
+
+/// Number of intervals over which the base delay is the minimum RTT
+const BASE_HISTORY: usize = 10;
+/// Length of the intervals over which the base delay is tracked
+const BASE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Configuration for the `Ledbat` congestion controller
+#[derive(Debug, Clone)]
+pub struct LedbatConfig {
+    max_datagram_size: u64,
+    initial_window: u64,
+    minimum_window: u64,
+    target_delay: Duration,
+}
+
+impl LedbatConfig {
+    /// The sender’s maximum UDP payload size. Does not include UDP or IP overhead.
+    ///
+    /// Used for calculating initial and minimum congestion windows.
+    pub fn max_datagram_size(&mut self, value: u64) -> &mut Self {
+        self.max_datagram_size = value;
+        self
+    }
+
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `2 * max_datagram_size`, as background transfers shouldn't burst.
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Default minimum congestion window.
+    ///
+    /// Recommended value: `2 * max_datagram_size`.
+    pub fn minimum_window(&mut self, value: u64) -> &mut Self {
+        self.minimum_window = value;
+        self
+    }
+
+    /// Queueing delay the controller tolerates before backing off
+    ///
+    /// Larger values make the connection less deferential to other traffic. Defaults to 60ms.
+    pub fn target_delay(&mut self, value: Duration) -> &mut Self {
+        self.target_delay = value;
+        self
+    }
+}
+
+impl Default for LedbatConfig {
+    fn default() -> Self {
+        const MAX_DATAGRAM_SIZE: u64 = 1232;
+        Self {
+            max_datagram_size: MAX_DATAGRAM_SIZE,
+            initial_window: 2 * MAX_DATAGRAM_SIZE,
+            minimum_window: 2 * MAX_DATAGRAM_SIZE,
+            target_delay: Duration::from_millis(60),
+        }
+    }
+}
+
+impl ControllerFactory for Arc<LedbatConfig> {
+    fn build(&self, now: Instant) -> Box<dyn Controller> {
+        Box::new(Ledbat::new(self.clone(), now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::congestion::test_util::round;
+
+    #[test]
+    fn yields_to_queueing() {
+        let base = Duration::from_millis(40);
+        let mut now = Instant::now();
+        let mut estimator = RttEstimator::new(base);
+        let mut ledbat = Ledbat::new(Arc::new(LedbatConfig::default()), now);
+
+        // An idle link is used
+        let initial = ledbat.window();
+        for _ in 0..10 {
+            round(&mut ledbat, &mut estimator, &mut now, base);
+        }
+        assert!(ledbat.window() > 8 * initial);
+
+        // Queueing caused by other traffic makes it back off, at most by half per RTT
+        let grown = ledbat.window();
+        round(&mut ledbat, &mut estimator, &mut now, base * 5);
+        assert!(!ledbat.slow_start);
+        assert!(ledbat.window() >= grown / 2);
+        for _ in 0..10 {
+            round(&mut ledbat, &mut estimator, &mut now, base * 5);
+        }
+        assert_eq!(ledbat.window(), 2 * 1232);
+    }
+
+    #[test]
+    fn base_delay_refresh() {
+        let mut now = Instant::now();
+        let mut estimator = RttEstimator::new(Duration::from_millis(40));
+        let mut ledbat = Ledbat::new(Arc::new(LedbatConfig::default()), now);
+        for _ in 0..10 {
+            round(
+                &mut ledbat,
+                &mut estimator,
+                &mut now,
+                Duration::from_millis(40),
+            );
+        }
+
+        // After the path's delay rises for good, the excess is taken for queueing at first
+        let longer = Duration::from_millis(120);
+        for _ in 0..20 {
+            round(&mut ledbat, &mut estimator, &mut now, longer);
+        }
+        assert_eq!(ledbat.window(), 2 * 1232);
+
+        // Once the old minimum has aged out of the history, the window grows again
+        let start = now;
+        while now - start < BASE_INTERVAL * BASE_HISTORY as u32 {
+            round(&mut ledbat, &mut estimator, &mut now, longer);
+        }
+        let window = ledbat.window();
+        for _ in 0..10 {
+            round(&mut ledbat, &mut estimator, &mut now, longer);
+        }
+        assert!(ledbat.window() > window, "{} {}", ledbat.window(), window);
+    }
+}
@@ -212,6 +212,11 @@ impl Assembler {
         self.bytes_read
     }
 
+    /// Number of bytes currently buffered, including duplicates in ordered mode
+    pub(crate) fn buffered(&self) -> usize {
+        self.buffered
+    }
+
     /// Discard all buffered data
     pub(crate) fn clear(&mut self) {
         self.data.clear();
@@ -172,6 +172,11 @@ impl CidState {
         Ok(limit > self.active_seq.len() as u64)
     }
 
+    /// Number of local connection IDs issued so far
+    pub(crate) fn issued(&self) -> u64 {
+        self.issued
+    }
+
     /// Length of local Connection IDs
     pub(crate) fn cid_len(&self) -> usize {
         self.cid_len
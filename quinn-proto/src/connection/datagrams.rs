@@ -4,7 +4,7 @@ use bytes::Bytes;
 use thiserror::Error;
 use tracing::{debug, trace};
 
-use super::Connection;
+use super::{Connection, Event};
 use crate::{
     frame::{Datagram, FrameStruct},
     packet::SpaceId,
@@ -19,7 +19,9 @@ pub struct Datagrams<'a> {
 impl<'a> Datagrams<'a> {
     /// Queue an unreliable, unordered datagram for immediate transmission
     ///
-    /// Returns `Err` iff a `len`-byte datagram cannot currently be sent
+    /// Returns `Err` iff a `len`-byte datagram cannot currently be sent. If the send buffer is
+    /// full, either older datagrams are dropped to make room or `data` is rejected, according to
+    /// `TransportConfig::datagram_drop_policy`.
     pub fn send(&mut self, data: Bytes) -> Result<(), SendDatagramError> {
         if self.conn.config.datagram_receive_buffer_size.is_none() {
             return Err(SendDatagramError::Disabled);
@@ -27,19 +29,25 @@ impl<'a> Datagrams<'a> {
         let max = self
             .max_size()
             .ok_or(SendDatagramError::UnsupportedByPeer)?;
-        while self.conn.datagrams.outgoing_total > self.conn.config.datagram_send_buffer_size {
-            let prev = self
-                .conn
-                .datagrams
-                .outgoing
-                .pop_front()
-                .expect("datagrams.outgoing_total desynchronized");
-            trace!(len = prev.data.len(), "dropping outgoing datagram");
-            self.conn.datagrams.outgoing_total -= prev.data.len();
-        }
         if data.len() > max {
             return Err(SendDatagramError::TooLarge);
         }
+        let limit = self.conn.config.datagram_send_buffer_size;
+        let state = &mut self.conn.datagrams;
+        if !state.outgoing.is_empty() && state.outgoing_total + data.len() > limit {
+            match self.conn.config.datagram_drop_policy {
+                DatagramDropPolicy::RejectNew => return Err(SendDatagramError::Blocked),
+                DatagramDropPolicy::DropOldest => {
+                    while !state.outgoing.is_empty() && state.outgoing_total + data.len() > limit {
+                        let prev = state.outgoing.pop_front().unwrap();
+                        trace!(len = prev.data.len(), "dropping outgoing datagram");
+                        state.outgoing_total -= prev.data.len();
+                        self.conn.stats.datagram_drops.send_buffer += 1;
+                    }
+                    self.conn.events.push_back(Event::DatagramsDropped);
+                }
+            }
+        }
         self.conn.datagrams.outgoing_total += data.len();
         self.conn.datagrams.outgoing.push_back(Datagram { data });
         Ok(())
@@ -66,10 +74,44 @@ impl<'a> Datagrams<'a> {
         Some(limit.min(max_size as u64) as usize)
     }
 
+    /// Discard queued datagrams that no longer fit in a packet
+    ///
+    /// The maximum size may shrink after a datagram was queued, e.g. if the peer switches to a
+    /// longer connection ID, in which case it would otherwise block the queue forever.
+    pub(super) fn drop_unsendable(&mut self) {
+        let max = match self.max_size() {
+            Some(x) => x,
+            None => return,
+        };
+        let state = &mut self.conn.datagrams;
+        let before = state.outgoing.len();
+        state.outgoing.retain(|x| x.data.len() <= max);
+        let dropped = before - state.outgoing.len();
+        if dropped == 0 {
+            return;
+        }
+        debug!(
+            dropped,
+            max, "dropping outgoing datagrams too large for the path"
+        );
+        state.outgoing_total = state.outgoing.iter().map(|x| x.data.len()).sum();
+        self.conn.stats.datagram_drops.unsendable += dropped as u64;
+        self.conn.events.push_back(Event::DatagramsDropped);
+    }
+
     /// Receive an unreliable, unordered datagram
     pub fn recv(&mut self) -> Option<Bytes> {
         self.conn.datagrams.recv()
     }
+
+    /// Number of datagrams next to be returned by [`recv()`](Self::recv) that were received
+    /// before the handshake completed
+    ///
+    /// On servers, such datagrams arrived in 0-RTT packets, which may have been replayed by an
+    /// attacker.
+    pub fn buffered_0rtt(&self) -> usize {
+        self.conn.datagrams.incoming_0rtt
+    }
 }
 
 #[derive(Default)]
@@ -78,6 +120,8 @@ pub(super) struct DatagramState {
     /// delivered to the application
     pub(super) recv_buffered: usize,
     pub(super) incoming: VecDeque<Datagram>,
+    /// Number of datagrams at the front of `incoming` received before the handshake completed
+    pub(super) incoming_0rtt: usize,
     pub(super) outgoing: VecDeque<Datagram>,
     pub(super) outgoing_total: usize,
 }
@@ -86,6 +130,7 @@ impl DatagramState {
     pub fn received(
         &mut self,
         datagram: Datagram,
+        early: bool,
         window: &Option<usize>,
     ) -> Result<bool, TransportError> {
         let window = match window {
@@ -109,6 +154,9 @@ impl DatagramState {
 
         self.recv_buffered += datagram.data.len();
         self.incoming.push_back(datagram);
+        if early {
+            self.incoming_0rtt += 1;
+        }
         Ok(was_empty)
     }
 
@@ -133,10 +181,26 @@ impl DatagramState {
     pub fn recv(&mut self) -> Option<Bytes> {
         let x = self.incoming.pop_front()?.data;
         self.recv_buffered -= x.len();
+        self.incoming_0rtt = self.incoming_0rtt.saturating_sub(1);
         Some(x)
     }
 }
 
+/// What to do when an outgoing datagram doesn't fit in the send buffer
+///
+/// See `TransportConfig::datagram_send_buffer_size`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DatagramDropPolicy {
+    /// Drop the oldest queued datagrams until the new one fits
+    ///
+    /// Suits real-time data, where fresh datagrams supersede stale ones.
+    DropOldest,
+    /// Refuse the new datagram with [`SendDatagramError::Blocked`]
+    ///
+    /// Leaves the application to decide what to discard or when to retry.
+    RejectNew,
+}
+
 /// Errors that can arise when sending a datagram
 #[derive(Debug, Error, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum SendDatagramError {
@@ -152,4 +216,9 @@ pub enum SendDatagramError {
     /// exceeded.
     #[error("datagram too large")]
     TooLarge,
+    /// The send buffer is full
+    ///
+    /// Only returned under [`DatagramDropPolicy::RejectNew`].
+    #[error("datagram send buffer full")]
+    Blocked,
 }
@@ -29,8 +29,8 @@ use crate::{
         EndpointEventInner, IssuedCid,
     },
     transport_parameters::TransportParameters,
-    Dir, Frame, Side, StreamId, Transmit, TransportError, TransportErrorCode, VarInt,
-    MAX_STREAM_COUNT, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE, TIMER_GRANULARITY,
+    Dir, Frame, PathParameters, Side, StreamId, Transmit, TransportError, TransportErrorCode,
+    VarInt, MAX_STREAM_COUNT, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE, TIMER_GRANULARITY,
 };
 
 mod assembler;
@@ -41,7 +41,7 @@ use cid_state::CidState;
 
 mod datagrams;
 use datagrams::DatagramState;
-pub use datagrams::{Datagrams, SendDatagramError};
+pub use datagrams::{DatagramDropPolicy, Datagrams, SendDatagramError};
 
 mod pacing;
 
@@ -50,6 +50,15 @@ use packet_builder::PacketBuilder;
 
 mod paths;
 use paths::PathData;
+pub use paths::RttEstimator;
+
+mod qlog;
+use qlog::QlogStream;
+#[cfg(feature = "qlog")]
+pub use qlog::{QlogDir, QlogFactory};
+
+mod resume;
+pub(crate) use resume::CarefulResume;
 
 mod send_buffer;
 
@@ -61,7 +70,7 @@ use spaces::Retransmits;
 use spaces::{PacketSpace, SendableFrames, SentPacket, ThinRetransmits};
 
 mod stats;
-pub use stats::ConnectionStats;
+pub use stats::{ConnectionStats, RttHistogram};
 
 mod streams;
 #[cfg(fuzzing)]
@@ -70,7 +79,8 @@ pub use streams::StreamsState;
 use streams::StreamsState;
 pub use streams::{
     ByteSlice, BytesArray, BytesSource, Chunks, FinishError, ReadError, ReadableError, RecvStream,
-    SendStream, ShouldTransmit, StreamEvent, Streams, UnknownStream, WriteError, Written,
+    RecvStreamState, RecvStreamStats, SchedulerKind, SendStream, SendStreamState, SendStreamStats,
+    ShouldTransmit, StreamEvent, StreamInfo, Streams, UnknownStream, WriteError, Written,
 };
 
 mod timer;
@@ -166,6 +176,12 @@ pub struct Connection {
     /// spoofing key updates.
     next_crypto: Option<KeyPair<Box<dyn PacketKey>>>,
     accepted_0rtt: bool,
+    /// Whether 0-RTT data rejected by the peer is being retransmitted in 1-RTT packets
+    replayed_0rtt: bool,
+    /// Whether 0-RTT data was discarded because Version Negotiation restarted the handshake
+    version_changed_0rtt: bool,
+    /// Bytes of 0-RTT packet payload accepted from the client
+    early_data_received: u64,
     /// Whether the idle timer should be reset the next time an ack-eliciting packet is transmitted.
     permit_idle_reset: bool,
     /// Negotiated idle timeout
@@ -173,6 +189,10 @@ pub struct Connection {
     timers: TimerTable,
     /// Number of packets received which could not be authenticated
     authentication_failures: u64,
+    /// Packets received before they could be processed, in order of arrival
+    undecryptable: VecDeque<(SocketAddr, Option<EcnCodepoint>, PartialDecode)>,
+    /// Total size of the packets in `undecryptable`
+    undecryptable_bytes: usize,
     /// Why the connection was lost, if it has been
     error: Option<ConnectionError>,
 
@@ -180,8 +200,18 @@ pub struct Connection {
     // Queued non-retransmittable 1-RTT data
     //
     path_response: Option<PathResponse>,
+    /// In-progress bandwidth probe, if any
+    bandwidth_probe: Option<BandwidthProbe>,
     close: bool,
 
+    //
+    // ACK frequency
+    //
+    /// Sequence number of the next ACK_FREQUENCY frame to send
+    ack_frequency_sequence: u64,
+    /// Maximum ACK delay most recently requested from the peer, if any
+    requested_max_ack_delay: Option<Duration>,
+
     //
     // Loss Detection
     //
@@ -210,8 +240,21 @@ pub struct Connection {
     datagrams: DatagramState,
     /// Connection level statistics
     stats: ConnectionStats,
+    /// Delivery rate and RTT last reported via `Event::DeliveryRateChanged`
+    reported_delivery_rate: Option<(u64, Duration)>,
     /// QUIC version used for the connection.
     version: u32,
+    /// How to restart the handshake with another version, for clients yet to see Version
+    /// Negotiation
+    version_negotiation: Option<VersionNegotiation>,
+    /// Whether the peer's address was validated before the connection was created
+    remote_validated: bool,
+    /// Whether keep-alives are sent while no streams are open
+    keep_alive_while_idle: bool,
+    /// qlog trace of the connection, if enabled
+    qlog: QlogStream,
+    /// Resumption of an earlier connection's congestion window, for clients with a path cache
+    resume: Option<CarefulResume>,
 }
 
 impl Connection {
@@ -227,6 +270,9 @@ impl Connection {
         cid_gen: &dyn ConnectionIdGenerator,
         now: Instant,
         version: u32,
+        version_negotiation: Option<VersionNegotiation>,
+        remote_validated: bool,
+        resume: Option<CarefulResume>,
     ) -> Self {
         let side = if server_config.is_some() {
             Side::Server
@@ -242,10 +288,8 @@ impl Connection {
             token: None,
             client_hello: None,
         });
+        let qlog = QlogStream::new(&config, side, init_cid, now);
         let mut rng = StdRng::from_entropy();
-        let path_validated = server_config
-            .as_ref()
-            .map_or(true, |c| c.use_stateless_retry);
         let mut this = Self {
             server_config,
             crypto,
@@ -257,7 +301,7 @@ impl Connection {
                 config.initial_rtt,
                 config.congestion_controller_factory.build(now),
                 now,
-                path_validated,
+                remote_validated,
             ),
             local_ip,
             prev_path: None,
@@ -280,15 +324,24 @@ impl Connection {
             prev_crypto: None,
             next_crypto: None,
             accepted_0rtt: false,
+            replayed_0rtt: false,
+            version_changed_0rtt: false,
+            early_data_received: 0,
             permit_idle_reset: true,
             idle_timeout: config.max_idle_timeout,
             timers: TimerTable::default(),
             authentication_failures: 0,
+            undecryptable: VecDeque::new(),
+            undecryptable_bytes: 0,
             error: None,
 
             path_response: None,
+            bandwidth_probe: None,
             close: false,
 
+            ack_frequency_sequence: 0,
+            requested_max_ack_delay: None,
+
             pto_count: 0,
 
             app_limited: false,
@@ -302,15 +355,28 @@ impl Connection {
                 config.max_concurrent_bidi_streams,
                 config.send_window,
                 config.receive_window,
+                config.max_receive_window,
                 config.stream_receive_window,
+                config.max_stream_receive_window,
+                config.stream_reassembly_buffer_size,
+                config.stream_scheduler,
             ),
             datagrams: DatagramState::default(),
+            rem_cids: CidQueue::with_capacity(rem_cid, config.active_connection_id_limit as usize),
             config,
-            rem_cids: CidQueue::new(rem_cid),
             rng,
             stats: ConnectionStats::default(),
+            reported_delivery_rate: None,
             version,
+            version_negotiation,
+            remote_validated,
+            keep_alive_while_idle: true,
+            qlog,
+            resume,
         };
+        if let Some(timeout) = this.config.handshake_timeout {
+            this.timers.set(Timer::Handshake, now + timeout);
+        }
         if side.is_client() {
             // Kick off the connection
             this.write_crypto();
@@ -407,6 +473,11 @@ impl Connection {
 
         let mut num_datagrams = 0;
 
+        self.streams
+            .expire_deadlines(now, &mut self.spaces[SpaceId::Data].pending);
+        self.streams.update_throttled(now);
+        self.set_stream_timers();
+
         // Send PATH_CHALLENGE for a previous path if necessary
         if let Some(ref mut prev_path) = self.prev_path {
             if prev_path.challenge_pending {
@@ -515,8 +586,9 @@ impl Connection {
                 continue;
             }
 
-            let mut ack_eliciting =
-                !self.spaces[space_id].pending.is_empty() || self.spaces[space_id].ping_pending;
+            let mut ack_eliciting = !self.spaces[space_id].pending.is_empty()
+                || self.spaces[space_id].ping_pending
+                || self.spaces[space_id].immediate_ack_pending;
             if space_id == SpaceId::Data {
                 ack_eliciting |= self.can_send_1rtt();
             }
@@ -677,6 +749,7 @@ impl Connection {
                 // have gotten any other ACK for the data earlier on.
                 if !self.spaces[space_id].pending_acks.ranges().is_empty() {
                     Self::populate_acks(
+                        now,
                         self.receiving_ecn,
                         &mut SentFrames::default(),
                         &mut self.spaces[space_id],
@@ -723,7 +796,8 @@ impl Connection {
                 break;
             }
 
-            let sent = self.populate_packet(space_id, &mut buf, buf_capacity - builder.tag_len);
+            let sent =
+                self.populate_packet(now, space_id, &mut buf, buf_capacity - builder.tag_len);
 
             // ACK-only packets should only be sent when explicitly allowed. If we write them due
             // to any other reason, there is a bug which leads to one component announcing write
@@ -735,7 +809,7 @@ impl Connection {
                     && !can_send.acks
                     && can_send.other
                     && (buf_capacity - builder.datagram_start)
-                        == self.path.max_udp_payload_size as _),
+                        == self.path.max_udp_payload_size as usize),
                 "SendableFrames was {:?}, but only ACKs have been written",
                 can_send
             );
@@ -743,6 +817,9 @@ impl Connection {
 
             if !sent.acks.is_empty() {
                 self.spaces[space_id].pending_acks.acks_sent();
+                if space_id == SpaceId::Data {
+                    self.timers.stop(Timer::MaxAckDelay);
+                }
             }
 
             // Keep information about the packet around until it gets finalized
@@ -789,6 +866,18 @@ impl Connection {
         })
     }
 
+    /// Arm the timers for stream rate limits and delivery deadlines
+    fn set_stream_timers(&mut self) {
+        match self.streams.next_unthrottle() {
+            Some(time) => self.timers.set(Timer::StreamRate, time),
+            None => self.timers.stop(Timer::StreamRate),
+        }
+        match self.streams.next_deadline() {
+            Some(time) => self.timers.set(Timer::StreamDeadline, time),
+            None => self.timers.stop(Timer::StreamDeadline),
+        }
+    }
+
     /// Indicate what types of frames are ready to send for the given space
     fn space_can_send(&self, space_id: SpaceId) -> SendableFrames {
         if self.spaces[space_id].crypto.is_some() {
@@ -835,12 +924,10 @@ impl Connection {
                 first_decode,
                 remaining,
             } => {
-                // If this packet could initiate a migration and we're a client or a server that
-                // forbids migration, drop the datagram. This could be relaxed to heuristically
-                // permit NAT-rebinding-like migration.
-                if remote != self.path.remote
-                    && self.server_config.as_ref().map_or(true, |x| !x.migration)
-                {
+                // If this packet could initiate a migration and we're a client, drop the datagram.
+                // Servers that forbid migration still process probing packets, and discard
+                // non-probing packets in `handle_packet` once they've been decrypted.
+                if remote != self.path.remote && self.server_config.is_none() {
                     trace!("discarding packet from unrecognized peer {}", remote);
                     return;
                 }
@@ -863,6 +950,8 @@ impl Connection {
                     self.handle_coalesced(now, remote, ecn, data);
                 }
 
+                self.process_undecryptable(now);
+
                 if was_anti_amplification_blocked {
                     // A prior attempt to set the loss detection timer may have failed due to
                     // anti-amplification, so ensure it's set now. Prevents a handshake deadlock if
@@ -908,12 +997,19 @@ impl Connection {
                     self.state = State::Drained;
                     self.endpoint_events.push_back(EndpointEventInner::Drained);
                 }
-                Timer::Idle => {
+                Timer::Idle | Timer::Handshake => {
                     self.kill(ConnectionError::TimedOut);
                 }
                 Timer::KeepAlive => {
-                    trace!("sending keep-alive");
-                    self.ping();
+                    if self.keep_alive_while_idle || self.streams.has_open_streams() {
+                        trace!("sending keep-alive");
+                        self.ping();
+                    } else {
+                        // Check again later, in case streams are opened without any packets
+                        // being received in the meantime
+                        trace!("skipping keep-alive while idle");
+                        self.reset_keep_alive(now);
+                    }
                 }
                 Timer::LossDetection => {
                     self.on_loss_detection_timeout(now);
@@ -931,6 +1027,14 @@ impl Connection {
                     self.path.challenge_pending = false;
                 }
                 Timer::Pacing => trace!("pacing timer expired"),
+                Timer::StreamRate => trace!("stream rate limit timer expired"),
+                Timer::StreamDeadline => {
+                    self.streams
+                        .expire_deadlines(now, &mut self.spaces[SpaceId::Data].pending);
+                }
+                Timer::MaxAckDelay => {
+                    self.spaces[SpaceId::Data].pending_acks.on_deadline();
+                }
                 Timer::PushNewCid => {
                     // Update `retire_prior_to` field in NEW_CONNECTION_ID frame
                     let num_new_cid = self.local_cid_state.on_cid_timeout().into();
@@ -968,6 +1072,7 @@ impl Connection {
     fn close_inner(&mut self, now: Instant, reason: Close) {
         let was_closed = self.state.is_closed();
         if !was_closed {
+            self.report_handshake_failure(&ConnectionError::LocallyClosed);
             self.close_common();
             self.set_close_timer(now);
             self.close = true;
@@ -984,7 +1089,9 @@ impl Connection {
     pub fn stats(&self) -> ConnectionStats {
         let mut stats = self.stats;
         stats.path.rtt = self.path.rtt.get();
+        stats.path.rtt_variance = self.path.rtt.variance();
         stats.path.cwnd = self.path.congestion.window();
+        stats.path.delivery_rate = self.delivery_rate();
 
         stats
     }
@@ -996,6 +1103,130 @@ impl Connection {
         self.spaces[self.highest_space].ping_pending = true;
     }
 
+    /// Set the number of streams of direction `dir` the peer may have open concurrently
+    ///
+    /// Overrides [`TransportConfig::max_concurrent_bidi_streams()`] or
+    /// [`TransportConfig::max_concurrent_uni_streams()`] for this connection, e.g. to shed load.
+    /// An increase is advertised to the peer promptly in a MAX_STREAMS frame. A decrease takes
+    /// effect gradually as the peer's existing streams close, since stream credit that has already
+    /// been issued can't be revoked.
+    pub fn set_max_concurrent_streams(&mut self, dir: Dir, count: VarInt) {
+        self.streams.set_max_concurrent(dir, count);
+        let pending = &mut self.spaces[SpaceId::Data].pending;
+        if self.streams.take_max_streams_dirty(dir) {
+            match dir {
+                Dir::Uni => pending.max_uni_stream_id = true,
+                Dir::Bi => pending.max_bi_stream_id = true,
+            }
+        }
+    }
+
+    /// Whether any stream, in either direction, has yet to be fully closed
+    pub fn has_open_streams(&self) -> bool {
+        self.streams.has_open_streams()
+    }
+
+    /// Whether the peer has yet to acknowledge data written to, or the end of, any send stream
+    ///
+    /// Streams that were reset don't count. Useful to defer [`close()`](Self::close) until data
+    /// already written won't be lost by closing.
+    pub fn has_undelivered_data(&self) -> bool {
+        self.streams.has_undelivered_data()
+    }
+
+    /// Whether to send keep-alives while no streams are open
+    ///
+    /// Only relevant if [`TransportConfig::keep_alive_interval()`] is set. Defaults to `true`. When
+    /// `false`, keep-alive packets are only sent while at least one stream is open, so that an
+    /// otherwise unused connection is allowed to go quiet and eventually time out, e.g. to let a
+    /// mobile device's radio sleep. Set back to `true` to keep the connection alive regardless.
+    pub fn set_keep_alive_while_idle(&mut self, value: bool) {
+        self.keep_alive_while_idle = value;
+    }
+
+    /// Notify the connection that its packets are now sent from a different local address
+    ///
+    /// Forgets the local IP address packets were previously sent from and sends a packet right
+    /// away, so that the peer learns of the new address without waiting for application data.
+    pub fn local_address_changed(&mut self) {
+        self.local_ip = None;
+        self.ping();
+    }
+
+    /// Deliberately move the connection to a new local address (clients only)
+    ///
+    /// Call once packets are sent from the new address. As required by RFC 9000 §9, switches to a
+    /// connection ID the server hasn't seen before, so that the old and new paths can't be linked,
+    /// and validates the new path with a PATH_CHALLENGE. Congestion control and RTT estimation
+    /// start over unless `ip_changed` is `false`, i.e. only the local port changed. Unlike
+    /// [`local_address_changed()`](Self::local_address_changed), fails without effect if the
+    /// server doesn't permit migration.
+    pub fn initiate_migration(
+        &mut self,
+        now: Instant,
+        ip_changed: bool,
+    ) -> Result<(), MigrateError> {
+        if self.side.is_server() {
+            return Err(MigrateError::NotClient);
+        }
+        if self.state.is_closed() {
+            return Err(MigrateError::Closed);
+        }
+        // Clients consider the handshake confirmed once the Handshake space has been discarded
+        if self.is_handshaking() || self.spaces[SpaceId::Handshake].crypto.is_some() {
+            return Err(MigrateError::HandshakeNotConfirmed);
+        }
+        if self.peer_params.disable_active_migration {
+            return Err(MigrateError::DisabledByPeer);
+        }
+        // Zero-length CIDs can't link paths, so they can be used on any number of them
+        if !self.rem_cids.active().is_empty() && self.update_rem_cid().is_err() {
+            return Err(MigrateError::NoConnectionId);
+        }
+
+        trace!(ip_changed, "migrating to new local address");
+        let mut new_path = if ip_changed {
+            PathData::new(
+                self.path.remote,
+                self.config.initial_rtt,
+                self.config.congestion_controller_factory.build(now),
+                now,
+                true,
+            )
+        } else {
+            let mut path = PathData::from_previous(self.path.remote, &self.path, now);
+            path.validated = true;
+            path
+        };
+        new_path.challenge = Some(self.rng.gen());
+        new_path.challenge_pending = true;
+        let prev_pto = self.pto();
+        self.path = new_path;
+        // Responses to packets sent from the old address can no longer be received
+        self.prev_path = None;
+        self.local_ip = None;
+        self.timers.set(
+            Timer::PathValidation,
+            now + 3 * cmp::max(self.pto(), prev_pto),
+        );
+        Ok(())
+    }
+
+    /// Probe for bandwidth beyond what the application is currently using
+    ///
+    /// Sends up to `max_bytes` of padding, subject to congestion control and pacing, allowing the
+    /// congestion controller to discover available headroom while the application is
+    /// app-limited. Once all probe packets have been acknowledged or declared lost,
+    /// `Event::BandwidthProbed` reports the resulting delivery rate estimate. Replaces any probe
+    /// already in progress. Probes only begin once the handshake has completed.
+    pub fn probe_bandwidth(&mut self, max_bytes: u64) {
+        self.bandwidth_probe = Some(BandwidthProbe {
+            remaining: max_bytes,
+            last_packet: None,
+        });
+        self.check_bandwidth_probe();
+    }
+
     #[doc(hidden)]
     pub fn initiate_key_update(&mut self) {
         self.update_keys(None, false);
@@ -1033,18 +1264,61 @@ impl Connection {
         self.state.is_drained()
     }
 
-    /// For clients, if the peer accepted the 0-RTT data packets
+    /// If 0-RTT data was accepted, by the peer for clients or from the peer for servers
     ///
     /// The value is meaningless until after the handshake completes.
     pub fn accepted_0rtt(&self) -> bool {
         self.accepted_0rtt
     }
 
+    /// Whether the handshake resumed an earlier TLS session rather than authenticating the server
+    /// afresh
+    ///
+    /// The value is meaningless until after the handshake completes.
+    pub fn is_resumed(&self) -> bool {
+        self.crypto.is_resumed()
+    }
+
+    /// For clients, if 0-RTT data rejected by the peer is being retransmitted in 1-RTT packets
+    ///
+    /// Rejected stream data is retransmitted unless the peer's transport parameters no longer
+    /// accommodate it, in which case streams opened during 0-RTT are discarded instead. The value
+    /// is meaningless until after the handshake completes.
+    pub fn replayed_0rtt(&self) -> bool {
+        self.replayed_0rtt
+    }
+
+    /// Whether 0-RTT data was discarded because the handshake restarted with another QUIC version
+    ///
+    /// The new handshake doesn't use 0-RTT, so [`has_0rtt()`](Self::has_0rtt) no longer reports it.
+    pub fn version_changed_0rtt(&self) -> bool {
+        self.version_changed_0rtt
+    }
+
     /// Whether 0-RTT is/was possible during the handshake
     pub fn has_0rtt(&self) -> bool {
         self.zero_rtt_enabled
     }
 
+    /// Account for a 0-RTT packet payload of `len` bytes, unless it would exceed the limit set by
+    /// the server's [`ZeroRttPolicy`](crate::ZeroRttPolicy)
+    fn admit_early_data(&mut self, len: usize) -> bool {
+        let limit = match self
+            .server_config
+            .as_ref()
+            .and_then(|config| config.zero_rtt_policy.as_ref())
+        {
+            Some(policy) => policy.max_early_data_size(),
+            None => return true,
+        };
+        let received = self.early_data_received + len as u64;
+        if received > limit {
+            return false;
+        }
+        self.early_data_received = received;
+        true
+    }
+
     /// Whether there are any pending retransmits
     pub fn has_pending_retransmits(&self) -> bool {
         !self.spaces[SpaceId::Data].pending.is_empty()
@@ -1055,6 +1329,19 @@ impl Connection {
         self.side
     }
 
+    /// The QUIC version in use on the wire for this connection
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// For servers, whether the client's address was validated with a Retry before the connection
+    /// was accepted
+    ///
+    /// Unvalidated clients may be spoofing their address. Always `true` for clients.
+    pub fn remote_address_validated(&self) -> bool {
+        self.remote_validated
+    }
+
     /// The latest socket address for this connection's peer
     pub fn remote_address(&self) -> SocketAddr {
         self.path.remote
@@ -1083,6 +1370,31 @@ impl Connection {
         self.path.rtt.get()
     }
 
+    /// Characteristics of the current path, as saved in a client's [`PathCache`](crate::PathCache)
+    pub fn path_parameters(&self) -> PathParameters {
+        PathParameters {
+            rtt: self.path.rtt.get(),
+            delivery_rate: self.delivery_rate(),
+        }
+    }
+
+    /// Current best estimate of the rate at which data can be delivered, in bytes per second
+    ///
+    /// Derived from the congestion window and the RTT.
+    pub fn delivery_rate(&self) -> u64 {
+        let rtt = self.path.rtt.get().as_micros().max(1) as u64;
+        self.path.congestion.window().saturating_mul(1_000_000) / rtt
+    }
+
+    /// Transport parameters sent by the peer
+    ///
+    /// `None` until the peer's transport parameters are known, which is guaranteed once the
+    /// handshake has completed. Clients attempting 0-RTT see the parameters remembered from the
+    /// previous connection until the handshake completes.
+    pub fn peer_transport_parameters(&self) -> Option<TransportParameters> {
+        self.peer_params.initial_src_cid.map(|_| self.peer_params)
+    }
+
     fn on_ack_received(
         &mut self,
         now: Instant,
@@ -1143,10 +1455,22 @@ impl Connection {
             };
             let rtt = instant_saturating_sub(now, self.spaces[space].largest_acked_packet_sent);
             self.path.rtt.update(ack_delay, rtt);
+            self.stats.path.record_rtt(rtt);
+            if space == SpaceId::Data {
+                if let Some(ref mut resume) = self.resume {
+                    resume.on_rtt_sample(now, rtt, &mut *self.path.congestion);
+                }
+            }
         }
 
         // Must be called before crypto/pto_count are clobbered
         self.detect_lost_packets(now, space);
+        self.qlog.metrics_updated(
+            now,
+            &self.path.rtt,
+            self.path.congestion.window(),
+            self.in_flight.bytes,
+        );
 
         if self.peer_completed_address_validation() {
             self.pto_count = 0;
@@ -1170,6 +1494,9 @@ impl Connection {
             }
         }
 
+        self.update_send_window();
+        self.check_delivery_rate();
+        self.check_bandwidth_probe();
         self.set_loss_detection_timer(now);
         Ok(())
     }
@@ -1208,13 +1535,16 @@ impl Connection {
         if info.ack_eliciting && self.path.challenge.is_none() {
             // Only pass ACKs to the congestion controller if we are not validating the current
             // path, so as to ignore any ACKs from older paths still coming in.
-            self.path.congestion.on_ack(
+            self.path.congestion.on_ack_with_rtt(
                 now,
                 info.time_sent,
                 info.size.into(),
                 self.app_limited,
-                self.path.rtt.get(),
+                &self.path.rtt,
             );
+            if let Some(ref mut resume) = self.resume {
+                resume.on_acked(info.time_sent, info.size.into());
+            }
         }
 
         // Update state for confirmed delivery of frames
@@ -1229,6 +1559,57 @@ impl Connection {
         }
     }
 
+    /// Resize the send window to track the congestion window, if enabled
+    fn update_send_window(&mut self) {
+        if let Some(max) = self.config.max_send_window {
+            let min = self.config.send_window;
+            let window = self.path.congestion.window().saturating_mul(2);
+            self.streams
+                .set_send_window(window.max(min).min(max.max(min)));
+        }
+    }
+
+    /// Report the bandwidth probe as complete once its last packet is no longer outstanding
+    fn check_bandwidth_probe(&mut self) {
+        let probe = match self.bandwidth_probe {
+            Some(ref probe) => probe,
+            None => return,
+        };
+        let largest_acked = self.spaces[SpaceId::Data].largest_acked_packet;
+        let done = probe.remaining == 0
+            && match probe.last_packet {
+                // Packets sent after the last probe packet being acknowledged implies the probe
+                // packet was either acknowledged or will be declared lost
+                Some(pn) => matches!(largest_acked, Some(largest) if largest >= pn),
+                None => true,
+            };
+        if done {
+            self.bandwidth_probe = None;
+            self.events.push_back(Event::BandwidthProbed {
+                delivery_rate: self.delivery_rate(),
+            });
+        }
+    }
+
+    /// Emit `Event::DeliveryRateChanged` if the estimates moved far enough, if enabled
+    fn check_delivery_rate(&mut self) {
+        let threshold = match self.config.delivery_rate_threshold {
+            Some(x) => f64::from(x),
+            None => return,
+        };
+        let (rate, rtt) = (self.delivery_rate(), self.path.rtt.get());
+        if let Some((old_rate, old_rtt)) = self.reported_delivery_rate {
+            let changed = |old: f64, new: f64| (new - old).abs() > old * threshold;
+            if !changed(old_rate as f64, rate as f64)
+                && !changed(old_rtt.as_secs_f64(), rtt.as_secs_f64())
+            {
+                return;
+            }
+        }
+        self.reported_delivery_rate = Some((rate, rtt));
+        self.events.push_back(Event::DeliveryRateChanged);
+    }
+
     fn set_key_discard_timer(&mut self, now: Instant) {
         let start = if self.zero_rtt_crypto.is_some() {
             now
@@ -1277,6 +1658,10 @@ impl Connection {
             _ => 2,
         };
         self.spaces[space].loss_probes = self.spaces[space].loss_probes.saturating_add(count);
+        if space == SpaceId::Data && self.requested_max_ack_delay.is_some() {
+            // The peer may be delaying its ACKs, which we can't afford to wait for
+            self.spaces[space].immediate_ack_pending = true;
+        }
         self.pto_count = self.pto_count.saturating_add(1);
         self.set_loss_detection_timer(now);
     }
@@ -1312,9 +1697,12 @@ impl Connection {
             let old_bytes_in_flight = self.in_flight.bytes;
             let largest_lost_sent = self.spaces[pn_space].sent_packets[&largest_lost].time_sent;
             self.lost_packets += lost_packets.len() as u64;
+            self.stats.path.lost_packets += lost_packets.len() as u64;
             trace!("packets lost: {:?}", lost_packets);
             for packet in &lost_packets {
                 let info = self.spaces[pn_space].sent_packets.remove(packet).unwrap(); // safe: lost_packets is populated just above
+                self.qlog.packet_lost(now, pn_space, *packet);
+                self.stats.path.lost_bytes += u64::from(info.size);
                 self.remove_in_flight(pn_space, &info);
                 for frame in info.stream_frames {
                     self.streams.retransmit(frame);
@@ -1337,6 +1725,9 @@ impl Connection {
                     largest_lost_sent,
                     in_persistent_congestion,
                 );
+                if let Some(ref mut resume) = self.resume {
+                    resume.on_congestion(&mut *self.path.congestion);
+                }
             }
         }
     }
@@ -1729,6 +2120,11 @@ impl Connection {
         ecn: Option<EcnCodepoint>,
         partial_decode: PartialDecode,
     ) {
+        if self.must_defer(&partial_decode) {
+            self.defer_packet(remote, ecn, partial_decode);
+            return;
+        }
+
         let header_crypto = if partial_decode.is_0rtt() {
             if let Some(ref crypto) = self.zero_rtt_crypto {
                 Some(&*crypto.header)
@@ -1760,6 +2156,64 @@ impl Connection {
         }
     }
 
+    /// Whether a packet can't be processed yet, but may become processable later
+    fn must_defer(&self, partial_decode: &PartialDecode) -> bool {
+        if self.state.is_closed() || partial_decode.is_0rtt() {
+            return false;
+        }
+        match partial_decode.space() {
+            // Keys for lower spaces are never needed again once discarded
+            Some(space) if self.spaces[space].crypto.is_none() => space > self.highest_space,
+            // 1-RTT packets can't be processed until the handshake is confirmed
+            Some(SpaceId::Data) => self.state.is_handshake() && !partial_decode.has_long_header(),
+            _ => false,
+        }
+    }
+
+    /// Buffer a packet for `process_undecryptable`, or drop it if the buffer is full
+    fn defer_packet(
+        &mut self,
+        remote: SocketAddr,
+        ecn: Option<EcnCodepoint>,
+        partial_decode: PartialDecode,
+    ) {
+        let len = partial_decode.len();
+        if self.undecryptable_bytes + len > self.config.undecryptable_packet_buffer_size {
+            debug!(
+                "dropping {:?} packet ({} bytes) that can't be processed yet",
+                partial_decode.space(),
+                len
+            );
+            self.stats.undecryptable.dropped += 1;
+            self.stats.undecryptable.dropped_bytes += len as u64;
+            return;
+        }
+        trace!(
+            "buffering {:?} packet ({} bytes) that can't be processed yet",
+            partial_decode.space(),
+            len
+        );
+        self.stats.undecryptable.buffered += 1;
+        self.undecryptable_bytes += len;
+        self.undecryptable.push_back((remote, ecn, partial_decode));
+    }
+
+    /// Process buffered packets which have become processable
+    fn process_undecryptable(&mut self, now: Instant) {
+        for _ in 0..self.undecryptable.len() {
+            let (remote, ecn, partial_decode) = match self.undecryptable.pop_front() {
+                Some(x) => x,
+                None => break,
+            };
+            if self.must_defer(&partial_decode) {
+                self.undecryptable.push_back((remote, ecn, partial_decode));
+                continue;
+            }
+            self.undecryptable_bytes -= partial_decode.len();
+            self.handle_decode(now, remote, ecn, partial_decode);
+        }
+    }
+
     fn handle_packet(
         &mut self,
         now: Instant,
@@ -1780,6 +2234,7 @@ impl Connection {
             return;
         }
 
+        let len = packet.payload.len() + packet.header_data.len();
         let was_closed = self.state.is_closed();
         let was_drained = self.state.is_drained();
         let stateless_reset = self
@@ -1832,9 +2287,23 @@ impl Connection {
                         return;
                     }
                 } else if self.state.is_handshake() && packet.header.is_short() {
-                    // TODO: SHOULD buffer these to improve reordering tolerance.
+                    // Normally deferred by `handle_decode` until the handshake completes
                     trace!("dropping short packet during handshake");
                     return;
+                } else if packet.header.is_0rtt() && !self.admit_early_data(packet.payload.len()) {
+                    // Treated as lost by the client, which retransmits the contents in 1-RTT
+                    debug!("dropping 0-RTT packet exceeding the early data limit");
+                    return;
+                } else if remote != self.path.remote
+                    && self.server_config.as_ref().map_or(false, |x| !x.migration)
+                    && !frame::Iter::new(packet.payload.clone().freeze()).all(|f| f.is_probing())
+                {
+                    // This could be relaxed to heuristically permit NAT-rebinding-like migration.
+                    trace!(
+                        "discarding non-probing packet from unrecognized peer {}",
+                        remote
+                    );
+                    return;
                 } else {
                     if !self.state.is_closed() {
                         let spin = match packet.header {
@@ -1850,6 +2319,8 @@ impl Connection {
                             packet.header.is_1rtt(),
                         );
                     }
+                    self.qlog
+                        .packet_received(now, &packet.header, number, len, &packet.payload);
                     self.process_decrypted_packet(now, remote, number, packet)
                 }
             }
@@ -1857,6 +2328,7 @@ impl Connection {
 
         // State transitions for error cases
         if let Err(conn_err) = result {
+            self.report_handshake_failure(&conn_err);
             self.error = Some(conn_err.clone());
             self.state = match conn_err {
                 ConnectionError::ApplicationClosed(reason) => State::closed(reason),
@@ -2049,18 +2521,32 @@ impl Connection {
                     if self.has_0rtt() {
                         if !self.crypto.early_data_accepted().unwrap() {
                             debug_assert!(self.side.is_client());
-                            debug!("0-RTT rejected");
                             self.accepted_0rtt = false;
-                            self.streams.zero_rtt_rejected();
-
-                            // Discard already-queued frames
-                            self.spaces[SpaceId::Data].pending = Retransmits::default();
-
-                            // Discard 0-RTT packets
                             let sent_packets =
                                 mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
-                            for (_, packet) in sent_packets {
-                                self.remove_in_flight(SpaceId::Data, &packet);
+                            // Stream data can be retransmitted as long as it's within the limits
+                            // the peer now advertises, and meant for the protocol now in use
+                            if self.crypto.early_data_replayable()
+                                && params.validate_resumption_from(&self.peer_params).is_ok()
+                            {
+                                debug!("0-RTT rejected, retransmitting in 1-RTT");
+                                self.replayed_0rtt = true;
+                                for (_, info) in sent_packets {
+                                    self.remove_in_flight(SpaceId::Data, &info);
+                                    self.spaces[SpaceId::Data].pending |= info.retransmits;
+                                }
+                                self.streams.retransmit_all_for_0rtt();
+                            } else {
+                                debug!("0-RTT rejected");
+                                self.streams.zero_rtt_rejected();
+
+                                // Discard already-queued frames
+                                self.spaces[SpaceId::Data].pending = Retransmits::default();
+
+                                // Discard 0-RTT packets
+                                for (_, packet) in sent_packets {
+                                    self.remove_in_flight(SpaceId::Data, &packet);
+                                }
                             }
                         } else {
                             self.accepted_0rtt = true;
@@ -2077,10 +2563,22 @@ impl Connection {
                     // Server-only
                     self.spaces[SpaceId::Data].pending.handshake_done = true;
                     self.discard_space(now, SpaceId::Handshake);
+                    // Servers only derive 0-RTT keys when accepting early data
+                    self.accepted_0rtt = self.zero_rtt_enabled;
                 }
 
+                if self.config.ack_frequency.is_some() && self.peer_params.min_ack_delay.is_some() {
+                    self.spaces[SpaceId::Data].pending.ack_frequency = true;
+                }
                 self.events.push_back(Event::Connected);
                 self.state = State::Established;
+                self.timers.stop(Timer::Handshake);
+                self.endpoint_events
+                    .push_back(EndpointEventInner::Established {
+                        resumed: self.crypto.is_resumed(),
+                        zero_rtt_offered: self.side.is_client() && self.zero_rtt_enabled,
+                        zero_rtt_accepted: self.accepted_0rtt,
+                    });
                 trace!("established");
                 Ok(())
             }
@@ -2135,18 +2633,43 @@ impl Connection {
                 if self.total_authed_packets > 1 {
                     return Ok(());
                 }
-                let supported = packet
+                let offered = packet
                     .payload
                     .chunks(4)
-                    .any(|x| match <[u8; 4]>::try_from(x) {
-                        Ok(version) => self.version == u32::from_be_bytes(version),
-                        Err(_) => false,
-                    });
-                if supported {
+                    .filter_map(|x| <[u8; 4]>::try_from(x).ok())
+                    .map(u32::from_be_bytes)
+                    .collect::<Vec<_>>();
+                if offered.contains(&self.version) {
                     return Ok(());
                 }
-                debug!("remote doesn't support our version");
-                Err(ConnectionError::VersionMismatch)
+                // Only one round of negotiation is permitted, preventing downgrade loops
+                let negotiation = match self.version_negotiation.take() {
+                    Some(x) => x,
+                    None => {
+                        debug!("remote doesn't support our version");
+                        return Err(ConnectionError::VersionMismatch);
+                    }
+                };
+                let version = match negotiation
+                    .supported_versions
+                    .iter()
+                    .find(|x| offered.contains(x))
+                {
+                    Some(&x) => x,
+                    None => {
+                        debug!("no mutually supported version");
+                        return Err(ConnectionError::VersionMismatch);
+                    }
+                };
+                let crypto = negotiation
+                    .crypto
+                    .start_session(version, &negotiation.server_name, &negotiation.params)
+                    .map_err(|e| {
+                        debug!("failed to restart handshake: {}", e);
+                        ConnectionError::VersionMismatch
+                    })?;
+                self.restart_handshake(now, version, crypto);
+                Ok(())
             }
             Header::Short { .. } => unreachable!(
                 "short packets received during handshake are discarded in handle_packet"
@@ -2154,6 +2677,46 @@ impl Connection {
         }
     }
 
+    /// Start the handshake over using `version` after the server rejected our initial choice
+    fn restart_handshake(&mut self, now: Instant, version: u32, crypto: Box<dyn crypto::Session>) {
+        debug!(
+            version = format_args!("{:#x}", version),
+            "restarting handshake"
+        );
+        self.version = version;
+        self.crypto = crypto;
+        // The server may still send a Retry, which must be the first packet of the new attempt
+        self.total_authed_packets = 0;
+
+        // 0-RTT data was protected with keys that belong to the abandoned session
+        if self.zero_rtt_enabled {
+            debug!("0-RTT rejected");
+            self.zero_rtt_enabled = false;
+            self.version_changed_0rtt = true;
+            self.zero_rtt_crypto = None;
+            self.set_peer_params(TransportParameters::default());
+            self.streams.zero_rtt_rejected();
+            self.spaces[SpaceId::Data].pending = Retransmits::default();
+            let sent_packets = mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
+            for (_, packet) in sent_packets {
+                self.remove_in_flight(SpaceId::Data, &packet);
+            }
+        }
+
+        self.discard_space(now, SpaceId::Initial);
+        self.spaces[SpaceId::Initial] = PacketSpace {
+            crypto: Some(self.crypto.initial_keys(&self.rem_handshake_cid, self.side)),
+            next_packet_number: self.spaces[SpaceId::Initial].next_packet_number,
+            ..PacketSpace::new(now)
+        };
+        self.state = State::Handshake(state::Handshake {
+            rem_cid_set: false,
+            token: None,
+            client_hello: None,
+        });
+        self.write_crypto();
+    }
+
     /// Process an Initial or Handshake packet payload
     fn process_early_payload(
         &mut self,
@@ -2190,7 +2753,9 @@ impl Connection {
                     self.on_ack_received(now, packet.header.space(), ack)?;
                 }
                 Frame::Close(reason) => {
-                    self.error = Some(reason.into());
+                    let reason = reason.into();
+                    self.report_handshake_failure(&reason);
+                    self.error = Some(reason);
                     self.state = State::Draining;
                     return Ok(());
                 }
@@ -2269,14 +2834,8 @@ impl Connection {
                 }
             }
             // Check whether this could be a probing packet
-            match frame {
-                Frame::Padding
-                | Frame::PathChallenge(_)
-                | Frame::PathResponse(_)
-                | Frame::NewConnectionId(_) => {}
-                _ => {
-                    is_probing_packet = false;
-                }
+            if !frame.is_probing() {
+                is_probing_packet = false;
             }
             match frame {
                 Frame::Invalid { ty, reason } => {
@@ -2288,8 +2847,13 @@ impl Connection {
                     self.read_crypto(SpaceId::Data, &frame, payload_len)?;
                 }
                 Frame::Stream(frame) => {
-                    if self.streams.received(frame, payload_len)?.should_transmit() {
-                        self.spaces[SpaceId::Data].pending.max_data = true;
+                    let pending = &mut self.spaces[SpaceId::Data].pending;
+                    if self
+                        .streams
+                        .received(frame, payload_len, pending)?
+                        .should_transmit()
+                    {
+                        pending.max_data = true;
                     }
                 }
                 Frame::Ack(ack) => {
@@ -2413,11 +2977,20 @@ impl Connection {
                         ));
                     }
 
-                    let retired = self.rem_cids.retire_prior_to(frame.retire_prior_to);
-                    self.spaces[SpaceId::Data]
-                        .pending
-                        .retire_cids
-                        .extend(retired);
+                    // Every retired CID must be reported back to the peer, so bound how many
+                    // can await retirement as permitted by RFC 9000 §5.1.2
+                    let retiring = frame
+                        .retire_prior_to
+                        .saturating_sub(self.rem_cids.active_seq());
+                    let pending_retire = &mut self.spaces[SpaceId::Data].pending.retire_cids;
+                    if pending_retire.len() as u64 + retiring
+                        > 2 * u64::from(self.config.active_connection_id_limit)
+                    {
+                        return Err(TransportError::CONNECTION_ID_LIMIT_ERROR(
+                            "too many CIDs to retire",
+                        ));
+                    }
+                    pending_retire.extend(self.rem_cids.retire_prior_to(frame.retire_prior_to));
 
                     use crate::cid_queue::InsertError;
                     let new_rem_cid = IssuedCid {
@@ -2464,10 +3037,12 @@ impl Connection {
                     // TODO: Cache, or perhaps forward to user?
                 }
                 Frame::Datagram(datagram) => {
-                    if self
-                        .datagrams
-                        .received(datagram, &self.config.datagram_receive_buffer_size)?
-                    {
+                    let early = self.is_handshaking();
+                    if self.datagrams.received(
+                        datagram,
+                        early,
+                        &self.config.datagram_receive_buffer_size,
+                    )? {
                         self.events.push_back(Event::DatagramReceived);
                     }
                 }
@@ -2479,11 +3054,33 @@ impl Connection {
                     }
                     if self.spaces[SpaceId::Handshake].crypto.is_some() {
                         self.discard_space(now, SpaceId::Handshake);
+                        self.migrate_to_preferred_address(now);
+                    }
+                }
+                Frame::AckFrequency(frame) => {
+                    let min_ack_delay = TIMER_GRANULARITY.as_micros() as u64;
+                    if frame.request_max_ack_delay.into_inner() < min_ack_delay {
+                        return Err(TransportError::PROTOCOL_VIOLATION(
+                            "requested ACK delay below min_ack_delay",
+                        ));
                     }
+                    self.spaces[SpaceId::Data]
+                        .pending_acks
+                        .set_frequency(&frame);
+                }
+                Frame::ImmediateAck => {
+                    self.spaces[SpaceId::Data]
+                        .pending_acks
+                        .immediate_ack_requested();
                 }
             }
         }
 
+        match self.spaces[SpaceId::Data].pending_acks.deadline() {
+            Some(deadline) => self.timers.set(Timer::MaxAckDelay, deadline),
+            None => self.timers.stop(Timer::MaxAckDelay),
+        }
+
         // Issue stream ID credit due to ACKs of outgoing finish/resets and incoming finish/resets
         // on stopped streams
         let pending = &mut self.spaces[SpaceId::Data].pending;
@@ -2502,7 +3099,10 @@ impl Connection {
             self.close = true;
         }
 
-        if remote != self.path.remote
+        // Clients never migrate in response to the peer, but may have just moved to the server's
+        // preferred address
+        if self.side.is_server()
+            && remote != self.path.remote
             && !is_probing_packet
             && number == self.spaces[SpaceId::Data].rx_packet
         {
@@ -2555,6 +3155,52 @@ impl Connection {
         );
     }
 
+    /// Move to the server's preferred address, if any, once the handshake is confirmed
+    ///
+    /// The original path is kept so that we can fall back to it if the new one can't be validated.
+    fn migrate_to_preferred_address(&mut self, now: Instant) {
+        let remote = match self.peer_params.preferred_address {
+            Some(ref info) if self.path.remote.is_ipv4() => info.address_v4.map(SocketAddr::V4),
+            Some(ref info) => info.address_v6.map(SocketAddr::V6),
+            None => return,
+        };
+        let remote = match remote {
+            Some(x) if x != self.path.remote => x,
+            _ => {
+                debug!("no usable preferred address for the current address family");
+                return;
+            }
+        };
+
+        trace!(%remote, "migrating to server's preferred address");
+        let mut new_path = PathData::new(
+            remote,
+            self.config.initial_rtt,
+            self.config.congestion_controller_factory.build(now),
+            now,
+            true,
+        );
+        new_path.challenge = Some(self.rng.gen());
+        new_path.challenge_pending = true;
+        let prev_pto = self.pto();
+
+        let mut prev = mem::replace(&mut self.path, new_path);
+        // The preferred address must be used with the CID supplied alongside it
+        if self.update_rem_cid().is_err() {
+            debug!("no CID available to use with the preferred address");
+            self.path = prev;
+            return;
+        }
+        prev.challenge = None;
+        prev.challenge_pending = false;
+        self.prev_path = Some(prev);
+
+        self.timers.set(
+            Timer::PathValidation,
+            now + 3 * cmp::max(self.pto(), prev_pto),
+        );
+    }
+
     /// Returns Err(()) if no CIDs were available
     fn update_rem_cid(&mut self) -> Result<(), ()> {
         let (reset_token, retired) = self.rem_cids.next().ok_or(())?;
@@ -2575,25 +3221,44 @@ impl Connection {
         Ok(())
     }
 
+    /// Record the CID advertised alongside our preferred address transport parameter
+    pub(crate) fn issued_preferred_address_cid(&mut self, cid: IssuedCid, now: Instant) {
+        self.local_cid_state.new_cids(&[cid], now);
+    }
+
     /// Issue an initial set of connection IDs to the peer
     fn issue_cids(&mut self, now: Instant) {
         if self.local_cid_state.cid_len() == 0 {
             return;
         }
 
-        // Subtract 1 to account for the CID we supplied while handshaking
-        let n = self.peer_params.issue_cids_limit() - 1;
+        // Account for the CIDs we supplied while handshaking
+        let n = self
+            .peer_params
+            .issue_cids_limit()
+            .saturating_sub(self.local_cid_state.issued());
         self.endpoint_events
             .push_back(EndpointEventInner::NeedIdentifiers(now, n));
     }
 
     fn populate_packet(
         &mut self,
+        now: Instant,
         space_id: SpaceId,
         buf: &mut Vec<u8>,
         max_size: usize,
     ) -> SentFrames {
         let mut sent = SentFrames::default();
+
+        // Occasionally lead with PADDING, so peers don't come to rely on it only trailing a packet
+        if self.config.grease && space_id == SpaceId::Data && self.rng.gen_ratio(1, 8) {
+            let len = self.rng.gen_range(1..=4);
+            if buf.len() + len < max_size {
+                trace!("PADDING * {}", len);
+                buf.resize(buf.len() + len, 0);
+            }
+        }
+
         let space = &mut self.spaces[space_id];
         let is_0rtt = space_id == SpaceId::Data && space.crypto.is_none();
 
@@ -2614,9 +3279,64 @@ impl Connection {
             self.stats.frame_tx.ping += 1;
         }
 
+        // IMMEDIATE_ACK
+        if !is_0rtt && mem::replace(&mut space.immediate_ack_pending, false) {
+            trace!("IMMEDIATE_ACK");
+            buf.write(frame::Type::IMMEDIATE_ACK);
+            sent.non_retransmits = true;
+            self.stats.frame_tx.immediate_ack += 1;
+        }
+
+        // ACK_FREQUENCY
+        if !is_0rtt
+            && buf.len() + frame::AckFrequency::SIZE_BOUND < max_size
+            && mem::replace(&mut space.pending.ack_frequency, false)
+        {
+            let config = self
+                .config
+                .ack_frequency
+                .as_ref()
+                .expect("ACK_FREQUENCY queued without configuration");
+            let peer_max_ack_delay = self.peer_params.max_ack_delay.0;
+            let min_ack_delay = Duration::from_micros(
+                self.peer_params
+                    .min_ack_delay
+                    .expect("ACK_FREQUENCY queued for peer without support")
+                    .into_inner(),
+            );
+            let max_ack_delay = config
+                .max_ack_delay
+                .unwrap_or_else(|| Duration::from_millis(peer_max_ack_delay))
+                .max(min_ack_delay);
+            let frame = frame::AckFrequency {
+                sequence: VarInt::from_u64(self.ack_frequency_sequence).unwrap(),
+                ack_eliciting_threshold: config.ack_eliciting_threshold,
+                request_max_ack_delay: VarInt::from_u64(max_ack_delay.as_micros() as u64)
+                    .unwrap_or(VarInt::MAX),
+                reordering_threshold: config.reordering_threshold,
+            };
+            trace!(
+                sequence = self.ack_frequency_sequence,
+                ?max_ack_delay,
+                "ACK_FREQUENCY"
+            );
+            frame.encode(buf);
+            self.ack_frequency_sequence += 1;
+            self.requested_max_ack_delay = Some(max_ack_delay);
+            sent.retransmits.get_or_create().ack_frequency = true;
+            self.stats.frame_tx.ack_frequency += 1;
+        }
+
         // ACK
         if !space.pending_acks.ranges().is_empty() {
-            Self::populate_acks(self.receiving_ecn, &mut sent, space, buf, &mut self.stats);
+            Self::populate_acks(
+                now,
+                self.receiving_ecn,
+                &mut sent,
+                space,
+                buf,
+                &mut self.stats,
+            );
         }
 
         // PATH_CHALLENGE
@@ -2646,6 +3366,23 @@ impl Connection {
             }
         }
 
+        // PING + PADDING for bandwidth probing
+        if buf.len() < max_size && space_id == SpaceId::Data && !is_0rtt {
+            if let Some(ref mut probe) = self.bandwidth_probe {
+                if probe.remaining > 0 {
+                    trace!("PING (bandwidth probe)");
+                    buf.write(frame::Type::PING);
+                    sent.non_retransmits = true;
+                    sent.requires_padding = true;
+                    self.stats.frame_tx.ping += 1;
+                    probe.remaining = probe
+                        .remaining
+                        .saturating_sub(u64::from(self.path.max_udp_payload_size));
+                    probe.last_packet = Some(space.next_packet_number - 1);
+                }
+            }
+        }
+
         // CRYPTO
         while buf.len() + frame::Crypto::SIZE_BOUND < max_size && !is_0rtt {
             let mut frame = match space.pending.crypto.pop_front() {
@@ -2690,6 +3427,8 @@ impl Connection {
 
         if space_id == SpaceId::Data {
             self.streams.write_control_frames(
+                now,
+                self.path.rtt.get(),
                 buf,
                 &mut space.pending,
                 &mut sent.retransmits,
@@ -2734,6 +3473,9 @@ impl Connection {
         }
 
         // DATAGRAM
+        if space_id == SpaceId::Data && !self.datagrams.outgoing.is_empty() {
+            self.datagrams().drop_unsendable();
+        }
         while buf.len() + Datagram::SIZE_BOUND < max_size && space_id == SpaceId::Data {
             match self.datagrams.write(buf, max_size) {
                 true => {
@@ -2746,8 +3488,9 @@ impl Connection {
 
         // STREAM
         if space_id == SpaceId::Data {
-            sent.stream_frames = self.streams.write_stream_frames(buf, max_size);
+            sent.stream_frames = self.streams.write_stream_frames(buf, max_size, now);
             self.stats.frame_tx.stream += sent.stream_frames.len() as u64;
+            self.set_stream_timers();
         }
 
         sent
@@ -2758,6 +3501,7 @@ impl Connection {
     /// This method assumes ACKs are pending, and should only be called if
     /// `!PendingAcks::ranges().is_empty()` returns `true`.
     fn populate_acks(
+        now: Instant,
         receiving_ecn: bool,
         sent: &mut SentFrames,
         space: &mut PacketSpace,
@@ -2775,7 +3519,7 @@ impl Connection {
         };
         sent.acks = space.pending_acks.ranges().clone();
 
-        let delay_micros = space.pending_acks.ack_delay().as_micros() as u64;
+        let delay_micros = space.pending_acks.ack_delay(now).as_micros() as u64;
 
         // TODO: This should come frome `TransportConfig` if that gets configurable
         let ack_delay_exp = TransportParameters::default().ack_delay_exponent;
@@ -2789,13 +3533,17 @@ impl Connection {
 
     fn close_common(&mut self) {
         trace!("connection closed");
+        if let Some(ref resume) = self.resume {
+            resume.save(self.path_parameters());
+        }
         for &timer in &Timer::VALUES {
             self.timers.stop(timer);
         }
     }
 
     fn set_close_timer(&mut self, now: Instant) {
-        self.timers.set(Timer::Close, now + 3 * self.pto());
+        let period = self.config.closing_period.unwrap_or_else(|| 3 * self.pto());
+        self.timers.set(Timer::Close, now + period);
     }
 
     /// Handle transport parameters received from the peer
@@ -3001,8 +3749,16 @@ impl Connection {
         self.rem_cids.active_seq()
     }
 
+    /// The active remote CID
+    #[cfg(test)]
+    pub(crate) fn rem_cid(&self) -> ConnectionId {
+        self.rem_cids.active()
+    }
+
     fn max_ack_delay(&self) -> Duration {
-        Duration::from_micros(self.peer_params.max_ack_delay.0 * 1000)
+        let advertised = Duration::from_micros(self.peer_params.max_ack_delay.0 * 1000);
+        self.requested_max_ack_delay
+            .map_or(advertised, |x| x.max(advertised))
     }
 
     /// Whether we have 1-RTT data to send
@@ -3017,6 +3773,8 @@ impl Connection {
                 .map_or(false, |x| x.challenge_pending)
             || self.path_response.is_some()
             || !self.datagrams.outgoing.is_empty()
+            || (self.spaces[SpaceId::Data].crypto.is_some()
+                && matches!(self.bandwidth_probe, Some(ref x) if x.remaining > 0))
     }
 
     /// Update counters to account for a packet becoming acknowledged, lost, or abandoned
@@ -3026,8 +3784,17 @@ impl Connection {
         self.spaces[space].in_flight -= u64::from(packet.size);
     }
 
+    /// Inform the endpoint of why an incoming connection is failing before its handshake completed
+    fn report_handshake_failure(&mut self, reason: &ConnectionError) {
+        if self.side.is_server() && self.state.is_handshake() {
+            self.endpoint_events
+                .push_back(EndpointEventInner::HandshakeFailed(reason.clone()));
+        }
+    }
+
     /// Terminate the connection instantly, without sending a close packet
     fn kill(&mut self, reason: ConnectionError) {
+        self.report_handshake_failure(&reason);
         self.close_common();
         self.error = Some(reason);
         self.state = State::Drained;
@@ -3082,6 +3849,26 @@ impl From<Close> for ConnectionError {
     }
 }
 
+/// Reasons why a client can't migrate a connection to a new local address
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateError {
+    /// Only clients may initiate migration
+    #[error("only clients may initiate migration")]
+    NotClient,
+    /// The handshake has yet to be confirmed
+    #[error("handshake not confirmed")]
+    HandshakeNotConfirmed,
+    /// The server asked clients not to migrate
+    #[error("migration disabled by peer")]
+    DisabledByPeer,
+    /// The server hasn't supplied an unused connection ID to migrate with
+    #[error("no unused connection ID")]
+    NoConnectionId,
+    /// The connection has been closed
+    #[error("connection closed")]
+    Closed,
+}
+
 // For compatibility with API consumers
 impl From<ConnectionError> for io::Error {
     fn from(x: ConnectionError) -> io::Error {
@@ -3216,6 +4003,25 @@ pub enum Event {
     Stream(StreamEvent),
     /// One or more application datagrams have been received
     DatagramReceived,
+    /// One or more outgoing application datagrams were discarded before being sent
+    ///
+    /// See `ConnectionStats::datagram_drops` for the cause.
+    DatagramsDropped,
+    /// The estimated delivery rate or RTT changed by more than
+    /// `TransportConfig::delivery_rate_threshold`
+    DeliveryRateChanged,
+    /// A bandwidth probe started by `Connection::probe_bandwidth` has completed
+    BandwidthProbed {
+        /// Estimated rate at which the path can deliver data after probing, in bytes per second
+        delivery_rate: u64,
+    },
+}
+
+struct BandwidthProbe {
+    /// Number of probe bytes yet to be sent
+    remaining: u64,
+    /// Packet number of the most recently sent probe packet
+    last_packet: Option<u64>,
 }
 
 struct PathResponse {
@@ -3248,6 +4054,15 @@ struct ZeroRttCrypto {
     packet: Box<dyn PacketKey>,
 }
 
+/// What a client needs to restart its handshake with a different version
+pub(crate) struct VersionNegotiation {
+    pub(crate) crypto: Arc<dyn crypto::ClientConfig>,
+    pub(crate) server_name: String,
+    pub(crate) params: TransportParameters,
+    /// Versions we're willing to switch to, in order of preference
+    pub(crate) supported_versions: Vec<u32>,
+}
+
 #[derive(Default)]
 struct SentFrames {
     retransmits: ThinRetransmits,
@@ -166,7 +166,16 @@ impl PacketBuilder {
         let ack_eliciting = self.ack_eliciting;
         let exact_number = self.exact_number;
         let space_id = self.space;
+        let packet_type = match (space_id, self.short_header) {
+            (SpaceId::Initial, _) => "initial",
+            (SpaceId::Handshake, _) => "handshake",
+            (SpaceId::Data, false) => "0RTT",
+            (SpaceId::Data, true) => "1RTT",
+        };
         let (size, padded) = self.finish(conn, buffer);
+        conn.stats.path.sent_packets += 1;
+        conn.qlog
+            .packet_sent(now, packet_type, exact_number, size, sent.as_ref());
         let sent = match sent {
             Some(sent) => sent,
             None => return,
@@ -85,7 +85,8 @@ impl PathData {
     }
 }
 
-#[derive(Copy, Clone)]
+/// RTT estimation for a particular network path
+#[derive(Copy, Clone, Debug)]
 pub struct RttEstimator {
     /// The most recent RTT measurement made when receiving an ack for a previously unacked packet
     latest: Duration,
@@ -98,7 +99,7 @@ pub struct RttEstimator {
 }
 
 impl RttEstimator {
-    fn new(initial_rtt: Duration) -> Self {
+    pub(crate) fn new(initial_rtt: Duration) -> Self {
         Self {
             latest: initial_rtt,
             smoothed: None,
@@ -107,7 +108,7 @@ impl RttEstimator {
         }
     }
 
-    pub fn update(&mut self, ack_delay: Duration, rtt: Duration) {
+    pub(crate) fn update(&mut self, ack_delay: Duration, rtt: Duration) {
         self.latest = rtt;
         // min_rtt ignores ack delay.
         self.min = cmp::min(self.min, self.latest);
@@ -132,10 +133,25 @@ impl RttEstimator {
         }
     }
 
+    /// The current best RTT estimation
     pub fn get(&self) -> Duration {
         self.smoothed.unwrap_or(self.latest)
     }
 
+    /// The most recent RTT sample
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
+    /// The minimum RTT seen on the path, ignoring ack delay
+    pub fn min(&self) -> Duration {
+        self.min
+    }
+
+    pub(crate) fn variance(&self) -> Duration {
+        self.var
+    }
+
     /// Conservative estimate of RTT
     ///
     /// Takes the maximum of smoothed and latest RTT, as recommended
@@ -144,7 +160,7 @@ impl RttEstimator {
         self.get().max(self.latest)
     }
 
-    pub fn pto_base(&self) -> Duration {
+    pub(crate) fn pto_base(&self) -> Duration {
         self.get() + cmp::max(4 * self.var, TIMER_GRANULARITY)
     }
 }
@@ -0,0 +1,343 @@
+//! Connection traces in the qlog format
+//!
+//! Traces are written as JSON text sequences (RFC 7464), following the qlog main schema
+//! (draft-ietf-quic-qlog-main-schema) and QUIC event definitions
+//! (draft-ietf-quic-qlog-quic-events), for analysis with tools such as qvis.
+
+use std::time::Instant;
+#[cfg(feature = "qlog")]
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "qlog")]
+use serde_json::{json, Value};
+#[cfg(feature = "qlog")]
+use tracing::warn;
+
+use super::{RttEstimator, SentFrames};
+#[cfg(feature = "qlog")]
+use crate::frame::{self, Frame};
+use crate::{packet::Header, packet::SpaceId, ConnectionId, Side, TransportConfig};
+
+/// Opens the destination of each connection's qlog trace
+///
+/// Configured with `TransportConfig::qlog`.
+#[cfg(feature = "qlog")]
+pub trait QlogFactory: Send + Sync {
+    /// Open a writer for the trace of a new connection, or return `None` to leave it untraced
+    ///
+    /// `odcid` is the destination connection ID of the client's first Initial packet, which
+    /// identifies the connection on both sides.
+    fn writer(&self, side: Side, odcid: ConnectionId) -> Option<Box<dyn Write + Send>>;
+}
+
+/// Writes the trace of each connection to its own file in a directory
+///
+/// Files are named `<odcid>_<side>.sqlog` after the original destination connection ID in hex
+/// and whether the trace is from the client's or the server's point of view.
+#[cfg(feature = "qlog")]
+#[derive(Debug, Clone)]
+pub struct QlogDir {
+    path: PathBuf,
+}
+
+#[cfg(feature = "qlog")]
+impl QlogDir {
+    /// Trace connections to files in the existing directory `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(feature = "qlog")]
+impl QlogFactory for QlogDir {
+    fn writer(&self, side: Side, odcid: ConnectionId) -> Option<Box<dyn Write + Send>> {
+        let side = match side {
+            Side::Client => "client",
+            Side::Server => "server",
+        };
+        let path = self.path.join(format!("{}_{}.sqlog", odcid, side));
+        match File::create(&path) {
+            Ok(file) => Some(Box::new(BufWriter::new(file))),
+            Err(e) => {
+                warn!(path = %path.display(), "failed to create qlog file: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// A connection's qlog trace, which does nothing unless the `qlog` feature is enabled and a
+/// [`QlogFactory`] was configured
+pub(super) struct QlogStream {
+    #[cfg(feature = "qlog")]
+    inner: Option<Inner>,
+}
+
+#[cfg(feature = "qlog")]
+struct Inner {
+    writer: Box<dyn Write + Send>,
+    /// Time from which events are timestamped
+    start: Instant,
+}
+
+impl QlogStream {
+    #[cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+    pub(super) fn new(
+        config: &TransportConfig,
+        side: Side,
+        odcid: ConnectionId,
+        now: Instant,
+    ) -> Self {
+        #[cfg(feature = "qlog")]
+        {
+            let inner = config
+                .qlog
+                .as_ref()
+                .and_then(|factory| factory.writer(side, odcid))
+                .and_then(|writer| {
+                    let mut inner = Inner { writer, start: now };
+                    let reference_time = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default();
+                    let header = json!({
+                        "qlog_version": "0.3",
+                        "qlog_format": "JSON-SEQ",
+                        "title": "quinn",
+                        "trace": {
+                            "vantage_point": {
+                                "type": if side.is_client() { "client" } else { "server" },
+                            },
+                            "common_fields": {
+                                "ODCID": odcid.to_string(),
+                                "time_format": "relative",
+                                "reference_time": millis(reference_time),
+                            },
+                        },
+                    });
+                    match inner.write(&header) {
+                        Ok(()) => Some(inner),
+                        Err(e) => {
+                            warn!("failed to write qlog trace: {}", e);
+                            None
+                        }
+                    }
+                });
+            Self { inner }
+        }
+        #[cfg(not(feature = "qlog"))]
+        Self {}
+    }
+
+    #[cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+    pub(super) fn packet_sent(
+        &mut self,
+        now: Instant,
+        packet_type: &str,
+        number: u64,
+        len: usize,
+        sent: Option<&SentFrames>,
+    ) {
+        #[cfg(feature = "qlog")]
+        self.event(now, "transport:packet_sent", || {
+            let mut frames = Vec::new();
+            if let Some(sent) = sent {
+                if !sent.acks.is_empty() {
+                    frames.push(json!({ "frame_type": "ack" }));
+                }
+                for meta in &sent.stream_frames {
+                    frames.push(json!({
+                        "frame_type": "stream",
+                        "stream_id": u64::from(meta.id),
+                        "offset": meta.offsets.start,
+                        "length": meta.offsets.end - meta.offsets.start,
+                        "fin": meta.fin,
+                    }));
+                }
+                if let Some(retransmits) = sent.retransmits.get() {
+                    if retransmits.max_data {
+                        frames.push(json!({ "frame_type": "max_data" }));
+                    }
+                    for &id in &retransmits.max_stream_data {
+                        frames.push(json!({
+                            "frame_type": "max_stream_data",
+                            "stream_id": u64::from(id),
+                        }));
+                    }
+                }
+            }
+            json!({
+                "header": { "packet_type": packet_type, "packet_number": number },
+                "raw": { "length": len },
+                "frames": frames,
+            })
+        });
+    }
+
+    #[cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+    pub(super) fn packet_received(
+        &mut self,
+        now: Instant,
+        header: &Header,
+        number: Option<u64>,
+        len: usize,
+        payload: &[u8],
+    ) {
+        #[cfg(feature = "qlog")]
+        self.event(now, "transport:packet_received", || {
+            let frames = frame::Iter::new(bytes::Bytes::copy_from_slice(payload))
+                .filter(|frame| !matches!(frame, Frame::Padding))
+                .map(|frame| frame_json(&frame))
+                .collect::<Vec<_>>();
+            let mut header_json = json!({ "packet_type": header_type(header) });
+            if let Some(number) = number {
+                header_json["packet_number"] = number.into();
+            }
+            json!({
+                "header": header_json,
+                "raw": { "length": len },
+                "frames": frames,
+            })
+        });
+    }
+
+    #[cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+    pub(super) fn packet_lost(&mut self, now: Instant, space: SpaceId, number: u64) {
+        #[cfg(feature = "qlog")]
+        self.event(now, "recovery:packet_lost", || {
+            let packet_type = match space {
+                SpaceId::Initial => "initial",
+                SpaceId::Handshake => "handshake",
+                SpaceId::Data => "1RTT",
+            };
+            json!({ "header": { "packet_type": packet_type, "packet_number": number } })
+        });
+    }
+
+    #[cfg_attr(not(feature = "qlog"), allow(unused_variables))]
+    pub(super) fn metrics_updated(
+        &mut self,
+        now: Instant,
+        rtt: &RttEstimator,
+        congestion_window: u64,
+        bytes_in_flight: u64,
+    ) {
+        #[cfg(feature = "qlog")]
+        self.event(now, "recovery:metrics_updated", || {
+            json!({
+                "min_rtt": millis(rtt.min()),
+                "smoothed_rtt": millis(rtt.get()),
+                "latest_rtt": millis(rtt.latest()),
+                "rtt_variance": millis(rtt.variance()),
+                "congestion_window": congestion_window,
+                "bytes_in_flight": bytes_in_flight,
+            })
+        });
+    }
+
+    /// Record an event named `name` with the data produced by `data`, if tracing
+    #[cfg(feature = "qlog")]
+    fn event(&mut self, now: Instant, name: &str, data: impl FnOnce() -> Value) {
+        let inner = match self.inner {
+            Some(ref mut x) => x,
+            None => return,
+        };
+        let event = json!({
+            "time": millis(now.saturating_duration_since(inner.start)),
+            "name": name,
+            "data": data(),
+        });
+        if let Err(e) = inner.write(&event) {
+            warn!("failed to write qlog trace, giving up: {}", e);
+            self.inner = None;
+        }
+    }
+}
+
+#[cfg(feature = "qlog")]
+impl Inner {
+    /// Write `record` as a JSON text sequence element
+    fn write(&mut self, record: &Value) -> io::Result<()> {
+        self.writer.write_all(&[0x1e])?;
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+/// Name of the type of packet with `header` in qlog's vocabulary
+#[cfg(feature = "qlog")]
+fn header_type(header: &Header) -> &'static str {
+    use crate::packet::LongType;
+    match *header {
+        Header::Initial { .. } => "initial",
+        Header::Long {
+            ty: LongType::Handshake,
+            ..
+        } => "handshake",
+        Header::Long {
+            ty: LongType::ZeroRtt,
+            ..
+        } => "0RTT",
+        Header::Retry { .. } => "retry",
+        Header::Short { .. } => "1RTT",
+        Header::VersionNegotiate { .. } => "version_negotiation",
+    }
+}
+
+/// Describe `frame`, including the details of stream and flow control frames
+#[cfg(feature = "qlog")]
+fn frame_json(frame: &Frame) -> Value {
+    match *frame {
+        Frame::Ack(_) => json!({ "frame_type": "ack" }),
+        Frame::Stream(ref x) => json!({
+            "frame_type": "stream",
+            "stream_id": u64::from(x.id),
+            "offset": x.offset,
+            "length": x.data.len(),
+            "fin": x.fin,
+        }),
+        Frame::MaxData(x) => json!({ "frame_type": "max_data", "maximum": x.into_inner() }),
+        Frame::MaxStreamData { id, offset } => json!({
+            "frame_type": "max_stream_data",
+            "stream_id": u64::from(id),
+            "maximum": offset,
+        }),
+        Frame::MaxStreams { dir, count } => json!({
+            "frame_type": "max_streams",
+            "stream_type": dir_name(dir),
+            "maximum": count,
+        }),
+        Frame::DataBlocked { offset } => json!({ "frame_type": "data_blocked", "limit": offset }),
+        Frame::StreamDataBlocked { id, offset } => json!({
+            "frame_type": "stream_data_blocked",
+            "stream_id": u64::from(id),
+            "limit": offset,
+        }),
+        Frame::StreamsBlocked { dir, limit } => json!({
+            "frame_type": "streams_blocked",
+            "stream_type": dir_name(dir),
+            "limit": limit,
+        }),
+        Frame::Datagram(ref x) => json!({ "frame_type": "datagram", "length": x.data.len() }),
+        Frame::Close(_) => json!({ "frame_type": "connection_close" }),
+        ref x => json!({ "frame_type": x.ty().to_string().to_lowercase() }),
+    }
+}
+
+#[cfg(feature = "qlog")]
+fn dir_name(dir: crate::Dir) -> &'static str {
+    match dir {
+        crate::Dir::Bi => "bidirectional",
+        crate::Dir::Uni => "unidirectional",
+    }
+}
+
+/// `x` in fractional milliseconds, as qlog expects
+#[cfg(feature = "qlog")]
+fn millis(x: Duration) -> f64 {
+    x.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,106 @@
+//! Careful resume of the congestion window of an earlier connection to the same server
+//!
+//! Follows draft-ietf-tsvwg-careful-resume: the saved path is only trusted once a round-trip time
+//! sample confirms it is unchanged, the window is then jumped to half the saved
+//! bandwidth-delay product, and the jump is retreated from if congestion is observed before any
+//! packet sent at the larger window is acknowledged.
+
+use std::{
+    cmp,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, trace};
+
+use crate::{congestion::Controller, PathCache, PathParameters};
+
+pub(crate) struct CarefulResume {
+    cache: Arc<dyn PathCache>,
+    server_name: String,
+    phase: Phase,
+    /// Whether the round-trip time of the path has been measured, making it worth saving
+    sampled: bool,
+}
+
+impl CarefulResume {
+    pub(crate) fn new(cache: Arc<dyn PathCache>, server_name: String) -> Self {
+        let phase = match cache.get(&server_name) {
+            Some(saved) => Phase::Reconnaissance(saved),
+            None => Phase::Done,
+        };
+        Self {
+            cache,
+            server_name,
+            phase,
+            sampled: false,
+        }
+    }
+
+    /// Jump the congestion window if the round-trip time `rtt` matches the saved path
+    pub(super) fn on_rtt_sample(&mut self, now: Instant, rtt: Duration, cc: &mut dyn Controller) {
+        self.sampled = true;
+        let saved = match self.phase {
+            Phase::Reconnaissance(saved) => saved,
+            _ => return,
+        };
+        self.phase = Phase::Done;
+        if rtt < saved.rtt / 2 || rtt > saved.rtt * 10 {
+            debug!(?rtt, saved = ?saved.rtt, "path changed, not resuming congestion window");
+            return;
+        }
+        let window = saved.window() / 2;
+        let pipesize = cc.window();
+        if window <= pipesize {
+            return;
+        }
+        debug!(window, "resuming congestion window");
+        cc.set_window(window);
+        self.phase = Phase::Unvalidated {
+            jumped_at: now,
+            pipesize,
+        };
+    }
+
+    /// Account for the acknowledgement of `bytes` sent at `sent`
+    pub(super) fn on_acked(&mut self, sent: Instant, bytes: u64) {
+        if let Phase::Unvalidated {
+            jumped_at,
+            ref mut pipesize,
+        } = self.phase
+        {
+            if sent > jumped_at {
+                trace!("resumed congestion window validated");
+                self.phase = Phase::Done;
+            } else {
+                *pipesize += bytes;
+            }
+        }
+    }
+
+    /// Retreat from an unvalidated jump after a congestion event
+    pub(super) fn on_congestion(&mut self, cc: &mut dyn Controller) {
+        if let Phase::Unvalidated { pipesize, .. } = self.phase {
+            debug!(pipesize, "congestion while resuming, retreating");
+            cc.set_window(cmp::min(cc.window(), pipesize / 2));
+            self.phase = Phase::Done;
+        }
+    }
+
+    /// Store the characteristics of the path for later connections
+    pub(super) fn save(&self, params: PathParameters) {
+        if self.sampled {
+            self.cache.put(&self.server_name, params);
+        }
+    }
+}
+
+enum Phase {
+    /// Waiting for a round-trip time sample to confirm the saved path still applies
+    Reconnaissance(PathParameters),
+    /// The window was jumped at `jumped_at`, from which `pipesize` grows as packets sent before
+    /// then are acknowledged
+    Unvalidated { jumped_at: Instant, pipesize: u64 },
+    /// Resumption completed, was retreated from, or does not apply
+    Done,
+}
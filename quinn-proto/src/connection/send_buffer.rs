@@ -172,6 +172,12 @@ impl SendBuffer {
         self.offset
     }
 
+    /// First stream offset not yet acknowledged by the peer, i.e. the length of the acknowledged
+    /// prefix of the stream
+    pub fn acked_offset(&self) -> u64 {
+        self.offset - self.unacked_len as u64
+    }
+
     /// Whether all sent data has been acknowledged
     pub fn is_fully_acked(&self) -> bool {
         self.unacked_len == 0
@@ -339,7 +345,7 @@ mod tests {
         buf.ack(4..7);
         assert_eq!(aggregate_unacked(&buf), &MSG[9..]);
         buf.ack(0..MSG_LEN);
-        assert_eq!(aggregate_unacked(&buf), &[]);
+        assert_eq!(aggregate_unacked(&buf), &[] as &[u8]);
     }
 
     #[test]
@@ -57,6 +57,8 @@ pub(crate) struct PacketSpace {
     /// Number of tail loss probes to send
     pub(crate) loss_probes: u32,
     pub(crate) ping_pending: bool,
+    /// Whether to ask the peer to acknowledge the next packet immediately
+    pub(crate) immediate_ack_pending: bool,
     /// Number of congestion control "in flight" bytes
     pub(crate) in_flight: u64,
     /// Number of packets sent in the current key phase
@@ -87,6 +89,7 @@ impl PacketSpace {
             loss_time: None,
             loss_probes: 0,
             ping_pending: false,
+            immediate_ack_pending: false,
             in_flight: 0,
             sent_with_keys: 0,
         }
@@ -141,7 +144,7 @@ impl PacketSpace {
 
     pub(crate) fn can_send(&self) -> SendableFrames {
         let acks = self.pending_acks.can_send();
-        let other = !self.pending.is_empty() || self.ping_pending;
+        let other = !self.pending.is_empty() || self.ping_pending || self.immediate_ack_pending;
 
         SendableFrames { acks, other }
     }
@@ -233,6 +236,7 @@ pub struct Retransmits {
     pub(crate) new_cids: Vec<IssuedCid>,
     pub(crate) retire_cids: Vec<u64>,
     pub(crate) handshake_done: bool,
+    pub(crate) ack_frequency: bool,
 }
 
 impl Retransmits {
@@ -247,6 +251,7 @@ impl Retransmits {
             && self.new_cids.is_empty()
             && self.retire_cids.is_empty()
             && !self.handshake_done
+            && !self.ack_frequency
     }
 }
 
@@ -263,6 +268,7 @@ impl Default for Retransmits {
             new_cids: Vec::new(),
             retire_cids: Vec::new(),
             handshake_done: false,
+            ack_frequency: false,
         }
     }
 }
@@ -283,6 +289,7 @@ impl ::std::ops::BitOrAssign for Retransmits {
         self.new_cids.extend(&rhs.new_cids);
         self.retire_cids.extend(rhs.retire_cids);
         self.handshake_done |= rhs.handshake_done;
+        self.ack_frequency |= rhs.ack_frequency;
     }
 }
 
@@ -431,12 +438,22 @@ impl SendableFrames {
 pub(crate) struct PendingAcks {
     permit_ack_only: bool,
     ranges: ArrayRangeSet,
-    /// This value will be used for calculating ACK delay once it is implemented
+    /// The largest packet number received, and when it was received
     ///
-    /// ACK delay will be the delay between when a packet arrived (`latest_incoming`)
-    /// and between it will be allowed to be acknowledged (`can_send() == true`).
-    latest_incoming: Option<Instant>,
-    ack_delay: Duration,
+    /// Used to compute the ACK delay, i.e. the delay between when the largest acknowledged packet
+    /// arrived and when the acknowledgement was sent.
+    largest: Option<(u64, Instant)>,
+    /// Whether the most recently received packet has already been found to be ack-eliciting
+    counted: bool,
+    /// Whether the most recently received packet arrived out of order, per the peer's reordering
+    /// threshold
+    reordered: bool,
+    /// Acknowledgement behavior requested by the peer through ACK_FREQUENCY frames
+    frequency: Option<AckFrequency>,
+    /// Number of ack-eliciting packets received since ACKs were last sent
+    ack_eliciting_since_ack: u64,
+    /// When the earliest ack-eliciting packet not yet acknowledged was received
+    earliest_ack_eliciting: Option<Instant>,
 }
 
 impl PendingAcks {
@@ -445,18 +462,72 @@ impl PendingAcks {
         self.permit_ack_only && !self.ranges.is_empty()
     }
 
-    /// Returns the duration the acknowledgement of the latest incoming packet has been delayed
-    pub fn ack_delay(&self) -> Duration {
-        self.ack_delay
+    /// Returns the duration the acknowledgement of the largest incoming packet has been delayed
+    pub fn ack_delay(&self, now: Instant) -> Duration {
+        self.largest.map_or(Duration::from_secs(0), |(_, time)| {
+            now.saturating_duration_since(time)
+        })
     }
 
     /// Should be called whenever an ACK eliciting frame was received
     ///
-    /// This requires sending new outgoing ACKs
+    /// This requires sending new outgoing ACKs, immediately unless the peer asked for them to be
+    /// delayed.
     pub fn ack_eliciting_frame_received(&mut self) {
+        if mem::replace(&mut self.counted, true) {
+            return;
+        }
+        let frequency = match self.frequency {
+            Some(ref x) => x,
+            None => {
+                self.permit_ack_only = true;
+                return;
+            }
+        };
+        self.ack_eliciting_since_ack += 1;
+        if self.earliest_ack_eliciting.is_none() {
+            self.earliest_ack_eliciting = self.largest.map(|(_, time)| time);
+        }
+        if self.reordered || self.ack_eliciting_since_ack > frequency.ack_eliciting_threshold {
+            self.permit_ack_only = true;
+        }
+    }
+
+    /// Should be called when the peer asks for an immediate acknowledgement
+    pub fn immediate_ack_requested(&mut self) {
         self.permit_ack_only = true;
     }
 
+    /// Apply an ACK_FREQUENCY frame, unless a more recent one has already been applied
+    pub fn set_frequency(&mut self, frame: &frame::AckFrequency) {
+        if let Some(ref x) = self.frequency {
+            if x.sequence >= frame.sequence.into_inner() {
+                return;
+            }
+        }
+        self.frequency = Some(AckFrequency {
+            sequence: frame.sequence.into_inner(),
+            ack_eliciting_threshold: frame.ack_eliciting_threshold.into_inner(),
+            max_ack_delay: Duration::from_micros(frame.request_max_ack_delay.into_inner()),
+            reordering_threshold: frame.reordering_threshold.into_inner(),
+        });
+    }
+
+    /// When ACKs must be sent to honor the maximum ACK delay, if they're being delayed
+    pub fn deadline(&self) -> Option<Instant> {
+        if self.permit_ack_only {
+            return None;
+        }
+        Some(self.earliest_ack_eliciting? + self.frequency.as_ref()?.max_ack_delay)
+    }
+
+    /// Should be called when the deadline returned by [`Self::deadline()`] passes
+    pub fn on_deadline(&mut self) {
+        if self.earliest_ack_eliciting.is_some() {
+            self.permit_ack_only = true;
+        }
+    }
+
     /// Should be called whenever ACKs have been sent
     ///
     /// This will suppress sending further ACKs until additional ACK eliciting frames arrive
@@ -468,13 +539,25 @@ impl PendingAcks {
         // is available in this space - because otherwise it would return
         // `true` purely due to the ACKs
         self.permit_ack_only = false;
+        self.ack_eliciting_since_ack = 0;
+        self.earliest_ack_eliciting = None;
     }
 
     /// Insert one packet that needs to be acknowledged
     pub fn insert_one(&mut self, packet: u64, now: Instant) {
-        self.ranges.insert_one(packet);
-        self.latest_incoming = Some(now);
+        self.counted = false;
+        self.reordered = match (&self.frequency, self.largest) {
+            (Some(frequency), Some((largest, _))) if frequency.reordering_threshold > 0 => {
+                // Either fills a gap, or opens one at least as large as the threshold
+                packet < largest || packet - largest > frequency.reordering_threshold
+            }
+            _ => false,
+        };
+        if self.largest.map_or(true, |(largest, _)| packet > largest) {
+            self.largest = Some((packet, now));
+        }
 
+        self.ranges.insert_one(packet);
         if self.ranges.len() > MAX_ACK_BLOCKS {
             self.ranges.pop_min();
         }
@@ -494,6 +577,15 @@ impl PendingAcks {
     }
 }
 
+/// Acknowledgement behavior requested by the peer
+#[derive(Debug, Copy, Clone)]
+struct AckFrequency {
+    sequence: u64,
+    ack_eliciting_threshold: u64,
+    max_ack_delay: Duration,
+    reordering_threshold: u64,
+}
+
 /// Ensures we can always fit all our ACKs in a single minimum-MTU packet with room to spare
 const MAX_ACK_BLOCKS: usize = 64;
 
@@ -18,16 +18,43 @@ pub struct UdpStats {
     pub transmits: u64,
 }
 
+/// Statistics about packets received before the connection was able to process them
+///
+/// Such packets, e.g. 1-RTT packets that arrive before the handshake keys, are buffered up to the
+/// limit set by `TransportConfig::undecryptable_packet_buffer_size`.
+#[derive(Default, Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct UndecryptableStats {
+    /// The amount of packets which were buffered
+    pub buffered: u64,
+    /// The amount of packets which were dropped because the buffer was full
+    pub dropped: u64,
+    /// The total amount of bytes in dropped packets
+    pub dropped_bytes: u64,
+}
+
+/// Statistics about outgoing application datagrams discarded before being sent
+#[derive(Default, Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct DatagramDropStats {
+    /// The amount of datagrams dropped to make room in the send buffer
+    pub send_buffer: u64,
+    /// The amount of datagrams dropped because they no longer fit in a packet
+    pub unsendable: u64,
+}
+
 /// Statistics about frames transmitted or received on a connection
 #[derive(Default, Copy, Clone)]
 #[non_exhaustive]
 pub struct FrameStats {
     pub acks: u64,
+    pub ack_frequency: u64,
     pub crypto: u64,
     pub connection_close: u64,
     pub data_blocked: u64,
     pub datagram: u64,
     pub handshake_done: u8,
+    pub immediate_ack: u64,
     pub max_data: u64,
     pub max_stream_data: u64,
     pub max_streams_bidi: u64,
@@ -82,6 +109,8 @@ impl FrameStats {
             Frame::PathResponse(_) => self.path_response += 1,
             Frame::Close(_) => self.connection_close += 1,
             Frame::HandshakeDone => self.handshake_done += 1,
+            Frame::AckFrequency(_) => self.ack_frequency += 1,
+            Frame::ImmediateAck => self.immediate_ack += 1,
             Frame::Invalid { .. } => {}
         }
     }
@@ -91,11 +120,13 @@ impl std::fmt::Debug for FrameStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FrameStats")
             .field("ACK", &self.acks)
+            .field("ACK_FREQUENCY", &self.ack_frequency)
             .field("CONNECTION_CLOSE", &self.connection_close)
             .field("CRYPTO", &self.crypto)
             .field("DATA_BLOCKED", &self.data_blocked)
             .field("DATAGRAM", &self.datagram)
             .field("HANDSHAKE_DONE", &self.handshake_done)
+            .field("IMMEDIATE_ACK", &self.immediate_ack)
             .field("MAX_DATA", &self.max_data)
             .field("MAX_STREAM_DATA", &self.max_stream_data)
             .field("MAX_STREAMS_BIDI", &self.max_streams_bidi)
@@ -116,16 +147,104 @@ impl std::fmt::Debug for FrameStats {
     }
 }
 
+/// Distribution of round-trip time samples over exponentially sized buckets
+///
+/// Bucket 0 counts samples below 1ms, and each following bucket `i` counts samples of at least
+/// `2^(i-1)` but below `2^i` milliseconds, except for the final bucket, which counts all samples of
+/// a second or more.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RttHistogram {
+    counts: [u64; RttHistogram::BUCKETS],
+}
+
+impl RttHistogram {
+    /// Number of buckets in the histogram
+    pub const BUCKETS: usize = 12;
+
+    pub(crate) fn record(&mut self, rtt: Duration) {
+        let millis = rtt.as_millis();
+        let bucket = (128 - millis.leading_zeros()) as usize;
+        self.counts[bucket.min(Self::BUCKETS - 1)] += 1;
+    }
+
+    /// Number of samples in each bucket
+    pub fn counts(&self) -> &[u64; Self::BUCKETS] {
+        &self.counts
+    }
+
+    /// Exclusive upper bound of the samples counted by bucket `i`, or `None` for the last bucket
+    pub fn upper_bound(i: usize) -> Option<Duration> {
+        if i + 1 >= Self::BUCKETS {
+            return None;
+        }
+        Some(Duration::from_millis(1 << i))
+    }
+
+    /// Total number of samples recorded
+    pub fn samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of the bucket containing the sample at `quantile`, e.g. `0.99` for the 99th
+    /// percentile
+    ///
+    /// Returns `None` if no samples have been recorded or the quantile falls into the last bucket.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let samples = self.samples();
+        if samples == 0 {
+            return None;
+        }
+        let target = ((samples as f64 * quantile).ceil() as u64).clamp(1, samples);
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Self::upper_bound(i);
+            }
+        }
+        None
+    }
+}
+
 /// Statistics related to a transmission path
 #[derive(Debug, Default, Copy, Clone)]
 #[non_exhaustive]
 pub struct PathStats {
     /// Current best estimate of this connection's latency (round-trip-time)
     pub rtt: Duration,
+    /// Variation in round-trip time, a measure of jitter
+    ///
+    /// Computed as the mean deviation of RTT samples from the smoothed RTT, as described in
+    /// RFC6298.
+    pub rtt_variance: Duration,
+    /// Smallest round-trip time sample observed
+    pub min_rtt: Duration,
+    /// Largest round-trip time sample observed
+    pub max_rtt: Duration,
+    /// Distribution of all round-trip time samples
+    pub rtt_histogram: RttHistogram,
     /// Current congestion window of the connection
     pub cwnd: u64,
+    /// Estimated rate at which the path can deliver data, in bytes per second
+    pub delivery_rate: u64,
     /// Congestion events on the connection
     pub congestion_events: u64,
+    /// Packets sent on the connection
+    pub sent_packets: u64,
+    /// Packets deemed lost, whose contents are retransmitted as needed
+    pub lost_packets: u64,
+    /// Bytes of ack-eliciting or padded packets deemed lost
+    pub lost_bytes: u64,
+}
+
+impl PathStats {
+    pub(crate) fn record_rtt(&mut self, rtt: Duration) {
+        if self.rtt_histogram.samples() == 0 || rtt < self.min_rtt {
+            self.min_rtt = rtt;
+        }
+        self.max_rtt = self.max_rtt.max(rtt);
+        self.rtt_histogram.record(rtt);
+    }
 }
 
 /// Connection statistics
@@ -142,4 +261,8 @@ pub struct ConnectionStats {
     pub frame_rx: FrameStats,
     /// Statistics related to the current transmission path
     pub path: PathStats,
+    /// Statistics about packets received before they could be processed
+    pub undecryptable: UndecryptableStats,
+    /// Statistics about outgoing application datagrams discarded before being sent
+    pub datagram_drops: DatagramDropStats,
 }
@@ -1,6 +1,8 @@
 use std::{
     cell::RefCell,
     collections::{hash_map, BinaryHeap, VecDeque},
+    io::IoSlice,
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
@@ -15,8 +17,8 @@ use recv::Recv;
 pub use recv::{Chunks, ReadError, ReadableError};
 
 mod send;
-pub use send::{ByteSlice, BytesArray, BytesSource, FinishError, WriteError, Written};
-use send::{Send, SendState};
+pub use send::{ByteSlice, BytesArray, BytesSource, FinishError, IoSlices, WriteError, Written};
+use send::{RateLimit, Send, SendState, StallTimeout};
 
 mod state;
 pub use state::StreamsState;
@@ -79,6 +81,39 @@ impl<'a> Streams<'a> {
     pub fn send_streams(&self) -> usize {
         self.state.send_streams
     }
+
+    /// Describe the currently open streams, ordered by ID
+    ///
+    /// Remotely initiated streams are only included once they've been accepted.
+    pub fn open_streams(&self) -> Vec<StreamInfo> {
+        let state = &*self.state;
+        let mut ids = state
+            .send
+            .keys()
+            .chain(state.recv.keys())
+            .copied()
+            .filter(|id| {
+                id.initiator() == state.side
+                    || id.index() < state.next_reported_remote[id.dir() as usize]
+            })
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .map(|id| StreamInfo {
+                id,
+                send: state.send.get(&id).map(|s| match s.state {
+                    SendState::Ready => SendStreamState::Ready,
+                    SendState::DataSent {
+                        finish_acked: false,
+                    } => SendStreamState::DataSent,
+                    SendState::DataSent { finish_acked: true } => SendStreamState::DataRecvd,
+                    SendState::ResetSent => SendStreamState::ResetSent,
+                }),
+                recv: state.recv.get(&id).map(|s| s.stream_state()),
+            })
+            .collect()
+    }
 }
 
 /// Access to streams
@@ -109,6 +144,65 @@ impl<'a> RecvStream<'a> {
         Chunks::new(self.id, ordered, self.state, self.pending)
     }
 
+    /// Statistics about the data received on the stream so far
+    pub fn stats(&self) -> Result<RecvStreamStats, UnknownStream> {
+        match self.state.recv.get(&self.id) {
+            Some(stream) => Ok(stream.stats()),
+            None => Err(UnknownStream { _private: () }),
+        }
+    }
+
+    /// Set the amount of flow control credit to maintain ahead of the data read from the stream
+    ///
+    /// Overrides [`TransportConfig::stream_receive_window()`] for this stream, and exempts it from
+    /// auto-tuning. An increase is advertised to the peer promptly. A decrease takes effect as the
+    /// application reads data, since credit already issued can't be revoked. The connection-level
+    /// receive window still applies.
+    ///
+    /// [`TransportConfig::stream_receive_window()`]: crate::TransportConfig::stream_receive_window
+    pub fn set_receive_window(&mut self, window: VarInt) -> Result<(), UnknownStream> {
+        let stream = self
+            .state
+            .recv
+            .get_mut(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+        stream.set_window(window.into());
+        if stream.max_stream_data().1.should_transmit() {
+            self.pending.max_stream_data.insert(self.id);
+        }
+        Ok(())
+    }
+
+    /// Stop issuing flow control credit for data beyond offset `max_data`
+    ///
+    /// Useful once the application knows how much data it will consume, e.g. when a length prefix
+    /// has been read, so that the peer can't make the connection buffer data that will never be
+    /// read. Credit already issued can't be revoked, so this only limits future credit. Replaces
+    /// any earlier limit.
+    pub fn set_max_data(&mut self, max_data: VarInt) -> Result<(), UnknownStream> {
+        let stream = self
+            .state
+            .recv
+            .get_mut(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
+        stream.set_max_data(max_data.into());
+        if stream.max_stream_data().1.should_transmit() {
+            self.pending.max_stream_data.insert(self.id);
+        }
+        Ok(())
+    }
+
+    /// The application error code the peer reset the stream with, if it has done so
+    ///
+    /// Once the reset has been yielded by [`read()`](Self::read), the stream is forgotten and this
+    /// fails with `UnknownStream`.
+    pub fn reset_code(&self) -> Result<Option<VarInt>, UnknownStream> {
+        match self.state.recv.get(&self.id) {
+            Some(stream) => Ok(stream.reset_code()),
+            None => Err(UnknownStream { _private: () }),
+        }
+    }
+
     /// Stop accepting data on the given receive stream
     ///
     /// Discards unread data and notifies the peer to stop transmitting. Once stopped, further
@@ -120,6 +214,7 @@ impl<'a> RecvStream<'a> {
         };
         let stream = entry.get_mut();
 
+        let buffered = stream.assembler.buffered();
         let (read_credits, stop_sending) = stream.stop()?;
         if stop_sending.should_transmit() {
             self.pending.stop_sending.push(frame::StopSending {
@@ -135,6 +230,8 @@ impl<'a> RecvStream<'a> {
             entry.remove();
             self.state.stream_freed(self.id, StreamHalf::Recv);
         }
+        // Stopping discards all buffered data
+        self.state.reassembly_buffered -= buffered;
 
         if self.state.add_read_credits(read_credits).should_transmit() {
             self.pending.max_data = true;
@@ -185,6 +282,13 @@ impl<'a> SendStream<'a> {
         self.write_source(&mut BytesArray::from_chunks(data))
     }
 
+    /// Send data from a sequence of buffers on the given stream
+    ///
+    /// Returns the number of bytes successfully written, taken from the buffers in order.
+    pub fn write_vectored(&mut self, data: &[IoSlice]) -> Result<usize, WriteError> {
+        Ok(self.write_source(&mut IoSlices::from_slices(data))?.bytes)
+    }
+
     fn write_source<B: BytesSource>(&mut self, source: &mut B) -> Result<Written, WriteError> {
         if self.conn_state.is_closed() {
             trace!(%self.id, "write blocked; connection draining");
@@ -228,6 +332,124 @@ impl<'a> SendStream<'a> {
         }
     }
 
+    /// Number of bytes written to the stream so far
+    pub fn written(&self) -> Result<u64, UnknownStream> {
+        match self.state.send.get(&self.id) {
+            Some(s) => Ok(s.offset()),
+            None => Err(UnknownStream { _private: () }),
+        }
+    }
+
+    /// Number of bytes at the start of the stream that the peer has acknowledged
+    ///
+    /// Data written past this offset may still be retransmitted if lost. Once a finished stream
+    /// has been fully acknowledged it is forgotten and [`StreamEvent::Finished`] is generated
+    /// instead.
+    ///
+    /// [`StreamEvent::Finished`]: crate::StreamEvent::Finished
+    pub fn acked(&self) -> Result<u64, UnknownStream> {
+        match self.state.send.get(&self.id) {
+            Some(s) => Ok(s.pending.acked_offset()),
+            None => Err(UnknownStream { _private: () }),
+        }
+    }
+
+    /// Generate a [`StreamEvent::Acked`] once the peer has acknowledged the first `offset` bytes
+    /// of the stream
+    ///
+    /// Replaces any earlier request for the same stream. If that data has already been
+    /// acknowledged, the event is generated immediately.
+    ///
+    /// [`StreamEvent::Acked`]: crate::StreamEvent::Acked
+    pub fn notify_acked(&mut self, offset: u64) -> Result<(), UnknownStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+        stream.notify_acked = Some(offset);
+        if stream.take_acked_notification() {
+            self.state
+                .events
+                .push_back(StreamEvent::Acked { id: self.id });
+        }
+        Ok(())
+    }
+
+    /// Reset the stream with `error_code` if it hasn't been finished and fully acknowledged by
+    /// `deadline`
+    ///
+    /// Suited to data that is worthless once stale, such as live media. When the deadline passes,
+    /// locally buffered data is dropped as if by [`reset()`](Self::reset), further writes fail with
+    /// [`WriteError::Expired`], and a [`StreamEvent::Expired`] is generated. Replaces any earlier
+    /// deadline.
+    pub fn set_deadline(
+        &mut self,
+        deadline: Instant,
+        error_code: VarInt,
+    ) -> Result<(), UnknownStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+        stream.deadline = Some((deadline, error_code));
+        if !self.state.deadlines.contains(&self.id) {
+            self.state.deadlines.push(self.id);
+        }
+        Ok(())
+    }
+
+    /// Remove the deadline set by [`set_deadline()`](Self::set_deadline), if any
+    pub fn clear_deadline(&mut self) -> Result<(), UnknownStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+        stream.deadline = None;
+        Ok(())
+    }
+
+    /// Reset the stream with `error_code` if it waits on the peer for `timeout` without making
+    /// progress
+    ///
+    /// The stream waits on the peer while it has unacknowledged data or is blocked by flow
+    /// control, and makes progress whenever data is acknowledged or flow control credit is issued.
+    /// Protects against peers that open streams but never read from them. A stalled stream expires
+    /// exactly like one whose [deadline](Self::set_deadline) passed. Replaces any earlier stall
+    /// timeout.
+    pub fn set_stall_timeout(
+        &mut self,
+        timeout: Duration,
+        error_code: VarInt,
+    ) -> Result<(), UnknownStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+        stream.stall = Some(StallTimeout::new(timeout, error_code));
+        if !self.state.deadlines.contains(&self.id) {
+            self.state.deadlines.push(self.id);
+        }
+        Ok(())
+    }
+
+    /// Remove the stall timeout set by [`set_stall_timeout()`](Self::set_stall_timeout), if any
+    pub fn clear_stall_timeout(&mut self) -> Result<(), UnknownStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+        stream.stall = None;
+        Ok(())
+    }
+
+    /// Whether the stream was reset because its delivery deadline passed or it stalled
+    pub fn is_expired(&self) -> Result<bool, UnknownStream> {
+        match self.state.send.get(&self.id) {
+            Some(s) => Ok(s.expired),
+            None => Err(UnknownStream { _private: () }),
+        }
+    }
+
     /// Finish a send stream, signalling that no more data will be sent.
     ///
     /// If this fails, no [`StreamEvent::Finished`] will be generated.
@@ -289,6 +511,14 @@ impl<'a> SendStream<'a> {
         Ok(())
     }
 
+    /// Statistics about the data sent on the stream so far
+    pub fn stats(&self) -> Result<SendStreamStats, UnknownStream> {
+        match self.state.send.get(&self.id) {
+            Some(stream) => Ok(stream.stats()),
+            None => Err(UnknownStream { _private: () }),
+        }
+    }
+
     /// Get the priority of a stream
     ///
     /// # Panics
@@ -301,6 +531,43 @@ impl<'a> SendStream<'a> {
 
         Ok(stream.priority)
     }
+
+    /// Limit the rate at which data from a stream is transmitted, in bytes per second
+    ///
+    /// `None` removes any limit. Capacity left unused by a rate-limited stream is available to
+    /// other streams on the same connection. A rate of zero suspends transmission of the stream's
+    /// data until the limit is changed.
+    pub fn set_max_rate(&mut self, rate: Option<u64>) -> Result<(), UnknownStream> {
+        let stream = match self.state.send.get_mut(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+
+        match (rate, &mut stream.rate_limit) {
+            (Some(rate), Some(limit)) => limit.set_rate(rate),
+            (Some(rate), limit @ None) => *limit = Some(RateLimit::new(rate)),
+            (None, limit) => *limit = None,
+        }
+        self.state.rate_limited |= rate.is_some();
+
+        // Let the scheduler reconsider a throttled stream under its new limit
+        let id = self.id;
+        if let Some(i) = self.state.throttled.iter().position(|&x| x == id) {
+            self.state.throttled.swap_remove(i);
+            push_pending(&mut self.state.pending, id, stream.priority);
+        }
+        Ok(())
+    }
+
+    /// Get the maximum transmission rate of a stream, in bytes per second
+    pub fn max_rate(&self) -> Result<Option<u64>, UnknownStream> {
+        let stream = match self.state.send.get(&self.id) {
+            Some(ss) => ss,
+            None => return Err(UnknownStream { _private: () }),
+        };
+
+        Ok(stream.rate_limit.as_ref().map(|limit| limit.rate))
+    }
 }
 
 fn push_pending(pending: &mut BinaryHeap<PendingLevel>, id: StreamId, priority: i32) {
@@ -359,6 +626,90 @@ impl Ord for PendingLevel {
     }
 }
 
+/// Description of an open stream, as returned by [`Streams::open_streams()`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// The stream's ID, which also identifies its directionality and initiator
+    pub id: StreamId,
+    /// State of the sending half, if it's still open
+    ///
+    /// Always `None` for remotely initiated unidirectional streams.
+    pub send: Option<SendStreamState>,
+    /// State of the receiving half, if it's still open
+    ///
+    /// Always `None` for locally initiated unidirectional streams.
+    pub recv: Option<RecvStreamState>,
+}
+
+/// Policy for sharing transmission capacity between streams of the same priority
+///
+/// Streams of higher priority are always served first, see [`SendStream::set_priority()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SchedulerKind {
+    /// Streams take turns, one frame at a time
+    ///
+    /// Gives every stream a fair share of the connection, so that e.g. a small RPC isn't held up
+    /// behind a large one.
+    RoundRobin,
+    /// Each stream is sent to completion before the next, in the order they became ready to send
+    ///
+    /// Minimizes the completion time of the first streams when transferring several objects in
+    /// bulk, since their data isn't interleaved. A stream that is written slowly keeps its place
+    /// only while it has data buffered.
+    Sequential,
+}
+
+/// Statistics about the sending half of a stream
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SendStreamStats {
+    /// Bytes written to the stream by the application
+    pub bytes_written: u64,
+    /// Bytes acknowledged by the peer, including those acknowledged out of order
+    pub bytes_acked: u64,
+    /// Bytes declared lost and queued for retransmission
+    pub bytes_retransmitted: u64,
+    /// Bytes the application may still write before the peer's flow control limit for the stream
+    /// is reached
+    pub flow_control_credit: u64,
+}
+
+/// Statistics about the receiving half of a stream
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecvStreamStats {
+    /// Highest stream offset received from the peer, including data that arrived out of order
+    pub bytes_received: u64,
+    /// Bytes read by the application
+    pub bytes_read: u64,
+    /// Bytes the peer may still send before reaching the flow control limit we've advertised
+    pub flow_control_credit: u64,
+}
+
+/// State of the sending half of a stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SendStreamState {
+    /// Data may be written to the stream
+    Ready,
+    /// The stream has been finished, and data is awaiting acknowledgement
+    DataSent,
+    /// All data has been acknowledged by the peer
+    DataRecvd,
+    /// The stream has been reset
+    ResetSent,
+}
+
+/// State of the receiving half of a stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecvStreamState {
+    /// Data is being received, and the stream's final size is unknown
+    Recv,
+    /// The peer has finished the stream, but not all data has been read
+    SizeKnown,
+    /// The peer has reset the stream
+    ResetRecvd,
+}
+
 /// Application events about streams
 #[derive(Debug, PartialEq, Eq)]
 pub enum StreamEvent {
@@ -379,11 +730,23 @@ pub enum StreamEvent {
         /// Which stream is now writable
         id: StreamId,
     },
+    /// Data requested with [`SendStream::notify_acked()`] has been acknowledged by the peer
+    Acked {
+        /// Which stream has acknowledged data
+        id: StreamId,
+    },
     /// A finished stream has been fully acknowledged or stopped
     Finished {
         /// Which stream has been finished
         id: StreamId,
     },
+    /// An outgoing stream was reset because its delivery deadline passed or it stalled
+    ///
+    /// See [`SendStream::set_deadline()`] and [`SendStream::set_stall_timeout()`].
+    Expired {
+        /// Which stream has expired
+        id: StreamId,
+    },
     /// The peer asked us to stop sending on an outgoing stream
     Stopped {
         /// Which stream has been stopped
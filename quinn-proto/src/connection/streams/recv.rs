@@ -1,10 +1,14 @@
 use std::collections::hash_map::Entry;
 use std::mem;
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
-use tracing::debug;
+use tracing::{debug, trace};
 
-use super::{Retransmits, ShouldTransmit, StreamHalf, StreamId, StreamsState, UnknownStream};
+use super::{
+    RecvStreamState, RecvStreamStats, Retransmits, ShouldTransmit, StreamHalf, StreamId,
+    StreamsState, UnknownStream,
+};
 use crate::connection::assembler::{Assembler, Chunk, IllegalOrderedRead};
 use crate::{frame, Dir, TransportError, VarInt};
 
@@ -13,6 +17,14 @@ pub(super) struct Recv {
     state: RecvState,
     pub(super) assembler: Assembler,
     sent_max_stream_data: u64,
+    /// Amount of credit to maintain ahead of the data read by the application
+    window: u64,
+    /// Time and read offset at which the current window auto-tuning measurement started
+    window_epoch: Option<(Instant, u64)>,
+    /// Whether `window` was set by the application, exempting it from auto-tuning
+    window_fixed: bool,
+    /// Offset beyond which the application doesn't want credit to be issued
+    max_data: Option<u64>,
     pub(super) end: u64,
     pub(super) stopped: bool,
 }
@@ -23,6 +35,10 @@ impl Recv {
             state: RecvState::default(),
             assembler: Assembler::new(),
             sent_max_stream_data: initial_max_data,
+            window: initial_max_data,
+            window_epoch: None,
+            window_fixed: false,
+            max_data: None,
             end: 0,
             stopped: false,
         }
@@ -95,21 +111,61 @@ impl Recv {
     /// transmission of the value is recommended. If the boolean value is
     /// `false` the new window should only be transmitted if a previous transmission
     /// had failed.
-    pub(super) fn max_stream_data(&mut self, stream_receive_window: u64) -> (u64, ShouldTransmit) {
-        let max_stream_data = self.assembler.bytes_read() + stream_receive_window;
+    pub(super) fn max_stream_data(&mut self) -> (u64, ShouldTransmit) {
+        let mut target = self.assembler.bytes_read() + self.window;
+        if let Some(max_data) = self.max_data {
+            target = target.min(max_data);
+        }
+        // Credit already issued can't be revoked, even if the window has since been reduced
+        let max_stream_data = target.max(self.sent_max_stream_data);
 
         // Only announce a window update if it's significant enough
         // to make it worthwhile sending a MAX_STREAM_DATA frame.
-        // We use here a fraction of the configured stream receive window to make
+        // We use here a fraction of the current stream receive window to make
         // the decision, and accomodate for streams using bigger windows requring
         // less updates. A fixed size would also work - but it would need to be
         // smaller than `stream_receive_window` in order to make sure the stream
         // does not get stuck.
+        // Credit up to the application's limit is sent regardless, as no more will follow.
         let diff = max_stream_data - self.sent_max_stream_data;
-        let transmit = self.receiving_unknown_size() && diff >= (stream_receive_window / 8);
+        let transmit = self.receiving_unknown_size()
+            && (diff >= (self.window / 8) || (diff > 0 && Some(max_stream_data) == self.max_data));
         (max_stream_data, ShouldTransmit(transmit))
     }
 
+    /// Grow the window if the application is consuming it quickly
+    ///
+    /// If a full window's worth of data was read in less than two round trips, the window is
+    /// likely what limits throughput, so it's doubled up to `max_window`.
+    pub(super) fn autotune_window(&mut self, now: Instant, rtt: Duration, max_window: u64) {
+        if self.window_fixed {
+            return;
+        }
+        let bytes_read = self.assembler.bytes_read();
+        match self.window_epoch {
+            Some((start, offset)) if bytes_read - offset >= self.window => {
+                if self.window < max_window && now.saturating_duration_since(start) < 2 * rtt {
+                    self.window = self.window.saturating_mul(2).min(max_window);
+                    trace!(window = self.window, "stream receive window increased");
+                }
+                self.window_epoch = Some((now, bytes_read));
+            }
+            Some(_) => {}
+            None => self.window_epoch = Some((now, bytes_read)),
+        }
+    }
+
+    /// Replace the window chosen by configuration and auto-tuning
+    pub(super) fn set_window(&mut self, window: u64) {
+        self.window = window;
+        self.window_fixed = true;
+    }
+
+    /// Never issue credit beyond `max_data`
+    pub(super) fn set_max_data(&mut self, max_data: u64) {
+        self.max_data = Some(max_data);
+    }
+
     /// Records that a `MAX_STREAM_DATA` announcing a certain window was sent
     ///
     /// This will suppress enqueuing further `MAX_STREAM_DATA` frames unless
@@ -130,6 +186,29 @@ impl Recv {
         matches!(self.state, RecvState::Recv { .. })
     }
 
+    pub(super) fn stream_state(&self) -> RecvStreamState {
+        match self.state {
+            RecvState::Recv { size: None } => RecvStreamState::Recv,
+            RecvState::Recv { size: Some(_) } => RecvStreamState::SizeKnown,
+            RecvState::ResetRecvd { .. } => RecvStreamState::ResetRecvd,
+        }
+    }
+
+    pub(super) fn stats(&self) -> RecvStreamStats {
+        RecvStreamStats {
+            bytes_received: self.end,
+            bytes_read: self.assembler.bytes_read(),
+            flow_control_credit: self.sent_max_stream_data.saturating_sub(self.end),
+        }
+    }
+
+    pub(super) fn reset_code(&self) -> Option<VarInt> {
+        match self.state {
+            RecvState::ResetRecvd { error_code, .. } => Some(error_code),
+            RecvState::Recv { .. } => None,
+        }
+    }
+
     fn final_offset(&self) -> Option<u64> {
         match self.state {
             RecvState::Recv { size } => size,
@@ -147,7 +226,7 @@ impl Recv {
     ) -> Result<bool, TransportError> {
         // Validate final_offset
         if let Some(offset) = self.final_offset() {
-            if offset != final_offset.into() {
+            if offset != u64::from(final_offset) {
                 return Err(TransportError::FINAL_SIZE_ERROR("inconsistent value"));
             }
         } else if self.end > final_offset.into() {
@@ -206,6 +285,8 @@ pub struct Chunks<'a> {
     pending: &'a mut Retransmits,
     state: ChunksState,
     read: u64,
+    /// Bytes buffered by the stream when reading began
+    buffered: usize,
 }
 
 impl<'a> Chunks<'a> {
@@ -225,7 +306,11 @@ impl<'a> Chunks<'a> {
             false => entry.remove(),
         };
 
-        recv.assembler.ensure_ordering(ordered)?;
+        let buffered = recv.assembler.buffered();
+        if let Err(e) = recv.assembler.ensure_ordering(ordered) {
+            streams.recv.insert(id, recv);
+            return Err(e.into());
+        }
         Ok(Self {
             id,
             ordered,
@@ -233,6 +318,7 @@ impl<'a> Chunks<'a> {
             pending,
             state: ChunksState::Readable(recv),
             read: 0,
+            buffered,
         })
     }
 
@@ -295,6 +381,13 @@ impl<'a> Chunks<'a> {
             return ShouldTransmit(false);
         }
 
+        // Streams which are no longer readable have nothing left buffered
+        let buffered = match state {
+            ChunksState::Readable(ref rs) => rs.assembler.buffered(),
+            _ => 0,
+        };
+        self.streams.reassembly_buffered -= self.buffered - buffered;
+
         let mut should_transmit = false;
         // We issue additional stream ID credit iff a remotely-initiated stream stream is finished or reset
         if matches!(state, ChunksState::Finished | ChunksState::Reset(_))
@@ -309,7 +402,7 @@ impl<'a> Chunks<'a> {
 
         // If the stream hasn't finished, we may need to issue stream-level flow control credit
         if let ChunksState::Readable(mut rs) = state {
-            let (_, max_stream_data) = rs.max_stream_data(self.streams.stream_receive_window);
+            let (_, max_stream_data) = rs.max_stream_data();
             should_transmit |= max_stream_data.0;
             if max_stream_data.0 {
                 self.pending.max_stream_data.insert(self.id);
@@ -1,6 +1,13 @@
+use std::{
+    convert::TryFrom,
+    io::IoSlice,
+    time::{Duration, Instant},
+};
+
 use bytes::Bytes;
 use thiserror::Error;
 
+use super::SendStreamStats;
 use crate::{connection::send_buffer::SendBuffer, frame, VarInt};
 
 #[derive(Debug)]
@@ -15,6 +22,18 @@ pub(super) struct Send {
     pub(super) connection_blocked: bool,
     /// The reason the peer wants us to stop, if `STOP_SENDING` was received
     pub(super) stop_reason: Option<VarInt>,
+    /// Offset at which to generate `StreamEvent::Acked` once the prefix up to it is acknowledged
+    pub(super) notify_acked: Option<u64>,
+    /// Limit on the rate at which this stream's data is scheduled for transmission
+    pub(super) rate_limit: Option<RateLimit>,
+    /// When to reset the stream if it hasn't been fully delivered, and the error code to use
+    pub(super) deadline: Option<(Instant, VarInt)>,
+    /// How long the stream may go without progress before being reset, if limited
+    pub(super) stall: Option<StallTimeout>,
+    /// Whether the stream was reset due to `deadline` passing or `stall` elapsing
+    pub(super) expired: bool,
+    /// Bytes of stream data declared lost and queued for retransmission
+    pub(super) retransmitted: u64,
 }
 
 impl Send {
@@ -27,16 +46,38 @@ impl Send {
             fin_pending: false,
             connection_blocked: false,
             stop_reason: None,
+            notify_acked: None,
+            rate_limit: None,
+            deadline: None,
+            stall: None,
+            expired: false,
+            retransmitted: 0,
         }
     }
 
+    pub(super) fn stats(&self) -> SendStreamStats {
+        SendStreamStats {
+            bytes_written: self.pending.offset(),
+            bytes_acked: self.pending.offset() - self.pending.unacked(),
+            bytes_retransmitted: self.retransmitted,
+            flow_control_credit: self.max_data.saturating_sub(self.pending.offset()),
+        }
+    }
+
+    /// Whether the stream's rate limit currently forbids sending more data
+    pub(super) fn is_throttled(&self) -> bool {
+        matches!(self.rate_limit, Some(ref limit) if limit.tokens <= 0)
+    }
+
     /// Whether the stream has been reset
     pub(super) fn is_reset(&self) -> bool {
         matches!(self.state, SendState::ResetSent { .. })
     }
 
     pub(super) fn finish(&mut self) -> Result<(), FinishError> {
-        if let Some(error_code) = self.stop_reason {
+        if self.expired {
+            Err(FinishError::Expired)
+        } else if let Some(error_code) = self.stop_reason {
             Err(FinishError::Stopped(error_code))
         } else if self.state == SendState::Ready {
             self.state = SendState::DataSent {
@@ -54,6 +95,9 @@ impl Send {
         source: &mut S,
         limit: u64,
     ) -> Result<Written, WriteError> {
+        if self.expired {
+            return Err(WriteError::Expired);
+        }
         if !self.is_writable() {
             return Err(WriteError::UnknownStream);
         }
@@ -130,19 +174,181 @@ impl Send {
         was_blocked
     }
 
+    /// Whether a requested `StreamEvent::Acked` is now due, clearing the request if so
+    pub(super) fn take_acked_notification(&mut self) -> bool {
+        match self.notify_acked {
+            Some(offset) if self.pending.acked_offset() >= offset => {
+                self.notify_acked = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub(super) fn offset(&self) -> u64 {
         self.pending.offset()
     }
 
+    /// When the stream is to be reset for lack of progress, if it's stalled
+    pub(super) fn stall_deadline(&self) -> Option<Instant> {
+        let stall = self.stall.as_ref()?;
+        Some(stall.since? + stall.timeout)
+    }
+
+    /// Restart the stall timer if the stream made progress, or stop it if there's nothing to wait
+    /// for
+    ///
+    /// The stream is waiting on the peer while it has unacknowledged data or is blocked by flow
+    /// control, and progresses when data is acknowledged or flow control credit is issued.
+    pub(super) fn update_stall(&mut self, now: Instant) {
+        let stall = match self.stall {
+            Some(ref mut x) => x,
+            None => return,
+        };
+        let waiting = self.pending.unacked() > 0
+            || self.pending.offset() == self.max_data
+            || self.connection_blocked;
+        let progress = (
+            self.pending.offset() - self.pending.unacked(),
+            self.max_data,
+        );
+        if !waiting {
+            stall.since = None;
+        } else if stall.since.is_none() || progress != stall.progress {
+            stall.since = Some(now);
+        }
+        stall.progress = progress;
+    }
+
     pub(super) fn is_pending(&self) -> bool {
         self.pending.has_unsent_data() || self.fin_pending
     }
 
+    /// Whether everything written to the stream, and its end if finished, has been acknowledged
+    pub(super) fn is_delivered(&self) -> bool {
+        match self.state {
+            SendState::Ready => self.pending.is_fully_acked(),
+            SendState::DataSent { finish_acked } => finish_acked && self.pending.is_fully_acked(),
+            SendState::ResetSent => true,
+        }
+    }
+
     pub(super) fn is_writable(&self) -> bool {
         matches!(self.state, SendState::Ready)
     }
 }
 
+/// Token bucket enforcing a stream's maximum transmission rate
+///
+/// A stream may send a frame whenever it has tokens left, possibly going into debt by up to one
+/// frame, which is paid off before it may send again.
+#[derive(Debug)]
+pub(super) struct RateLimit {
+    /// Bytes per second
+    pub(super) rate: u64,
+    /// Bytes that may be sent immediately
+    tokens: i64,
+    /// When `tokens` was last replenished
+    updated: Option<Instant>,
+}
+
+impl RateLimit {
+    pub(super) fn new(rate: u64) -> Self {
+        let mut this = Self {
+            rate,
+            tokens: 0,
+            updated: None,
+        };
+        this.tokens = this.burst();
+        this
+    }
+
+    /// Change the rate, keeping any accumulated debt
+    pub(super) fn set_rate(&mut self, rate: u64) {
+        self.rate = rate;
+        self.tokens = self.tokens.min(self.burst());
+    }
+
+    /// Credit tokens accumulated since the last update
+    pub(super) fn refill(&mut self, now: Instant) {
+        let updated = match self.updated {
+            Some(x) => x,
+            None => {
+                self.updated = Some(now);
+                return;
+            }
+        };
+        let elapsed = now.saturating_duration_since(updated).as_nanos();
+        let rate = u128::from(self.rate);
+        let added = rate * elapsed / 1_000_000_000;
+        if added == 0 {
+            return;
+        }
+        let burst = self.burst();
+        self.tokens = self
+            .tokens
+            .saturating_add(i64::try_from(added).unwrap_or(i64::MAX));
+        if self.tokens >= burst {
+            self.tokens = burst;
+            self.updated = Some(now);
+        } else {
+            // Only advance by the time it took to earn whole bytes, so that frequent calls don't
+            // round away the fractional credit
+            let earned = added * 1_000_000_000 / rate;
+            self.updated = Some(updated + Duration::from_nanos(earned as u64));
+        }
+    }
+
+    pub(super) fn consume(&mut self, bytes: u64) {
+        self.tokens = self
+            .tokens
+            .saturating_sub(i64::try_from(bytes).unwrap_or(i64::MAX));
+    }
+
+    /// When the stream will next be permitted to send, if it is currently throttled
+    pub(super) fn ready_at(&self) -> Option<Instant> {
+        if self.tokens > 0 {
+            return self.updated;
+        }
+        if self.rate == 0 {
+            return None;
+        }
+        let deficit = (1 - i128::from(self.tokens)) as u128;
+        let rate = u128::from(self.rate);
+        // Round up so the deficit has certainly been paid off by then
+        let nanos = deficit * 1_000_000_000 / rate + 1;
+        let delay = Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+        self.updated.map(|x| x + delay)
+    }
+
+    /// Maximum number of tokens that can accumulate, allowing for short bursts
+    fn burst(&self) -> i64 {
+        i64::try_from(self.rate / 20).unwrap_or(i64::MAX).max(1)
+    }
+}
+
+/// Limit on how long a stream may wait on the peer without making progress
+#[derive(Debug)]
+pub(super) struct StallTimeout {
+    pub(super) timeout: Duration,
+    pub(super) error_code: VarInt,
+    /// When the stream started waiting without progress, if it's waiting
+    since: Option<Instant>,
+    /// Bytes acknowledged and flow control credit as of `since`
+    progress: (u64, u64),
+}
+
+impl StallTimeout {
+    pub(super) fn new(timeout: Duration, error_code: VarInt) -> Self {
+        Self {
+            timeout,
+            error_code,
+            since: None,
+            progress: (0, 0),
+        }
+    }
+}
+
 /// A [`BytesSource`] implementation for `&'a mut [Bytes]`
 ///
 /// The type allows to dequeue [`Bytes`] chunks from an array of chunks, up to
@@ -223,6 +429,56 @@ impl<'a> BytesSource for ByteSlice<'a> {
     }
 }
 
+/// A [`BytesSource`] implementation for `&[IoSlice]`
+///
+/// Data from consecutive slices is copied into a single [`Bytes`] chunk per call, so scattered
+/// buffers cost one allocation rather than one per slice.
+pub struct IoSlices<'a> {
+    /// The wrapped slices
+    slices: &'a [IoSlice<'a>],
+    /// The amount of slices consumed from this source
+    consumed: usize,
+    /// The amount of bytes consumed from the first unconsumed slice
+    offset: usize,
+}
+
+impl<'a> IoSlices<'a> {
+    pub fn from_slices(slices: &'a [IoSlice<'a>]) -> Self {
+        Self {
+            slices,
+            consumed: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> BytesSource for IoSlices<'a> {
+    fn pop_chunk(&mut self, limit: usize) -> (Bytes, usize) {
+        let available = self.slices[self.consumed..]
+            .iter()
+            .map(|slice| slice.len())
+            .sum::<usize>()
+            - self.offset;
+        let mut buf = Vec::with_capacity(limit.min(available));
+        let mut chunks_consumed = 0;
+
+        while self.consumed < self.slices.len() {
+            let data = &self.slices[self.consumed][self.offset..];
+            let n = data.len().min(limit - buf.len());
+            buf.extend_from_slice(&data[..n]);
+            if n < data.len() {
+                self.offset += n;
+                break;
+            }
+            self.consumed += 1;
+            self.offset = 0;
+            chunks_consumed += 1;
+        }
+
+        (Bytes::from(buf), chunks_consumed)
+    }
+}
+
 /// A source of one or more buffers which can be converted into `Bytes` buffers on demand
 ///
 /// The purpose of this data type is to defer conversion as long as possible,
@@ -273,6 +529,10 @@ pub enum WriteError {
     /// [`StreamEvent::Finished`]: crate::StreamEvent::Finished
     #[error("stopped by peer: code {0}")]
     Stopped(VarInt),
+    /// The stream's delivery deadline passed before all of its data was acknowledged, or it stalled,
+    /// and it has been reset
+    #[error("stream expired")]
+    Expired,
     /// The stream has not been opened or has already been finished or reset
     #[error("unknown stream")]
     UnknownStream,
@@ -299,6 +559,10 @@ pub enum FinishError {
     /// [`StreamEvent::Finished`]: crate::StreamEvent::Finished
     #[error("stopped by peer: code {0}")]
     Stopped(VarInt),
+    /// The stream's delivery deadline passed before all of its data was acknowledged, or it stalled,
+    /// and it has been reset
+    #[error("stream expired")]
+    Expired,
     /// The stream has not been opened or was already finished or reset
     #[error("unknown stream")]
     UnknownStream,
@@ -362,6 +626,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn io_slices() {
+        let parts: [&[u8]; 6] = [b"", b"Hello ", b"World", b"", b" 123456789", b""];
+        let full = parts.concat();
+        let slices = parts.iter().map(|x| IoSlice::new(x)).collect::<Vec<_>>();
+        for limit in 0..=full.len() {
+            let mut source = IoSlices::from_slices(&slices);
+            let (chunk, consumed) = source.pop_chunk(limit);
+            assert_eq!(&chunk[..], &full[..limit]);
+            // Empty slices count as consumed once reached
+            let expected_consumed = match limit {
+                0..=5 => 1,
+                6..=10 => 2,
+                11..=20 => 4,
+                _ => 6,
+            };
+            assert_eq!(consumed, expected_consumed, "limit {}", limit);
+
+            // The remainder is yielded by subsequent calls
+            let (rest, _) = source.pop_chunk(usize::MAX);
+            assert_eq!(&rest[..], &full[limit..]);
+        }
+    }
+
     #[test]
     fn byte_slice() {
         let full = b"Hello World 123456789 ABCDEFGHJIJKLMNOPQRSTUVWXYZ".to_owned();
@@ -2,6 +2,7 @@ use std::{
     collections::{binary_heap::PeekMut, hash_map, BinaryHeap, VecDeque},
     convert::TryFrom,
     mem,
+    time::{Duration, Instant},
 };
 
 use bytes::BufMut;
@@ -9,8 +10,8 @@ use fxhash::FxHashMap;
 use tracing::{debug, trace};
 
 use super::{
-    push_pending, PendingLevel, Recv, Retransmits, Send, SendState, ShouldTransmit, StreamEvent,
-    StreamHalf, ThinRetransmits,
+    push_pending, PendingLevel, Recv, Retransmits, SchedulerKind, Send, SendState, ShouldTransmit,
+    StreamEvent, StreamHalf, ThinRetransmits,
 };
 use crate::{
     coding::BufMutExt,
@@ -30,6 +31,11 @@ pub struct StreamsState {
     pub(super) max: [u64; 2],
     // Maximum that can be remotely initiated
     max_remote: [u64; 2],
+    /// Desired number of concurrently open remotely initiated streams
+    max_concurrent_remote_count: [u64; 2],
+    /// Number of remotely initiated streams the peer may open or has open, i.e. that have been
+    /// allocated but not yet freed
+    allocated_remote_count: [u64; 2],
     // Lowest that hasn't actually been opened
     pub(super) next_remote: [u64; 2],
     /// Whether the remote endpoint has opened any streams the application doesn't know about yet,
@@ -44,16 +50,30 @@ pub struct StreamsState {
     pub(super) send_streams: usize,
     /// Streams with outgoing data queued
     pub(super) pending: BinaryHeap<PendingLevel>,
-
-    events: VecDeque<StreamEvent>,
+    /// Streams with outgoing data queued that are waiting for their rate limit to permit sending
+    pub(super) throttled: Vec<StreamId>,
+    /// Whether any stream has been given a rate limit, requiring `pending` to be checked for
+    /// throttled streams
+    pub(super) rate_limited: bool,
+    /// How streams of equal priority share transmission capacity
+    scheduler: SchedulerKind,
+    /// Send streams which have been given a delivery deadline
+    pub(super) deadlines: Vec<StreamId>,
+
+    pub(super) events: VecDeque<StreamEvent>,
     /// Streams blocked on connection-level flow control or stream window space
     ///
     /// Streams are only added to this list when a write fails.
     pub(super) connection_blocked: Vec<StreamId>,
     /// Connection-level flow control budget dictated by the peer
     pub(super) max_data: u64,
-    /// The initial receive window
+    /// The current connection-level receive window
     receive_window: u64,
+    /// Limit up to which `receive_window` may grow
+    max_receive_window: u64,
+    /// Time and amount of data consumed at which the current `receive_window` auto-tuning
+    /// measurement started
+    receive_window_epoch: Option<(Instant, u64)>,
     /// Limit on incoming data, which is transmitted through `MAX_DATA` frames
     local_max_data: u64,
     /// The last value of `MAX_DATA` which had been queued for transmission in
@@ -65,12 +85,18 @@ pub struct StreamsState {
     data_recvd: u64,
     /// Total quantity of unacknowledged outgoing data
     pub(super) unacked_data: u64,
-    /// Configured upper bound for `unacked_data`
+    /// Current upper bound for `unacked_data`
     pub(super) send_window: u64,
     /// Configured upper bound for how much unacked data the peer can send us per stream
     pub(super) stream_receive_window: u64,
+    /// Limit up to which per-stream receive windows may grow
+    max_stream_receive_window: u64,
     /// Whether the corresponding `max_remote` has increased
     max_streams_dirty: [bool; 2],
+    /// Total number of bytes buffered for reassembly across all receive streams
+    pub(super) reassembly_buffered: usize,
+    /// Upper bound for `reassembly_buffered`, if any
+    max_reassembly_buffered: Option<usize>,
 
     // Pertinent state from the TransportParameters supplied by the peer
     initial_max_stream_data_uni: VarInt,
@@ -85,7 +111,11 @@ impl StreamsState {
         max_remote_bi: VarInt,
         send_window: u64,
         receive_window: VarInt,
+        max_receive_window: Option<VarInt>,
         stream_receive_window: VarInt,
+        max_stream_receive_window: Option<VarInt>,
+        max_reassembly_buffered: Option<usize>,
+        scheduler: SchedulerKind,
     ) -> Self {
         let mut this = Self {
             side,
@@ -93,16 +123,26 @@ impl StreamsState {
             recv: FxHashMap::default(),
             next: [0, 0],
             max: [0, 0],
-            max_remote: [max_remote_bi.into(), max_remote_uni.into()],
+            max_remote: [0, 0],
+            max_concurrent_remote_count: [max_remote_bi.into(), max_remote_uni.into()],
+            allocated_remote_count: [0, 0],
             next_remote: [0, 0],
             opened: [false, false],
             next_reported_remote: [0, 0],
             send_streams: 0,
             pending: BinaryHeap::new(),
+            throttled: Vec::new(),
+            rate_limited: false,
+            scheduler,
+            deadlines: Vec::new(),
             events: VecDeque::new(),
             connection_blocked: Vec::new(),
             max_data: 0,
             receive_window: receive_window.into(),
+            max_receive_window: max_receive_window
+                .map_or(0, VarInt::into_inner)
+                .max(receive_window.into_inner()),
+            receive_window_epoch: None,
             local_max_data: receive_window.into(),
             sent_max_data: receive_window,
             data_sent: 0,
@@ -110,17 +150,21 @@ impl StreamsState {
             unacked_data: 0,
             send_window,
             stream_receive_window: stream_receive_window.into(),
+            max_stream_receive_window: max_stream_receive_window
+                .map_or(0, VarInt::into_inner)
+                .max(stream_receive_window.into_inner()),
             max_streams_dirty: [false, false],
+            reassembly_buffered: 0,
+            max_reassembly_buffered,
             initial_max_stream_data_uni: 0u32.into(),
             initial_max_stream_data_bidi_local: 0u32.into(),
             initial_max_stream_data_bidi_remote: 0u32.into(),
         };
 
         for dir in Dir::iter() {
-            for i in 0..this.max_remote[dir as usize] {
-                this.insert(true, StreamId::new(!side, dir, i));
-            }
+            this.ensure_remote_streams(dir);
         }
+        this.max_streams_dirty = [false, false];
 
         this
     }
@@ -139,10 +183,30 @@ impl StreamsState {
         }
     }
 
+    /// Set the number of remotely initiated streams of direction `dir` that may be open at once
+    ///
+    /// Additional stream ID credit is issued immediately if `count` exceeds the number of streams
+    /// currently allocated. Reducing `count` takes effect as already allocated streams are freed,
+    /// since credit already issued to the peer can't be revoked.
+    pub fn set_max_concurrent(&mut self, dir: Dir, count: VarInt) {
+        self.max_concurrent_remote_count[dir as usize] = count.into();
+        self.ensure_remote_streams(dir);
+    }
+
+    /// Allocate remote streams until `max_concurrent_remote_count` is reached
+    fn ensure_remote_streams(&mut self, dir: Dir) {
+        let new_count = self.max_concurrent_remote_count[dir as usize]
+            .saturating_sub(self.allocated_remote_count[dir as usize]);
+        for _ in 0..new_count {
+            self.alloc_remote_stream(dir);
+        }
+    }
+
     fn alloc_remote_stream(&mut self, dir: Dir) {
         self.max_remote[dir as usize] += 1;
         let id = StreamId::new(!self.side, dir, self.max_remote[dir as usize] - 1);
         self.insert(true, id);
+        self.allocated_remote_count[dir as usize] += 1;
         self.max_streams_dirty[dir as usize] = true;
     }
 
@@ -161,6 +225,8 @@ impl StreamsState {
             self.next[dir as usize] = 0;
         }
         self.pending.clear();
+        self.throttled.clear();
+        self.deadlines.clear();
         self.send_streams = 0;
         self.data_sent = 0;
         self.connection_blocked.clear();
@@ -173,6 +239,7 @@ impl StreamsState {
         &mut self,
         frame: frame::Stream,
         payload_len: usize,
+        pending: &mut Retransmits,
     ) -> Result<ShouldTransmit, TransportError> {
         let stream = frame.id;
         self.validate_receive_id(stream).map_err(|e| {
@@ -193,9 +260,20 @@ impl StreamsState {
             return Ok(ShouldTransmit(false));
         }
 
+        let buffered = rs.assembler.buffered();
         let (new_bytes, closed) =
             rs.ingest(frame, payload_len, self.data_recvd, self.local_max_data)?;
         self.data_recvd = self.data_recvd.saturating_add(new_bytes);
+        let reassembly_buffered = self.reassembly_buffered - buffered + rs.assembler.buffered();
+        self.reassembly_buffered = reassembly_buffered;
+        if matches!(self.max_reassembly_buffered, Some(max) if reassembly_buffered > max) {
+            debug!(
+                %stream,
+                buffered = reassembly_buffered,
+                "stream reassembly buffer limit exceeded, stopping stream"
+            );
+            return Ok(self.stop_over_limit(stream, pending));
+        }
 
         if !rs.stopped {
             self.on_stream_frame(true, stream);
@@ -212,6 +290,35 @@ impl StreamsState {
         Ok(self.add_read_credits(new_bytes))
     }
 
+    /// Stop a stream whose data pushed reassembly over `max_reassembly_buffered`
+    ///
+    /// The peer is asked to stop sending with error code 0 and the buffered data is discarded, as
+    /// if the application had called [`RecvStream::stop`](super::RecvStream::stop). Returns
+    /// whether a `MAX_DATA` frame needs to be transmitted.
+    fn stop_over_limit(&mut self, id: StreamId, pending: &mut Retransmits) -> ShouldTransmit {
+        let mut entry = match self.recv.entry(id) {
+            hash_map::Entry::Occupied(e) => e,
+            hash_map::Entry::Vacant(_) => unreachable!("stream was just written to"),
+        };
+        let stream = entry.get_mut();
+        let buffered = stream.assembler.buffered();
+        let (read_credits, stop_sending) = stream.stop().expect("stopped streams buffer no data");
+        if stop_sending.should_transmit() {
+            pending.stop_sending.push(frame::StopSending {
+                id,
+                error_code: 0u32.into(),
+            });
+        }
+        if !stream.receiving_unknown_size() {
+            entry.remove();
+            self.stream_freed(id, StreamHalf::Recv);
+        }
+        self.reassembly_buffered -= buffered;
+        // Let the application find out that the stream is gone
+        self.on_stream_frame(true, id);
+        self.add_read_credits(read_credits)
+    }
+
     /// Process incoming RESET_STREAM frame
     ///
     /// If successful, returns whether a `MAX_DATA` frame needs to be transmitted
@@ -238,6 +345,7 @@ impl StreamsState {
         };
 
         // State transition
+        let buffered = rs.assembler.buffered();
         if !rs.reset(
             error_code,
             final_offset,
@@ -247,6 +355,8 @@ impl StreamsState {
             // Redundant reset
             return Ok(ShouldTransmit(false));
         }
+        // Resetting discards all buffered data
+        self.reassembly_buffered -= buffered;
         let bytes_read = rs.assembler.bytes_read();
         let stopped = rs.stopped;
         let end = rs.end;
@@ -257,7 +367,7 @@ impl StreamsState {
         self.on_stream_frame(!stopped, id);
 
         // Update flow control
-        Ok(if bytes_read != final_offset.into() {
+        Ok(if bytes_read != u64::from(final_offset) {
             // bytes_read is always <= end, so this won't underflow.
             self.data_recvd = self
                 .data_recvd
@@ -294,6 +404,23 @@ impl StreamsState {
         }
     }
 
+    /// Whether any stream, in either direction, has yet to be fully closed
+    pub fn has_open_streams(&self) -> bool {
+        self.send.keys().chain(self.recv.keys()).any(|id| {
+            let next = if id.initiator() == self.side {
+                self.next
+            } else {
+                self.next_remote
+            };
+            id.index() < next[id.dir() as usize]
+        })
+    }
+
+    /// Whether the peer has yet to acknowledge data or the end of any send stream
+    pub fn has_undelivered_data(&self) -> bool {
+        self.send.values().any(|s| !s.is_delivered())
+    }
+
     pub fn can_send(&self) -> bool {
         self.pending
             .peek()
@@ -302,6 +429,8 @@ impl StreamsState {
 
     pub fn write_control_frames(
         &mut self,
+        now: Instant,
+        rtt: Duration,
         buf: &mut Vec<u8>,
         pending: &mut Retransmits,
         retransmits: &mut ThinRetransmits,
@@ -354,6 +483,7 @@ impl StreamsState {
         // MAX_DATA
         if pending.max_data && buf.len() + 9 < max_size {
             pending.max_data = false;
+            self.autotune_receive_window(now, rtt);
 
             // `local_max_data` can grow bigger than `VarInt`.
             // For transmission inside QUIC frames we need to clamp it to the
@@ -390,7 +520,8 @@ impl StreamsState {
             }
             retransmits.get_or_create().max_stream_data.insert(id);
 
-            let (max, _) = rs.max_stream_data(self.stream_receive_window);
+            rs.autotune_window(now, rtt, self.max_stream_receive_window);
+            let (max, _) = rs.max_stream_data();
             rs.record_sent_max_stream_data(max);
 
             trace!(stream = %id, max = max, "MAX_STREAM_DATA");
@@ -427,7 +558,133 @@ impl StreamsState {
         }
     }
 
-    pub fn write_stream_frames(&mut self, buf: &mut Vec<u8>, max_buf_size: usize) -> StreamMetaVec {
+    /// Move streams between `pending` and `throttled` according to their rate limits
+    ///
+    /// Must be called before deciding whether stream data can be sent, so that `pending` only
+    /// contains streams that are permitted to send.
+    pub fn update_throttled(&mut self, now: Instant) {
+        if !self.rate_limited {
+            return;
+        }
+
+        // Writes and retransmits may queue streams that are over their limit
+        let send = &mut self.send;
+        let throttled = &mut self.throttled;
+        let mut removed = false;
+        for level in self.pending.iter() {
+            level.queue.borrow_mut().retain(|id| {
+                let stream = match send.get_mut(id) {
+                    Some(s) => s,
+                    None => return true,
+                };
+                if let Some(ref mut limit) = stream.rate_limit {
+                    limit.refill(now);
+                }
+                if stream.is_throttled() {
+                    throttled.push(*id);
+                    removed = true;
+                    return false;
+                }
+                true
+            });
+        }
+        if removed {
+            self.pending = mem::take(&mut self.pending)
+                .into_iter()
+                .filter(|level| !level.queue.borrow().is_empty())
+                .collect();
+        }
+
+        let mut released = Vec::new();
+        self.throttled.retain(|id| {
+            let stream = match send.get_mut(id) {
+                Some(s) => s,
+                // Stream was reset with pending data and the reset was acknowledged
+                None => return false,
+            };
+            if let Some(ref mut limit) = stream.rate_limit {
+                limit.refill(now);
+            }
+            if stream.is_throttled() {
+                return true;
+            }
+            released.push((*id, stream.priority));
+            false
+        });
+        for (id, priority) in released {
+            push_pending(&mut self.pending, id, priority);
+        }
+    }
+
+    /// Reset streams whose delivery deadline has passed or which have stalled for too long
+    pub fn expire_deadlines(&mut self, now: Instant, pending: &mut Retransmits) {
+        if self.deadlines.is_empty() {
+            return;
+        }
+        let send = &mut self.send;
+        let unacked_data = &mut self.unacked_data;
+        let events = &mut self.events;
+        self.deadlines.retain(|&id| {
+            let stream = match send.get_mut(&id) {
+                Some(s) => s,
+                // Stream was finished and fully acknowledged in time
+                None => return false,
+            };
+            stream.update_stall(now);
+            let (error_code, reason) = match stream.deadline {
+                Some((deadline, error_code)) if deadline <= now => {
+                    (error_code, "delivery deadline passed")
+                }
+                _ => match stream.stall {
+                    Some(ref stall) if stream.stall_deadline().map_or(false, |x| x <= now) => {
+                        (stall.error_code, "stalled")
+                    }
+                    _ => return stream.deadline.is_some() || stream.stall.is_some(),
+                },
+            };
+            stream.deadline = None;
+            stream.stall = None;
+            if stream.is_reset() {
+                return false;
+            }
+            debug!(%id, reason, "expiring stream");
+            *unacked_data -= stream.pending.unacked();
+            stream.reset();
+            stream.expired = true;
+            pending.reset_stream.push((id, error_code));
+            events.push_back(StreamEvent::Expired { id });
+            false
+        });
+    }
+
+    /// The earliest delivery deadline or stall timeout of any stream
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines
+            .iter()
+            .filter_map(|id| self.send.get(id))
+            .flat_map(|s| {
+                s.deadline
+                    .map(|x| x.0)
+                    .into_iter()
+                    .chain(s.stall_deadline())
+            })
+            .min()
+    }
+
+    /// When the earliest throttled stream will be permitted to send again
+    pub fn next_unthrottle(&self) -> Option<Instant> {
+        self.throttled
+            .iter()
+            .filter_map(|id| self.send.get(id)?.rate_limit.as_ref()?.ready_at())
+            .min()
+    }
+
+    pub fn write_stream_frames(
+        &mut self,
+        buf: &mut Vec<u8>,
+        max_buf_size: usize,
+        now: Instant,
+    ) -> StreamMetaVec {
         let mut stream_frames = StreamMetaVec::new();
         while buf.len() + frame::Stream::SIZE_BOUND < max_buf_size {
             if max_buf_size
@@ -480,10 +737,22 @@ impl StreamsState {
                 stream.fin_pending = false;
             }
 
-            if stream.is_pending() {
+            let throttled = match stream.rate_limit {
+                Some(ref mut limit) => {
+                    limit.refill(now);
+                    limit.consume(offsets.end - offsets.start);
+                    stream.is_throttled()
+                }
+                None => false,
+            };
+
+            if stream.is_pending() && !throttled {
                 if level.priority == stream.priority {
                     // Enqueue for the same level
-                    level.queue.get_mut().push_back(id);
+                    match self.scheduler {
+                        SchedulerKind::RoundRobin => level.queue.get_mut().push_back(id),
+                        SchedulerKind::Sequential => level.queue.get_mut().push_front(id),
+                    }
                 } else {
                     // Enqueue for a different level. If the current level is empty, drop it
                     if level.queue.borrow().is_empty() && num_levels != 1 {
@@ -495,10 +764,15 @@ impl StreamsState {
                     }
                     push_pending(&mut self.pending, id, stream.priority);
                 }
-            } else if level.queue.borrow().is_empty() && num_levels != 1 {
-                // We keep the last level around even in empty form so that
-                // the next insert doesn't have to reallocate the queue
-                PeekMut::pop(level);
+            } else {
+                if stream.is_pending() {
+                    self.throttled.push(id);
+                }
+                if level.queue.borrow().is_empty() && num_levels != 1 {
+                    // We keep the last level around even in empty form so that
+                    // the next insert doesn't have to reallocate the queue
+                    PeekMut::pop(level);
+                }
             }
 
             let meta = frame::StreamMeta { id, offsets, fin };
@@ -550,7 +824,11 @@ impl StreamsState {
         }
         let id = frame.id;
         self.unacked_data -= frame.offsets.end - frame.offsets.start;
-        if !stream.ack(frame) {
+        let finished = stream.ack(frame);
+        if stream.take_acked_notification() {
+            self.events.push_back(StreamEvent::Acked { id });
+        }
+        if !finished {
             // The stream is unfinished or may still need retransmits
             return;
         }
@@ -570,6 +848,7 @@ impl StreamsState {
             push_pending(&mut self.pending, frame.id, stream.priority);
         }
         stream.fin_pending |= frame.fin;
+        stream.retransmitted += frame.offsets.end - frame.offsets.start;
         stream.pending.retransmit(frame.offsets);
     }
 
@@ -650,7 +929,15 @@ impl StreamsState {
 
     /// Returns the maximum amount of data this is allowed to be written on the connection
     pub fn write_limit(&self) -> u64 {
-        (self.max_data - self.data_sent).min(self.send_window - self.unacked_data)
+        (self.max_data - self.data_sent).min(self.send_window.saturating_sub(self.unacked_data))
+    }
+
+    /// Change the limit on unacknowledged outgoing data
+    ///
+    /// Data already sent in excess of a reduced limit is unaffected; further writes block until
+    /// enough of it has been acknowledged.
+    pub fn set_send_window(&mut self, value: u64) {
+        self.send_window = value;
     }
 
     /// Yield stream events
@@ -760,6 +1047,35 @@ impl StreamsState {
         ShouldTransmit(diff >= (self.receive_window / 8))
     }
 
+    /// Grow the connection-level receive window if the application is consuming it quickly
+    ///
+    /// Follows the same approach as the per-stream windows: if a full window's worth of data was
+    /// consumed in less than two round trips, the window is likely what limits throughput, so it's
+    /// doubled up to `max_receive_window`.
+    fn autotune_receive_window(&mut self, now: Instant, rtt: Duration) {
+        let consumed = self.local_max_data.saturating_sub(self.receive_window);
+        match self.receive_window_epoch {
+            Some((start, offset)) if consumed - offset >= self.receive_window => {
+                if self.receive_window < self.max_receive_window
+                    && now.saturating_duration_since(start) < 2 * rtt
+                {
+                    let window = self
+                        .receive_window
+                        .saturating_mul(2)
+                        .min(self.max_receive_window);
+                    self.local_max_data = self
+                        .local_max_data
+                        .saturating_add(window - self.receive_window);
+                    self.receive_window = window;
+                    trace!(window, "connection receive window increased");
+                }
+                self.receive_window_epoch = Some((now, consumed));
+            }
+            Some(_) => {}
+            None => self.receive_window_epoch = Some((now, consumed)),
+        }
+    }
+
     /// Update counters for removal of a stream
     pub(super) fn stream_freed(&mut self, id: StreamId, half: StreamHalf) {
         if id.initiator() != self.side {
@@ -769,7 +1085,8 @@ impl StreamsState {
                     StreamHalf::Recv => !self.send.contains_key(&id),
                 };
             if fully_free {
-                self.alloc_remote_stream(id.dir());
+                self.allocated_remote_count[id.dir() as usize] -= 1;
+                self.ensure_remote_streams(id.dir());
             }
         }
         if half == StreamHalf::Send {
@@ -794,7 +1111,11 @@ mod tests {
             128u32.into(),
             1024 * 1024,
             (1024 * 1024u32).into(),
+            None,
             (1024 * 1024u32).into(),
+            None,
+            None,
+            SchedulerKind::RoundRobin,
         )
     }
 
@@ -813,7 +1134,8 @@ mod tests {
                         fin: true,
                         data: Bytes::from_static(&[0; MESSAGE_SIZE]),
                     },
-                    2048
+                    2048,
+                    &mut Retransmits::default()
                 )
                 .unwrap(),
             ShouldTransmit(false)
@@ -854,7 +1176,8 @@ mod tests {
                         fin: false,
                         data: Bytes::from_static(&[0; 2048]),
                     },
-                    2048
+                    2048,
+                    &mut Retransmits::default()
                 )
                 .unwrap(),
             ShouldTransmit(false)
@@ -917,7 +1240,8 @@ mod tests {
                         fin: false,
                         data: Bytes::from_static(&[0; 0]),
                     },
-                    0
+                    0,
+                    &mut Retransmits::default()
                 )
                 .unwrap(),
             ShouldTransmit(false)
@@ -980,7 +1304,8 @@ mod tests {
                         fin: false,
                         data: Bytes::from_static(&[0; 32]),
                     },
-                    32
+                    32,
+                    &mut Retransmits::default()
                 )
                 .unwrap(),
             ShouldTransmit(false)
@@ -1012,7 +1337,8 @@ mod tests {
                         fin: true,
                         data: Bytes::from_static(&[0; 16]),
                     },
-                    16
+                    16,
+                    &mut Retransmits::default()
                 )
                 .unwrap(),
             ShouldTransmit(false)
@@ -1035,7 +1361,8 @@ mod tests {
                         fin: false,
                         data: Bytes::from_static(&[0; 32])
                     },
-                    32
+                    32,
+                    &mut Retransmits::default()
                 )
                 .unwrap(),
             ShouldTransmit(false)
@@ -1125,6 +1452,150 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reassembly_buffer_limit() {
+        let mut client = make(Side::Client);
+        client.max_reassembly_buffered = Some(4096);
+        let id = |i| StreamId::new(Side::Server, Dir::Uni, i);
+        let frame = |i, offset| frame::Stream {
+            id: id(i),
+            offset,
+            fin: false,
+            data: Bytes::from_static(&[0; 1024]),
+        };
+
+        // Gapped data on two streams
+        let _ = client
+            .received(frame(0, 1024), 1024, &mut Retransmits::default())
+            .unwrap();
+        let _ = client
+            .received(frame(1, 1024), 1024, &mut Retransmits::default())
+            .unwrap();
+        let _ = client
+            .received(frame(0, 0), 1024, &mut Retransmits::default())
+            .unwrap();
+        assert_eq!(client.reassembly_buffered, 3072);
+
+        // Reading and stopping release buffer space
+        let mut pending = Retransmits::default();
+        let mut recv = RecvStream {
+            id: id(0),
+            state: &mut client,
+            pending: &mut pending,
+        };
+        let mut chunks = recv.read(true).unwrap();
+        while let Ok(Some(_)) = chunks.next(usize::MAX) {}
+        let _ = chunks.finalize();
+        assert_eq!(client.reassembly_buffered, 1024);
+        let mut recv = RecvStream {
+            id: id(1),
+            state: &mut client,
+            pending: &mut pending,
+        };
+        recv.stop(0u32.into()).unwrap();
+        assert_eq!(client.reassembly_buffered, 0);
+
+        // Spreading gapped data over more streams doesn't get around the limit
+        for i in 2..6 {
+            let _ = client
+                .received(frame(i, 1024), 1024, &mut Retransmits::default())
+                .unwrap();
+        }
+
+        // The stream that exceeds the limit is stopped rather than the connection closed
+        let mut pending = Retransmits::default();
+        let _ = client.received(frame(6, 1024), 1024, &mut pending).unwrap();
+        assert_eq!(client.reassembly_buffered, 4096);
+        assert_eq!(pending.stop_sending.len(), 1);
+        assert_eq!(pending.stop_sending[0].id, id(6));
+        assert!(client.recv[&id(6)].stopped);
+
+        // Further data on the stopped stream is credited without being buffered
+        let credit = client.local_max_data;
+        let _ = client
+            .received(frame(6, 2048), 1024, &mut Retransmits::default())
+            .unwrap();
+        assert_eq!(client.reassembly_buffered, 4096);
+        assert_eq!(client.local_max_data - credit, 1024);
+    }
+
+    #[test]
+    fn stream_receive_window_autotuning() {
+        let mut client = StreamsState::new(
+            Side::Client,
+            128u32.into(),
+            128u32.into(),
+            1024 * 1024,
+            (1024 * 1024u32).into(),
+            None,
+            1024u32.into(),
+            Some(4096u32.into()),
+            None,
+            SchedulerKind::RoundRobin,
+        );
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        let rtt = Duration::from_millis(100);
+        let start = Instant::now();
+
+        // Deliver and read `len` more bytes, then return the stream credit issued at `now`
+        let mut offset = 0;
+        let mut exchange = |client: &mut StreamsState, len: usize, now: Instant| {
+            let _ = client
+                .received(
+                    frame::Stream {
+                        id,
+                        offset,
+                        fin: false,
+                        data: vec![0; len].into(),
+                    },
+                    len,
+                    &mut Retransmits::default(),
+                )
+                .unwrap();
+            offset += len as u64;
+            let mut pending = Retransmits::default();
+            let mut recv = RecvStream {
+                id,
+                state: client,
+                pending: &mut pending,
+            };
+            let mut chunks = recv.read(true).unwrap();
+            while let Ok(Some(_)) = chunks.next(usize::MAX) {}
+            let _ = chunks.finalize();
+
+            let mut buf = Vec::new();
+            client.write_control_frames(
+                now,
+                rtt,
+                &mut buf,
+                &mut pending,
+                &mut ThinRetransmits::default(),
+                &mut FrameStats::default(),
+                usize::MAX,
+            );
+            frame::Iter::new(buf.into())
+                .find_map(|frame| match frame {
+                    frame::Frame::MaxStreamData { id: x, offset } if x == id => Some(offset),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // The first window read starts the measurement
+        assert_eq!(exchange(&mut client, 1024, start), 1024 + 1024);
+        // A window read within two round trips doubles the window
+        let now = start + Duration::from_millis(10);
+        assert_eq!(exchange(&mut client, 1024, now), 2048 + 2048);
+        // A window read slowly leaves it unchanged
+        let now = start + Duration::from_secs(1);
+        assert_eq!(exchange(&mut client, 2048, now), 4096 + 2048);
+        // Growth stops at the configured maximum
+        let now = now + Duration::from_millis(10);
+        assert_eq!(exchange(&mut client, 2048, now), 6144 + 4096);
+        let now = now + Duration::from_millis(10);
+        assert_eq!(exchange(&mut client, 4096, now), 10240 + 4096);
+    }
+
     #[test]
     fn stream_priority() {
         let mut server = make(Side::Server);
@@ -1172,7 +1643,7 @@ mod tests {
         high.write(b"high").unwrap();
 
         let mut buf = Vec::with_capacity(40);
-        let meta = server.write_stream_frames(&mut buf, 40);
+        let meta = server.write_stream_frames(&mut buf, 40, Instant::now());
         assert_eq!(meta[0].id, id_high);
         assert_eq!(meta[1].id, id_mid);
         assert_eq!(meta[2].id, id_low);
@@ -1181,6 +1652,93 @@ mod tests {
         assert_eq!(server.pending.len(), 1);
     }
 
+    #[test]
+    fn same_priority_round_robin() {
+        let mut server = make(Side::Server);
+        server.set_params(&TransportParameters {
+            initial_max_streams_bidi: 2u32.into(),
+            initial_max_data: 100u32.into(),
+            initial_max_stream_data_bidi_remote: 100u32.into(),
+            ..Default::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let ids = [
+            streams.open(Dir::Bi).unwrap(),
+            streams.open(Dir::Bi).unwrap(),
+        ];
+        for &id in &ids {
+            let mut stream = SendStream {
+                id,
+                state: &mut server,
+                pending: &mut pending,
+                conn_state: &state,
+            };
+            stream.set_priority(1).unwrap();
+            stream.write(&[0; 40]).unwrap();
+        }
+
+        // Streams of equal priority take turns, one frame at a time
+        let max_size = frame::Stream::SIZE_BOUND + 8;
+        for i in 0..4 {
+            let mut buf = Vec::with_capacity(max_size);
+            let meta = server.write_stream_frames(&mut buf, max_size, Instant::now());
+            assert_eq!(meta.len(), 1);
+            assert_eq!(meta[0].id, ids[i % 2]);
+        }
+    }
+
+    #[test]
+    fn sequential_scheduler() {
+        let mut server = make(Side::Server);
+        server.scheduler = SchedulerKind::Sequential;
+        server.set_params(&TransportParameters {
+            initial_max_streams_bidi: 2u32.into(),
+            initial_max_data: 100u32.into(),
+            initial_max_stream_data_bidi_remote: 100u32.into(),
+            ..Default::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let ids = [
+            streams.open(Dir::Bi).unwrap(),
+            streams.open(Dir::Bi).unwrap(),
+        ];
+        for &id in &ids {
+            let mut stream = SendStream {
+                id,
+                state: &mut server,
+                pending: &mut pending,
+                conn_state: &state,
+            };
+            stream.write(&[0; 40]).unwrap();
+        }
+
+        // The first stream is drained before the second is touched
+        let max_size = frame::Stream::SIZE_BOUND + 8;
+        let mut sent = Vec::new();
+        loop {
+            let mut buf = Vec::with_capacity(max_size);
+            let meta = server.write_stream_frames(&mut buf, max_size, Instant::now());
+            if meta.is_empty() {
+                break;
+            }
+            sent.extend(meta.iter().map(|m| m.id));
+        }
+        let boundary = sent.iter().position(|&id| id == ids[1]).unwrap();
+        assert!(boundary > 1);
+        assert!(sent[..boundary].iter().all(|&id| id == ids[0]));
+        assert!(sent[boundary..].iter().all(|&id| id == ids[1]));
+    }
+
     #[test]
     fn requeue_stream_priority() {
         let mut server = make(Side::Server);
@@ -1231,7 +1789,7 @@ mod tests {
         high.set_priority(-1).unwrap();
 
         let mut buf = Vec::with_capacity(1000);
-        let meta = server.write_stream_frames(&mut buf, 40);
+        let meta = server.write_stream_frames(&mut buf, 40, Instant::now());
         assert_eq!(meta.len(), 1);
         assert_eq!(meta[0].id, id_high);
 
@@ -1239,7 +1797,7 @@ mod tests {
         assert_eq!(server.pending.len(), 2);
 
         // Send the remaining data. The initial mid priority one should go first now
-        let meta = server.write_stream_frames(&mut buf, 1000);
+        let meta = server.write_stream_frames(&mut buf, 1000, Instant::now());
         assert_eq!(meta.len(), 2);
         assert_eq!(meta[0].id, id_mid);
         assert_eq!(meta[1].id, id_high);
@@ -1248,6 +1806,82 @@ mod tests {
         assert_eq!(server.pending.len(), 1);
     }
 
+    #[test]
+    fn stream_rate_limit() {
+        let mut server = make(Side::Server);
+        server.set_params(&TransportParameters {
+            initial_max_streams_bidi: 2u32.into(),
+            initial_max_data: 100_000u32.into(),
+            initial_max_stream_data_bidi_remote: 100_000u32.into(),
+            ..Default::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let id_limited = streams.open(Dir::Bi).unwrap();
+        let id_free = streams.open(Dir::Bi).unwrap();
+
+        let mut limited = SendStream {
+            id: id_limited,
+            state: &mut server,
+            pending: &mut pending,
+            conn_state: &state,
+        };
+        // Permits a burst of 1000 bytes
+        limited.set_max_rate(Some(20_000)).unwrap();
+        assert_eq!(limited.max_rate().unwrap(), Some(20_000));
+        limited.write(&[0; 5000]).unwrap();
+        let mut free = SendStream {
+            id: id_free,
+            state: &mut server,
+            pending: &mut pending,
+            conn_state: &state,
+        };
+        free.write(&[0; 5000]).unwrap();
+
+        let now = Instant::now();
+        let mut sent = [0; 2];
+        for _ in 0..10 {
+            server.update_throttled(now);
+            let mut buf = Vec::with_capacity(1200);
+            for meta in server.write_stream_frames(&mut buf, 1200, now) {
+                let len = meta.offsets.end - meta.offsets.start;
+                sent[(meta.id == id_free) as usize] += len;
+            }
+        }
+        // The limited stream sends a single frame before running out of credit, while the other
+        // stream is unaffected
+        assert!(sent[0] > 0 && sent[0] < 1200);
+        assert_eq!(sent[1], 5000);
+        assert!(!server.can_send());
+
+        // Once enough time has passed for the debt to be repaid, the stream may send again
+        let ready = server.next_unthrottle().unwrap();
+        let debt = sent[0] - 1000;
+        assert!(ready > now + Duration::from_micros(debt * 50));
+        server.update_throttled(ready - Duration::from_micros(1));
+        assert!(!server.can_send());
+        server.update_throttled(ready);
+        assert!(server.can_send());
+        assert_eq!(server.next_unthrottle(), None);
+
+        // Removing the limit releases a throttled stream immediately
+        let mut buf = Vec::with_capacity(1200);
+        server.write_stream_frames(&mut buf, 1200, ready);
+        assert!(!server.can_send());
+        let mut limited = SendStream {
+            id: id_limited,
+            state: &mut server,
+            pending: &mut pending,
+            conn_state: &state,
+        };
+        limited.set_max_rate(None).unwrap();
+        assert!(server.can_send());
+    }
+
     #[test]
     fn stop_finished() {
         let mut client = make(Side::Client);
@@ -1262,6 +1896,7 @@ mod tests {
                     data: Bytes::from_static(&[0; 32]),
                 },
                 32,
+                &mut Retransmits::default(),
             )
             .unwrap();
         let mut pending = Retransmits::default();
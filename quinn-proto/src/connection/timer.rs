@@ -18,10 +18,18 @@ pub(crate) enum Timer {
     Pacing = 6,
     /// When to invalidate old CID and proactively push new one via NEW_CONNECTION_ID frame
     PushNewCid = 7,
+    /// When to give up on a connection whose handshake has not completed
+    Handshake = 8,
+    /// When a stream's rate limit will allow it to send more data
+    StreamRate = 9,
+    /// When a stream's delivery deadline passes
+    StreamDeadline = 10,
+    /// When delayed ACKs must be sent to honor the peer's requested maximum ACK delay
+    MaxAckDelay = 11,
 }
 
 impl Timer {
-    pub(crate) const VALUES: [Self; 8] = [
+    pub(crate) const VALUES: [Self; 12] = [
         Timer::LossDetection,
         Timer::Idle,
         Timer::Close,
@@ -30,13 +38,17 @@ impl Timer {
         Timer::KeepAlive,
         Timer::Pacing,
         Timer::PushNewCid,
+        Timer::Handshake,
+        Timer::StreamRate,
+        Timer::StreamDeadline,
+        Timer::MaxAckDelay,
     ];
 }
 
 /// A table of data associated with each distinct kind of `Timer`
 #[derive(Debug, Copy, Clone, Default)]
 pub(crate) struct TimerTable {
-    data: [Option<Instant>; 8],
+    data: [Option<Instant>; 12],
 }
 
 impl TimerTable {
@@ -52,6 +52,30 @@ pub trait Session: Send + 'static {
     /// If the 0-RTT-encrypted data has been accepted by the peer
     fn early_data_accepted(&self) -> Option<bool>;
 
+    /// Whether 0-RTT data the server rejected may be retransmitted once the handshake completes
+    /// (clients only)
+    ///
+    /// Early data must not be resent if the negotiated application protocol differs from the one
+    /// the resumed session was established with (RFC 9001 §4.6.2). The default implementation
+    /// can't tell, so rejected data is discarded.
+    fn early_data_replayable(&self) -> bool {
+        false
+    }
+
+    /// Refuse any 0-RTT data the client offers (servers only)
+    ///
+    /// Must be called before the client's first handshake message is read. The default
+    /// implementation does nothing, so sessions that can't refuse early data accept it as before.
+    fn reject_early_data(&mut self) {}
+
+    /// Whether the session resumed an earlier one instead of performing a full handshake
+    ///
+    /// Only meaningful once the handshake has completed. The default implementation reports no
+    /// resumption.
+    fn is_resumed(&self) -> bool {
+        false
+    }
+
     /// Returns `true` until the connection is fully established.
     fn is_handshaking(&self) -> bool;
 
@@ -113,9 +137,10 @@ pub struct Keys {
 
 /// Client-side configuration for the crypto protocol
 pub trait ClientConfig: Send + Sync {
-    /// Start a client session with this configuration
+    /// Start a client session with this configuration, speaking QUIC `version`
     fn start_session(
         self: Arc<Self>,
+        version: u32,
         server_name: &str,
         params: &TransportParameters,
     ) -> Result<Box<dyn Session>, ConnectError>;
@@ -123,14 +148,19 @@ pub trait ClientConfig: Send + Sync {
 
 /// Server-side configuration for the crypto protocol
 pub trait ServerConfig: Send + Sync {
-    /// Start a server session with this configuration
-    fn start_session(self: Arc<Self>, params: &TransportParameters) -> Box<dyn Session>;
+    /// Start a server session with this configuration, speaking QUIC `version`
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn Session>;
 
-    /// Create the initial set of keys given the client's initial destination ConnectionId
-    fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> Keys;
+    /// Create the initial set of keys for QUIC `version` given the client's initial destination
+    /// ConnectionId
+    fn initial_keys(&self, version: u32, dst_cid: &ConnectionId, side: Side) -> Keys;
 
-    /// Generate the integrity tag for a retry packet
-    fn retry_tag(&self, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16];
+    /// Generate the integrity tag for a retry packet of QUIC `version`
+    fn retry_tag(&self, version: u32, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16];
 }
 
 /// Keys used to protect packet payloads
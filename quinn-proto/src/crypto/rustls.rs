@@ -1,7 +1,12 @@
-use std::{any::Any, convert::TryInto, io, str, sync::Arc};
+use std::{
+    any::Any,
+    convert::TryInto,
+    io, str,
+    sync::{Arc, Mutex},
+};
 
 use bytes::BytesMut;
-use ring::aead;
+use ring::{aead, digest};
 pub use rustls::Error;
 use rustls::{
     self,
@@ -15,15 +20,27 @@ use rustls::{
 use crate::{
     crypto::{self, CryptoError, ExportKeyingMaterialError, HeaderKey, KeyPair, Keys},
     transport_parameters::TransportParameters,
-    CertificateChain, ConnectError, ConnectionId, PrivateKey, Side, TransportError,
+    CertificateChain, ConnectError, ConnectionId, PrivateKey, SessionCache, Side, TransportError,
     TransportErrorCode,
 };
 
+mod revocation;
+pub use revocation::{CertificateRevocationList, RevocationCheck, RevocationVerifier};
+mod transparency;
+pub use transparency::{SctPolicy, SctVerifier};
+mod verify;
+pub use verify::{spki_sha256, SpkiPinningVerifier, WebPkiServerVerifier};
+
 /// A rustls TLS session
 pub struct TlsSession {
     using_alpn: bool,
     got_handshake_data: bool,
     next_secrets: Option<Secrets>,
+    /// Handshake messages received from the server, for clients
+    server_messages: MessageScanner,
+    /// Application protocols of the session tickets received, for clients
+    tickets: Option<TicketProtocols>,
+    version: Version,
     inner: Connection,
 }
 
@@ -36,9 +53,116 @@ impl TlsSession {
     }
 }
 
+/// Tracks the types of the TLS handshake messages in a stream of handshake data
+#[derive(Default)]
+struct MessageScanner {
+    /// Partially received header of the next message
+    header: [u8; 4],
+    header_len: usize,
+    /// Bytes left in the body of the current message
+    remaining: usize,
+    /// Type of the current message
+    ty: u8,
+    /// Whether a `Certificate` or `CompressedCertificate` message was seen
+    saw_certificate: bool,
+    /// Whether a complete `NewSessionTicket` message was seen since this was last cleared
+    saw_ticket: bool,
+}
+
+impl MessageScanner {
+    fn scan(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(buf.len());
+                self.remaining -= n;
+                buf = &buf[n..];
+                self.saw_ticket |= self.remaining == 0 && self.ty == NEW_SESSION_TICKET;
+                continue;
+            }
+            let n = (self.header.len() - self.header_len).min(buf.len());
+            self.header[self.header_len..self.header_len + n].copy_from_slice(&buf[..n]);
+            self.header_len += n;
+            buf = &buf[n..];
+            if self.header_len == self.header.len() {
+                self.header_len = 0;
+                self.ty = self.header[0];
+                self.remaining = self.header[1..]
+                    .iter()
+                    .fold(0, |acc, &x| acc << 8 | usize::from(x));
+                self.saw_certificate |= self.ty == CERTIFICATE || self.ty == COMPRESSED_CERTIFICATE;
+            }
+        }
+    }
+}
+
+const NEW_SESSION_TICKET: u8 = 4;
+const CERTIFICATE: u8 = 11;
+const COMPRESSED_CERTIFICATE: u8 = 25;
+
+/// Records the application protocol each session ticket was issued for
+///
+/// rustls doesn't keep the protocol with client tickets, but early data may only be retransmitted
+/// under the protocol it was written for (RFC 9001 §4.6.2). The protocol of the latest ticket for
+/// a server name is kept in the session storage alongside a digest of the server's transport
+/// parameters, which rustls stores with each ticket and hands back when resuming from it.
+struct TicketProtocols {
+    storage: Arc<dyn rustls::client::StoresClientSessions>,
+    /// Key the protocol of the latest ticket is stored under
+    key: Vec<u8>,
+    /// Protocol of the ticket the connection is resuming with, if known
+    resumed: Option<Option<Vec<u8>>>,
+}
+
+impl TicketProtocols {
+    /// `resumed_params` are the server transport parameters remembered from the ticket the
+    /// connection is resuming with, if any
+    fn new(
+        config: &rustls::ClientConfig,
+        server_name: &str,
+        resumed_params: Option<&[u8]>,
+    ) -> Self {
+        let storage = config.session_storage.clone();
+        let key = [&b"quinn-alpn:"[..], server_name.as_bytes()].concat();
+        let resumed = match (resumed_params, storage.get(&key)) {
+            (Some(params), Some(record))
+                if record.len() >= DIGEST_LEN
+                    && record[..DIGEST_LEN]
+                        == *digest::digest(&digest::SHA256, params).as_ref() =>
+            {
+                let protocol = &record[DIGEST_LEN..];
+                Some(if protocol.is_empty() {
+                    None
+                } else {
+                    Some(protocol.to_vec())
+                })
+            }
+            _ => None,
+        };
+        Self {
+            storage,
+            key,
+            resumed,
+        }
+    }
+
+    /// Note that the ticket just received, carrying the server transport parameters `params`, was
+    /// issued for `protocol`
+    fn record(&self, params: Option<&[u8]>, protocol: Option<&[u8]>) {
+        let params = match params {
+            Some(x) => x,
+            None => return,
+        };
+        let mut record = digest::digest(&digest::SHA256, params).as_ref().to_vec();
+        record.extend_from_slice(protocol.unwrap_or(&[]));
+        self.storage.put(self.key.clone(), record);
+    }
+}
+
+const DIGEST_LEN: usize = 32;
+
 impl crypto::Session for TlsSession {
     fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> Keys {
-        initial_keys(dst_cid, side)
+        initial_keys(self.version, dst_cid, side)
     }
 
     fn handshake_data(&self) -> Option<Box<dyn Any>> {
@@ -72,13 +196,47 @@ impl crypto::Session for TlsSession {
         }
     }
 
+    fn early_data_replayable(&self) -> bool {
+        match self.tickets.as_ref().and_then(|x| x.resumed.as_ref()) {
+            Some(protocol) => protocol.as_deref() == self.inner.alpn_protocol(),
+            None => false,
+        }
+    }
+
+    fn reject_early_data(&mut self) {
+        if let Connection::Server(ref mut session) = self.inner {
+            session.reject_early_data();
+        }
+    }
+
+    fn is_resumed(&self) -> bool {
+        match self.inner {
+            // rustls doesn't report this to clients, but servers only send a certificate during a
+            // full handshake
+            Connection::Client(_) => {
+                !self.inner.is_handshaking() && !self.server_messages.saw_certificate
+            }
+            Connection::Server(ref session) => session.received_resumption_data().is_some(),
+        }
+    }
+
     fn is_handshaking(&self) -> bool {
         self.inner.is_handshaking()
     }
 
     fn read_handshake(&mut self, buf: &[u8]) -> Result<bool, TransportError> {
+        if let Connection::Client(_) = self.inner {
+            self.server_messages.scan(buf);
+        }
+        revocation::take_revoked();
         self.inner.read_hs(buf).map_err(|e| {
-            if let Some(alert) = self.inner.alert() {
+            if revocation::take_revoked() {
+                TransportError {
+                    code: TransportErrorCode::CERTIFICATE_REVOKED,
+                    frame: None,
+                    reason: e.to_string(),
+                }
+            } else if let Some(alert) = self.inner.alert() {
                 TransportError {
                     code: TransportErrorCode::crypto(alert.get_u8()),
                     frame: None,
@@ -88,6 +246,15 @@ impl crypto::Session for TlsSession {
                 TransportError::PROTOCOL_VIOLATION(format!("TLS error: {}", e))
             }
         })?;
+        if self.server_messages.saw_ticket {
+            self.server_messages.saw_ticket = false;
+            if let Some(ref tickets) = self.tickets {
+                tickets.record(
+                    self.inner.quic_transport_parameters(),
+                    self.inner.alpn_protocol(),
+                );
+            }
+        }
         if !self.got_handshake_data {
             // Hack around the lack of an explicit signal from rustls to reflect ClientHello being
             // ready on incoming connections, or ALPN negotiation completing on outgoing
@@ -166,11 +333,7 @@ impl crypto::Session for TlsSession {
         let tag_start = tag_start + pseudo_packet.len();
         pseudo_packet.extend_from_slice(payload);
 
-        let nonce = aead::Nonce::assume_unique_for_key(RETRY_INTEGRITY_NONCE);
-        let key = aead::LessSafeKey::new(
-            aead::UnboundKey::new(&aead::AES_128_GCM, &RETRY_INTEGRITY_KEY).unwrap(),
-        );
-
+        let (nonce, key) = retry_integrity_key(self.version);
         let (aad, tag) = pseudo_packet.split_at_mut(tag_start);
         key.open_in_place(nonce, aead::Aad::from(aad), tag).is_ok()
     }
@@ -187,12 +350,29 @@ impl crypto::Session for TlsSession {
     }
 }
 
-const RETRY_INTEGRITY_KEY: [u8; 16] = [
+fn retry_integrity_key(version: Version) -> (aead::Nonce, aead::LessSafeKey) {
+    let (key, nonce) = match version {
+        Version::V1 => (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
+        _ => (&RETRY_INTEGRITY_KEY_DRAFT, RETRY_INTEGRITY_NONCE_DRAFT),
+    };
+    (
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, key).unwrap()),
+    )
+}
+
+const RETRY_INTEGRITY_KEY_DRAFT: [u8; 16] = [
     0xcc, 0xce, 0x18, 0x7e, 0xd0, 0x9a, 0x09, 0xd0, 0x57, 0x28, 0x15, 0x5a, 0x6c, 0xb9, 0x6b, 0xe1,
 ];
-const RETRY_INTEGRITY_NONCE: [u8; 12] = [
+const RETRY_INTEGRITY_NONCE_DRAFT: [u8; 12] = [
     0xe5, 0x49, 0x30, 0xf9, 0x7f, 0x21, 0x36, 0xf0, 0x53, 0x0a, 0x8c, 0x1c,
 ];
+const RETRY_INTEGRITY_KEY_V1: [u8; 16] = [
+    0xbe, 0x0c, 0x69, 0x0b, 0x9f, 0x66, 0x57, 0x5a, 0x1d, 0x76, 0x6b, 0x54, 0xe3, 0x68, 0xc8, 0x4e,
+];
+const RETRY_INTEGRITY_NONCE_V1: [u8; 12] = [
+    0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
+];
 
 impl crypto::HeaderKey for HeaderProtectionKey {
     fn decrypt(&self, pn_offset: usize, packet: &mut [u8]) {
@@ -239,55 +419,69 @@ pub struct HandshakeData {
 impl crypto::ClientConfig for rustls::ClientConfig {
     fn start_session(
         self: Arc<Self>,
+        version: u32,
         server_name: &str,
         params: &TransportParameters,
     ) -> Result<Box<dyn crypto::Session>, ConnectError> {
+        let version = interpret_version(version);
+        // Accept IPv6 addresses in the bracketed form used in URLs
+        let name = server_name
+            .strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .unwrap_or(server_name);
+        let inner = rustls::ClientConnection::new_quic(
+            self.clone(),
+            version,
+            name.try_into()
+                .map_err(|_| ConnectError::InvalidDnsName(server_name.into()))?,
+            to_vec(params),
+        )
+        .unwrap();
+        // When resuming, rustls exposes the server parameters remembered with the ticket
+        let tickets = TicketProtocols::new(&self, name, inner.quic_transport_parameters());
         Ok(Box::new(TlsSession {
             using_alpn: !self.alpn_protocols.is_empty(),
             got_handshake_data: false,
             next_secrets: None,
-            inner: Connection::Client(
-                rustls::ClientConnection::new_quic(
-                    self,
-                    Version::V1Draft,
-                    server_name
-                        .try_into()
-                        .map_err(|_| ConnectError::InvalidDnsName(server_name.into()))?,
-                    to_vec(params),
-                )
-                .unwrap(),
-            ),
+            server_messages: MessageScanner::default(),
+            tickets: Some(tickets),
+            version,
+            inner: Connection::Client(inner),
         }))
     }
 }
 
 impl crypto::ServerConfig for rustls::ServerConfig {
-    fn start_session(self: Arc<Self>, params: &TransportParameters) -> Box<dyn crypto::Session> {
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn crypto::Session> {
+        let version = interpret_version(version);
         Box::new(TlsSession {
             using_alpn: !self.alpn_protocols.is_empty(),
             got_handshake_data: false,
             next_secrets: None,
+            server_messages: MessageScanner::default(),
+            tickets: None,
+            version,
             inner: Connection::Server(
-                rustls::ServerConnection::new_quic(self, Version::V1Draft, to_vec(params)).unwrap(),
+                rustls::ServerConnection::new_quic(self, version, to_vec(params)).unwrap(),
             ),
         })
     }
 
-    fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> Keys {
-        initial_keys(dst_cid, side)
+    fn initial_keys(&self, version: u32, dst_cid: &ConnectionId, side: Side) -> Keys {
+        initial_keys(interpret_version(version), dst_cid, side)
     }
 
-    fn retry_tag(&self, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
+    fn retry_tag(&self, version: u32, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
         let mut pseudo_packet = Vec::with_capacity(packet.len() + orig_dst_cid.len() + 1);
         pseudo_packet.push(orig_dst_cid.len() as u8);
         pseudo_packet.extend_from_slice(orig_dst_cid);
         pseudo_packet.extend_from_slice(packet);
 
-        let nonce = aead::Nonce::assume_unique_for_key(RETRY_INTEGRITY_NONCE);
-        let key = aead::LessSafeKey::new(
-            aead::UnboundKey::new(&aead::AES_128_GCM, &RETRY_INTEGRITY_KEY).unwrap(),
-        );
-
+        let (nonce, key) = retry_integrity_key(interpret_version(version));
         let tag = key
             .seal_in_place_separate_tag(nonce, aead::Aad::from(pseudo_packet), &mut [])
             .unwrap();
@@ -303,8 +497,19 @@ fn to_vec(params: &TransportParameters) -> Vec<u8> {
     bytes
 }
 
-pub(crate) fn initial_keys(dst_cid: &ConnectionId, side: Side) -> Keys {
-    let keys = rustls::quic::Keys::initial(Version::V1Draft, dst_cid, side.is_client());
+/// Map a QUIC version onto the TLS behavior it calls for
+///
+/// Versions without a dedicated mapping, such as reserved versions used for greasing, are treated
+/// like the drafts.
+fn interpret_version(version: u32) -> Version {
+    match version {
+        0x0000_0001 => Version::V1,
+        _ => Version::V1Draft,
+    }
+}
+
+pub(crate) fn initial_keys(version: Version, dst_cid: &ConnectionId, side: Side) -> Keys {
+    let keys = rustls::quic::Keys::initial(version, dst_cid, side.is_client());
     Keys {
         header: KeyPair {
             local: Box::new(keys.local.header),
@@ -368,6 +573,142 @@ pub fn client_config(roots: rustls::RootCertStore) -> rustls::ClientConfig {
     cfg
 }
 
+/// Initialize a sane QUIC-compatible TLS client configuration which selects the certificate to
+/// present to servers requesting client authentication with `selector`
+pub fn client_config_with_cert_selector<F>(
+    roots: rustls::RootCertStore,
+    selector: F,
+) -> rustls::ClientConfig
+where
+    F: Fn(&CertificateRequest) -> Option<(CertificateChain, PrivateKey)> + Send + Sync + 'static,
+{
+    let mut cfg = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_client_cert_resolver(Arc::new(ClientCertSelector(selector)));
+    cfg.enable_early_data = true;
+    cfg
+}
+
+/// Stores rustls client sessions in a [`SessionCache`]
+///
+/// Assign to [`rustls::ClientConfig::session_storage`] to let a custom configuration resume
+/// sessions, including with 0-RTT, across process restarts.
+pub struct SessionStorage(Arc<dyn SessionCache>);
+
+impl SessionStorage {
+    /// Store sessions in `cache`
+    pub fn new(cache: Arc<dyn SessionCache>) -> Self {
+        Self(cache)
+    }
+}
+
+impl rustls::client::StoresClientSessions for SessionStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.0.put(key, value);
+        true
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key)
+    }
+}
+
+/// A server's request for a client certificate
+#[derive(Debug)]
+pub struct CertificateRequest<'a> {
+    /// DER-encoded distinguished names of the certificate authorities accepted by the server
+    ///
+    /// Empty if the server did not restrict the issuers it accepts.
+    pub acceptable_issuers: &'a [&'a [u8]],
+    /// The signature schemes supported by the server
+    pub signature_schemes: &'a [rustls::SignatureScheme],
+}
+
+/// Adapts a closure to [`rustls::client::ResolvesClientCert`]
+struct ClientCertSelector<F>(F);
+
+impl<F> rustls::client::ResolvesClientCert for ClientCertSelector<F>
+where
+    F: Fn(&CertificateRequest) -> Option<(CertificateChain, PrivateKey)> + Send + Sync,
+{
+    fn resolve(
+        &self,
+        acceptable_issuers: &[&[u8]],
+        sigschemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        let (cert_chain, key) = (self.0)(&CertificateRequest {
+            acceptable_issuers,
+            signature_schemes: sigschemes,
+        })?;
+        let key = match rustls::sign::any_supported_type(&key.inner) {
+            Ok(x) => x,
+            Err(_) => {
+                tracing::warn!("unsupported client certificate key type");
+                return None;
+            }
+        };
+        Some(Arc::new(rustls::sign::CertifiedKey::new(
+            cert_chain.certs,
+            key,
+        )))
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Presents a single certificate chain to clients, stapling an OCSP response that can be
+/// replaced at any time
+///
+/// Stapling spares clients that require revocation information from fetching it from the
+/// certificate authority themselves. Since OCSP responses expire, servers should periodically
+/// fetch a fresh response and install it with [`set_ocsp_response()`]. Changes affect
+/// subsequent handshakes only.
+///
+/// [`set_ocsp_response()`]: StapledCertResolver::set_ocsp_response
+pub struct StapledCertResolver {
+    key: Mutex<Arc<rustls::sign::CertifiedKey>>,
+}
+
+impl StapledCertResolver {
+    /// Present `cert_chain`, authenticated by `key`, with an optional DER-encoded OCSP response
+    pub fn new(
+        cert_chain: CertificateChain,
+        key: PrivateKey,
+        ocsp_response: Option<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        let key = rustls::sign::any_supported_type(&key.inner)
+            .map_err(|_| Error::General("invalid private key".into()))?;
+        let mut key = rustls::sign::CertifiedKey::new(cert_chain.certs, key);
+        key.ocsp = ocsp_response;
+        Ok(Self {
+            key: Mutex::new(Arc::new(key)),
+        })
+    }
+
+    /// Replace the stapled OCSP response, or stop stapling one if `None`
+    pub fn set_ocsp_response(&self, ocsp_response: Option<Vec<u8>>) {
+        let mut key = self.key.lock().unwrap();
+        let mut new = (**key).clone();
+        new.ocsp = ocsp_response;
+        *key = Arc::new(new);
+    }
+}
+
+impl rustls::server::ResolvesServerCert for StapledCertResolver {
+    fn resolve(
+        &self,
+        _client_hello: rustls::server::ClientHello,
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        Some(self.key.lock().unwrap().clone())
+    }
+}
+
 /// Initialize a sane QUIC-compatible TLS server configuration
 ///
 /// QUIC requires that TLS 1.3 be enabled, and that the maximum early data size is either 0 or
@@ -387,3 +728,19 @@ pub fn server_config(
     cfg.max_early_data_size = u32::MAX;
     Ok(cfg)
 }
+
+/// Initialize a sane QUIC-compatible TLS server configuration which selects the certificate to
+/// present to each client with `resolver`
+pub fn server_config_with_resolver(
+    resolver: Arc<dyn rustls::server::ResolvesServerCert>,
+) -> rustls::ServerConfig {
+    let mut cfg = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    cfg.max_early_data_size = u32::MAX;
+    cfg
+}
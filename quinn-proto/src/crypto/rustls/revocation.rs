@@ -0,0 +1,320 @@
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, ServerName},
+    Certificate, Error,
+};
+
+use super::verify::{der, names};
+
+/// A certificate revocation list whose signature has been verified
+///
+/// Once the list's `nextUpdate` time has passed, certificates from its issuer are rejected until
+/// the list is replaced by a fresh one. Lists carrying critical extensions that aren't understood,
+/// such as delta CRLs and indirect CRLs, are refused.
+#[derive(Debug, Clone)]
+pub struct CertificateRevocationList {
+    /// Contents of the DER-encoded `Name` of the issuer
+    issuer: Box<[u8]>,
+    /// Contents of the DER-encoded serial numbers of revoked certificates
+    serials: Vec<Box<[u8]>>,
+    /// When the list is due to be replaced, if ever
+    next_update: Option<SystemTime>,
+}
+
+impl CertificateRevocationList {
+    /// Parse a DER-encoded X.509 CRL, checking that it's signed by `issuer`
+    pub fn from_der(crl: &[u8], issuer: &Certificate) -> Result<Self, webpki::Error> {
+        use webpki::Error::BadDer;
+        let mut crl = der::expect(&mut &crl[..], der::SEQUENCE).ok_or(BadDer)?;
+        let tbs_raw = der::expect_raw(&mut crl, der::SEQUENCE).ok_or(BadDer)?;
+        let mut algorithm = der::expect(&mut crl, der::SEQUENCE).ok_or(BadDer)?;
+        let algorithm = der::expect(&mut algorithm, der::OID).ok_or(BadDer)?;
+        let signature = match der::expect(&mut crl, der::BIT_STRING).ok_or(BadDer)? {
+            [0, signature @ ..] => signature,
+            _ => return Err(BadDer),
+        };
+
+        // Authenticate the list before looking at its contents
+        let issuer_names = names(&issuer.0).ok_or(BadDer)?;
+        let issuer_cert = webpki::EndEntityCert::try_from(&issuer.0[..])?;
+        let mut result = Err(webpki::Error::UnsupportedSignatureAlgorithm);
+        for alg in signature_algorithms(algorithm) {
+            result = issuer_cert.verify_signature(alg, tbs_raw, signature);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result?;
+
+        let mut tbs = der::expect(&mut &tbs_raw[..], der::SEQUENCE).ok_or(BadDer)?;
+        if tbs.first() == Some(&der::INTEGER) {
+            der::read(&mut tbs).ok_or(BadDer)?;
+        }
+        der::expect(&mut tbs, der::SEQUENCE).ok_or(BadDer)?;
+        let crl_issuer = der::expect(&mut tbs, der::SEQUENCE).ok_or(BadDer)?;
+        if crl_issuer != issuer_names.subject {
+            return Err(webpki::Error::UnknownIssuer);
+        }
+        // thisUpdate, then the optional nextUpdate
+        time(&mut tbs).ok_or(BadDer)?;
+        let next_update = match tbs.first() {
+            Some(&UTC_TIME) | Some(&GENERALIZED_TIME) => Some(time(&mut tbs).ok_or(BadDer)?),
+            _ => None,
+        };
+
+        let mut serials = Vec::new();
+        if tbs.first() == Some(&der::SEQUENCE) {
+            let mut revoked = der::expect(&mut tbs, der::SEQUENCE).ok_or(BadDer)?;
+            while !revoked.is_empty() {
+                let mut entry = der::expect(&mut revoked, der::SEQUENCE).ok_or(BadDer)?;
+                let serial = der::expect(&mut entry, der::INTEGER).ok_or(BadDer)?;
+                time(&mut entry).ok_or(BadDer)?;
+                if !entry.is_empty() {
+                    let extensions = der::expect(&mut entry, der::SEQUENCE).ok_or(BadDer)?;
+                    check_extensions(extensions, &[REASON_CODE, INVALIDITY_DATE])?;
+                }
+                serials.push(serial.into());
+            }
+        }
+        if !tbs.is_empty() {
+            let mut extensions = der::expect(&mut tbs, CRL_EXTENSIONS).ok_or(BadDer)?;
+            let extensions = der::expect(&mut extensions, der::SEQUENCE).ok_or(BadDer)?;
+            check_extensions(extensions, &[AUTHORITY_KEY_ID, CRL_NUMBER])?;
+        }
+
+        Ok(Self {
+            issuer: issuer_names.subject.into(),
+            serials,
+            next_update,
+        })
+    }
+
+    /// Check the DER-encoded certificate `cert` against this list at time `now`
+    fn check(&self, cert: &[u8], now: SystemTime) -> Result<(), Error> {
+        let names = match names(cert) {
+            Some(names) if names.issuer == &self.issuer[..] => names,
+            _ => return Ok(()),
+        };
+        if self.next_update.map_or(false, |next| next < now) {
+            return Err(Error::InvalidCertificateData(
+                "invalid peer certificate: revocation list expired".into(),
+            ));
+        }
+        if self.serials.iter().any(|x| &x[..] == names.serial) {
+            REVOKED.with(|x| x.set(true));
+            return Err(Error::InvalidCertificateData(
+                "invalid peer certificate: revoked".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Refuse extensions marked critical unless their DER-encoded OID is in `known`
+fn check_extensions(mut extensions: &[u8], known: &[&[u8]]) -> Result<(), webpki::Error> {
+    use webpki::Error::BadDer;
+    while !extensions.is_empty() {
+        let mut extension = der::expect(&mut extensions, der::SEQUENCE).ok_or(BadDer)?;
+        let oid = der::expect(&mut extension, der::OID).ok_or(BadDer)?;
+        let critical = match der::read(&mut extension).ok_or(BadDer)? {
+            (der::BOOLEAN, value) => value == [0xff],
+            _ => false,
+        };
+        if critical && !known.contains(&oid) {
+            return Err(webpki::Error::UnsupportedCriticalExtension);
+        }
+    }
+    Ok(())
+}
+
+/// Read a DER-encoded UTCTime or GeneralizedTime in the restricted form RFC 5280 requires
+fn time(input: &mut &[u8]) -> Option<SystemTime> {
+    let (tag, value) = der::read(input)?;
+    let (year, rest) = match tag {
+        UTC_TIME if value.len() == 13 => {
+            let year = digits(&value[..2])?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &value[2..],
+            )
+        }
+        GENERALIZED_TIME if value.len() == 15 => (digits(&value[..4])?, &value[4..]),
+        _ => return None,
+    };
+    if rest[10] != b'Z' {
+        return None;
+    }
+    let (month, day) = (digits(&rest[0..2])?, digits(&rest[2..4])?);
+    let (hour, minute, second) = (
+        digits(&rest[4..6])?,
+        digits(&rest[6..8])?,
+        digits(&rest[8..10])?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if year < 1970 || second > 59 {
+        return None;
+    }
+    // Days since the epoch of the civil date, after Howard Hinnant's `days_from_civil`
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn digits(x: &[u8]) -> Option<u64> {
+    x.iter().try_fold(0, |acc, &d| match d {
+        b'0'..=b'9' => Some(acc * 10 + u64::from(d - b'0')),
+        _ => None,
+    })
+}
+
+/// Rejects server certificates that have been revoked
+///
+/// Every certificate in the server's chain is checked against a set of
+/// [`CertificateRevocationList`]s and, if set, a custom check that is also passed the server's
+/// stapled OCSP response, if any. Revocation can only narrow the set of chains the wrapped
+/// verifier accepts, so the chain must first pass that verifier, typically a
+/// [`WebPkiServerVerifier`](super::WebPkiServerVerifier).
+///
+/// Handshakes with servers presenting a revoked certificate fail with
+/// [`TransportErrorCode::CERTIFICATE_REVOKED`](crate::TransportErrorCode::CERTIFICATE_REVOKED).
+pub struct RevocationVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    crls: Vec<CertificateRevocationList>,
+    check: Option<Box<RevocationCheck>>,
+}
+
+/// Custom revocation check, passed the end-entity certificate, the intermediates and the stapled
+/// OCSP response, and returning whether the end-entity certificate is revoked
+pub type RevocationCheck = dyn Fn(&Certificate, &[Certificate], &[u8]) -> bool + Send + Sync;
+
+impl RevocationVerifier {
+    /// Accept certificates approved by `inner` that aren't revoked by any of `crls`
+    pub fn new(
+        inner: Arc<dyn ServerCertVerifier>,
+        crls: impl IntoIterator<Item = CertificateRevocationList>,
+    ) -> Self {
+        Self {
+            inner,
+            crls: crls.into_iter().collect(),
+            check: None,
+        }
+    }
+
+    /// Additionally reject certificates for which `check` returns `true`
+    ///
+    /// This can be used to check OCSP responses, whether stapled by the server or fetched from
+    /// the responder named in the certificate.
+    pub fn with_check<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&Certificate, &[Certificate], &[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.check = Some(Box::new(check));
+        self
+    }
+}
+
+impl ServerCertVerifier for RevocationVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        for cert in Some(end_entity).into_iter().chain(intermediates) {
+            for crl in &self.crls {
+                crl.check(&cert.0, now)?;
+            }
+        }
+        if let Some(ref check) = self.check {
+            if check(end_entity, intermediates, ocsp_response) {
+                REVOKED.with(|x| x.set(true));
+                return Err(Error::InvalidCertificateData(
+                    "invalid peer certificate: revoked".into(),
+                ));
+            }
+        }
+        Ok(verified)
+    }
+}
+
+thread_local! {
+    /// Set when [`RevocationVerifier`] rejects a revoked certificate
+    ///
+    /// rustls' errors can't carry the reason a certificate was rejected, but verifiers are called
+    /// synchronously from within the session, so the reason is passed alongside on this thread.
+    static REVOKED: Cell<bool> = Cell::new(false);
+}
+
+/// Whether a certificate was rejected as revoked since the last call
+pub(super) fn take_revoked() -> bool {
+    REVOKED.with(|x| x.replace(false))
+}
+
+/// Candidate algorithms for the signature algorithm identified by `oid`
+///
+/// ECDSA algorithm identifiers don't specify the curve, so several may apply.
+fn signature_algorithms(oid: &[u8]) -> SignatureAlgorithms {
+    static ECDSA_SHA256: SignatureAlgorithms =
+        &[&webpki::ECDSA_P256_SHA256, &webpki::ECDSA_P384_SHA256];
+    static ECDSA_SHA384: SignatureAlgorithms =
+        &[&webpki::ECDSA_P256_SHA384, &webpki::ECDSA_P384_SHA384];
+    static RSA_SHA256: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA256];
+    static RSA_SHA384: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA384];
+    static RSA_SHA512: SignatureAlgorithms = &[&webpki::RSA_PKCS1_2048_8192_SHA512];
+    static ED25519: SignatureAlgorithms = &[&webpki::ED25519];
+    match oid {
+        ECDSA_WITH_SHA256 => ECDSA_SHA256,
+        ECDSA_WITH_SHA384 => ECDSA_SHA384,
+        SHA256_WITH_RSA => RSA_SHA256,
+        SHA384_WITH_RSA => RSA_SHA384,
+        SHA512_WITH_RSA => RSA_SHA512,
+        ED25519_OID => ED25519,
+        _ => &[],
+    }
+}
+
+type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
+
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+/// Context-specific tag of the `crlExtensions` field of `tbsCertList`
+const CRL_EXTENSIONS: u8 = 0xa0;
+
+/// DER-encoded OIDs of CRL and CRL entry extensions that don't affect which certificates a list
+/// revokes
+const AUTHORITY_KEY_ID: &[u8] = &[0x55, 0x1d, 0x23];
+const CRL_NUMBER: &[u8] = &[0x55, 0x1d, 0x14];
+const REASON_CODE: &[u8] = &[0x55, 0x1d, 0x15];
+const INVALIDITY_DATE: &[u8] = &[0x55, 0x1d, 0x18];
+
+/// DER-encoded OIDs of supported CRL signature algorithms
+const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const ED25519_OID: &[u8] = &[0x2b, 0x65, 0x70];
@@ -0,0 +1,308 @@
+use std::{
+    convert::TryInto,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::{digest, signature};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, ServerName},
+    Certificate, Error,
+};
+use tracing::debug;
+
+use super::verify::{der, extension, names, spki, tbs_certificate, EXTENSIONS};
+
+/// Whether connections must be backed by a valid signed certificate timestamp
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SctPolicy {
+    /// Reject servers that don't present at least one SCT issued by a known log
+    Require,
+    /// Accept servers that present no SCTs, or only SCTs issued by unknown logs
+    ///
+    /// SCTs claiming to be issued by a known log are still rejected if invalid.
+    BestEffort,
+}
+
+/// Verifies the signed certificate timestamps (SCTs) of server certificates against a set of
+/// Certificate Transparency logs
+///
+/// Both SCTs stapled by the server during the handshake and SCTs embedded in the server's
+/// certificate are considered. Embedded SCTs can only be verified if the server presents the
+/// certificate's issuer among its intermediates, in any position. An SCT only shows that a
+/// certificate was logged, not that it should be trusted, so SCTs are checked after the chain has
+/// been accepted by the wrapped verifier, typically a
+/// [`WebPkiServerVerifier`](super::WebPkiServerVerifier).
+pub struct SctVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    logs: &'static [&'static sct::Log<'static>],
+    policy: SctPolicy,
+}
+
+impl SctVerifier {
+    /// Accept certificates approved by `inner` that satisfy `policy` with respect to `logs`
+    pub fn new(
+        inner: Arc<dyn ServerCertVerifier>,
+        logs: &'static [&'static sct::Log<'static>],
+        policy: SctPolicy,
+    ) -> Self {
+        Self {
+            inner,
+            logs,
+            policy,
+        }
+    }
+}
+
+impl ServerCertVerifier for SctVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let scts = scts.collect::<Vec<_>>();
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        )?;
+
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::FailedToGetCurrentTime)?
+            .as_millis() as u64;
+        let mut results = scts
+            .iter()
+            .map(|sct| sct::verify_sct(&end_entity.0, sct, now, self.logs).map(|_| ()))
+            .collect::<Vec<_>>();
+        let embedded = embedded_scts(&end_entity.0).ok_or(Error::InvalidCertificateEncoding)?;
+        match issuer(&end_entity.0, intermediates) {
+            Some(issuer) => {
+                let entry = precert_entry(&end_entity.0, &issuer.0)
+                    .ok_or(Error::InvalidCertificateEncoding)?;
+                results.extend(
+                    embedded
+                        .iter()
+                        .map(|sct| verify_precert_sct(&entry, sct, now, self.logs)),
+                );
+            }
+            None if !embedded.is_empty() => {
+                debug!("can't verify embedded SCTs without the certificate's issuer");
+            }
+            None => {}
+        }
+
+        let mut valid = false;
+        for result in results {
+            match result {
+                Ok(()) => valid = true,
+                Err(e) if e.should_be_fatal() => return Err(Error::InvalidSct(e)),
+                Err(e) => debug!("ignoring SCT: {:?}", e),
+            }
+        }
+        if self.policy == SctPolicy::Require && !valid {
+            return Err(Error::InvalidCertificateData(
+                "invalid peer certificate: no valid signed certificate timestamp".into(),
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn request_scts(&self) -> bool {
+        true
+    }
+}
+
+/// Verify an SCT issued for a precertificate, given the `PreCert` structure `entry`
+///
+/// Follows `sct::verify_sct`, which only supports SCTs for final certificates.
+fn verify_precert_sct(
+    entry: &[u8],
+    sct: &[u8],
+    now: u64,
+    logs: &[&sct::Log],
+) -> Result<(), sct::Error> {
+    use sct::Error::*;
+    let mut input = sct;
+    let version = take(&mut input, 1).ok_or(MalformedSct)?[0];
+    if version != 0 {
+        return Err(UnsupportedSctVersion);
+    }
+    let log_id = take(&mut input, 32).ok_or(MalformedSct)?;
+    let timestamp = take(&mut input, 8).ok_or(MalformedSct)?;
+    let extensions_len = take(&mut input, 2).ok_or(MalformedSct)?;
+    let extensions = take(&mut input, u16_at(extensions_len).into()).ok_or(MalformedSct)?;
+    let algorithm = u16_at(take(&mut input, 2).ok_or(MalformedSct)?);
+    let signature = take_u16_prefixed(&mut input).ok_or(MalformedSct)?;
+    if !input.is_empty() {
+        return Err(MalformedSct);
+    }
+
+    let log = logs
+        .iter()
+        .find(|log| log.id[..] == *log_id)
+        .ok_or(UnknownLog)?;
+    let algorithm: &dyn signature::VerificationAlgorithm = match algorithm {
+        0x0403 => &signature::ECDSA_P256_SHA256_ASN1,
+        0x0503 => &signature::ECDSA_P384_SHA384_ASN1,
+        0x0401 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        0x0501 => &signature::RSA_PKCS1_2048_8192_SHA384,
+        _ => return Err(InvalidSignature),
+    };
+
+    // version, signature_type = certificate_timestamp
+    let mut signed = vec![0, 0];
+    signed.extend_from_slice(timestamp);
+    signed.extend_from_slice(entry);
+    signed.extend_from_slice(extensions_len);
+    signed.extend_from_slice(extensions);
+    signature::UnparsedPublicKey::new(algorithm, log.key)
+        .verify(&signed, signature)
+        .map_err(|_| InvalidSignature)?;
+
+    if u64::from_be_bytes(timestamp.try_into().unwrap()) > now {
+        return Err(TimestampInFuture);
+    }
+    Ok(())
+}
+
+/// Extract the SCTs embedded in a DER-encoded certificate
+///
+/// Returns `None` if the certificate is malformed.
+fn embedded_scts(cert: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut result = Vec::new();
+    let list = match extension(cert, SCT_LIST)? {
+        Some(x) => x,
+        None => return Some(result),
+    };
+    let mut list = der::expect(&mut &list[..], der::OCTET_STRING)?;
+    let mut scts = take_u16_prefixed(&mut list)?;
+    while !scts.is_empty() {
+        result.push(take_u16_prefixed(&mut scts)?);
+    }
+    Some(result)
+}
+
+/// Find the certificate among `candidates` that issued the DER-encoded certificate `cert`
+///
+/// Candidates must be named as the certificate's issuer, and if both carry key identifiers, the
+/// candidate's subject key identifier must match the certificate's authority key identifier.
+fn issuer<'a>(cert: &[u8], candidates: &'a [Certificate]) -> Option<&'a Certificate> {
+    let issuer_name = names(cert)?.issuer;
+    let authority_key_id = authority_key_id(cert)?;
+    candidates.iter().find(|candidate| {
+        if names(&candidate.0).map_or(true, |names| names.subject != issuer_name) {
+            return false;
+        }
+        match (authority_key_id, subject_key_id(&candidate.0)) {
+            (Some(expected), Some(Some(actual))) => expected == actual,
+            (_, Some(_)) => true,
+            // Malformed
+            (_, None) => false,
+        }
+    })
+}
+
+/// Get the `keyIdentifier` of the authority key identifier extension of a DER-encoded certificate
+///
+/// Returns `None` if the certificate is malformed, and `Some(None)` if the identifier is absent.
+fn authority_key_id(cert: &[u8]) -> Option<Option<&[u8]>> {
+    let value = match extension(cert, AUTHORITY_KEY_ID)? {
+        Some(x) => x,
+        None => return Some(None),
+    };
+    let mut fields = der::expect(&mut &value[..], der::SEQUENCE)?;
+    while !fields.is_empty() {
+        let (tag, value) = der::read(&mut fields)?;
+        if tag == KEY_IDENTIFIER {
+            return Some(Some(value));
+        }
+    }
+    Some(None)
+}
+
+/// Get the subject key identifier of a DER-encoded certificate
+///
+/// Returns `None` if the certificate is malformed, and `Some(None)` if the identifier is absent.
+fn subject_key_id(cert: &[u8]) -> Option<Option<&[u8]>> {
+    match extension(cert, SUBJECT_KEY_ID)? {
+        Some(value) => Some(Some(der::expect(&mut &value[..], der::OCTET_STRING)?)),
+        None => Some(None),
+    }
+}
+
+/// Construct the `signed_entry` of a precertificate SCT for a certificate issued by `issuer`
+///
+/// This includes the entry type, the issuer key hash and the certificate's `tbsCertificate`
+/// without the SCT list extension, as it was before the SCTs were embedded.
+fn precert_entry(cert: &[u8], issuer: &[u8]) -> Option<Vec<u8>> {
+    let mut tbs = tbs_certificate(cert)?;
+    let mut fields = Vec::new();
+    while !tbs.is_empty() {
+        let (tag, field) = der::read_raw(&mut tbs)?;
+        if tag != EXTENSIONS {
+            fields.extend_from_slice(field);
+            continue;
+        }
+        let mut extensions = der::expect(&mut &field[..], EXTENSIONS)?;
+        let mut extensions = der::expect(&mut extensions, der::SEQUENCE)?;
+        let mut kept = Vec::new();
+        while !extensions.is_empty() {
+            let extension = der::expect_raw(&mut extensions, der::SEQUENCE)?;
+            let mut contents = der::expect(&mut &extension[..], der::SEQUENCE)?;
+            if der::expect(&mut contents, der::OID)? != SCT_LIST {
+                kept.extend_from_slice(extension);
+            }
+        }
+        if !kept.is_empty() {
+            let mut sequence = Vec::new();
+            der::write(der::SEQUENCE, &kept, &mut sequence);
+            der::write(EXTENSIONS, &sequence, &mut fields);
+        }
+    }
+    let mut precert = Vec::new();
+    der::write(der::SEQUENCE, &fields, &mut precert);
+
+    // entry_type = precert_entry
+    let mut entry = vec![0, 1];
+    entry.extend_from_slice(digest::digest(&digest::SHA256, spki(issuer)?).as_ref());
+    entry.extend_from_slice(&(precert.len() as u32).to_be_bytes()[1..]);
+    entry.extend_from_slice(&precert);
+    Some(entry)
+}
+
+/// Read the next `n` bytes of `input`
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (value, rest) = input.split_at(n);
+    *input = rest;
+    Some(value)
+}
+
+/// Read a TLS-encoded value with a 16-bit length prefix
+fn take_u16_prefixed<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u16_at(take(input, 2)?);
+    take(input, len.into())
+}
+
+fn u16_at(x: &[u8]) -> u16 {
+    u16::from_be_bytes([x[0], x[1]])
+}
+
+/// DER-encoded OID of the authority key identifier extension, 2.5.29.35
+const AUTHORITY_KEY_ID: &[u8] = &[0x55, 0x1d, 0x23];
+/// DER-encoded OID of the subject key identifier extension, 2.5.29.14
+const SUBJECT_KEY_ID: &[u8] = &[0x55, 0x1d, 0x0e];
+/// Context-specific tag of the `keyIdentifier` field of `AuthorityKeyIdentifier`
+const KEY_IDENTIFIER: u8 = 0x80;
+/// DER-encoded OID of the embedded SCT list extension, 1.3.6.1.4.1.11129.2.4.2
+const SCT_LIST: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
@@ -0,0 +1,516 @@
+use std::{
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use ring::digest;
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, ServerName, WebPkiVerifier},
+    Certificate, Error, OwnedTrustAnchor, RootCertStore,
+};
+
+/// Verifies server certificates against a set of trusted roots using WebPKI
+///
+/// Certificates for DNS names are checked by rustls' own [`WebPkiVerifier`]. Unlike it, servers
+/// may also be identified by IP address, in which case the certificate must carry a matching
+/// iPAddress subject alternative name. This is the verifier used by the
+/// [`ClientConfig`](crate::ClientConfig) constructors.
+pub struct WebPkiServerVerifier {
+    inner: WebPkiVerifier,
+    /// The roots of `inner`, which rustls doesn't expose, for checking certificates for IP
+    /// addresses
+    roots: Vec<TrustAnchor>,
+}
+
+impl WebPkiServerVerifier {
+    /// Construct a verifier that trusts the DER-encoded root certificates `roots`
+    pub fn new(roots: impl IntoIterator<Item = Certificate>) -> Result<Self, webpki::Error> {
+        let mut store = RootCertStore::empty();
+        let mut anchors = Vec::new();
+        for cert in roots {
+            let anchor = webpki::TrustAnchor::try_from_cert_der(&cert.0)?;
+            store.add_server_trust_anchors(std::iter::once(
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                ),
+            ));
+            anchors.push(TrustAnchor {
+                subject: anchor.subject.into(),
+                spki: anchor.spki.into(),
+                name_constraints: anchor.name_constraints.map(Into::into),
+            });
+        }
+        Ok(Self {
+            inner: WebPkiVerifier::new(store, None),
+            roots: anchors,
+        })
+    }
+
+    /// Number of trusted roots
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Whether no roots are trusted
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Check a certificate for the IP address `ip`
+    ///
+    /// rustls' verifier refuses IP addresses outright, so the chain is validated with the same
+    /// webpki call it would make for a DNS name before checking the iPAddress names.
+    fn verify_ip_address(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        ip: IpAddr,
+        now: SystemTime,
+    ) -> Result<(), Error> {
+        let cert = webpki::EndEntityCert::try_from(&end_entity.0[..]).map_err(pki_error)?;
+        let intermediates = intermediates
+            .iter()
+            .map(|cert| &cert.0[..])
+            .collect::<Vec<_>>();
+        let roots = self
+            .roots
+            .iter()
+            .map(|x| webpki::TrustAnchor {
+                subject: &x.subject,
+                spki: &x.spki,
+                name_constraints: x.name_constraints.as_deref(),
+            })
+            .collect::<Vec<_>>();
+        let now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&roots),
+            &intermediates,
+            now,
+        )
+        .map_err(pki_error)?;
+
+        let valid = ip_addresses(&end_entity.0)
+            .ok_or(Error::InvalidCertificateEncoding)?
+            .iter()
+            .any(|&x| same_address(x, ip));
+        if !valid {
+            return Err(pki_error(webpki::Error::CertNotValidForName));
+        }
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for WebPkiServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        match *server_name {
+            ServerName::IpAddress(ip) => {
+                self.verify_ip_address(end_entity, intermediates, ip, now)?;
+                Ok(ServerCertVerified::assertion())
+            }
+            _ => self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            ),
+        }
+    }
+}
+
+impl fmt::Debug for WebPkiServerVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebPkiServerVerifier")
+            .field("roots", &self.roots.len())
+            .finish()
+    }
+}
+
+/// Requires the server's certificate chain to contain one of a set of pinned public keys
+///
+/// Pins are SHA-256 hashes of DER-encoded SubjectPublicKeyInfo structures, as computed by
+/// [`spki_sha256()`]. A pin matches if webpki can validate the server's certificate up to the
+/// pinned key: the server's own key, a presented intermediate that issued its certificate, or one
+/// of the trusted roots it chains to. Presenting a pinned certificate that didn't issue the
+/// server's certificate is not enough. Pinning a CA's key rather than the server's own allows the
+/// server certificate to be renewed without updating clients. Pins restrict which trusted chains
+/// are accepted rather than replacing validation, so the wrapped verifier is typically the
+/// [`WebPkiServerVerifier`] holding `roots`.
+pub struct SpkiPinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: Vec<[u8; 32]>,
+    /// Trusted roots carrying a pinned key
+    pinned_roots: Vec<TrustAnchor>,
+}
+
+impl SpkiPinningVerifier {
+    /// Accept certificates approved by `inner` whose chain to one of `roots` carries one of `pins`
+    pub fn new(
+        inner: Arc<dyn ServerCertVerifier>,
+        roots: &WebPkiServerVerifier,
+        pins: impl IntoIterator<Item = [u8; 32]>,
+    ) -> Self {
+        let pins = pins.into_iter().collect::<Vec<_>>();
+        let pinned_roots = roots
+            .roots
+            .iter()
+            .filter(|root| {
+                // Trust anchors hold the contents of the SubjectPublicKeyInfo; pins hash all of it
+                let mut spki = Vec::new();
+                der::write(der::SEQUENCE, &root.spki, &mut spki);
+                pins.contains(&sha256(&spki))
+            })
+            .cloned()
+            .collect();
+        Self {
+            inner,
+            pins,
+            pinned_roots,
+        }
+    }
+
+    /// Whether `end_entity` carries a pinned key or chains to a certificate or root that does
+    fn is_pinned(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> bool {
+        let pinned =
+            |cert: &Certificate| spki_sha256(cert).map_or(false, |hash| self.pins.contains(&hash));
+        if pinned(end_entity) {
+            return true;
+        }
+        // Treat the pinned keys as the only trust anchors, so that webpki only succeeds if it can
+        // build a path through which one of them really issued the end entity
+        let anchors = self
+            .pinned_roots
+            .iter()
+            .map(|x| webpki::TrustAnchor {
+                subject: &x.subject,
+                spki: &x.spki,
+                name_constraints: x.name_constraints.as_deref(),
+            })
+            .chain(
+                intermediates
+                    .iter()
+                    .filter(|cert| pinned(cert))
+                    .filter_map(|cert| webpki::TrustAnchor::try_from_cert_der(&cert.0).ok()),
+            )
+            .collect::<Vec<_>>();
+        if anchors.is_empty() {
+            return false;
+        }
+        let cert = match webpki::EndEntityCert::try_from(&end_entity.0[..]) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        let now = match webpki::Time::try_from(now) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        let intermediates = intermediates
+            .iter()
+            .map(|cert| &cert.0[..])
+            .collect::<Vec<_>>();
+        cert.verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&anchors),
+            &intermediates,
+            now,
+        )
+        .is_ok()
+    }
+}
+
+impl ServerCertVerifier for SpkiPinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        if !self.is_pinned(end_entity, intermediates, now) {
+            return Err(Error::InvalidCertificateData(
+                "invalid peer certificate: no pinned public key".into(),
+            ));
+        }
+        Ok(verified)
+    }
+}
+
+/// Compute the SHA-256 hash of the DER-encoded SubjectPublicKeyInfo of `cert`, as used for
+/// public key pinning
+///
+/// Returns `None` if the certificate is malformed.
+pub fn spki_sha256(cert: &Certificate) -> Option<[u8; 32]> {
+    Some(sha256(spki(&cert.0)?))
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let hash = digest::digest(&digest::SHA256, data);
+    let mut result = [0; 32];
+    result.copy_from_slice(hash.as_ref());
+    result
+}
+
+#[derive(Debug, Clone)]
+struct TrustAnchor {
+    subject: Vec<u8>,
+    spki: Vec<u8>,
+    name_constraints: Option<Vec<u8>>,
+}
+
+/// Whether a certificate issued for `cert` covers a connection to `peer`
+///
+/// IPv4-mapped IPv6 addresses are considered equal to the IPv4 address they represent.
+fn same_address(cert: IpAddr, peer: IpAddr) -> bool {
+    fn canonical(x: IpAddr) -> IpAddr {
+        match x {
+            IpAddr::V6(v6) => match v6.to_ipv4() {
+                Some(v4) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(v4),
+                _ => x,
+            },
+            x => x,
+        }
+    }
+    canonical(cert) == canonical(peer)
+}
+
+/// Extract the iPAddress subject alternative names from a DER-encoded certificate
+///
+/// Returns `None` if the certificate is malformed.
+fn ip_addresses(cert: &[u8]) -> Option<Vec<IpAddr>> {
+    let mut result = Vec::new();
+    let names = match extension(cert, SUBJECT_ALT_NAME)? {
+        Some(x) => x,
+        None => return Some(result),
+    };
+    let mut names = der::expect(&mut &names[..], der::SEQUENCE)?;
+    while !names.is_empty() {
+        let (tag, value) = der::read(&mut names)?;
+        if tag != IP_ADDRESS {
+            continue;
+        }
+        result.push(match value.len() {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(value).unwrap())),
+            16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap())),
+            _ => return None,
+        });
+    }
+    Some(result)
+}
+
+/// Find the value of the extension identified by the DER-encoded OID `id` in a certificate
+///
+/// Returns `None` if the certificate is malformed, and `Some(None)` if the extension is absent.
+pub(super) fn extension<'a>(cert: &'a [u8], id: &[u8]) -> Option<Option<&'a [u8]>> {
+    let mut tbs = tbs_certificate(cert)?;
+    loop {
+        if tbs.is_empty() {
+            return Some(None);
+        }
+        let (tag, value) = der::read(&mut tbs)?;
+        if tag == EXTENSIONS {
+            let mut extensions = der::expect(&mut &value[..], der::SEQUENCE)?;
+            while !extensions.is_empty() {
+                let mut extension = der::expect(&mut extensions, der::SEQUENCE)?;
+                let oid = der::expect(&mut extension, der::OID)?;
+                let (mut tag, mut value) = der::read(&mut extension)?;
+                if tag == der::BOOLEAN {
+                    // Criticality
+                    let next = der::read(&mut extension)?;
+                    tag = next.0;
+                    value = next.1;
+                }
+                if tag != der::OCTET_STRING {
+                    return None;
+                }
+                if oid == id {
+                    return Some(Some(value));
+                }
+            }
+            return Some(None);
+        }
+    }
+}
+
+/// Get the DER encoding of the `subjectPublicKeyInfo` field of a DER-encoded certificate
+pub(super) fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let mut tbs = tbs_certificate(cert)?;
+    if tbs.first() == Some(&VERSION) {
+        der::read(&mut tbs)?;
+    }
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        der::read(&mut tbs)?;
+    }
+    der::expect_raw(&mut tbs, der::SEQUENCE)
+}
+
+/// Get the contents of the `issuer`, `serialNumber` and `subject` fields of a DER-encoded
+/// certificate
+pub(super) fn names(cert: &[u8]) -> Option<CertNames<'_>> {
+    let mut tbs = tbs_certificate(cert)?;
+    if tbs.first() == Some(&VERSION) {
+        der::read(&mut tbs)?;
+    }
+    let serial = der::expect(&mut tbs, der::INTEGER)?;
+    der::expect(&mut tbs, der::SEQUENCE)?;
+    let issuer = der::expect(&mut tbs, der::SEQUENCE)?;
+    der::expect(&mut tbs, der::SEQUENCE)?;
+    let subject = der::expect(&mut tbs, der::SEQUENCE)?;
+    Some(CertNames {
+        issuer,
+        serial,
+        subject,
+    })
+}
+
+pub(super) struct CertNames<'a> {
+    pub(super) issuer: &'a [u8],
+    pub(super) serial: &'a [u8],
+    pub(super) subject: &'a [u8],
+}
+
+/// Get the contents of the `tbsCertificate` field of a DER-encoded certificate
+pub(super) fn tbs_certificate(cert: &[u8]) -> Option<&[u8]> {
+    let mut cert = der::expect(&mut &cert[..], der::SEQUENCE)?;
+    der::expect(&mut cert, der::SEQUENCE)
+}
+
+/// Minimal DER decoding, sufficient to navigate certificates
+pub(super) mod der {
+    pub const BOOLEAN: u8 = 0x01;
+    pub const INTEGER: u8 = 0x02;
+    pub const BIT_STRING: u8 = 0x03;
+    pub const OCTET_STRING: u8 = 0x04;
+    pub const OID: u8 = 0x06;
+    pub const SEQUENCE: u8 = 0x30;
+
+    /// Read a tag-length-value triple, returning the tag and value
+    pub fn read<'a>(input: &mut &'a [u8]) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = input.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first < 0x80 {
+            usize::from(first)
+        } else {
+            let n = usize::from(first & 0x7f);
+            if n == 0 || n > 4 || rest.len() < n {
+                return None;
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | usize::from(b));
+            rest = &rest[n..];
+            len
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (value, rest) = rest.split_at(len);
+        *input = rest;
+        Some((tag, value))
+    }
+
+    /// Read a tag-length-value triple, returning the tag and its full encoding
+    pub fn read_raw<'a>(input: &mut &'a [u8]) -> Option<(u8, &'a [u8])> {
+        let start = *input;
+        let (tag, _) = read(input)?;
+        Some((tag, &start[..start.len() - input.len()]))
+    }
+
+    /// Read a value with tag `tag`, returning its full encoding
+    pub fn expect_raw<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+        match read_raw(input)? {
+            (x, value) if x == tag => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Append the encoding of a value with tag `tag` to `out`
+    pub fn write(tag: u8, value: &[u8], out: &mut Vec<u8>) {
+        out.push(tag);
+        let len = value.len().to_be_bytes();
+        let skip = len.iter().take_while(|&&b| b == 0).count();
+        match value.len() {
+            x if x < 0x80 => out.push(x as u8),
+            _ => {
+                out.push(0x80 | (len.len() - skip) as u8);
+                out.extend_from_slice(&len[skip..]);
+            }
+        }
+        out.extend_from_slice(value);
+    }
+
+    /// Read a value with tag `tag`
+    pub fn expect<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+        match read(input)? {
+            (x, value) if x == tag => Some(value),
+            _ => None,
+        }
+    }
+}
+
+fn pki_error(error: webpki::Error) -> Error {
+    use webpki::Error::*;
+    match error {
+        BadDer | BadDerTime => Error::InvalidCertificateEncoding,
+        InvalidSignatureForPublicKey => Error::InvalidCertificateSignature,
+        UnsupportedSignatureAlgorithm | UnsupportedSignatureAlgorithmForPublicKey => {
+            Error::InvalidCertificateSignatureType
+        }
+        e => Error::InvalidCertificateData(format!("invalid peer certificate: {}", e)),
+    }
+}
+
+/// DER-encoded OID of the subject alternative name extension, 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+/// Context-specific tag of the `version` field of `tbsCertificate`
+const VERSION: u8 = 0xa0;
+/// Context-specific tag of the `extensions` field of `tbsCertificate`
+pub(super) const EXTENSIONS: u8 = 0xa3;
+/// Context-specific tag of the iPAddress `GeneralName` variant
+const IP_ADDRESS: u8 = 0x87;
+
+/// Signature algorithms accepted in certificates, a copy of the private list rustls' verifier uses
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
@@ -31,6 +31,14 @@ impl Certificate {
     pub fn as_der(&self) -> &[u8] {
         &self.inner.0
     }
+
+    /// Compute the SHA-256 hash of the certificate's public key, for use with
+    /// [`ClientConfigBuilder::pinned_keys()`](crate::ClientConfigBuilder::pinned_keys)
+    ///
+    /// Returns `None` if the certificate is malformed.
+    pub fn spki_sha256(&self) -> Option<[u8; 32]> {
+        super::rustls::spki_sha256(&self.inner)
+    }
 }
 
 impl From<rustls::Certificate> for Certificate {
@@ -1,11 +1,11 @@
 use std::{
     collections::{HashMap, VecDeque},
     convert::TryFrom,
-    fmt, iter,
+    fmt, iter, mem,
     net::{IpAddr, SocketAddr},
     ops::{Index, IndexMut},
     sync::Arc,
-    time::{Instant, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
@@ -18,8 +18,8 @@ use tracing::{debug, trace, warn};
 use crate::{
     cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator},
     coding::BufMutExt,
-    config::{ClientConfig, EndpointConfig, ServerConfig},
-    connection::{Connection, ConnectionError},
+    config::{ClientConfig, EndpointConfig, ResponseRateLimit, ServerConfig},
+    connection::{CarefulResume, Connection, ConnectionError, VersionNegotiation},
     crypto::Keys,
     frame,
     packet::{Header, Packet, PacketDecodeError, PacketNumber, PartialDecode},
@@ -27,7 +27,8 @@ use crate::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, EcnCodepoint, EndpointEvent,
         EndpointEventInner, IssuedCid,
     },
-    transport_parameters::TransportParameters,
+    sni::{self, ClientHello},
+    transport_parameters::{PreferredAddress, TransportParameters},
     ResetToken, RetryToken, Side, Transmit, TransportError, INITIAL_MAX_UDP_PAYLOAD_SIZE,
     MAX_CID_SIZE, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE,
 };
@@ -65,6 +66,15 @@ pub struct Endpoint {
     ///
     /// Equivalent to a `ServerConfig.accept_buffer` of `0`, but can be changed after the endpoint is constructed.
     reject_new_connections: bool,
+    /// Number of incoming connections whose handshake has not yet completed
+    incoming_handshakes: usize,
+    /// Number of incoming connections the application has yet to accept
+    unaccepted: usize,
+    version_negotiation_limiter: ResponseLimiter,
+    stateless_reset_limiter: ResponseLimiter,
+    stats: EndpointStats,
+    /// Recent failed incoming connection attempts, oldest first
+    handshake_failures: VecDeque<HandshakeFailure>,
 }
 
 impl Endpoint {
@@ -82,6 +92,12 @@ impl Endpoint {
             connections: Slab::new(),
             local_cid_generator: (config.connection_id_generator_factory.as_ref())(),
             reject_new_connections: false,
+            incoming_handshakes: 0,
+            unaccepted: 0,
+            version_negotiation_limiter: ResponseLimiter::default(),
+            stateless_reset_limiter: ResponseLimiter::default(),
+            stats: EndpointStats::default(),
+            handshake_failures: VecDeque::new(),
             config,
             server_config,
         }
@@ -97,6 +113,14 @@ impl Endpoint {
         self.transmits.pop_front()
     }
 
+    /// Get the next record of a failed incoming connection attempt
+    ///
+    /// Only the most recent failures are retained, so this should be called regularly by servers
+    /// interested in them.
+    pub fn poll_handshake_failure(&mut self) -> Option<HandshakeFailure> {
+        self.handshake_failures.pop_front()
+    }
+
     /// Replace the server configuration, affecting new incoming connections only
     pub fn set_server_config(&mut self, server_config: Option<Arc<ServerConfig>>) {
         self.server_config = server_config;
@@ -111,6 +135,37 @@ impl Endpoint {
         event: EndpointEvent,
     ) -> Option<ConnectionEvent> {
         use EndpointEventInner::*;
+        if self.connections[ch].detached {
+            // The connection has moved to another endpoint, which its events were flushed to
+            // beforehand; all that's left is to release the handle, and whichever identifiers
+            // haven't been taken over by a connection attached since
+            match event.0 {
+                Drained => {
+                    let conn = self.connections.remove(ch.0);
+                    if self.connection_ids_initial.get(&conn.init_cid) == Some(&ch) {
+                        self.connection_ids_initial.remove(&conn.init_cid);
+                    }
+                    for cid in conn.loc_cids.values() {
+                        if self.connection_ids.get(cid) == Some(&ch) {
+                            self.connection_ids.remove(cid);
+                        }
+                    }
+                    if self.connection_remotes.get(&conn.initial_remote) == Some(&ch) {
+                        self.connection_remotes.remove(&conn.initial_remote);
+                    }
+                    if let Some((remote, token)) = conn.reset_token {
+                        if self.connection_reset_tokens.get(remote, &token) == Some(&ch) {
+                            self.connection_reset_tokens.remove(remote, token);
+                        }
+                    }
+                }
+                ref e => {
+                    debug_assert!(false, "event for a detached connection: {:?}", e);
+                    warn!("ignoring event for a detached connection: {:?}", e);
+                }
+            }
+            return None;
+        }
         match event.0 {
             NeedIdentifiers(now, n) => {
                 return Some(self.send_new_identifiers(now, ch, n));
@@ -132,8 +187,36 @@ impl Endpoint {
                     }
                 }
             }
+            HandshakeFailed(error) => {
+                let conn = &self.connections[ch];
+                if conn.handshaking {
+                    let remote = conn.initial_remote;
+                    self.handshake_failed(remote, error.into());
+                }
+            }
+            Established {
+                resumed,
+                zero_rtt_offered,
+                zero_rtt_accepted,
+            } => {
+                self.stats.handshakes += 1;
+                self.stats.resumed_handshakes += resumed as u64;
+                self.stats.zero_rtt_offered += zero_rtt_offered as u64;
+                self.stats.zero_rtt_accepted += zero_rtt_accepted as u64;
+                let conn = &mut self.connections[ch];
+                if conn.handshaking {
+                    conn.handshaking = false;
+                    self.incoming_handshakes -= 1;
+                }
+            }
             Drained => {
                 let conn = self.connections.remove(ch.0);
+                if conn.handshaking {
+                    self.incoming_handshakes -= 1;
+                }
+                if conn.unaccepted {
+                    self.unaccepted -= 1;
+                }
                 if conn.init_cid.len() > 0 {
                     self.connection_ids_initial.remove(&conn.init_cid);
                 }
@@ -174,7 +257,22 @@ impl Endpoint {
                     debug!("dropping packet with unsupported version");
                     return None;
                 }
+                self.handshake_failed(remote, HandshakeFailureReason::UnsupportedVersion(version));
+                if let Some(ref limit) = self.config.version_negotiation_rate_limit {
+                    if !self
+                        .version_negotiation_limiter
+                        .allow(now, remote.ip(), limit)
+                    {
+                        debug!(
+                            "not sending version negotiation to {}: rate limited",
+                            remote
+                        );
+                        self.stats.version_negotiations_limited += 1;
+                        return None;
+                    }
+                }
                 trace!("sending version negotiation");
+                self.stats.version_negotiations += 1;
                 // Negotiate versions
                 let mut buf = Vec::<u8>::new();
                 Header::VersionNegotiate {
@@ -189,7 +287,9 @@ impl Endpoint {
                 } else {
                     buf.write::<u32>(0x0a1a_2a4a);
                 }
-                buf.write(self.config.initial_version); // supported version
+                for &version in &self.config.supported_versions {
+                    buf.write(version);
+                }
                 self.transmits.push_back(Transmit {
                     destination: remote,
                     ecn: None,
@@ -259,7 +359,7 @@ impl Endpoint {
 
         if !self.is_server() {
             debug!("packet for unrecognized connection {}", dst_cid);
-            self.stateless_reset(datagram_len, remote, local_ip, &dst_cid);
+            self.stateless_reset(now, datagram_len, remote, local_ip, &dst_cid);
             return None;
         }
 
@@ -276,12 +376,13 @@ impl Endpoint {
                 return None;
             }
 
-            let crypto = self
-                .server_config
-                .as_ref()
-                .unwrap()
-                .crypto
-                .initial_keys(&dst_cid, Side::Server);
+            let crypto = self.server_config.as_ref().unwrap().crypto.initial_keys(
+                first_decode
+                    .version()
+                    .expect("Initial packets have a version"),
+                &dst_cid,
+                Side::Server,
+            );
             return match first_decode.finish(Some(&*crypto.header.remote)) {
                 Ok(packet) => self
                     .handle_first_packet(now, remote, local_ip, ecn, packet, remaining, &crypto)
@@ -299,7 +400,7 @@ impl Endpoint {
         //
 
         if !dst_cid.is_empty() {
-            self.stateless_reset(datagram_len, remote, local_ip, &dst_cid);
+            self.stateless_reset(now, datagram_len, remote, local_ip, &dst_cid);
         } else {
             trace!("dropping unrecognized short packet without ID");
         }
@@ -308,6 +409,7 @@ impl Endpoint {
 
     fn stateless_reset(
         &mut self,
+        now: Instant,
         inciting_dgram_len: usize,
         remote: SocketAddr,
         local_ip: Option<IpAddr>,
@@ -316,6 +418,11 @@ impl Endpoint {
         /// Minimum amount of padding for the stateless reset to look like a short-header packet
         const MIN_PADDING_LEN: usize = 5;
 
+        if self.local_cid_generator.validate(dst_cid).is_err() {
+            debug!("dropping packet with invalid CID {}", dst_cid);
+            return;
+        }
+
         // Prevent amplification attacks and reset loops by ensuring we pad to at most 1 byte
         // smaller than the inciting packet.
         let max_padding_len = match inciting_dgram_len.checked_sub(RESET_TOKEN_SIZE) {
@@ -326,7 +433,16 @@ impl Endpoint {
             }
         };
 
+        if let Some(ref limit) = self.config.stateless_reset_rate_limit {
+            if !self.stateless_reset_limiter.allow(now, remote.ip(), limit) {
+                debug!("not sending stateless reset to {}: rate limited", remote);
+                self.stats.stateless_resets_limited += 1;
+                return;
+            }
+        }
+
         debug!("sending stateless reset for {} to {}", dst_cid, remote);
+        self.stats.stateless_resets += 1;
         let mut buf = Vec::<u8>::new();
         // Resets with at least this much padding can't possibly be distinguished from real packets
         const IDEAL_MIN_PADDING_LEN: usize = MIN_PADDING_LEN + MAX_CID_SIZE;
@@ -376,6 +492,7 @@ impl Endpoint {
                 config,
                 server_name: server_name.into(),
             },
+            self.config.initial_version,
             Instant::now(),
         )?;
         Ok((ch, conn))
@@ -404,6 +521,32 @@ impl Endpoint {
         ConnectionEvent(ConnectionEventInner::NewIdentifiers(ids, now))
     }
 
+    /// Construct the preferred address transport parameter, if one is configured
+    ///
+    /// Clients use the accompanying CID once they move to the preferred address, so it's issued
+    /// with sequence number 1 rather than in a NEW_CONNECTION_ID frame.
+    fn preferred_address(&mut self) -> Option<(IssuedCid, PreferredAddress)> {
+        let config = self.server_config.as_ref()?;
+        let (address_v4, address_v6) = (config.preferred_address_v4, config.preferred_address_v6);
+        if (address_v4.is_none() && address_v6.is_none()) || self.local_cid_generator.cid_len() == 0
+        {
+            return None;
+        }
+        let id = self.new_cid();
+        let cid = IssuedCid {
+            sequence: 1,
+            id,
+            reset_token: ResetToken::new(&*self.config.reset_key, &id),
+        };
+        let info = PreferredAddress {
+            address_v4,
+            address_v6,
+            connection_id: cid.id,
+            stateless_reset_token: cid.reset_token,
+        };
+        Some((cid, info))
+    }
+
     fn new_cid(&mut self) -> ConnectionId {
         loop {
             let cid = self.local_cid_generator.generate_cid();
@@ -421,10 +564,14 @@ impl Endpoint {
         remote: SocketAddr,
         local_ip: Option<IpAddr>,
         opts: ConnectionOpts,
+        version: u32,
         now: Instant,
     ) -> Result<(ConnectionHandle, Connection), ConnectError> {
         let loc_cid = self.new_cid();
-        let (server_config, tls, transport_config) = match opts {
+        let mut preferred_cid = None;
+        let mut version_negotiation = None;
+        let mut resume = None;
+        let (server_config, tls, transport_config, remote_validated) = match opts {
             ConnectionOpts::Client {
                 config,
                 server_name,
@@ -436,19 +583,35 @@ impl Endpoint {
                     loc_cid,
                     None,
                 );
-                (
-                    None,
-                    config.crypto.start_session(&server_name, &params)?,
-                    config.transport,
-                )
+                let tls = config
+                    .crypto
+                    .clone()
+                    .start_session(version, &server_name, &params)?;
+                resume = config
+                    .path_cache
+                    .map(|cache| CarefulResume::new(cache, server_name.clone()));
+                version_negotiation = Some(VersionNegotiation {
+                    crypto: config.crypto,
+                    server_name,
+                    params,
+                    supported_versions: self.config.supported_versions.clone(),
+                });
+                (None, tls, config.transport, true)
             }
             ConnectionOpts::Server {
                 orig_dst_cid,
                 retry_src_cid,
+                server_name,
+                reject_early_data,
             } => {
+                let preferred_address = self.preferred_address().map(|(cid, info)| {
+                    preferred_cid = Some(cid);
+                    info
+                });
                 let config = self.server_config.as_ref().unwrap();
+                let (crypto, transport) = config.select(server_name.as_deref());
                 let params = TransportParameters::new(
-                    &config.transport,
+                    transport,
                     &self.config,
                     self.local_cid_generator.as_ref(),
                     loc_cid,
@@ -458,17 +621,26 @@ impl Endpoint {
                     stateless_reset_token: Some(ResetToken::new(&*self.config.reset_key, &loc_cid)),
                     original_dst_cid: Some(orig_dst_cid),
                     retry_src_cid,
+                    preferred_address,
                     ..params
                 };
+                let mut tls = crypto.clone().start_session(version, &server_params);
+                if reject_early_data {
+                    debug!("rejecting 0-RTT data");
+                    tls.reject_early_data();
+                }
                 (
                     Some(config.clone()),
-                    config.crypto.clone().start_session(&server_params),
-                    config.transport.clone(),
+                    tls,
+                    transport.clone(),
+                    // The client proved it can receive packets at its address by echoing a token
+                    // from our Retry
+                    retry_src_cid.is_some(),
                 )
             }
         };
 
-        let conn = Connection::new(
+        let mut conn = Connection::new(
             server_config,
             transport_config,
             init_cid,
@@ -479,19 +651,33 @@ impl Endpoint {
             tls,
             self.local_cid_generator.as_ref(),
             now,
-            self.config.initial_version,
+            version,
+            version_negotiation,
+            remote_validated,
+            resume,
         );
+        let mut loc_cids: FxHashMap<u64, ConnectionId> = iter::once((0, loc_cid)).collect();
+        if let Some(cid) = preferred_cid {
+            conn.issued_preferred_address_cid(cid, now);
+            loc_cids.insert(cid.sequence, cid.id);
+        }
         let id = self.connections.insert(ConnectionMeta {
             init_cid,
-            cids_issued: 0,
-            loc_cids: iter::once((0, loc_cid)).collect(),
+            cids_issued: loc_cids.len() as u64 - 1,
+            loc_cids,
             initial_remote: remote,
             reset_token: None,
+            handshaking: false,
+            unaccepted: false,
+            detached: false,
         });
         let ch = ConnectionHandle(id);
 
         if self.local_cid_generator.cid_len() > 0 {
             self.connection_ids.insert(loc_cid, ch);
+            if let Some(cid) = preferred_cid {
+                self.connection_ids.insert(cid.id, ch);
+            }
         } else {
             self.connection_remotes.insert(remote, ch);
         }
@@ -508,14 +694,14 @@ impl Endpoint {
         rest: Option<BytesMut>,
         crypto: &Keys,
     ) -> Option<(ConnectionHandle, Connection)> {
-        let (src_cid, dst_cid, token, packet_number) = match packet.header {
+        let (src_cid, dst_cid, token, packet_number, version) = match packet.header {
             Header::Initial {
                 src_cid,
                 dst_cid,
                 ref token,
                 number,
-                ..
-            } => (src_cid, dst_cid, token.clone(), number),
+                version,
+            } => (src_cid, dst_cid, token.clone(), number, version),
             _ => panic!("non-initial packet in handle_first_packet()"),
         };
         let packet_number = packet_number.expand(0);
@@ -544,14 +730,18 @@ impl Endpoint {
         let server_config = self.server_config.as_ref().unwrap();
 
         if self.connections.len() >= server_config.concurrent_connections as usize
+            || self.unaccepted >= server_config.accept_buffer as usize
             || self.reject_new_connections
             || self.is_full()
         {
             debug!("refusing connection");
+            self.stats.connections_refused += 1;
+            self.handshake_failed(remote, HandshakeFailureReason::Refused);
             self.initial_close(
                 remote,
                 local_ip,
                 crypto,
+                version,
                 &src_cid,
                 &temp_loc_cid,
                 TransportError::CONNECTION_REFUSED(""),
@@ -559,8 +749,41 @@ impl Endpoint {
             return None;
         }
 
+        // Once too many handshakes are in progress, clients must prove ownership of their address
+        // before we commit any more resources, and are refused if they have already done so
+        let handshakes_full =
+            self.incoming_handshakes >= server_config.max_incoming_handshakes as usize;
+        let require_retry = match server_config.retry_policy {
+            Some(ref policy) => policy.require_retry(remote, self.incoming_handshakes),
+            None => server_config.use_stateless_retry,
+        };
+        let use_retry = require_retry || handshakes_full;
+        let token_codec = server_config
+            .retry_policy
+            .as_ref()
+            .and_then(|policy| policy.token_codec());
+
+        // A token is checked even when no Retry is required, since it may come from one we sent
+        // while too many handshakes were in progress. Tokens that don't validate are ignored in
+        // that case, as they were before retries could be triggered by load.
+        let retried = if token.is_empty() {
+            None
+        } else {
+            match token_codec {
+                Some(codec) => codec.decode(remote, &dst_cid, &token),
+                None => {
+                    RetryToken::from_bytes(&*server_config.token_key, &remote, &dst_cid, &token)
+                        .ok()
+                        .filter(|token| {
+                            token.issued + server_config.retry_token_lifetime > SystemTime::now()
+                        })
+                        .map(|token| token.orig_dst_cid)
+                }
+            }
+        };
+
         if dst_cid.len() < 8
-            && (!server_config.use_stateless_retry
+            && (!(use_retry || retried.is_some())
                 || dst_cid.len() != self.local_cid_generator.cid_len())
         {
             debug!(
@@ -571,6 +794,7 @@ impl Endpoint {
                 remote,
                 local_ip,
                 crypto,
+                version,
                 &src_cid,
                 &temp_loc_cid,
                 TransportError::PROTOCOL_VIOLATION("invalid destination CID length"),
@@ -578,29 +802,57 @@ impl Endpoint {
             return None;
         }
 
-        let (retry_src_cid, orig_dst_cid) = if server_config.use_stateless_retry {
-            if token.is_empty() {
+        let (retry_src_cid, orig_dst_cid) = match retried {
+            Some(orig_dst_cid) => {
+                if handshakes_full {
+                    debug!("refusing connection: too many handshakes in progress");
+                    self.stats.connections_refused += 1;
+                    self.handshake_failed(remote, HandshakeFailureReason::Refused);
+                    self.initial_close(
+                        remote,
+                        local_ip,
+                        crypto,
+                        version,
+                        &src_cid,
+                        &temp_loc_cid,
+                        TransportError::CONNECTION_REFUSED(""),
+                    );
+                    return None;
+                }
+                (Some(dst_cid), orig_dst_cid)
+            }
+            None if !use_retry => (None, dst_cid),
+            None if token.is_empty() => {
                 // First Initial
-                let mut random_bytes = vec![0u8; RetryToken::RANDOM_BYTES_LEN];
-                self.rng.fill_bytes(&mut random_bytes);
+                let token = match token_codec {
+                    Some(codec) => codec.encode(remote, &dst_cid, &temp_loc_cid),
+                    None => {
+                        let mut random_bytes = vec![0u8; RetryToken::RANDOM_BYTES_LEN];
+                        self.rng.fill_bytes(&mut random_bytes);
 
-                let token = RetryToken {
-                    orig_dst_cid: dst_cid,
-                    issued: SystemTime::now(),
-                    random_bytes: &random_bytes,
-                }
-                .encode(&*server_config.token_key, &remote, &temp_loc_cid);
+                        RetryToken {
+                            orig_dst_cid: dst_cid,
+                            issued: SystemTime::now(),
+                            random_bytes: &random_bytes,
+                        }
+                        .encode(
+                            &*server_config.token_key,
+                            &remote,
+                            &temp_loc_cid,
+                        )
+                    }
+                };
 
                 let header = Header::Retry {
                     src_cid: temp_loc_cid,
                     dst_cid: src_cid,
-                    version: self.config.initial_version,
+                    version,
                 };
 
                 let mut buf = Vec::new();
                 let encode = header.encode(&mut buf);
                 buf.put_slice(&token);
-                buf.extend_from_slice(&server_config.crypto.retry_tag(&dst_cid, &buf));
+                buf.extend_from_slice(&server_config.crypto.retry_tag(version, &dst_cid, &buf));
                 encode.finish(&mut buf, &*crypto.header.local, None);
 
                 self.transmits.push_back(Transmit {
@@ -612,30 +864,36 @@ impl Endpoint {
                 });
                 return None;
             }
-
-            match RetryToken::from_bytes(&*server_config.token_key, &remote, &dst_cid, &token) {
-                Ok(token)
-                    if token.issued + server_config.retry_token_lifetime > SystemTime::now() =>
-                {
-                    (Some(dst_cid), token.orig_dst_cid)
-                }
-                _ => {
-                    debug!("rejecting invalid stateless retry token");
-                    self.initial_close(
-                        remote,
-                        local_ip,
-                        crypto,
-                        &src_cid,
-                        &temp_loc_cid,
-                        TransportError::INVALID_TOKEN(""),
-                    );
-                    return None;
-                }
+            None => {
+                debug!("rejecting invalid stateless retry token");
+                self.handshake_failed(remote, HandshakeFailureReason::InvalidToken);
+                self.initial_close(
+                    remote,
+                    local_ip,
+                    crypto,
+                    version,
+                    &src_cid,
+                    &temp_loc_cid,
+                    TransportError::INVALID_TOKEN(""),
+                );
+                return None;
             }
-        } else {
-            (None, dst_cid)
         };
 
+        let hello =
+            if server_config.server_names.is_empty() && server_config.zero_rtt_policy.is_none() {
+                ClientHello::default()
+            } else {
+                sni::parse(packet.payload.clone().freeze())
+            };
+        let reject_early_data = match (&server_config.zero_rtt_policy, &hello.psk_identity) {
+            (Some(policy), Some(ticket)) if hello.early_data => {
+                !policy.accept_early_data(remote, ticket)
+            }
+            // The ticket didn't fit in the first packet, so the offer can't be vetted
+            (Some(_), None) => hello.early_data,
+            _ => false,
+        };
         let (ch, mut conn) = self
             .add_connection(
                 dst_cid,
@@ -645,13 +903,20 @@ impl Endpoint {
                 ConnectionOpts::Server {
                     retry_src_cid,
                     orig_dst_cid,
+                    server_name: hello.server_name,
+                    reject_early_data,
                 },
+                version,
                 now,
             )
             .unwrap();
         if dst_cid.len() != 0 {
             self.connection_ids_initial.insert(dst_cid, ch);
         }
+        self.connections[ch].handshaking = true;
+        self.incoming_handshakes += 1;
+        self.connections[ch].unaccepted = true;
+        self.unaccepted += 1;
         match conn.handle_first_packet(now, remote, ecn, packet_number as u64, packet, rest) {
             Ok(()) => {
                 trace!(id = ch.0, icid = %dst_cid, "connection incoming");
@@ -659,20 +924,41 @@ impl Endpoint {
             }
             Err(e) => {
                 debug!("handshake failed: {}", e);
+                self.handshake_failed(remote, e.clone().into());
                 self.handle_event(ch, EndpointEvent(EndpointEventInner::Drained));
                 if let ConnectionError::TransportError(e) = e {
-                    self.initial_close(remote, local_ip, crypto, &src_cid, &temp_loc_cid, e);
+                    self.initial_close(
+                        remote,
+                        local_ip,
+                        crypto,
+                        version,
+                        &src_cid,
+                        &temp_loc_cid,
+                        e,
+                    );
                 }
                 None
             }
         }
     }
 
+    /// Record a failed incoming connection attempt
+    fn handshake_failed(&mut self, remote: SocketAddr, reason: HandshakeFailureReason) {
+        debug!(%remote, ?reason, "incoming handshake failed");
+        self.stats.handshake_failures += 1;
+        if self.handshake_failures.len() >= MAX_HANDSHAKE_FAILURES {
+            self.handshake_failures.pop_front();
+        }
+        self.handshake_failures
+            .push_back(HandshakeFailure { remote, reason });
+    }
+
     fn initial_close(
         &mut self,
         destination: SocketAddr,
         local_ip: Option<IpAddr>,
         crypto: &Keys,
+        version: u32,
         remote_id: &ConnectionId,
         local_id: &ConnectionId,
         reason: TransportError,
@@ -683,7 +969,7 @@ impl Endpoint {
             src_cid: *local_id,
             number,
             token: Bytes::new(),
-            version: self.config.initial_version,
+            version,
         };
 
         let mut buf = Vec::<u8>::new();
@@ -707,11 +993,127 @@ impl Endpoint {
         })
     }
 
+    /// Prepare to move a connection to another endpoint
+    ///
+    /// The returned state can be passed to [`attach()`](Self::attach) on the endpoint that is to
+    /// take over the connection, after which the `Connection` is driven by that endpoint as usual.
+    /// Every event the connection emitted beforehand must first be passed to
+    /// [`handle_event()`](Self::handle_event), and any resulting `ConnectionEvent` delivered,
+    /// since events such as requests for new CIDs or reset token updates change the routing state
+    /// being handed over. If the connection can't be attached elsewhere, pass the state back to
+    /// [`restore()`](Self::restore).
+    ///
+    /// Datagrams for the connection keep being routed to `ch`, for the caller to forward to the
+    /// connection, since the peer may still send to this endpoint for a while. Once detached, the
+    /// connection must only pass a drained event for `ch`, which stops this routing and releases
+    /// the handle; any other event is a bug and is ignored.
+    ///
+    /// To keep stateless resets working for connection IDs issued before the move, both endpoints
+    /// should share the same `EndpointConfig::reset_key`.
+    pub fn detach(&mut self, ch: ConnectionHandle) -> DetachedConnection {
+        let meta = &mut self.connections[ch];
+        let initial_routed = !meta.init_cid.is_empty()
+            && self.connection_ids_initial.get(&meta.init_cid) == Some(&ch);
+        let detached = DetachedConnection {
+            meta: meta.clone(),
+            initial_routed,
+        };
+        if mem::replace(&mut meta.handshaking, false) {
+            self.incoming_handshakes -= 1;
+        }
+        if mem::replace(&mut meta.unaccepted, false) {
+            self.unaccepted -= 1;
+        }
+        meta.detached = true;
+        detached
+    }
+
+    /// Undo [`detach()`](Self::detach), for a connection that couldn't be attached elsewhere
+    ///
+    /// `ch` must be the handle `conn` was detached from, and not have been released yet.
+    pub fn restore(&mut self, ch: ConnectionHandle, conn: DetachedConnection) {
+        let meta = &mut self.connections[ch];
+        debug_assert!(meta.detached, "restoring a connection that wasn't detached");
+        *meta = conn.meta;
+        if meta.handshaking {
+            self.incoming_handshakes += 1;
+        }
+        if meta.unaccepted {
+            self.unaccepted += 1;
+        }
+    }
+
+    /// Start routing datagrams to a connection detached from another endpoint
+    ///
+    /// Returns the connection's handle on this endpoint. Fails without side effects if the
+    /// connection's identifiers can't be routed by this endpoint. Identifiers still routed to a
+    /// detached connection are taken over, so a connection can return to an endpoint it recently
+    /// left.
+    pub fn attach(&mut self, conn: &DetachedConnection) -> Result<ConnectionHandle, AttachError> {
+        let meta = &conn.meta;
+        let cid_len = self.local_cid_generator.cid_len();
+        if meta.loc_cids.values().any(|cid| cid.len() != cid_len) {
+            return Err(AttachError::ConnectionIdLength);
+        }
+        let live =
+            |ch: Option<&ConnectionHandle>| ch.map_or(false, |ch| !self.connections[*ch].detached);
+        let in_use = if cid_len > 0 {
+            meta.loc_cids
+                .values()
+                .any(|cid| live(self.connection_ids.get(cid)))
+        } else {
+            live(self.connection_remotes.get(&meta.initial_remote))
+        };
+        if in_use || (conn.initial_routed && live(self.connection_ids_initial.get(&meta.init_cid)))
+        {
+            return Err(AttachError::ConnectionIdInUse);
+        }
+
+        let ch = ConnectionHandle(self.connections.insert(meta.clone()));
+        if conn.initial_routed {
+            self.connection_ids_initial.insert(meta.init_cid, ch);
+        }
+        if cid_len > 0 {
+            for &cid in meta.loc_cids.values() {
+                self.connection_ids.insert(cid, ch);
+            }
+        } else {
+            self.connection_remotes.insert(meta.initial_remote, ch);
+        }
+        if let Some((remote, token)) = meta.reset_token {
+            self.connection_reset_tokens.insert(remote, token, ch);
+        }
+        if meta.handshaking {
+            self.incoming_handshakes += 1;
+        }
+        if meta.unaccepted {
+            self.unaccepted += 1;
+        }
+        Ok(ch)
+    }
+
+    /// Note that the application has taken responsibility for the incoming connection `ch`
+    ///
+    /// Frees up space in the [`ServerConfig::accept_buffer()`]. Has no effect on outgoing or
+    /// already accepted connections.
+    pub fn accept_connection(&mut self, ch: ConnectionHandle) {
+        let conn = &mut self.connections[ch];
+        if conn.unaccepted {
+            conn.unaccepted = false;
+            self.unaccepted -= 1;
+        }
+    }
+
     /// Unconditionally reject future incoming connections
     pub fn reject_new_connections(&mut self) {
         self.reject_new_connections = true;
     }
 
+    /// Counters for the stateless responses sent by this endpoint
+    pub fn stats(&self) -> EndpointStats {
+        self.stats
+    }
+
     /// Access the configuration used by this endpoint
     pub fn config(&self) -> &EndpointConfig {
         &self.config
@@ -759,11 +1161,14 @@ impl fmt::Debug for Endpoint {
             .field("config", &self.config)
             .field("server_config", &self.server_config)
             .field("reject_new_connections", &self.reject_new_connections)
+            .field("incoming_handshakes", &self.incoming_handshakes)
+            .field("unaccepted", &self.unaccepted)
+            .field("stats", &self.stats)
             .finish()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct ConnectionMeta {
     init_cid: ConnectionId,
     /// Number of local connection IDs that have been issued in NEW_CONNECTION_ID frames.
@@ -777,8 +1182,187 @@ pub(crate) struct ConnectionMeta {
     /// Reset token provided by the peer for the CID we're currently sending to, and the address
     /// being sent to
     reset_token: Option<(SocketAddr, ResetToken)>,
+    /// Whether this is an incoming connection that hasn't completed its handshake
+    handshaking: bool,
+    /// Whether this is an incoming connection that the application hasn't accepted yet
+    unaccepted: bool,
+    /// Whether the connection has been moved to another endpoint, leaving only its handle behind
+    detached: bool,
 }
 
+/// Routing state of a connection being moved between endpoints
+///
+/// Produced by [`Endpoint::detach()`] and consumed by [`Endpoint::attach()`].
+#[derive(Debug, Clone)]
+pub struct DetachedConnection {
+    meta: ConnectionMeta,
+    /// Whether the connection's initial destination CID was routed to it
+    initial_routed: bool,
+}
+
+/// Errors preventing a [`DetachedConnection`] from being attached to an endpoint
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum AttachError {
+    /// The endpoint uses connection IDs of a different length than the connection's
+    #[error("incompatible connection ID length")]
+    ConnectionIdLength,
+    /// One of the connection's identifiers is already routed to another connection
+    #[error("connection ID in use")]
+    ConnectionIdInUse,
+}
+
+/// Statistics about an endpoint's handshakes and the responses it sent to packets not belonging to
+/// any connection
+#[derive(Debug, Default, Copy, Clone)]
+#[non_exhaustive]
+pub struct EndpointStats {
+    /// The amount of Version Negotiation packets sent
+    pub version_negotiations: u64,
+    /// The amount of Version Negotiation packets not sent due to the rate limit
+    pub version_negotiations_limited: u64,
+    /// The amount of stateless resets sent
+    pub stateless_resets: u64,
+    /// The amount of stateless resets not sent due to the rate limit
+    pub stateless_resets_limited: u64,
+    /// The amount of incoming connection attempts which failed before completing the handshake
+    pub handshake_failures: u64,
+    /// The amount of incoming connection attempts refused with `CONNECTION_REFUSED` because the
+    /// endpoint was at capacity or no longer accepting connections
+    ///
+    /// Included in [`handshake_failures`](Self::handshake_failures).
+    pub connections_refused: u64,
+    /// The amount of connections, incoming or outgoing, which completed the handshake
+    pub handshakes: u64,
+    /// The amount of completed handshakes which resumed an earlier TLS session
+    pub resumed_handshakes: u64,
+    /// The amount of completed outgoing handshakes for which 0-RTT data could be sent
+    ///
+    /// Servers don't learn of 0-RTT attempts that they reject, so this only counts client
+    /// connections.
+    pub zero_rtt_offered: u64,
+    /// The amount of completed handshakes in which 0-RTT data was accepted
+    pub zero_rtt_accepted: u64,
+}
+
+/// Record of an incoming connection attempt that failed before completing the handshake
+///
+/// Obtained from [`Endpoint::poll_handshake_failure()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeFailure {
+    /// Address the connection attempt came from
+    pub remote: SocketAddr,
+    /// Why the handshake failed
+    pub reason: HandshakeFailureReason,
+}
+
+/// Stage at which an incoming connection attempt failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HandshakeFailureReason {
+    /// The client used a QUIC version not supported by this endpoint
+    ///
+    /// A Version Negotiation packet was sent in response, unless rate limited.
+    UnsupportedVersion(u32),
+    /// The address validation token presented by the client was invalid or expired
+    InvalidToken,
+    /// The endpoint refused the connection, e.g. because it was at capacity
+    Refused,
+    /// The TLS handshake failed with an alert
+    Tls {
+        /// TLS alert code
+        alert: u8,
+        /// Whether the alert was raised by this endpoint, rather than by the client
+        local: bool,
+    },
+    /// The client didn't complete the handshake in time
+    TimedOut,
+    /// The connection was closed for another reason
+    Closed(ConnectionError),
+}
+
+impl From<ConnectionError> for HandshakeFailureReason {
+    fn from(error: ConnectionError) -> Self {
+        let (code, local) = match error {
+            ConnectionError::TimedOut => return Self::TimedOut,
+            ConnectionError::TransportError(ref e) => (e.code, true),
+            ConnectionError::ConnectionClosed(ref close) => (close.error_code, false),
+            _ => return Self::Closed(error),
+        };
+        match code.tls_alert() {
+            Some(alert) => Self::Tls { alert, local },
+            None => Self::Closed(error),
+        }
+    }
+}
+
+/// Maximum number of failed handshakes retained for [`Endpoint::poll_handshake_failure()`]
+const MAX_HANDSHAKE_FAILURES: usize = 256;
+
+/// Enforces a `ResponseRateLimit` over one-second windows
+///
+/// Per-address counts share the global window and are all forgotten when it ends, so tracking a
+/// new address never requires scanning for stale entries.
+#[derive(Debug, Default)]
+struct ResponseLimiter {
+    global: RateWindow,
+    /// Responses sent to each address in the current window
+    ///
+    /// Uses a standard `HashMap` to protect against hash collision attacks.
+    per_address: HashMap<IpAddr, u32>,
+}
+
+impl ResponseLimiter {
+    /// Record a response to `remote`, unless doing so would exceed `limit`
+    fn allow(&mut self, now: Instant, remote: IpAddr, limit: &ResponseRateLimit) -> bool {
+        if !self.global.is_current(now) {
+            self.per_address.clear();
+        }
+        if self.global.count(now) >= limit.global {
+            return false;
+        }
+        if !self.per_address.contains_key(&remote) && self.per_address.len() >= MAX_RATE_LIMITED {
+            return false;
+        }
+        let sent = self.per_address.entry(remote).or_default();
+        if *sent >= limit.per_address {
+            return false;
+        }
+        *sent += 1;
+        self.global.sent += 1;
+        true
+    }
+}
+
+/// Number of responses sent since the start of the current window
+#[derive(Debug, Default, Copy, Clone)]
+struct RateWindow {
+    start: Option<Instant>,
+    sent: u32,
+}
+
+impl RateWindow {
+    fn is_current(&self, now: Instant) -> bool {
+        matches!(self.start, Some(start) if now < start + RATE_WINDOW)
+    }
+
+    /// Responses sent in the window containing `now`, starting a new window if necessary
+    fn count(&mut self, now: Instant) -> u32 {
+        if !self.is_current(now) {
+            self.start = Some(now);
+            self.sent = 0;
+        }
+        self.sent
+    }
+}
+
+/// Length of the windows over which response rates are measured
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Maximum number of addresses for which recent responses are tracked
+///
+/// Responses to further addresses are suppressed until the current window ends.
+const MAX_RATE_LIMITED: usize = 4096;
+
 /// Internal identifier for a `Connection` currently associated with an endpoint
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct ConnectionHandle(pub usize);
@@ -819,6 +1403,8 @@ enum ConnectionOpts {
     Server {
         retry_src_cid: Option<ConnectionId>,
         orig_dst_cid: ConnectionId,
+        server_name: Option<String>,
+        reject_early_data: bool,
     },
 }
 
@@ -837,7 +1423,7 @@ pub enum ConnectError {
     /// Try using longer connection IDs.
     #[error("too many connections")]
     TooManyConnections,
-    /// The domain name supplied was malformed
+    /// The server name supplied was neither a valid domain name nor an IP address
     #[error("invalid DNS name: {0}")]
     InvalidDnsName(String),
     /// The remote [`SocketAddr`] supplied was malformed
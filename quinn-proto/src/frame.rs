@@ -129,7 +129,9 @@ frame_types! {
     CONNECTION_CLOSE = 0x1c,
     APPLICATION_CLOSE = 0x1d,
     HANDSHAKE_DONE = 0x1e,
+    IMMEDIATE_ACK = 0x1f,
     // DATAGRAM
+    ACK_FREQUENCY = 0xaf,
 }
 
 const STREAM_TYS: RangeInclusive<u64> = RangeInclusive::new(0x08, 0x0f);
@@ -159,6 +161,8 @@ pub enum Frame {
     Datagram(Datagram),
     Invalid { ty: Type, reason: &'static str },
     HandshakeDone,
+    AckFrequency(AckFrequency),
+    ImmediateAck,
 }
 
 impl Frame {
@@ -199,8 +203,21 @@ impl Frame {
             Datagram(_) => Type(*DATAGRAM_TYS.start()),
             Invalid { ty, .. } => ty,
             HandshakeDone => Type::HANDSHAKE_DONE,
+            AckFrequency(_) => Type::ACK_FREQUENCY,
+            ImmediateAck => Type::IMMEDIATE_ACK,
         }
     }
+
+    /// Whether this frame may appear in a probing packet, which does not cause a migration
+    pub fn is_probing(&self) -> bool {
+        matches!(
+            *self,
+            Frame::Padding
+                | Frame::PathChallenge(_)
+                | Frame::PathResponse(_)
+                | Frame::NewConnectionId(_)
+        )
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -683,6 +700,13 @@ impl Iter {
                 token: self.take_len()?,
             },
             Type::HANDSHAKE_DONE => Frame::HandshakeDone,
+            Type::ACK_FREQUENCY => Frame::AckFrequency(AckFrequency {
+                sequence: self.bytes.get()?,
+                ack_eliciting_threshold: self.bytes.get()?,
+                request_max_ack_delay: self.bytes.get()?,
+                reordering_threshold: self.bytes.get()?,
+            }),
+            Type::IMMEDIATE_ACK => Frame::ImmediateAck,
             _ => {
                 if let Some(s) = ty.stream() {
                     Frame::Stream(Stream {
@@ -869,6 +893,36 @@ impl Datagram {
     }
 }
 
+/// Request for the peer to change how often it acknowledges packets
+///
+/// From draft-ietf-quic-ack-frequency.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AckFrequency {
+    /// Distinguishes newer requests from older ones, which are ignored
+    pub sequence: VarInt,
+    /// Number of ack-eliciting packets the peer may receive before acknowledging them
+    pub ack_eliciting_threshold: VarInt,
+    /// Maximum time the peer may delay an acknowledgement, in microseconds
+    pub request_max_ack_delay: VarInt,
+    /// How far packets may arrive out of order before the peer acknowledges them immediately, or
+    /// 0 to ignore reordering
+    pub reordering_threshold: VarInt,
+}
+
+impl FrameStruct for AckFrequency {
+    const SIZE_BOUND: usize = 2 + 4 * 8;
+}
+
+impl AckFrequency {
+    pub(crate) fn encode<W: BufMut>(&self, out: &mut W) {
+        out.write(Type::ACK_FREQUENCY);
+        out.write(self.sequence);
+        out.write(self.ack_eliciting_threshold);
+        out.write(self.request_max_ack_delay);
+        out.write(self.reordering_threshold);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -900,4 +954,27 @@ mod test {
             ref x => panic!("incorrect frame {:?}", x),
         }
     }
+
+    #[test]
+    fn ack_frequency_coding() {
+        let frame = AckFrequency {
+            sequence: VarInt(42),
+            ack_eliciting_threshold: VarInt(10),
+            request_max_ack_delay: VarInt(50_000),
+            reordering_threshold: VarInt(2),
+        };
+        let mut buf = Vec::new();
+        frame.encode(&mut buf);
+        buf.write(Type::IMMEDIATE_ACK);
+        let frames = Iter::new(Bytes::from(buf)).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 2);
+        match frames[0] {
+            Frame::AckFrequency(x) => assert_eq!(x, frame),
+            ref x => panic!("incorrect frame {:?}", x),
+        }
+        match frames[1] {
+            Frame::ImmediateAck => {}
+            ref x => panic!("incorrect frame {:?}", x),
+        }
+    }
 }
@@ -31,6 +31,7 @@ pub mod coding;
 mod constant_time;
 mod packet;
 mod range_set;
+mod sni;
 #[cfg(all(test, feature = "rustls"))]
 mod tests;
 pub mod transport_parameters;
@@ -40,14 +41,21 @@ pub use varint::{VarInt, VarIntBoundsExceeded};
 
 mod connection;
 pub use crate::connection::{
-    BytesSource, Chunk, Chunks, Connection, ConnectionError, ConnectionStats, Datagrams, Event,
-    FinishError, ReadError, ReadableError, RecvStream, SendDatagramError, SendStream, StreamEvent,
-    Streams, UnknownStream, WriteError, Written,
+    BytesSource, Chunk, Chunks, Connection, ConnectionError, ConnectionStats, DatagramDropPolicy,
+    Datagrams, Event, FinishError, MigrateError, ReadError, ReadableError, RecvStream,
+    RecvStreamState, RecvStreamStats, RttEstimator, RttHistogram, SchedulerKind, SendDatagramError,
+    SendStream, SendStreamState, SendStreamStats, StreamEvent, StreamInfo, Streams, UnknownStream,
+    WriteError, Written,
 };
+#[cfg(feature = "qlog")]
+pub use crate::connection::{QlogDir, QlogFactory};
 
 mod config;
+#[cfg(feature = "rustls")]
+pub use config::ClientConfigBuilder;
 pub use config::{
-    ClientConfig, ConfigError, EndpointConfig, IdleTimeout, ServerConfig, TransportConfig,
+    AckFrequencyConfig, ClientConfig, ConfigError, EndpointConfig, IdleTimeout, ResponseRateLimit,
+    ServerConfig, TransportConfig,
 };
 
 pub mod crypto;
@@ -59,7 +67,10 @@ use crate::frame::Frame;
 pub use crate::frame::{ApplicationClose, ConnectionClose, Datagram};
 
 mod endpoint;
-pub use crate::endpoint::{ConnectError, ConnectionHandle, DatagramEvent, Endpoint};
+pub use crate::endpoint::{
+    AttachError, ConnectError, ConnectionHandle, DatagramEvent, DetachedConnection, Endpoint,
+    EndpointStats, HandshakeFailure, HandshakeFailureReason,
+};
 
 mod shared;
 pub use crate::shared::{ConnectionEvent, ConnectionId, EcnCodepoint, EndpointEvent};
@@ -70,7 +81,23 @@ pub use crate::transport_error::{Code as TransportErrorCode, Error as TransportE
 pub mod congestion;
 
 mod cid_generator;
-pub use crate::cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator};
+pub use crate::cid_generator::{
+    ConnectionIdGenerator, InvalidCid, RandomConnectionIdGenerator, ShardedConnectionIdGenerator,
+};
+#[cfg(feature = "ring")]
+pub use crate::cid_generator::{LoadBalancerConfig, LoadBalancerConnectionIdGenerator};
+
+mod retry_policy;
+pub use crate::retry_policy::{RetryPolicy, RetryTokenCodec};
+
+mod zero_rtt_policy;
+pub use crate::zero_rtt_policy::ZeroRttPolicy;
+
+mod session_cache;
+pub use crate::session_cache::SessionCache;
+
+mod path_cache;
+pub use crate::path_cache::{PathCache, PathParameters};
 
 mod token;
 use token::{ResetToken, RetryToken};
@@ -125,9 +152,14 @@ pub mod fuzzing {
     }
 }
 
-/// The QUIC protocol version implemented.
-pub const DEFAULT_SUPPORTED_VERSIONS: &[u32] =
-    &[0xff00_001d, 0xff00_001e, 0xff00_001f, 0xff00_0020];
+/// The QUIC protocol versions implemented: drafts 29 through 32, and version 1 (RFC 9000)
+pub const DEFAULT_SUPPORTED_VERSIONS: &[u32] = &[
+    0xff00_001d,
+    0xff00_001e,
+    0xff00_001f,
+    0xff00_0020,
+    0x0000_0001,
+];
 
 /// Whether an endpoint was the initiator of a connection
 #[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
@@ -247,6 +279,12 @@ impl From<StreamId> for VarInt {
     }
 }
 
+impl From<StreamId> for u64 {
+    fn from(x: StreamId) -> u64 {
+        x.0
+    }
+}
+
 impl From<VarInt> for StreamId {
     fn from(v: VarInt) -> Self {
         Self(v.0)
@@ -91,6 +91,15 @@ impl PartialDecode {
         self.plain_header.dst_cid()
     }
 
+    /// The QUIC version of a long header packet
+    pub(crate) fn version(&self) -> Option<u32> {
+        use self::PlainHeader::*;
+        match self.plain_header {
+            Initial { version, .. } | Long { version, .. } | Retry { version, .. } => Some(version),
+            _ => None,
+        }
+    }
+
     /// Length of QUIC packet being decoded
     pub fn len(&self) -> usize {
         self.buf.get_ref().len()
@@ -828,9 +837,10 @@ mod tests {
     #[test]
     fn header_encoding() {
         use crate::{crypto::rustls::initial_keys, Side};
+        use rustls::quic::Version;
 
         let dcid = ConnectionId::new(&hex!("06b858ec6f80452b"));
-        let client = initial_keys(&dcid, Side::Client);
+        let client = initial_keys(Version::V1Draft, &dcid, Side::Client);
         let mut buf = Vec::new();
         let header = Header::Initial {
             number: PacketNumber::U8(0),
@@ -860,7 +870,7 @@ mod tests {
             )[..]
         );
 
-        let server = initial_keys(&dcid, Side::Server);
+        let server = initial_keys(Version::V1Draft, &dcid, Side::Server);
         let supported_versions = DEFAULT_SUPPORTED_VERSIONS.to_vec();
         let decode = PartialDecode::new(buf.as_slice().into(), 0, &supported_versions)
             .unwrap()
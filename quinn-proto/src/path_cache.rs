@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+/// Characteristics of the network path to a server, as observed by an earlier connection
+///
+/// Obtained from [`Connection::path_parameters()`](crate::Connection::path_parameters), and
+/// stored in a [`PathCache`] when a client connection closes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PathParameters {
+    /// Smoothed round-trip time of the path
+    pub rtt: Duration,
+    /// Rate at which the path delivered data, in bytes per second
+    pub delivery_rate: u64,
+}
+
+impl PathParameters {
+    /// The bandwidth-delay product of the path, in bytes
+    pub(crate) fn window(&self) -> u64 {
+        let rtt = self.rtt.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.delivery_rate.saturating_mul(rtt) / 1_000_000
+    }
+}
+
+/// Stores the path characteristics observed by client connections, allowing later connections
+/// to the same server to skip most of slow start
+///
+/// Configured with [`ClientConfig::path_cache`](crate::ClientConfig::path_cache). Connections
+/// look up the parameters saved for their server name, and once their first round-trip time
+/// sample confirms the path is unchanged, jump their congestion window to half the saved
+/// bandwidth-delay product following the careful resume draft (draft-ietf-tsvwg-careful-resume).
+/// The jump is retreated from if it causes congestion before being validated. This matters most
+/// on long, fat paths such as satellite links, where slow start takes many seconds to ramp up.
+///
+/// Implementations can persist entries to disk so that they survive process restarts. Entries are
+/// keyed by the server name passed when connecting, and should be discarded once stale, since
+/// they describe the path only at the time they were stored.
+pub trait PathCache: Send + Sync {
+    /// Store `params` for connections to `server_name`, replacing any earlier value
+    fn put(&self, server_name: &str, params: PathParameters);
+
+    /// The most recent parameters stored for `server_name`, if any
+    fn get(&self, server_name: &str) -> Option<PathParameters>;
+}
@@ -0,0 +1,59 @@
+use std::net::SocketAddr;
+
+use crate::shared::ConnectionId;
+
+/// Decides which incoming connections must validate their address with a Retry
+///
+/// Configured with [`ServerConfig::retry_policy()`](crate::ServerConfig::retry_policy), in which
+/// case it supersedes [`ServerConfig::use_stateless_retry()`](crate::ServerConfig::use_stateless_retry).
+/// Clients are sent a Retry regardless of the policy while
+/// [`ServerConfig::max_incoming_handshakes()`](crate::ServerConfig::max_incoming_handshakes) is
+/// exceeded.
+pub trait RetryPolicy: Send + Sync {
+    /// Whether the client at `remote` must prove ownership of its address before its connection
+    /// attempt is accepted
+    ///
+    /// `incoming_handshakes` is the number of incoming connections currently handshaking, allowing
+    /// Retry to be required only under load.
+    fn require_retry(&self, remote: SocketAddr, incoming_handshakes: usize) -> bool;
+
+    /// Scheme for minting and validating the tokens carried by Retry packets
+    ///
+    /// Tokens are protected with [`ServerConfig::token_key()`](crate::ServerConfig::token_key)
+    /// and expire after [`ServerConfig::retry_token_lifetime()`](crate::ServerConfig::retry_token_lifetime)
+    /// if `None`, which is the default.
+    fn token_codec(&self) -> Option<&dyn RetryTokenCodec> {
+        None
+    }
+}
+
+/// Mints and validates the tokens carried by Retry packets
+///
+/// Allows tokens to be shared with other infrastructure, e.g. a separate anti-DDoS appliance
+/// which answers connection attempts with Retry packets on the server's behalf.
+pub trait RetryTokenCodec: Send + Sync {
+    /// Construct the token sent to the client at `remote` in a Retry packet
+    ///
+    /// `orig_dst_cid` is the destination connection ID of the client's first Initial packet, and
+    /// `retry_src_cid` the connection ID the client is told to use in its next Initial packet.
+    /// Tokens must be authenticated so that clients can't forge them.
+    fn encode(
+        &self,
+        remote: SocketAddr,
+        orig_dst_cid: &ConnectionId,
+        retry_src_cid: &ConnectionId,
+    ) -> Vec<u8>;
+
+    /// Validate the `token` echoed by the client at `remote`, returning the original destination
+    /// connection ID it was minted for
+    ///
+    /// `retry_src_cid` is the destination connection ID of the client's Initial packet carrying
+    /// the token. Returns `None` if the token is invalid or has expired, in which case the
+    /// connection attempt is refused.
+    fn decode(
+        &self,
+        remote: SocketAddr,
+        retry_src_cid: &ConnectionId,
+        token: &[u8],
+    ) -> Option<ConnectionId>;
+}
@@ -0,0 +1,17 @@
+/// Stores the state clients need to resume sessions, and hence send 0-RTT data
+///
+/// Configured with
+/// [`ClientConfigBuilder::session_cache()`](crate::ClientConfigBuilder::session_cache), or for a
+/// custom rustls configuration with
+/// [`crypto::rustls::SessionStorage`](crate::crypto::rustls::SessionStorage). Implementations can
+/// persist entries to disk or a keyring so that sessions survive process restarts. Values hold
+/// session tickets along with the server transport parameters that 0-RTT depends on; keys and
+/// values are opaque and need only be returned as they were stored. Values are secret, since
+/// they allow whoever holds them to resume the client's sessions.
+pub trait SessionCache: Send + Sync {
+    /// Store `value` for `key`, replacing any earlier value
+    fn put(&self, key: Vec<u8>, value: Vec<u8>);
+
+    /// The most recent value stored for `key`, if any
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+}
@@ -2,7 +2,7 @@ use std::{fmt, net::SocketAddr, time::Instant};
 
 use bytes::{Buf, BufMut, BytesMut};
 
-use crate::{coding::BufExt, packet::PartialDecode, ResetToken, MAX_CID_SIZE};
+use crate::{coding::BufExt, packet::PartialDecode, ConnectionError, ResetToken, MAX_CID_SIZE};
 
 /// Events sent from an Endpoint to a Connection
 #[derive(Debug)]
@@ -22,6 +22,15 @@ pub(crate) enum ConnectionEventInner {
     NewIdentifiers(Vec<IssuedCid>, Instant),
 }
 
+impl ConnectionEvent {
+    /// Determine whether this event carries a datagram received from the peer
+    ///
+    /// Useful for telling when the peer has started reaching a connection through a new endpoint.
+    pub fn is_datagram(&self) -> bool {
+        matches!(self.0, ConnectionEventInner::Datagram { .. })
+    }
+}
+
 /// Events sent from a Connection to an Endpoint
 #[derive(Debug)]
 pub struct EndpointEvent(pub(crate) EndpointEventInner);
@@ -47,6 +56,15 @@ impl EndpointEvent {
 pub(crate) enum EndpointEventInner {
     /// The connection has been drained
     Drained,
+    /// The handshake has completed
+    Established {
+        /// Whether an earlier TLS session was resumed
+        resumed: bool,
+        /// Whether 0-RTT data could be sent (clients only)
+        zero_rtt_offered: bool,
+        /// Whether 0-RTT data was accepted
+        zero_rtt_accepted: bool,
+    },
     /// The reset token and/or address eligible for generating resets has been updated
     ResetToken(SocketAddr, ResetToken),
     /// The connection needs connection identifiers
@@ -54,6 +72,8 @@ pub(crate) enum EndpointEventInner {
     /// Stop routing connection ID for this sequence number to the connection
     /// When `bool == true`, a new connection ID will be issued to peer
     RetireConnectionId(Instant, u64, bool),
+    /// An incoming connection failed before completing its handshake
+    HandshakeFailed(ConnectionError),
 }
 
 /// Protocol-level identifier for a connection.
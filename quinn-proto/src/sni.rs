@@ -0,0 +1,208 @@
+//! Extraction of details of the ClientHello from a client's first Initial packet
+//!
+//! Lets a server choose a configuration for an incoming connection and decide whether to accept
+//! 0-RTT data before handing the ClientHello to the TLS implementation. Parsing is best-effort:
+//! anything after an unexpected byte is ignored.
+
+use std::convert::TryInto;
+
+use bytes::Bytes;
+
+use crate::frame::{self, Frame};
+
+/// TLS handshake message type of a ClientHello
+const CLIENT_HELLO: u8 = 1;
+/// TLS extension type of the server name indication
+const SERVER_NAME: u16 = 0;
+/// TLS extension type of the pre-shared keys offered for resumption
+const PRE_SHARED_KEY: u16 = 41;
+/// TLS extension type of the early data indication
+const EARLY_DATA: u16 = 42;
+/// Server name type of a DNS host name
+const HOST_NAME: u8 = 0;
+
+/// Details of a ClientHello
+#[derive(Debug, Default)]
+pub(crate) struct ClientHello {
+    /// The lowercase host name requested by the server name indication
+    pub(crate) server_name: Option<String>,
+    /// Whether the client offered 0-RTT data
+    pub(crate) early_data: bool,
+    /// The identity of the first pre-shared key offered, i.e. the session ticket being resumed
+    pub(crate) psk_identity: Option<Vec<u8>>,
+}
+
+/// Parse the ClientHello in the decrypted `payload` of a client's first Initial packet
+///
+/// Only the CRYPTO data contiguous from offset 0 is considered, so details which don't fit in
+/// the first packet are missing.
+pub(crate) fn parse(payload: Bytes) -> ClientHello {
+    let mut frames = frame::Iter::new(payload)
+        .filter_map(|frame| match frame {
+            Frame::Crypto(x) => Some(x),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    frames.sort_unstable_by_key(|x| x.offset);
+    let mut data = Vec::new();
+    for frame in frames {
+        let end = frame.offset + frame.data.len() as u64;
+        if frame.offset > data.len() as u64 {
+            break;
+        }
+        if end > data.len() as u64 {
+            data.extend_from_slice(&frame.data[data.len() - frame.offset as usize..]);
+        }
+    }
+    let mut hello = ClientHello::default();
+    parse_client_hello(&data, &mut hello);
+    hello
+}
+
+/// Fill in `hello` from the extensions of a possibly truncated ClientHello message
+fn parse_client_hello(mut data: &[u8], hello: &mut ClientHello) -> Option<()> {
+    if take(&mut data, 1)?[0] != CLIENT_HELLO {
+        return None;
+    }
+    let len = take_len(&mut data, 3)?;
+    let mut body = &data[..len.min(data.len())];
+    // legacy_version, random
+    take(&mut body, 2 + 32)?;
+    // legacy_session_id
+    let len = take_len(&mut body, 1)?;
+    take(&mut body, len)?;
+    // cipher_suites
+    let len = take_len(&mut body, 2)?;
+    take(&mut body, len)?;
+    // legacy_compression_methods
+    let len = take_len(&mut body, 1)?;
+    take(&mut body, len)?;
+    let len = take_len(&mut body, 2)?;
+    let mut extensions = &body[..len.min(body.len())];
+    while !extensions.is_empty() {
+        let ty = u16::from_be_bytes(take(&mut extensions, 2)?.try_into().unwrap());
+        let len = take_len(&mut extensions, 2)?;
+        let mut extension = take(&mut extensions, len)?;
+        match ty {
+            SERVER_NAME => hello.server_name = parse_server_name(extension),
+            EARLY_DATA => hello.early_data = true,
+            PRE_SHARED_KEY => {
+                let len = take_len(&mut extension, 2)?;
+                let mut identities = take(&mut extension, len)?;
+                let len = take_len(&mut identities, 2)?;
+                hello.psk_identity = Some(take(&mut identities, len)?.to_vec());
+            }
+            _ => {}
+        }
+    }
+    Some(())
+}
+
+/// Find the host name in a server name extension
+fn parse_server_name(mut extension: &[u8]) -> Option<String> {
+    let len = take_len(&mut extension, 2)?;
+    let mut names = take(&mut extension, len)?;
+    while !names.is_empty() {
+        let ty = take(&mut names, 1)?[0];
+        let len = take_len(&mut names, 2)?;
+        let name = take(&mut names, len)?;
+        if ty == HOST_NAME {
+            let name = std::str::from_utf8(name).ok()?;
+            return Some(name.trim_end_matches('.').to_ascii_lowercase());
+        }
+    }
+    None
+}
+
+/// Split off the first `n` bytes of `data`
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if data.len() < n {
+        return None;
+    }
+    let (head, tail) = data.split_at(n);
+    *data = tail;
+    Some(head)
+}
+
+/// Split off a big-endian length field of `n` bytes
+fn take_len(data: &mut &[u8], n: usize) -> Option<usize> {
+    Some(
+        take(data, n)?
+            .iter()
+            .fold(0, |acc, &byte| acc << 8 | usize::from(byte)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_hello(extensions: &[u8]) -> Vec<u8> {
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[1, 0xff]); // session ID
+        hello.extend_from_slice(&[0, 2, 0x13, 0x01]); // cipher suites
+        hello.extend_from_slice(&[1, 0]); // compression methods
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(extensions);
+        let mut msg = vec![CLIENT_HELLO, 0];
+        msg.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&hello);
+        msg
+    }
+
+    fn sni_extension(name: &[u8]) -> Vec<u8> {
+        let mut ext = SERVER_NAME.to_be_bytes().to_vec();
+        ext.extend_from_slice(&(name.len() as u16 + 5).to_be_bytes());
+        ext.extend_from_slice(&(name.len() as u16 + 3).to_be_bytes());
+        ext.push(HOST_NAME);
+        ext.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        ext.extend_from_slice(name);
+        ext
+    }
+
+    fn parse_bytes(data: &[u8]) -> ClientHello {
+        let mut hello = ClientHello::default();
+        parse_client_hello(data, &mut hello);
+        hello
+    }
+
+    fn server_name(data: &[u8]) -> Option<String> {
+        parse_bytes(data).server_name
+    }
+
+    #[test]
+    fn parse() {
+        // An unrelated extension precedes the server name
+        let mut extensions = vec![0, 10, 0, 2, 0, 29];
+        extensions.extend_from_slice(&sni_extension(b"Example.com."));
+        let hello = client_hello(&extensions);
+        assert_eq!(server_name(&hello).as_deref(), Some("example.com"));
+
+        assert_eq!(server_name(&client_hello(&[0, 10, 0, 2, 0, 29])), None);
+        // Truncated before the server name is complete
+        assert_eq!(server_name(&hello[..hello.len() - 1]), None);
+        assert_eq!(server_name(&[2, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn parse_resumption() {
+        let mut extensions = EARLY_DATA.to_be_bytes().to_vec();
+        extensions.extend_from_slice(&[0, 0]);
+        extensions.extend_from_slice(&PRE_SHARED_KEY.to_be_bytes());
+        // Extension, identities, and first identity lengths, followed by the ticket age
+        extensions.extend_from_slice(&[0, 14, 0, 12, 0, 6]);
+        extensions.extend_from_slice(b"ticket");
+        extensions.extend_from_slice(&[0; 4]);
+        let data = client_hello(&extensions);
+        let hello = parse_bytes(&data);
+        assert!(hello.early_data);
+        assert_eq!(hello.psk_identity.as_deref(), Some(&b"ticket"[..]));
+        assert_eq!(hello.server_name, None);
+
+        // Truncated within the ticket
+        let hello = parse_bytes(&data[..data.len() - 6]);
+        assert!(hello.early_data);
+        assert_eq!(hello.psk_identity, None);
+    }
+}
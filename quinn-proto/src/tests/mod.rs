@@ -1,7 +1,12 @@
 use std::{
-    convert::TryInto,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
-    sync::Arc,
+    collections::{HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    iter,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
@@ -14,8 +19,11 @@ use rustls::internal::msgs::enums::AlertDescription;
 use tracing::info;
 
 use super::*;
-use crate::cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator};
-use crate::{Certificate, CertificateChain, PrivateKey};
+use crate::cid_generator::{
+    ConnectionIdGenerator, LoadBalancerConfig, LoadBalancerConnectionIdGenerator,
+    RandomConnectionIdGenerator, ShardedConnectionIdGenerator,
+};
+use crate::{crypto::rustls::SctPolicy, Certificate, CertificateChain, PrivateKey};
 mod util;
 use util::*;
 
@@ -40,13 +48,65 @@ fn version_negotiate_server() {
     if let Some(Transmit { contents, .. }) = io {
         assert_ne!(contents[0] & 0x80, 0);
         assert_eq!(&contents[1..15], hex!("00000000 04 00000000 04 00000000"));
-        assert!(contents[15..].chunks(4).any(|x| {
-            DEFAULT_SUPPORTED_VERSIONS.contains(&u32::from_be_bytes(x.try_into().unwrap()))
-        }));
+        let offered = contents[15..]
+            .chunks(4)
+            .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert!(DEFAULT_SUPPORTED_VERSIONS
+            .iter()
+            .all(|version| offered.contains(version)));
     }
     assert_matches!(server.poll_transmit(), None);
 }
 
+#[test]
+fn stateless_response_rate_limit() {
+    let _guard = subscribe();
+    let limit = ResponseRateLimit {
+        global: 3,
+        per_address: 2,
+    };
+    let mut config = EndpointConfig::default();
+    config
+        .version_negotiation_rate_limit(Some(limit))
+        .stateless_reset_rate_limit(Some(limit));
+    let mut server = Endpoint::new(Arc::new(config), Some(Arc::new(server_config())));
+    let a = "[::2]:7890".parse().unwrap();
+    let b = "[::3]:7890".parse().unwrap();
+    let now = Instant::now();
+    let responses = |server: &mut Endpoint, now: Instant, remote: SocketAddr, packet: &[u8]| {
+        assert!(server
+            .handle(now, remote, None, None, packet.into())
+            .is_none());
+        iter::from_fn(|| server.poll_transmit()).count()
+    };
+
+    // Long-header packet with reserved version number
+    let unsupported = hex!("80 0a1a2a3a 04 00000000 04 00000000 00");
+    assert_eq!(responses(&mut server, now, a, &unsupported), 1);
+    assert_eq!(responses(&mut server, now, a, &unsupported), 1);
+    // Per-address limit reached
+    assert_eq!(responses(&mut server, now, a, &unsupported), 0);
+    assert_eq!(responses(&mut server, now, b, &unsupported), 1);
+    // Global limit reached
+    assert_eq!(responses(&mut server, now, b, &unsupported), 0);
+    let later = now + Duration::from_secs(1);
+    assert_eq!(responses(&mut server, later, a, &unsupported), 1);
+
+    // Short-header packet for an unknown connection
+    let mut unknown = vec![0x40; 64];
+    unknown[1..9].copy_from_slice(&[0xab; 8]);
+    assert_eq!(responses(&mut server, later, a, &unknown), 1);
+    assert_eq!(responses(&mut server, later, a, &unknown), 1);
+    assert_eq!(responses(&mut server, later, a, &unknown), 0);
+
+    let stats = server.stats();
+    assert_eq!(stats.version_negotiations, 4);
+    assert_eq!(stats.version_negotiations_limited, 2);
+    assert_eq!(stats.stateless_resets, 2);
+    assert_eq!(stats.stateless_resets_limited, 1);
+}
+
 #[test]
 fn version_negotiate_client() {
     let _guard = subscribe();
@@ -87,11 +147,87 @@ fn version_negotiate_client() {
     );
 }
 
+#[test]
+fn negotiated_version() {
+    let _guard = subscribe();
+    let version = DEFAULT_SUPPORTED_VERSIONS[1];
+    let mut client_config = EndpointConfig::default();
+    client_config
+        .supported_versions(DEFAULT_SUPPORTED_VERSIONS.to_vec(), version)
+        .unwrap();
+    let client = Endpoint::new(Arc::new(client_config), None);
+    let server = Endpoint::new(Default::default(), Some(Arc::new(server_config())));
+    let mut pair = Pair::new_from_endpoint(client, server);
+    let (client_ch, server_ch) = pair.connect();
+    assert_eq!(pair.client_conn_mut(client_ch).version(), version);
+    assert_eq!(pair.server_conn_mut(server_ch).version(), version);
+}
+
+#[test]
+fn version_negotiation_restart() {
+    let _guard = subscribe();
+    // Start out with a reserved version to force version negotiation
+    const GREASE: u32 = 0x1a2a_3a4a;
+    let mut client_config = EndpointConfig::default();
+    client_config
+        .supported_versions(vec![GREASE, 1], GREASE)
+        .unwrap();
+    let mut server_config = EndpointConfig::default();
+    server_config.supported_versions(vec![1], 1).unwrap();
+    let client = Endpoint::new(Arc::new(client_config), None);
+    let server = Endpoint::new(
+        Arc::new(server_config),
+        Some(Arc::new(ServerConfig {
+            use_stateless_retry: true,
+            ..self::server_config()
+        })),
+    );
+    let mut pair = Pair::new_from_endpoint(client, server);
+    let config = self::client_config();
+    let client_ch = pair.begin_connect(config.clone());
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert_eq!(pair.client_conn_mut(client_ch).version(), 1);
+    assert_eq!(pair.server_conn_mut(server_ch).version(), 1);
+    assert_eq!(pair.server.endpoint.stats().version_negotiations, 1);
+    pair.client
+        .connections
+        .get_mut(&client_ch)
+        .unwrap()
+        .close(pair.time, VarInt(0), [][..].into());
+    pair.drive();
+    pair.client.connections.clear();
+    pair.server.connections.clear();
+
+    // 0-RTT data is sent with the reserved version again, and is discarded when the handshake
+    // restarts
+    info!("resuming session");
+    let client_ch = pair.begin_connect(config);
+    assert!(pair.client_conn_mut(client_ch).has_0rtt());
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .write(b"Hello, 0-RTT!")
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.server.endpoint.stats().version_negotiations, 2);
+    assert!(pair.client_conn_mut(client_ch).version_changed_0rtt());
+    assert!(!pair.client_conn_mut(client_ch).has_0rtt());
+    assert!(!pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert_matches!(
+        pair.client_send(client_ch, s).write(b"again"),
+        Err(WriteError::UnknownStream)
+    );
+    let server_ch = pair.server.assert_accept();
+    assert_eq!(pair.server_conn_mut(server_ch).version(), 1);
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), None);
+}
+
 #[test]
 fn lifecycle() {
     let _guard = subscribe();
     let mut pair = Pair::default();
     let (client_ch, server_ch) = pair.connect();
+    assert!(!pair.server_conn_mut(server_ch).remote_address_validated());
     assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
     assert!(pair.client_conn_mut(client_ch).using_ecn());
     assert!(pair.server_conn_mut(server_ch).using_ecn());
@@ -125,7 +261,59 @@ fn stateless_retry() {
             ..server_config()
         },
     );
-    pair.connect();
+    let (client_ch, server_ch) = pair.connect();
+    assert!(pair.server_conn_mut(server_ch).remote_address_validated());
+    assert!(pair.client_conn_mut(client_ch).remote_address_validated());
+}
+
+#[test]
+fn retry_policy() {
+    struct Policy {
+        require: bool,
+        encoded: AtomicUsize,
+    }
+
+    impl RetryPolicy for Policy {
+        fn require_retry(&self, _: SocketAddr, incoming_handshakes: usize) -> bool {
+            assert_eq!(incoming_handshakes, 0);
+            self.require
+        }
+
+        fn token_codec(&self) -> Option<&dyn RetryTokenCodec> {
+            Some(self)
+        }
+    }
+
+    impl RetryTokenCodec for Policy {
+        fn encode(&self, _: SocketAddr, orig_dst_cid: &ConnectionId, _: &ConnectionId) -> Vec<u8> {
+            self.encoded.fetch_add(1, Ordering::Relaxed);
+            [&b"token"[..], orig_dst_cid].concat()
+        }
+
+        fn decode(&self, _: SocketAddr, _: &ConnectionId, token: &[u8]) -> Option<ConnectionId> {
+            token.strip_prefix(b"token").map(ConnectionId::new)
+        }
+    }
+
+    let _guard = subscribe();
+    for &require in &[true, false] {
+        let policy = Arc::new(Policy {
+            require,
+            encoded: AtomicUsize::new(0),
+        });
+        let mut server = ServerConfig {
+            use_stateless_retry: true,
+            ..server_config()
+        };
+        server.retry_policy(Some(policy.clone()));
+        let mut pair = Pair::new(Default::default(), server);
+        let (_, server_ch) = pair.connect();
+        assert_eq!(
+            pair.server_conn_mut(server_ch).remote_address_validated(),
+            require
+        );
+        assert_eq!(policy.encoded.load(Ordering::Relaxed), require as usize);
+    }
 }
 
 #[test]
@@ -252,6 +440,136 @@ fn finish_stream_simple() {
     let _ = chunks.finalize();
 }
 
+#[test]
+fn stream_stats() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let initial = pair.client_send(client_ch, s).stats().unwrap();
+    assert_eq!(initial.bytes_written, 0);
+    assert!(initial.flow_control_credit > 0);
+
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive();
+
+    let stats = pair.client_send(client_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_written, MSG.len() as u64);
+    assert_eq!(stats.bytes_acked, MSG.len() as u64);
+    assert_eq!(stats.bytes_retransmitted, 0);
+    assert_eq!(
+        stats.flow_control_credit,
+        initial.flow_control_credit - MSG.len() as u64
+    );
+
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let stats = pair.server_recv(server_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_received, MSG.len() as u64);
+    assert_eq!(stats.bytes_read, 0);
+
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Ok(Some(chunk)) if chunk.bytes == MSG);
+    let _ = chunks.finalize();
+    let stats = pair.server_recv(server_ch, s).stats().unwrap();
+    assert_eq!(stats.bytes_read, MSG.len() as u64);
+}
+
+#[test]
+fn open_streams() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Bi).unwrap();
+    let u = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.client_send(client_ch, u).write(b"hello").unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    assert_eq!(
+        pair.client_streams(client_ch).open_streams(),
+        [
+            StreamInfo {
+                id: s,
+                send: Some(SendStreamState::DataSent),
+                recv: Some(RecvStreamState::Recv),
+            },
+            StreamInfo {
+                id: u,
+                send: Some(SendStreamState::Ready),
+                recv: None,
+            },
+        ]
+    );
+    pair.drive();
+
+    // Streams the application hasn't accepted yet aren't reported
+    assert_eq!(pair.server_streams(server_ch).open_streams(), []);
+    assert_eq!(pair.server_streams(server_ch).accept(Dir::Bi), Some(s));
+    assert_eq!(
+        pair.server_streams(server_ch).open_streams(),
+        [StreamInfo {
+            id: s,
+            send: Some(SendStreamState::Ready),
+            recv: Some(RecvStreamState::SizeKnown),
+        }]
+    );
+
+    // Fully acknowledged halves are closed
+    assert_eq!(
+        pair.client_streams(client_ch).open_streams(),
+        [
+            StreamInfo {
+                id: s,
+                send: None,
+                recv: Some(RecvStreamState::Recv),
+            },
+            StreamInfo {
+                id: u,
+                send: Some(SendStreamState::Ready),
+                recv: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn stream_acked() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"hello";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    assert_eq!(pair.client_send(client_ch, s).written().unwrap(), 5);
+    assert_eq!(pair.client_send(client_ch, s).acked().unwrap(), 0);
+    pair.client_send(client_ch, s).notify_acked(5).unwrap();
+    pair.drive();
+
+    assert_eq!(pair.client_send(client_ch, s).acked().unwrap(), 5);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Acked { id })) if id == s
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    // Requests for data that's already acknowledged are satisfied immediately
+    pair.client_send(client_ch, s).notify_acked(3).unwrap();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Acked { id })) if id == s
+    );
+
+    // Nothing is reported unless requested
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).acked().unwrap(), 10);
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+}
+
 #[test]
 fn reset_stream() {
     let _guard = subscribe();
@@ -274,11 +592,117 @@ fn reset_stream() {
         Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
     );
     assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    assert_eq!(pair.server_recv(server_ch, s).reset_code(), Ok(Some(ERROR)));
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Err(ReadError::Reset(ERROR)));
+    let _ = chunks.finalize();
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+}
+
+#[test]
+fn stream_deadline() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    const MSG: &[u8] = b"hello";
+    const ERROR: VarInt = VarInt(42);
+
+    // Streams delivered in time are unaffected
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    let deadline = pair.time + Duration::from_millis(100);
+    pair.client_send(client_ch, s)
+        .set_deadline(deadline, ERROR)
+        .unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    pair.time = deadline;
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    // Streams that aren't finished by the deadline are reset
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    let deadline = pair.time;
+    pair.client_send(client_ch, s)
+        .set_deadline(deadline, ERROR)
+        .unwrap();
+    pair.drive_client();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Expired { id })) if id == s
+    );
+    assert_matches!(pair.client_send(client_ch, s).is_expired(), Ok(true));
+    assert_matches!(
+        pair.client_send(client_ch, s).write(MSG),
+        Err(WriteError::Expired)
+    );
+    assert_matches!(
+        pair.client_send(client_ch, s).finish(),
+        Err(FinishError::Expired)
+    );
+    pair.drive();
+
+    assert!(pair.server_streams(server_ch).accept(Dir::Uni).is_some());
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
     let mut recv = pair.server_recv(server_ch, s);
     let mut chunks = recv.read(false).unwrap();
     assert_matches!(chunks.next(usize::MAX), Err(ReadError::Reset(ERROR)));
     let _ = chunks.finalize();
+}
+
+#[test]
+fn stream_stall_timeout() {
+    let _guard = subscribe();
+    let mut server_config = server_config();
+    Arc::get_mut(&mut server_config.transport)
+        .unwrap()
+        .stream_receive_window(8u32.into());
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+    const MSG: &[u8] = b"hello";
+    const ERROR: VarInt = VarInt(42);
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    // Streams that keep making progress are unaffected
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .set_stall_timeout(TIMEOUT, ERROR)
+        .unwrap();
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+    );
     assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    // Streams the peer never reads from are reset once flow control has blocked them for too long
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .set_stall_timeout(TIMEOUT, ERROR)
+        .unwrap();
+    assert_eq!(pair.client_send(client_ch, s).write(&[0; 16]).unwrap(), 8);
+    let start = pair.time;
+    pair.drive();
+    assert!(pair.time >= start + TIMEOUT);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Expired { id })) if id == s
+    );
+
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(_));
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Err(ReadError::Reset(ERROR)));
+    let _ = chunks.finalize();
 }
 
 #[test]
@@ -376,6 +800,72 @@ fn reject_missing_client_cert() {
                     if error.code == TransportErrorCode::crypto(AlertDescription::CertificateRequired.get_u8()));
 }
 
+#[test]
+fn client_cert_selector() {
+    let _guard = subscribe();
+
+    let key = rustls::PrivateKey(CERTIFICATE.serialize_private_key_der());
+    let cert = rustls::Certificate(util::CERTIFICATE.serialize_der().unwrap());
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&cert).unwrap();
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_client_cert_verifier(rustls::server::AllowAnyAuthenticatedClient::new(roots))
+        .with_single_cert(vec![cert.clone()], key)
+        .unwrap();
+
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig::with_crypto(Arc::new(config)),
+    );
+
+    let requested = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let client_config = {
+        let requested = requested.clone();
+        let cert = Certificate::from(cert.clone());
+        ClientConfig::builder(vec![cert.clone()])
+            .unwrap()
+            .client_cert_selector(move |request| {
+                assert!(!request.signature_schemes.is_empty());
+                requested.store(true, std::sync::atomic::Ordering::Relaxed);
+                let key = PrivateKey::from_der(&CERTIFICATE.serialize_private_key_der()).unwrap();
+                Some((CertificateChain::from_certs(vec![cert.clone()]), key))
+            })
+            .build()
+    };
+
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert!(requested.load(std::sync::atomic::Ordering::Relaxed));
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Connected)
+    );
+    let identity = pair
+        .server_conn_mut(server_ch)
+        .crypto_session()
+        .peer_identity();
+    let certs = identity.unwrap().downcast::<CertificateChain>().unwrap();
+    assert_eq!(certs.iter().next(), Some(&cert));
+}
+
 #[test]
 fn congestion() {
     let _guard = subscribe();
@@ -425,7 +915,10 @@ fn zero_rtt_happypath() {
     // Establish normal connection
     let client_ch = pair.begin_connect(config.clone());
     pair.drive();
-    pair.server.assert_accept();
+    let server_ch = pair.server.assert_accept();
+    assert!(!pair.client_conn_mut(client_ch).is_resumed());
+    assert!(!pair.server_conn_mut(server_ch).is_resumed());
+    assert!(!pair.server_conn_mut(server_ch).accepted_0rtt());
     pair.client
         .connections
         .get_mut(&client_ch)
@@ -445,7 +938,21 @@ fn zero_rtt_happypath() {
     pair.client_send(client_ch, s).write(MSG).unwrap();
     pair.drive();
     assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert!(pair.client_conn_mut(client_ch).is_resumed());
     let server_ch = pair.server.assert_accept();
+    assert!(pair.server_conn_mut(server_ch).accepted_0rtt());
+    assert!(pair.server_conn_mut(server_ch).is_resumed());
+
+    let client_stats = pair.client.stats();
+    assert_eq!(client_stats.handshakes, 2);
+    assert_eq!(client_stats.resumed_handshakes, 1);
+    assert_eq!(client_stats.zero_rtt_offered, 1);
+    assert_eq!(client_stats.zero_rtt_accepted, 1);
+    let server_stats = pair.server.stats();
+    assert_eq!(server_stats.handshakes, 2);
+    assert_eq!(server_stats.resumed_handshakes, 1);
+    assert_eq!(server_stats.zero_rtt_offered, 0);
+    assert_eq!(server_stats.zero_rtt_accepted, 1);
 
     let mut recv = pair.server_recv(server_ch, s);
     let mut chunks = recv.read(false).unwrap();
@@ -458,21 +965,155 @@ fn zero_rtt_happypath() {
 }
 
 #[test]
-fn zero_rtt_rejection() {
+fn zero_rtt_policy() {
+    struct Policy {
+        accept: bool,
+        limit: u64,
+        tickets: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl ZeroRttPolicy for Policy {
+        fn accept_early_data(&self, _: SocketAddr, ticket: &[u8]) -> bool {
+            self.tickets.lock().unwrap().push(ticket.to_vec());
+            self.accept
+        }
+
+        fn max_early_data_size(&self) -> u64 {
+            self.limit
+        }
+    }
+
+    let _guard = subscribe();
+    // Rejected data, and accepted data exceeding the limit, are retransmitted after the handshake
+    for &(accept, limit) in &[(true, u64::MAX), (false, u64::MAX), (true, 0)] {
+        let policy = Arc::new(Policy {
+            accept,
+            limit,
+            tickets: Mutex::new(Vec::new()),
+        });
+        let mut server = server_config();
+        server.zero_rtt_policy(Some(policy.clone()));
+        let mut pair = Pair::new(Default::default(), server);
+        let config = client_config();
+        let client_ch = pair.begin_connect(config.clone());
+        pair.drive();
+        pair.server.assert_accept();
+        assert!(policy.tickets.lock().unwrap().is_empty());
+        pair.client.connections.get_mut(&client_ch).unwrap().close(
+            pair.time,
+            VarInt(0),
+            [][..].into(),
+        );
+        pair.drive();
+
+        pair.client.addr = SocketAddr::new(
+            Ipv6Addr::LOCALHOST.into(),
+            CLIENT_PORTS.lock().unwrap().next().unwrap(),
+        );
+        info!("resuming session");
+        let client_ch = pair.begin_connect(config);
+        assert!(pair.client_conn_mut(client_ch).has_0rtt());
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        const MSG: &[u8] = b"Hello, 0-RTT!";
+        pair.client_send(client_ch, s).write(MSG).unwrap();
+        pair.client_datagrams(client_ch)
+            .send(Bytes::from_static(MSG))
+            .unwrap();
+        pair.drive();
+        let server_ch = pair.server.assert_accept();
+        assert_eq!(pair.server_conn_mut(server_ch).accepted_0rtt(), accept);
+        assert_eq!(pair.client_conn_mut(client_ch).accepted_0rtt(), accept);
+        assert_eq!(policy.tickets.lock().unwrap().len(), 1);
+        assert!(!policy.tickets.lock().unwrap()[0].is_empty());
+        if accept && limit == 0 {
+            assert!(pair.client_conn_mut(client_ch).lost_packets() > 0);
+        }
+        // Datagrams aren't retransmitted
+        if accept && limit > 0 {
+            assert_eq!(pair.server_datagrams(server_ch).buffered_0rtt(), 1);
+            assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), MSG);
+        }
+        assert_eq!(pair.server_datagrams(server_ch).buffered_0rtt(), 0);
+
+        let mut recv = pair.server_recv(server_ch, s);
+        let mut chunks = recv.read(false).unwrap();
+        assert_matches!(
+            chunks.next(usize::MAX),
+            Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+        );
+        let _ = chunks.finalize();
+    }
+}
+
+#[test]
+fn session_cache() {
+    #[derive(Default)]
+    struct Cache(Mutex<HashMap<Vec<u8>, Vec<u8>>>);
+
+    impl SessionCache for Cache {
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) {
+            self.0.lock().unwrap().insert(key, value);
+        }
+
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.0.lock().unwrap().get(key).cloned()
+        }
+    }
+
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let cache = Arc::new(Cache::default());
+    let cert = Certificate::from_der(&CERTIFICATE.serialize_der().unwrap()).unwrap();
+    // Session storage combines with other options
+    let config = ClientConfig::builder(vec![cert.clone()])
+        .unwrap()
+        .session_cache(cache.clone())
+        .pinned_keys(cert.spki_sha256())
+        .build();
+    let client_ch = pair.begin_connect(config);
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client
+        .connections
+        .get_mut(&client_ch)
+        .unwrap()
+        .close(pair.time, VarInt(0), [][..].into());
+    pair.drive();
+    assert!(!cache.0.lock().unwrap().is_empty());
+
+    // A fresh configuration sharing the cache, as after a restart, can resume with 0-RTT
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let config = ClientConfig::builder(vec![cert])
+        .unwrap()
+        .session_cache(cache)
+        .build();
+    let client_ch = pair.begin_connect(config);
+    assert!(pair.client_conn_mut(client_ch).has_0rtt());
+    pair.drive();
+    assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert!(pair.client_conn_mut(client_ch).is_resumed());
+}
+
+#[test]
+fn zero_rtt_rejection() {
     let _guard = subscribe();
     let mut server_crypto = server_crypto();
-    server_crypto.alpn_protocols = vec!["foo".into(), "bar".into()];
+    server_crypto.alpn_protocols = vec!["foo".into()];
     let server_config = ServerConfig::with_crypto(Arc::new(server_crypto));
     let mut pair = Pair::new(Arc::new(EndpointConfig::default()), server_config);
     let mut client_crypto = client_crypto();
-    client_crypto.alpn_protocols = vec!["foo".into()];
+    client_crypto.alpn_protocols = vec!["foo".into(), "bar".into()];
     let client_config = ClientConfig {
         transport: Default::default(),
-        crypto: Arc::new(client_crypto.clone()),
+        crypto: Arc::new(client_crypto),
+        path_cache: None,
     };
 
     // Establish normal connection
-    let client_ch = pair.begin_connect(client_config);
+    let client_ch = pair.begin_connect(client_config.clone());
     pair.drive();
     let server_ch = pair.server.assert_accept();
     assert_matches!(
@@ -498,21 +1139,28 @@ fn zero_rtt_rejection() {
     pair.client.connections.clear();
     pair.server.connections.clear();
 
-    // Changing protocols invalidates 0-RTT
-    client_crypto.alpn_protocols = vec!["bar".into()];
-    let client_config = ClientConfig {
-        transport: Default::default(),
-        crypto: Arc::new(client_crypto),
-    };
+    // Changing protocols invalidates 0-RTT, and data written for the old protocol must not be
+    // delivered under the new one
+    let mut server_crypto = self::server_crypto();
+    server_crypto.alpn_protocols = vec!["bar".into()];
+    pair.server
+        .set_server_config(Some(Arc::new(ServerConfig::with_crypto(Arc::new(
+            server_crypto,
+        )))));
     info!("resuming session");
     let client_ch = pair.begin_connect(client_config);
     assert!(pair.client_conn_mut(client_ch).has_0rtt());
     let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
-    const MSG: &[u8] = b"Hello, 0-RTT!";
-    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.client_send(client_ch, s)
+        .write(b"Hello, 0-RTT!")
+        .unwrap();
     pair.drive();
     assert!(!pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert!(!pair.client_conn_mut(client_ch).replayed_0rtt());
     let server_ch = pair.server.assert_accept();
+    // Servers report whether they accepted 0-RTT data, not just whether it was offered
+    assert!(!pair.server_conn_mut(server_ch).accepted_0rtt());
+    assert_eq!(pair.server.stats().zero_rtt_accepted, 0);
     assert_matches!(
         pair.server_conn_mut(server_ch).poll(),
         Some(Event::HandshakeDataReady)
@@ -522,14 +1170,128 @@ fn zero_rtt_rejection() {
         Some(Event::Connected)
     );
     assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+    assert_eq!(pair.server_streams(server_ch).accept(Dir::Uni), None);
+    // The stream is available for reuse under the new protocol
+    assert_eq!(pair.client_streams(client_ch).open(Dir::Uni), Some(s));
+}
+
+struct RejectEarlyData;
+
+impl ZeroRttPolicy for RejectEarlyData {
+    fn accept_early_data(&self, _: SocketAddr, _: &[u8]) -> bool {
+        false
+    }
+}
+
+#[test]
+fn zero_rtt_rejection_replays_streams() {
+    let _guard = subscribe();
+    let mut server_crypto = server_crypto();
+    server_crypto.alpn_protocols = vec!["foo".into()];
+    let server_crypto = Arc::new(server_crypto);
+    let mut pair = Pair::new(
+        Arc::new(EndpointConfig::default()),
+        ServerConfig::with_crypto(server_crypto.clone()),
+    );
+    let mut client_crypto = client_crypto();
+    client_crypto.alpn_protocols = vec!["foo".into()];
+    let client_config = ClientConfig {
+        transport: Default::default(),
+        crypto: Arc::new(client_crypto),
+        path_cache: None,
+    };
+    let client_ch = pair.begin_connect(client_config.clone());
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client
+        .connections
+        .get_mut(&client_ch)
+        .unwrap()
+        .close(pair.time, VarInt(0), [][..].into());
+    pair.drive();
+    pair.client.connections.clear();
+    pair.server.connections.clear();
+
+    // Rejected under the protocol it was written for, early data is retransmitted in 1-RTT
+    let mut server_config = ServerConfig::with_crypto(server_crypto);
+    server_config.zero_rtt_policy(Some(Arc::new(RejectEarlyData)));
+    pair.server.set_server_config(Some(Arc::new(server_config)));
+    let client_ch = pair.begin_connect(client_config);
+    assert!(pair.client_conn_mut(client_ch).has_0rtt());
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"Hello, 0-RTT!";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert!(pair.client_conn_mut(client_ch).replayed_0rtt());
+
+    let server_ch = pair.server.assert_accept();
+    assert_eq!(pair.server_streams(server_ch).accept(Dir::Uni), Some(s));
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+    );
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn zero_rtt_rejection_discards_streams() {
+    let _guard = subscribe();
+    let mut server_crypto = server_crypto();
+    server_crypto.alpn_protocols = vec!["foo".into()];
+    let server_crypto = Arc::new(server_crypto);
+    let mut pair = Pair::new(
+        Arc::new(EndpointConfig::default()),
+        ServerConfig::with_crypto(server_crypto.clone()),
+    );
+    let mut client_crypto = client_crypto();
+    client_crypto.alpn_protocols = vec!["foo".into()];
+    let client_ch = pair.begin_connect(ClientConfig {
+        transport: Default::default(),
+        crypto: Arc::new(client_crypto.clone()),
+        path_cache: None,
+    });
+    pair.drive();
+    pair.server.assert_accept();
+    pair.client
+        .connections
+        .get_mut(&client_ch)
+        .unwrap()
+        .close(pair.time, VarInt(0), [][..].into());
+    pair.drive();
+    pair.client.connections.clear();
+    pair.server.connections.clear();
+
+    // The server no longer permits as much data as was sent in 0-RTT
+    let mut server_config = ServerConfig::with_crypto(server_crypto);
+    Arc::get_mut(&mut server_config.transport)
+        .unwrap()
+        .stream_receive_window(4u32.into());
+    server_config.zero_rtt_policy(Some(Arc::new(RejectEarlyData)));
+    pair.server.set_server_config(Some(Arc::new(server_config)));
+    let client_ch = pair.begin_connect(ClientConfig {
+        transport: Default::default(),
+        crypto: Arc::new(client_crypto),
+        path_cache: None,
+    });
+    assert!(pair.client_conn_mut(client_ch).has_0rtt());
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .write(b"Hello, 0-RTT!")
+        .unwrap();
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert!(!pair.client_conn_mut(client_ch).replayed_0rtt());
     let s2 = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
     assert_eq!(s, s2);
 
+    let server_ch = pair.server.assert_accept();
     let mut recv = pair.server_recv(server_ch, s2);
     let mut chunks = recv.read(false).unwrap();
     assert_eq!(chunks.next(usize::MAX), Err(ReadError::Blocked));
     let _ = chunks.finalize();
-    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
 }
 
 #[test]
@@ -544,6 +1306,7 @@ fn alpn_success() {
     let client_config = ClientConfig {
         transport: Default::default(),
         crypto: Arc::new(client_crypto),
+        path_cache: None,
     };
 
     // Establish normal connection
@@ -579,6 +1342,7 @@ fn server_alpn_unset() {
     let client_config = ClientConfig {
         transport: Default::default(),
         crypto: Arc::new(client_crypto),
+        path_cache: None,
     };
 
     let client_ch = pair.begin_connect(client_config);
@@ -617,6 +1381,7 @@ fn alpn_mismatch() {
     let client_config = ClientConfig {
         transport: Default::default(),
         crypto: Arc::new(client_crypto),
+        path_cache: None,
     };
 
     let client_ch = pair.begin_connect(client_config);
@@ -627,6 +1392,125 @@ fn alpn_mismatch() {
     );
 }
 
+#[test]
+fn handshake_failures() {
+    let _guard = subscribe();
+    let mut server_crypto = server_crypto();
+    server_crypto.alpn_protocols = vec!["foo".into()];
+    let server_config = ServerConfig::with_crypto(Arc::new(server_crypto));
+    let mut pair = Pair::new(Arc::new(EndpointConfig::default()), server_config);
+    let client_addr = pair.client.addr;
+
+    // Unsupported version
+    let now = pair.time;
+    assert!(pair
+        .server
+        .handle(
+            now,
+            client_addr,
+            None,
+            None,
+            hex!("80 0a1a2a3a 04 00000000 04 00000000 00")[..].into(),
+        )
+        .is_none());
+    assert_eq!(
+        pair.server.poll_handshake_failure(),
+        Some(HandshakeFailure {
+            remote: client_addr,
+            reason: HandshakeFailureReason::UnsupportedVersion(0x0a1a_2a3a),
+        })
+    );
+
+    // TLS alert raised by the server
+    let client_config = |alpn: &str| {
+        let mut client_crypto = client_crypto();
+        client_crypto.alpn_protocols = vec![alpn.into()];
+        ClientConfig {
+            transport: Default::default(),
+            crypto: Arc::new(client_crypto),
+            path_cache: None,
+        }
+    };
+    pair.begin_connect(client_config("bar"));
+    pair.drive();
+    assert_eq!(
+        pair.server.poll_handshake_failure(),
+        Some(HandshakeFailure {
+            remote: client_addr,
+            reason: HandshakeFailureReason::Tls {
+                alert: 0x78,
+                local: true,
+            },
+        })
+    );
+
+    // Client vanishing mid-handshake
+    pair.begin_connect(client_config("foo"));
+    pair.drive_client();
+    pair.drive_server();
+    pair.client.connections.clear();
+    pair.client.inbound.clear();
+    pair.drive();
+    // Blocked by anti-amplification, the server waits out the idle timeout
+    pair.time += Duration::from_secs(10);
+    pair.drive_server();
+    assert_eq!(
+        pair.server.poll_handshake_failure(),
+        Some(HandshakeFailure {
+            remote: client_addr,
+            reason: HandshakeFailureReason::TimedOut,
+        })
+    );
+    assert_eq!(pair.server.poll_handshake_failure(), None);
+    assert_eq!(pair.server.stats().handshake_failures, 3);
+}
+
+#[test]
+fn set_max_concurrent_streams() {
+    let _guard = subscribe();
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            max_concurrent_uni_streams: 1u32.into(),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    assert!(pair.client_streams(client_ch).open(Dir::Uni).is_some());
+    assert_eq!(pair.client_streams(client_ch).open(Dir::Uni), None);
+
+    // Raising the limit grants credit without waiting for streams to close
+    pair.server_conn_mut(server_ch)
+        .set_max_concurrent_streams(Dir::Uni, 3u32.into());
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Available { dir: Dir::Uni }))
+    );
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    assert!(pair.client_streams(client_ch).open(Dir::Uni).is_some());
+    assert_eq!(pair.client_streams(client_ch).open(Dir::Uni), None);
+
+    // Lowering the limit withholds credit until enough streams have closed
+    pair.server_conn_mut(server_ch)
+        .set_max_concurrent_streams(Dir::Uni, 1u32.into());
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    // The streams opened before `s` are implicitly opened too
+    while pair.server_streams(server_ch).accept(Dir::Uni).unwrap() != s {}
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_eq!(chunks.next(usize::MAX), Ok(None));
+    let _ = chunks.finalize();
+    pair.drive();
+    assert_eq!(pair.client_streams(client_ch).open(Dir::Uni), None);
+}
+
 #[test]
 fn stream_id_limit() {
     let _guard = subscribe();
@@ -932,6 +1816,90 @@ fn idle_timeout() {
     );
 }
 
+#[test]
+fn handshake_timeout() {
+    let _guard = subscribe();
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(100);
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            handshake_timeout: Some(HANDSHAKE_TIMEOUT),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive_client();
+    pair.drive_server();
+    let server_ch = pair.server.assert_accept();
+    let start = pair.time;
+
+    // Simulate total S->C packet loss, stalling the handshake
+    pair.client.inbound.clear();
+    let mut closed_at = pair.time;
+    while !pair.server_conn_mut(server_ch).is_closed() {
+        closed_at = pair.time;
+        if !pair.step() {
+            if let Some(t) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = t;
+            }
+        }
+        pair.client.inbound.clear();
+    }
+
+    // Well before the 10s idle timeout
+    assert!(closed_at - start < 2 * HANDSHAKE_TIMEOUT);
+    let mut reason = None;
+    while let Some(event) = pair.server_conn_mut(server_ch).poll() {
+        if let Event::ConnectionLost { reason: x } = event {
+            reason = Some(x);
+        }
+    }
+    assert_matches!(reason, Some(ConnectionError::TimedOut));
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+}
+
+#[test]
+fn handshake_timeout_after_established() {
+    let _guard = subscribe();
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            handshake_timeout: Some(Duration::from_millis(100)),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (_, server_ch) = pair.connect();
+    pair.time += Duration::from_secs(1);
+    let now = pair.time;
+    pair.server_conn_mut(server_ch).handle_timeout(now);
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+}
+
+#[test]
+fn closing_period() {
+    let _guard = subscribe();
+    const PERIOD: Duration = Duration::from_millis(5);
+    let mut transport = TransportConfig::default();
+    transport.closing_period(Some(PERIOD));
+    let mut pair = Pair::default();
+    let client_ch = pair.begin_connect(ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    });
+    pair.drive();
+    pair.server.assert_accept();
+
+    // Drained after the configured period rather than three PTOs
+    let now = pair.time;
+    let conn = pair.client_conn_mut(client_ch);
+    conn.close(now, VarInt(0), Bytes::new());
+    assert_eq!(conn.poll_timeout(), Some(now + PERIOD));
+    conn.handle_timeout(now + PERIOD);
+    assert!(conn.is_drained());
+}
+
 #[test]
 fn connection_close_sends_acks() {
     let _guard = subscribe();
@@ -957,12 +1925,173 @@ fn connection_close_sends_acks() {
 }
 
 #[test]
-fn concurrent_connections_full() {
+fn ack_frequency() {
     let _guard = subscribe();
-    let mut pair = Pair::new(
-        Default::default(),
-        ServerConfig {
-            concurrent_connections: 0,
+    const MAX_ACK_DELAY: Duration = Duration::from_millis(50);
+
+    // Measure how long the server waits for a PING to be acknowledged under `config`
+    let ack_time = |config: Option<AckFrequencyConfig>| {
+        let mut server_config = server_config();
+        Arc::get_mut(&mut server_config.transport)
+            .unwrap()
+            .ack_frequency(config);
+        let mut pair = Pair::new(Default::default(), server_config);
+        let (client_ch, server_ch) = pair.connect();
+        pair.drive();
+        let sent = pair
+            .server_conn_mut(server_ch)
+            .stats()
+            .frame_tx
+            .ack_frequency;
+        let received = pair
+            .client_conn_mut(client_ch)
+            .stats()
+            .frame_rx
+            .ack_frequency;
+        assert_eq!(sent, config.map_or(0, |_| 1));
+        assert_eq!(received, sent);
+
+        let acks = pair.client_conn_mut(client_ch).stats().frame_tx.acks;
+        let start = pair.time;
+        pair.server_conn_mut(server_ch).ping();
+        pair.drive();
+        assert_eq!(
+            pair.client_conn_mut(client_ch).stats().frame_tx.acks,
+            acks + 1
+        );
+        pair.time - start
+    };
+
+    let immediate = ack_time(None);
+    let delayed = ack_time(Some(AckFrequencyConfig {
+        ack_eliciting_threshold: VarInt(9),
+        max_ack_delay: Some(MAX_ACK_DELAY),
+        ..AckFrequencyConfig::default()
+    }));
+    assert!(immediate < MAX_ACK_DELAY);
+    assert!(delayed >= immediate + MAX_ACK_DELAY);
+}
+
+#[test]
+fn sharded_cid_generator() {
+    for &(shard, shards) in &[(0, 1), (2, 3), (6, 7), (254, 255)] {
+        let mut generator = ShardedConnectionIdGenerator::new(8, 3, shard, shards);
+        for _ in 0..100 {
+            let cid = generator.generate_cid();
+            assert_eq!(cid.len(), 8);
+            assert_eq!(cid[3] % shards, shard);
+            assert!(generator.validate(&cid).is_ok());
+        }
+    }
+
+    // Packets for unknown connections only elicit a stateless reset if their CID is for this shard
+    let mut config = EndpointConfig::default();
+    config.cid_generator(|| Box::new(ShardedConnectionIdGenerator::new(8, 3, 1, 4)));
+    let mut server = Endpoint::new(Arc::new(config), Some(Arc::new(server_config())));
+    let remote = "[::2]:7890".parse().unwrap();
+    let now = Instant::now();
+    let mut unknown = [0x40; 64];
+    for &(byte, resets) in &[(4 * 7 + 1, 1), (4 * 7 + 2, 0)] {
+        unknown[1..9].copy_from_slice(&[byte; 8]);
+        assert!(server
+            .handle(now, remote, None, None, unknown[..].into())
+            .is_none());
+        assert_eq!(iter::from_fn(|| server.poll_transmit()).count(), resets);
+    }
+}
+
+#[test]
+fn encrypted_cid_generator() {
+    let _guard = subscribe();
+    let key = [0x42; 16];
+    assert!(LoadBalancerConfig::new(7, 3, 6, key).is_err());
+    assert!(LoadBalancerConfig::new(0, 3, 3, key).is_err());
+    assert!(LoadBalancerConfig::new(0, 5, 6, key).is_err());
+
+    // Both even and odd plaintext lengths
+    for &(server_id, nonce_len) in &[(&[1, 2, 3][..], 6), (&[4, 5][..], 4), (&[6][..], 9)] {
+        let config = LoadBalancerConfig::new(2, server_id.len(), nonce_len, key).unwrap();
+        let mut generator =
+            LoadBalancerConnectionIdGenerator::new(config.clone(), server_id).unwrap();
+        let mut seen = HashSet::new();
+        for _ in 0..100 {
+            let cid = generator.generate_cid();
+            assert_eq!(cid.len(), config.cid_len());
+            assert_eq!(cid[0], 2 << 5 | (config.cid_len() - 1) as u8);
+            assert_eq!(config.server_id(&cid), Some(server_id.to_vec()));
+            assert!(generator.validate(&cid).is_ok());
+            assert!(seen.insert(cid));
+        }
+    }
+
+    // The server ID can't be recovered without the key
+    let config = LoadBalancerConfig::new(2, 3, 6, key).unwrap();
+    assert!(LoadBalancerConnectionIdGenerator::new(config.clone(), &[1, 2]).is_err());
+    let cid = LoadBalancerConnectionIdGenerator::new(config.clone(), &[1, 2, 3])
+        .unwrap()
+        .generate_cid();
+    assert!(!cid.windows(3).any(|x| x == [1, 2, 3]));
+    let other = LoadBalancerConfig::new(2, 3, 6, [0x43; 16]).unwrap();
+    assert_ne!(other.server_id(&cid), Some(vec![1, 2, 3]));
+
+    // Connections can be established and migrate using the generated CIDs
+    let mut endpoint_config = EndpointConfig::default();
+    endpoint_config.cid_generator(move || {
+        Box::new(LoadBalancerConnectionIdGenerator::new(config.clone(), &[1, 2, 3]).unwrap())
+    });
+    let mut pair = Pair::new(Arc::new(endpoint_config), server_config());
+    let (client_ch, server_ch) = pair.connect();
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert_eq!(
+        pair.server_conn_mut(server_ch).remote_address(),
+        pair.client.addr
+    );
+}
+
+#[test]
+fn plaintext_cid_generator() {
+    let _guard = subscribe();
+    assert!(LoadBalancerConfig::plaintext(7, 3, 6).is_err());
+    assert!(LoadBalancerConfig::plaintext(0, 3, 3).is_err());
+    assert!(LoadBalancerConfig::plaintext(0, 12, 8).is_err());
+
+    let config = LoadBalancerConfig::plaintext(1, 12, 7).unwrap();
+    assert_eq!(config.cid_len(), MAX_CID_SIZE);
+    let server_id = [7; 12];
+    let mut generator = LoadBalancerConnectionIdGenerator::new(config.clone(), &server_id).unwrap();
+    let mut seen = HashSet::new();
+    for _ in 0..100 {
+        let cid = generator.generate_cid();
+        assert_eq!(cid[0], 1 << 5 | (MAX_CID_SIZE - 1) as u8);
+        assert_eq!(cid[1..13], server_id);
+        assert_eq!(config.server_id(&cid), Some(server_id.to_vec()));
+        assert!(generator.validate(&cid).is_ok());
+        assert!(seen.insert(cid));
+    }
+
+    let mut endpoint_config = EndpointConfig::default();
+    assert!(endpoint_config
+        .load_balancer(config.clone(), &server_id[..3])
+        .is_err());
+    endpoint_config.load_balancer(config, &server_id).unwrap();
+    let mut pair = Pair::new(Arc::new(endpoint_config), server_config());
+    let (client_ch, _) = pair.connect();
+    let cid = pair.client_conn_mut(client_ch).rem_cid();
+    assert_eq!(cid[1..13], server_id);
+}
+
+#[test]
+fn concurrent_connections_full() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            concurrent_connections: 0,
             ..server_config()
         },
     );
@@ -980,6 +2109,115 @@ fn concurrent_connections_full() {
     assert_eq!(pair.server.connections.len(), 0);
     assert_eq!(pair.server.known_connections(), 0);
     assert_eq!(pair.server.known_cids(), 0);
+    let stats = pair.server.stats();
+    assert_eq!(stats.connections_refused, 1);
+    assert_eq!(stats.handshake_failures, 1);
+}
+
+#[test]
+fn accept_buffer_full() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            accept_buffer: 1,
+            ..server_config()
+        },
+    );
+    pair.connect();
+    let server_ch = *pair.server.connections.keys().next().unwrap();
+
+    // The established but unaccepted connection fills the buffer
+    let refused_ch = pair.begin_connect(client_config());
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(refused_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::ConnectionClosed(frame::ConnectionClose {
+                error_code: TransportErrorCode::CONNECTION_REFUSED,
+                ..
+            }),
+        })
+    );
+
+    // Accepting it frees up space
+    pair.server.accept_connection(server_ch);
+    pair.connect();
+}
+
+#[test]
+fn max_incoming_handshakes() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            max_incoming_handshakes: 1,
+            ..server_config()
+        },
+    );
+
+    // Stall the first handshake so that it remains in progress
+    let stalled_ch = pair.begin_connect(client_config());
+    pair.drive_client();
+    pair.drive_server();
+    pair.server.assert_accept();
+    pair.client.inbound.clear();
+
+    // The next connection attempt is sent a Retry, then refused once its address is validated
+    let refused_ch = pair.begin_connect(client_config());
+    for _ in 0..3 {
+        pair.drive_client();
+        pair.drive_server();
+    }
+    pair.drive_client();
+    assert_eq!(pair.server.connections.len(), 1);
+    assert_matches!(
+        pair.client_conn_mut(refused_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::ConnectionClosed(frame::ConnectionClose {
+                error_code: TransportErrorCode::CONNECTION_REFUSED,
+                ..
+            }),
+        })
+    );
+
+    // Completing the stalled handshake frees up capacity
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(stalled_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(stalled_ch).poll(),
+        Some(Event::Connected)
+    );
+    pair.connect();
+}
+
+#[test]
+fn unverifiable_token_ignored_without_retry() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            use_stateless_retry: true,
+            ..server_config()
+        },
+    );
+
+    // Obtain a Retry token, then lose the key it was issued under and stop requiring retries
+    pair.begin_connect(client_config());
+    pair.drive_client();
+    pair.drive_server();
+    pair.drive_client();
+    assert_eq!(pair.server.connections.len(), 0);
+    pair.server
+        .set_server_config(Some(Arc::new(server_config())));
+
+    // The token is ignored rather than answered with INVALID_TOKEN
+    pair.drive_server();
+    assert_eq!(pair.server.connections.len(), 1);
+    assert_eq!(pair.server.endpoint.stats().handshake_failures, 0);
 }
 
 #[test]
@@ -1026,6 +2264,244 @@ fn migration() {
     );
 }
 
+#[test]
+fn client_initiated_migration() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let client_ch = pair.begin_connect(client_config());
+    let now = pair.time;
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .initiate_migration(now, true),
+        Err(MigrateError::HandshakeNotConfirmed)
+    );
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert_eq!(
+        pair.server_conn_mut(server_ch)
+            .initiate_migration(now, true),
+        Err(MigrateError::NotClient)
+    );
+
+    let old_cid = pair.client_conn_mut(client_ch).rem_cid();
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .initiate_migration(now, true)
+        .unwrap();
+    // Observers can't link the new path to the old one by connection ID
+    assert_ne!(pair.client_conn_mut(client_ch).rem_cid(), old_cid);
+    pair.drive();
+    assert_eq!(
+        pair.server_conn_mut(server_ch).remote_address(),
+        pair.client.addr
+    );
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .path_challenge,
+        1
+    );
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+}
+
+#[test]
+fn migrate_between_endpoints() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    // Move the client's connection to an endpoint with a socket of its own
+    let detached = pair.client.endpoint.detach(client_ch);
+    let mut endpoint = Endpoint::new(Default::default(), None);
+    let new_ch = endpoint.attach(&detached).unwrap();
+    assert_eq!(
+        endpoint.attach(&detached),
+        Err(AttachError::ConnectionIdInUse)
+    );
+    let conn = pair.client.connections.remove(&client_ch).unwrap();
+    assert!(pair
+        .client
+        .handle_event(client_ch, EndpointEvent::drained())
+        .is_none());
+    assert_eq!(pair.client.known_connections(), 0);
+    pair.client.endpoint = endpoint;
+    pair.client.connections.insert(new_ch, conn);
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client_conn_mut(new_ch).local_address_changed();
+
+    let s = pair.client_streams(new_ch).open(Dir::Uni).unwrap();
+    pair.client_send(new_ch, s).write(b"moved").unwrap();
+    pair.client_send(new_ch, s).finish().unwrap();
+    pair.drive();
+    assert_eq!(
+        pair.server_conn_mut(server_ch).remote_address(),
+        pair.client.addr
+    );
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Ok(Some(chunk)) if chunk.bytes == b"moved"[..]);
+    let _ = chunks.finalize();
+
+    // The connection's identifiers are now routed by its new endpoint
+    assert!(pair.client.known_cids() > 1);
+    assert!(!pair.client_conn_mut(new_ch).is_closed());
+}
+
+#[test]
+fn detached_connection_routing() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    // Until its handle is released, a detached connection's datagrams are still routed to it, so
+    // packets the peer sent before learning of a move don't provoke stateless resets
+    let detached = pair.client.endpoint.detach(client_ch);
+    pair.server_conn_mut(server_ch).ping();
+    pair.drive();
+    assert_eq!(pair.client.endpoint.stats().stateless_resets, 0);
+    assert_eq!(pair.client_conn_mut(client_ch).stats().frame_rx.ping, 1);
+
+    // A connection that couldn't be moved carries on where it was
+    pair.client.endpoint.restore(client_ch, detached);
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert_eq!(pair.server_conn_mut(server_ch).stats().frame_rx.ping, 1);
+
+    // Once released, the connection's identifiers are no longer routed
+    pair.client.endpoint.detach(client_ch);
+    assert!(pair
+        .client
+        .handle_event(client_ch, EndpointEvent::drained())
+        .is_none());
+    assert_eq!(pair.client.known_cids(), 0);
+    pair.client.connections.remove(&client_ch);
+    pair.server_conn_mut(server_ch).ping();
+    pair.drive();
+    assert_eq!(pair.client.endpoint.stats().stateless_resets, 1);
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::Reset
+        })
+    );
+}
+
+#[test]
+fn migration_disabled() {
+    let _guard = subscribe();
+    let mut server_config = server_config();
+    server_config.migration(false);
+    let mut pair = Pair::new(Default::default(), server_config);
+    let (client_ch, server_ch) = pair.connect();
+    let now = pair.time;
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .initiate_migration(now, true),
+        Err(MigrateError::DisabledByPeer)
+    );
+
+    let old_addr = pair.client.addr;
+    pair.client.addr = SocketAddr::new(
+        Ipv4Addr::new(127, 0, 0, 1).into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive_client();
+    pair.drive_server();
+    assert_eq!(pair.server_conn_mut(server_ch).remote_address(), old_addr);
+    assert!(pair.client.inbound.is_empty());
+
+    // The connection is unaffected once the client is back on its original address
+    pair.client.addr = old_addr;
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+}
+
+fn preferred_address_pair() -> (Pair, SocketAddr) {
+    let preferred = SocketAddrV6::new(
+        Ipv6Addr::LOCALHOST,
+        SERVER_PORTS.lock().unwrap().next().unwrap(),
+        0,
+        0,
+    );
+    let mut server_config = server_config();
+    server_config.preferred_address_v6(Some(preferred));
+    (
+        Pair::new(Default::default(), server_config),
+        SocketAddr::V6(preferred),
+    )
+}
+
+#[test]
+fn preferred_address() {
+    let _guard = subscribe();
+    let (mut pair, preferred) = preferred_address_pair();
+    let client_ch = pair.begin_connect(client_config());
+    // Start routing to the preferred address as soon as the client moves to it
+    loop {
+        pair.client.drive(pair.time, pair.server.addr);
+        if pair.client_conn_mut(client_ch).remote_address() == preferred {
+            pair.server.addr = preferred;
+            break;
+        }
+        assert!(pair.step(), "client never migrated");
+    }
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+
+    let client = pair.client_conn_mut(client_ch);
+    assert_eq!(client.remote_address(), preferred);
+    let params = client.peer_transport_parameters().unwrap();
+    assert_eq!(
+        params.preferred_address_v6().map(SocketAddr::V6),
+        Some(preferred)
+    );
+    assert_eq!(params.preferred_address_v4(), None);
+    assert_eq!(client.stats().frame_rx.path_response, 1);
+    assert!(!client.is_closed());
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+}
+
+#[test]
+fn preferred_address_unreachable() {
+    let _guard = subscribe();
+    let (mut pair, preferred) = preferred_address_pair();
+    let client_ch = pair.begin_connect(client_config());
+    loop {
+        pair.client.drive(pair.time, pair.server.addr);
+        if pair.client_conn_mut(client_ch).remote_address() == preferred {
+            break;
+        }
+        assert!(pair.step(), "client never migrated");
+    }
+    // Path validation fails, so the client falls back to the original address
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+    assert_eq!(
+        pair.client_conn_mut(client_ch).remote_address(),
+        pair.server.addr
+    );
+
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+}
+
 fn test_flow_control(config: TransportConfig, window_size: usize) {
     let _guard = subscribe();
     let mut pair = Pair::new(
@@ -1147,6 +2623,259 @@ fn conn_flow_control() {
     );
 }
 
+#[test]
+fn send_window_autotuning() {
+    let _guard = subscribe();
+    // The send window tracks twice the congestion window, within the configured bounds
+    for &(min, max) in &[(1_000, 1_000_000), (1_000, 10_000), (100_000, 1_000_000)] {
+        let mut transport = TransportConfig::default();
+        transport.send_window(min).max_send_window(Some(max));
+        let mut pair = Pair::default();
+        let client_ch = pair.begin_connect(ClientConfig {
+            transport: Arc::new(transport),
+            ..client_config()
+        });
+        pair.drive();
+        pair.server.assert_accept();
+
+        let cwnd = pair.client_conn_mut(client_ch).stats().path.cwnd;
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        let written = pair
+            .client_send(client_ch, s)
+            .write(&vec![0; 2 * max as usize])
+            .unwrap();
+        assert_eq!(written as u64, (2 * cwnd).max(min).min(max));
+    }
+}
+
+#[test]
+fn delivery_rate_changed() {
+    let _guard = subscribe();
+    // Count the reports during the handshake and during a transfer that grows the estimate
+    let reports = |threshold| {
+        let mut transport = TransportConfig::default();
+        transport.delivery_rate_threshold(threshold);
+        let mut pair = Pair::default();
+        pair.latency = Duration::from_millis(10);
+        let client_ch = pair.begin_connect(ClientConfig {
+            transport: Arc::new(transport),
+            ..client_config()
+        });
+        pair.drive();
+        pair.server.assert_accept();
+        let count = |pair: &mut Pair| {
+            let mut events = 0;
+            while let Some(event) = pair.client_conn_mut(client_ch).poll() {
+                if let Event::DeliveryRateChanged = event {
+                    events += 1;
+                }
+            }
+            events
+        };
+        let handshake = count(&mut pair);
+
+        let rate = pair.client_conn_mut(client_ch).delivery_rate();
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        pair.client_send(client_ch, s)
+            .write(&[0xAB; 100_000])
+            .unwrap();
+        pair.drive();
+        assert!(pair.client_conn_mut(client_ch).delivery_rate() > rate);
+        (handshake, count(&mut pair))
+    };
+
+    assert_eq!(reports(None), (0, 0));
+    // The first estimate is always reported, later ones only if they move past the threshold
+    let (handshake, transfer) = reports(Some(0.1));
+    assert_eq!(handshake, 1);
+    assert!(transfer > 0);
+    assert_eq!(reports(Some(1000.0)), (1, 0));
+}
+
+#[test]
+fn probe_bandwidth() {
+    let _guard = subscribe();
+    const PROBE: u64 = 200_000;
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect();
+    let rate = pair.client_conn_mut(client_ch).delivery_rate();
+    let sent = pair.client_conn_mut(client_ch).stats().udp_tx.bytes;
+
+    pair.client_conn_mut(client_ch).probe_bandwidth(PROBE);
+    pair.drive();
+    let mut probed = None;
+    while let Some(event) = pair.client_conn_mut(client_ch).poll() {
+        if let Event::BandwidthProbed { delivery_rate } = event {
+            assert!(probed.is_none());
+            probed = Some(delivery_rate);
+        }
+    }
+    assert!(probed.expect("probe didn't complete") > rate);
+    let probe_bytes = pair.client_conn_mut(client_ch).stats().udp_tx.bytes - sent;
+    assert!(probe_bytes >= PROBE);
+    assert!(probe_bytes < 2 * PROBE);
+
+    // Empty probes complete immediately
+    pair.client_conn_mut(client_ch).probe_bandwidth(0);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::BandwidthProbed { .. })
+    );
+}
+
+#[test]
+fn rtt_distribution() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .write(&[0xAB; 50_000])
+        .unwrap();
+    pair.drive();
+
+    let stats = pair.client_conn_mut(client_ch).stats().path;
+    let histogram = stats.rtt_histogram;
+    assert!(histogram.samples() > 1);
+    assert!(stats.min_rtt >= Duration::from_millis(20));
+    assert!(stats.min_rtt <= stats.rtt && stats.rtt <= stats.max_rtt);
+    // Samples of 20ms and up to 32ms fall into the [16ms, 32ms) bucket
+    assert_eq!(
+        RttHistogram::upper_bound(5),
+        Some(Duration::from_millis(32))
+    );
+    assert!(histogram.counts()[5] > 0);
+    assert_eq!(histogram.quantile(0.0), Some(Duration::from_millis(32)));
+    assert!(histogram.quantile(1.0).unwrap() > stats.max_rtt);
+    assert_eq!(RttHistogram::upper_bound(RttHistogram::BUCKETS - 1), None);
+    assert_eq!(RttHistogram::default().quantile(0.5), None);
+}
+
+#[test]
+fn receive_window_autotuning() {
+    let _guard = subscribe();
+    const WINDOW: usize = 16_000;
+    const MAX_WINDOW: usize = 256_000;
+    let mut transport = TransportConfig::default();
+    transport
+        .receive_window((WINDOW as u32).into())
+        .max_receive_window(Some((MAX_WINDOW as u32).into()))
+        .stream_receive_window((4 * MAX_WINDOW as u32).into());
+    let server = ServerConfig {
+        transport: Arc::new(transport),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, server_ch) = pair.connect();
+    let msg = vec![0xAB; 2 * MAX_WINDOW];
+
+    // Stream continuously, with the application reading data as soon as it arrives
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let mut largest = 0;
+    let mut total = 0;
+    while total < 8 * MAX_WINDOW {
+        if let Ok(written) = pair.client_send(client_ch, s).write(&msg) {
+            assert!(written <= MAX_WINDOW);
+            largest = largest.max(written);
+        }
+        if !pair.step() {
+            if let Some(t) = min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                pair.time = t;
+            }
+        }
+
+        let mut recv = pair.server_recv(server_ch, s);
+        let mut chunks = recv.read(true).unwrap();
+        while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+            total += chunk.bytes.len();
+        }
+        let _ = chunks.finalize();
+    }
+    assert!(largest > WINDOW);
+}
+
+#[test]
+fn stream_receive_window_override() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.stream_receive_window(1000u32.into());
+    let server = ServerConfig {
+        transport: Arc::new(transport),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let msg = [0xAB; 10_000];
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(1000));
+    pair.drive();
+
+    // Raising the window issues credit without the application reading anything
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    pair.server_recv(server_ch, s)
+        .set_receive_window(5000u32.into())
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(4000));
+    pair.drive();
+
+    // Lowering it only limits future credit
+    pair.server_recv(server_ch, s)
+        .set_receive_window(100u32.into())
+        .unwrap();
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    while let Ok(Some(_)) = chunks.next(usize::MAX) {}
+    let _ = chunks.finalize();
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(100));
+}
+
+#[test]
+fn stream_max_data() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.stream_receive_window(1000u32.into());
+    let server = ServerConfig {
+        transport: Arc::new(transport),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let msg = [0xAB; 10_000];
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(1000));
+    pair.drive();
+
+    // Credit stops at the limit, however much is read
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    pair.server_recv(server_ch, s)
+        .set_max_data(1050u32.into())
+        .unwrap();
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    while let Ok(Some(_)) = chunks.next(usize::MAX) {}
+    let _ = chunks.finalize();
+    pair.drive();
+    assert_eq!(pair.client_send(client_ch, s).write(&msg), Ok(50));
+    pair.drive();
+
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    while let Ok(Some(_)) = chunks.next(usize::MAX) {}
+    let _ = chunks.finalize();
+    pair.drive();
+    assert_eq!(
+        pair.client_send(client_ch, s).write(&msg),
+        Err(WriteError::Blocked)
+    );
+}
+
 #[test]
 fn stop_opens_bidi() {
     let _guard = subscribe();
@@ -1239,6 +2968,21 @@ fn zero_length_cid() {
     pair.connect();
 }
 
+#[test]
+fn configured_cid_len() {
+    let _guard = subscribe();
+    assert!(EndpointConfig::default().cid_len(MAX_CID_SIZE + 1).is_err());
+    for &len in &[0, 4, MAX_CID_SIZE] {
+        let mut config = EndpointConfig::default();
+        config.cid_len(len).unwrap();
+        let mut pair = Pair::new(Arc::new(config), server_config());
+        let (client_ch, server_ch) = pair.connect();
+        // Each side addresses the other with CIDs of the configured length
+        assert_eq!(pair.client_conn_mut(client_ch).rem_cid().len(), len);
+        assert_eq!(pair.server_conn_mut(server_ch).rem_cid().len(), len);
+    }
+}
+
 #[test]
 fn keep_alive() {
     let _guard = subscribe();
@@ -1266,6 +3010,43 @@ fn keep_alive() {
     }
 }
 
+#[test]
+fn keep_alive_while_idle() {
+    let _guard = subscribe();
+    const IDLE_TIMEOUT: u64 = 10;
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            keep_alive_interval: Some(Duration::from_millis(IDLE_TIMEOUT / 2)),
+            max_idle_timeout: Some(VarInt(IDLE_TIMEOUT)),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+
+    for &open_stream in &[true, false] {
+        let mut pair = Pair::new(Default::default(), server.clone());
+        let (client_ch, server_ch) = pair.connect();
+        pair.server_conn_mut(server_ch)
+            .set_keep_alive_while_idle(false);
+        if open_stream {
+            let s = pair.server_streams(server_ch).open(Dir::Uni).unwrap();
+            pair.server_send(server_ch, s).write(b"hello").unwrap();
+        }
+        let end = pair.time + Duration::from_millis(20 * IDLE_TIMEOUT);
+        while pair.time < end && !pair.client_conn_mut(client_ch).is_closed() {
+            if !pair.step() {
+                match min_opt(pair.client.next_wakeup(), pair.server.next_wakeup()) {
+                    Some(time) => pair.time = time,
+                    None => break,
+                }
+            }
+        }
+        // Keep-alives are only sent while a stream is open
+        assert_eq!(pair.client_conn_mut(client_ch).is_closed(), !open_stream);
+        assert_eq!(pair.server_conn_mut(server_ch).is_closed(), !open_stream);
+    }
+}
+
 #[test]
 fn cid_rotation() {
     let _guard = subscribe();
@@ -1312,15 +3093,129 @@ fn cid_rotation() {
             "Checking active cid sequence range before {:?} seconds",
             round * CID_TIMEOUT.as_secs()
         );
-        let _bound = (left_bound, right_bound);
+        let _bound = (left_bound, right_bound);
+        assert_matches!(
+            pair.server_conn_mut(server_ch).active_local_cid_seq(),
+            _bound
+        );
+        round += 1;
+        left_bound += active_cid_num;
+        right_bound += active_cid_num;
+        pair.drive_server();
+    }
+}
+
+#[test]
+fn active_connection_id_limit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport.active_connection_id_limit(2);
+    let client_ch = pair.begin_connect(ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    });
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+
+    let params = pair
+        .server_conn_mut(server_ch)
+        .peer_transport_parameters()
+        .unwrap();
+    assert_eq!(params.active_connection_id_limit(), VarInt(2));
+    let params = pair
+        .client_conn_mut(client_ch)
+        .peer_transport_parameters()
+        .unwrap();
+    assert_eq!(params.active_connection_id_limit(), VarInt(5));
+    // Only a single spare CID is issued to the client
+    let (first, last) = pair.server_conn_mut(server_ch).active_local_cid_seq();
+    assert_eq!(last - first + 1, 2);
+    let (first, last) = pair.client_conn_mut(client_ch).active_local_cid_seq();
+    assert_eq!(last - first + 1, 5);
+}
+
+#[test]
+fn peer_transport_parameters() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport
+        .max_concurrent_bidi_streams(42u32.into())
+        .max_idle_timeout(Some(VarInt(5_000).into()))
+        .datagram_receive_buffer_size(None);
+    let server = ServerConfig {
+        transport: Arc::new(transport),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let client_ch = pair.begin_connect(client_config());
+    assert!(pair
+        .client_conn_mut(client_ch)
+        .peer_transport_parameters()
+        .is_none());
+    pair.drive();
+    let server_ch = pair.server.assert_accept();
+
+    let params = pair
+        .client_conn_mut(client_ch)
+        .peer_transport_parameters()
+        .unwrap();
+    assert_eq!(params.initial_max_streams_bidi(), VarInt(42));
+    assert_eq!(params.max_idle_timeout(), VarInt(5_000));
+    assert_eq!(params.max_datagram_frame_size(), None);
+    assert_eq!(params.preferred_address_v6(), None);
+
+    let params = pair
+        .server_conn_mut(server_ch)
+        .peer_transport_parameters()
+        .unwrap();
+    assert_eq!(params.initial_max_streams_bidi(), VarInt(100));
+    assert!(params.max_datagram_frame_size().is_some());
+}
+
+#[test]
+fn server_name_config() {
+    let _guard = subscribe();
+    let alt = rcgen::generate_simple_self_signed(vec!["alt.example".into()]).unwrap();
+    let alt_key = PrivateKey::from_der(&alt.serialize_private_key_der()).unwrap();
+    let alt = Certificate::from_der(&alt.serialize_der().unwrap()).unwrap();
+    let mut transport = TransportConfig::default();
+    transport.max_idle_timeout(Some(VarInt(5_000).into()));
+    let mut server = server_config();
+    server.server_name(
+        "Alt.Example.",
+        Arc::new(server_crypto_with_cert(alt.clone(), alt_key)),
+        Arc::new(transport),
+    );
+    let mut pair = Pair::new(Default::default(), server);
+
+    for (name, cert, idle_timeout) in &[
+        ("alt.example", alt, VarInt(5_000)),
+        (
+            "localhost",
+            Certificate::from_der(&CERTIFICATE.serialize_der().unwrap()).unwrap(),
+            VarInt(10_000),
+        ),
+    ] {
+        info!(name, "connecting");
+        let config = client_config_with_certs(vec![cert.clone()]);
+        let (client_ch, client_conn) = pair.client.connect(config, pair.server.addr, name).unwrap();
+        pair.client.connections.insert(client_ch, client_conn);
+        pair.drive();
+        pair.server.assert_accept();
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::HandshakeDataReady)
+        );
         assert_matches!(
-            pair.server_conn_mut(server_ch).active_local_cid_seq(),
-            _bound
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::Connected)
         );
-        round += 1;
-        left_bound += active_cid_num;
-        right_bound += active_cid_num;
-        pair.drive_server();
+        let params = pair
+            .client_conn_mut(client_ch)
+            .peer_transport_parameters()
+            .unwrap();
+        assert_eq!(params.max_idle_timeout(), *idle_timeout);
     }
 }
 
@@ -1431,7 +3326,15 @@ fn handshake_1rtt_handling() {
 
     pair.drive();
 
-    assert!(pair.client_conn_mut(client_ch).lost_packets() != 0);
+    // The 1-RTT data was buffered until the handshake completed, rather than dropped
+    assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+    assert!(
+        pair.server_conn_mut(server_ch)
+            .stats()
+            .undecryptable
+            .buffered
+            > 0
+    );
     let mut recv = pair.server_recv(server_ch, s);
     let mut chunks = recv.read(false).unwrap();
     assert_matches!(
@@ -1512,6 +3415,37 @@ fn congested_tail_loss() {
     pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
 }
 
+#[test]
+fn path_stats_loss() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    let stats = pair.client_conn_mut(client_ch).stats().path;
+    assert!(stats.sent_packets > 0);
+    assert_eq!(stats.lost_packets, 0);
+    assert_eq!(stats.lost_bytes, 0);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+    pair.drive_client();
+    pair.server.inbound.clear(); // Lose it
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+
+    let new_stats = pair.client_conn_mut(client_ch).stats().path;
+    assert!(new_stats.sent_packets > stats.sent_packets + 1);
+    assert_eq!(
+        new_stats.lost_packets,
+        pair.client_conn_mut(client_ch).lost_packets()
+    );
+    assert!(new_stats.lost_packets > 0);
+    assert!(new_stats.lost_bytes >= 1024);
+}
+
 #[test]
 fn datagram_send_recv() {
     let _guard = subscribe();
@@ -1571,6 +3505,58 @@ fn datagram_recv_buffer_overflow() {
     assert_matches!(pair.server_datagrams(server_ch).recv(), None);
 }
 
+#[test]
+fn datagram_send_buffer_policy() {
+    let _guard = subscribe();
+    const BUFFER: usize = 100;
+    const DATA1: &[u8] = &[0xAB; (BUFFER / 2) + 1];
+    const DATA2: &[u8] = &[0xBC; (BUFFER / 2) + 1];
+    for &policy in &[
+        DatagramDropPolicy::DropOldest,
+        DatagramDropPolicy::RejectNew,
+    ] {
+        let server = ServerConfig {
+            transport: Arc::new(TransportConfig {
+                datagram_send_buffer_size: BUFFER,
+                datagram_drop_policy: policy,
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        };
+        let mut pair = Pair::new(Default::default(), server);
+        let (client_ch, server_ch) = pair.connect();
+        assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+
+        pair.server_datagrams(server_ch).send(DATA1.into()).unwrap();
+        let result = pair.server_datagrams(server_ch).send(DATA2.into());
+        let (expected, dropped) = match policy {
+            DatagramDropPolicy::DropOldest => {
+                assert_matches!(result, Ok(()));
+                assert_matches!(
+                    pair.server_conn_mut(server_ch).poll(),
+                    Some(Event::DatagramsDropped)
+                );
+                (DATA2, 1)
+            }
+            DatagramDropPolicy::RejectNew => {
+                assert_matches!(result, Err(SendDatagramError::Blocked));
+                (DATA1, 0)
+            }
+        };
+        let stats = pair.server_conn_mut(server_ch).stats();
+        assert_eq!(stats.datagram_drops.send_buffer, dropped);
+        assert_eq!(stats.datagram_drops.unsendable, 0);
+
+        pair.drive();
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::DatagramReceived)
+        );
+        assert_eq!(pair.client_datagrams(client_ch).recv().unwrap(), expected);
+        assert_matches!(pair.client_datagrams(client_ch).recv(), None);
+    }
+}
+
 #[test]
 fn datagram_unsupported() {
     let _guard = subscribe();
@@ -1609,6 +3595,7 @@ fn large_initial() {
     let cfg = ClientConfig {
         transport: Default::default(),
         crypto: Arc::new(client_crypto),
+        path_cache: None,
     };
     let client_ch = pair.begin_connect(cfg);
     pair.drive();
@@ -1845,6 +3832,662 @@ fn server_can_send_3_inital_packets() {
     );
 }
 
+/// Ensures that packets arriving before the keys needed to decrypt them are buffered, up to the
+/// configured limit
+#[test]
+fn reordered_handshake_packets() {
+    let _guard = subscribe();
+
+    let (cert, key) = big_cert_and_key();
+    for &buffer_size in &[16 * 1024, 0] {
+        let server = server_config_with_cert(cert.clone(), key.clone());
+        let mut client = client_config_with_certs(vec![cert.clone()]);
+        Arc::get_mut(&mut client.transport)
+            .unwrap()
+            .undecryptable_packet_buffer_size(buffer_size);
+        let mut pair = Pair::new(Default::default(), server);
+
+        let client_ch = pair.begin_connect(client);
+        pair.drive_client();
+        pair.drive_server();
+        // Deliver the Initial packet carrying the server's handshake keys last
+        let initial = pair.client.inbound.pop_front().unwrap();
+        pair.client.inbound.push_back(initial);
+        pair.drive();
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::HandshakeDataReady)
+        );
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::Connected)
+        );
+
+        let stats = pair.client_conn_mut(client_ch).stats().undecryptable;
+        if buffer_size == 0 {
+            assert_eq!(stats.buffered, 0);
+            assert!(stats.dropped > 0);
+            assert!(stats.dropped_bytes > 0);
+        } else {
+            assert!(stats.buffered > 0);
+            assert_eq!(stats.dropped, 0);
+        }
+    }
+}
+
+#[test]
+fn ip_address_server_name() {
+    let _guard = subscribe();
+    let mut params = rcgen::CertificateParams::new(vec!["localhost".into()]);
+    params
+        .subject_alt_names
+        .push(rcgen::SanType::IpAddress(Ipv6Addr::LOCALHOST.into()));
+    let cert = rcgen::Certificate::from_params(params).unwrap();
+    let key = PrivateKey::from_der(&cert.serialize_private_key_der()).unwrap();
+    let cert = Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
+    let mut pair = Pair::new(
+        Default::default(),
+        server_config_with_cert(cert.clone(), key),
+    );
+
+    for (name, valid) in &[
+        ("::1", true),
+        ("[::1]", true),
+        ("localhost", true),
+        ("::2", false),
+        ("127.0.0.1", false),
+    ] {
+        info!(name, "connecting");
+        let config = ClientConfig::with_root_certificates(vec![cert.clone()]).unwrap();
+        let (client_ch, client_conn) = pair.client.connect(config, pair.server.addr, name).unwrap();
+        pair.client.connections.insert(client_ch, client_conn);
+        pair.drive();
+        assert_certificate_accepted(&mut pair, client_ch, *valid);
+    }
+}
+
+/// Check that the client's handshake completed, or failed with a bad_certificate alert
+fn assert_certificate_accepted(pair: &mut Pair, client_ch: ConnectionHandle, accepted: bool) {
+    if accepted {
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::HandshakeDataReady)
+        );
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::Connected)
+        );
+    } else {
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::ConnectionLost { reason: ConnectionError::TransportError(ref e) })
+            if e.code == TransportErrorCode::crypto(0x2a)
+        );
+    }
+}
+
+#[test]
+fn pinned_keys() {
+    let _guard = subscribe();
+    let cert = Certificate::from_der(&CERTIFICATE.serialize_der().unwrap()).unwrap();
+    let other = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let other = Certificate::from_der(&other.serialize_der().unwrap()).unwrap();
+    assert_ne!(cert.spki_sha256(), other.spki_sha256());
+    let mut pair = Pair::default();
+
+    for (pins, valid) in &[
+        (
+            vec![other.spki_sha256().unwrap(), cert.spki_sha256().unwrap()],
+            true,
+        ),
+        (vec![other.spki_sha256().unwrap()], false),
+        (vec![], false),
+    ] {
+        let config = ClientConfig::builder(vec![cert.clone()])
+            .unwrap()
+            .pinned_keys(pins.clone())
+            .build();
+        let client_ch = pair.begin_connect(config);
+        pair.drive();
+        assert_certificate_accepted(&mut pair, client_ch, *valid);
+    }
+}
+
+#[test]
+fn pinned_ca_keys() {
+    let ca = || {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        rcgen::Certificate::from_params(params).unwrap()
+    };
+    let root = ca();
+    let issuer = ca();
+    let issuer_der = issuer.serialize_der_with_signer(&root).unwrap();
+    let leaf =
+        rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec!["localhost".into()]))
+            .unwrap();
+    let leaf = Certificate::from_der(&leaf.serialize_der_with_signer(&issuer).unwrap()).unwrap();
+    let root = Certificate::from_der(&root.serialize_der().unwrap()).unwrap();
+    let issuer = Certificate::from_der(&issuer_der).unwrap();
+    let unrelated = Certificate::from_der(&CERTIFICATE.serialize_der().unwrap()).unwrap();
+
+    let roots =
+        Arc::new(crypto::rustls::WebPkiServerVerifier::new(vec![root.inner.clone()]).unwrap());
+    // The unrelated certificate is presented too, but plays no part in the server's chain
+    let intermediates = [issuer.inner.clone(), unrelated.inner.clone()];
+    let verify = |pin: &Certificate| {
+        let verifier =
+            crypto::rustls::SpkiPinningVerifier::new(roots.clone(), &roots, pin.spki_sha256());
+        rustls::client::ServerCertVerifier::verify_server_cert(
+            &verifier,
+            &leaf.inner,
+            &intermediates,
+            &rustls::ServerName::try_from("localhost").unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    };
+    verify(&leaf).unwrap();
+    verify(&issuer).unwrap();
+    // The root isn't presented, but the chain leads to it
+    verify(&root).unwrap();
+    verify(&unrelated).unwrap_err();
+}
+
+#[test]
+fn ocsp_stapling() {
+    let _guard = subscribe();
+    let cert = Certificate::from_der(&CERTIFICATE.serialize_der().unwrap()).unwrap();
+    let key = PrivateKey::from_der(&CERTIFICATE.serialize_private_key_der()).unwrap();
+    let resolver = Arc::new(
+        crypto::rustls::StapledCertResolver::new(
+            CertificateChain::from_certs(vec![cert.clone()]),
+            key,
+            Some(b"first".to_vec()),
+        )
+        .unwrap(),
+    );
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig::with_cert_resolver(resolver.clone()),
+    );
+
+    let recorder = Arc::new(OcspRecorder {
+        inner: Arc::new(
+            crypto::rustls::WebPkiServerVerifier::new(vec![cert.inner.clone()]).unwrap(),
+        ),
+        responses: Default::default(),
+    });
+    let mut crypto = crypto::rustls::client_config(rustls::RootCertStore::empty());
+    crypto
+        .dangerous()
+        .set_certificate_verifier(recorder.clone());
+    // Resumed sessions skip certificate verification
+    crypto.session_storage = Arc::new(rustls::client::NoClientSessionStorage {});
+    let config = ClientConfig {
+        transport: Default::default(),
+        crypto: Arc::new(crypto),
+        path_cache: None,
+    };
+
+    pair.begin_connect(config.clone());
+    pair.drive();
+    resolver.set_ocsp_response(Some(b"second".to_vec()));
+    pair.begin_connect(config.clone());
+    pair.drive();
+    resolver.set_ocsp_response(None);
+    pair.begin_connect(config);
+    pair.drive();
+    assert_eq!(
+        *recorder.responses.lock().unwrap(),
+        [&b"first"[..], &b"second"[..], &b""[..]]
+    );
+}
+
+/// Records the OCSP responses stapled by servers
+struct OcspRecorder {
+    inner: Arc<dyn rustls::client::ServerCertVerifier>,
+    responses: std::sync::Mutex<Vec<Vec<u8>>>,
+}
+
+impl rustls::client::ServerCertVerifier for OcspRecorder {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        self.responses.lock().unwrap().push(ocsp_response.to_vec());
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+}
+
+#[test]
+fn revoked_certificate() {
+    let _guard = subscribe();
+    let mut ca = rcgen::CertificateParams::new(Vec::new());
+    ca.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = rcgen::Certificate::from_params(ca).unwrap();
+    let ca_der = ca.serialize_der().unwrap();
+    let leaves = [1, 2]
+        .iter()
+        .map(|&serial| {
+            let mut params = rcgen::CertificateParams::new(vec!["localhost".into()]);
+            params.serial_number = Some(serial);
+            let leaf = rcgen::Certificate::from_params(params).unwrap();
+            let der = leaf.serialize_der_with_signer(&ca).unwrap();
+            let key = PrivateKey::from_der(&leaf.serialize_private_key_der()).unwrap();
+            (der, key)
+        })
+        .collect::<Vec<_>>();
+
+    // Revoke the first leaf
+    let mut fields = der_split(&mut der_split(&mut &leaves[0].0[..]).1).1;
+    let _version = der_split(&mut fields);
+    let revoked_serial = der_split(&mut fields).0;
+    let crl = sign_crl(&ca, None, &[(revoked_serial, &[])], &[]);
+
+    let ca = Certificate::from_der(&ca_der).unwrap();
+    let leaf = Certificate::from_der(&leaves[1].0).unwrap();
+    assert!(crypto::rustls::CertificateRevocationList::from_der(&crl, &leaf.inner).is_err());
+    let crl = crypto::rustls::CertificateRevocationList::from_der(&crl, &ca.inner).unwrap();
+
+    for (i, (der, key)) in leaves.into_iter().enumerate() {
+        let cert = Certificate::from_der(&der).unwrap();
+        let mut pair = Pair::new(Default::default(), server_config_with_cert(cert, key));
+        let config = ClientConfig::builder(vec![ca.clone()])
+            .unwrap()
+            .crls(vec![crl.clone()])
+            .build();
+        let client_ch = pair.begin_connect(config);
+        pair.drive();
+        if i == 0 {
+            assert_matches!(
+                pair.client_conn_mut(client_ch).poll(),
+                Some(Event::ConnectionLost { reason: ConnectionError::TransportError(ref e) })
+                if e.code == TransportErrorCode::CERTIFICATE_REVOKED
+            );
+        } else {
+            assert_matches!(
+                pair.client_conn_mut(client_ch).poll(),
+                Some(Event::HandshakeDataReady)
+            );
+            assert_matches!(
+                pair.client_conn_mut(client_ch).poll(),
+                Some(Event::Connected)
+            );
+        }
+    }
+}
+
+#[test]
+fn crl_validity() {
+    let mut ca = rcgen::CertificateParams::new(Vec::new());
+    ca.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let ca = rcgen::Certificate::from_params(ca).unwrap();
+    let leaf =
+        rcgen::Certificate::from_params(rcgen::CertificateParams::new(vec!["localhost".into()]))
+            .unwrap();
+    let leaf = Certificate::from_der(&leaf.serialize_der_with_signer(&ca).unwrap()).unwrap();
+    let ca_cert = Certificate::from_der(&ca.serialize_der().unwrap()).unwrap();
+    let parse =
+        |crl: &[u8]| crypto::rustls::CertificateRevocationList::from_der(crl, &ca_cert.inner);
+    let unrevoked = der_encode(0x02, &[0x7f]);
+    // id-ce-deltaCRLIndicator, 2.5.29.27, marked critical
+    let delta = der_encode(
+        0x30,
+        &[
+            &der_encode(0x06, &[0x55, 0x1d, 0x1b])[..],
+            &der_encode(0x01, &[0xff]),
+            &der_encode(0x04, &der_encode(0x02, &[1])),
+        ]
+        .concat(),
+    );
+    // id-ce-certificateIssuer, 2.5.29.29, marked critical, as found in indirect CRLs
+    let indirect = der_encode(
+        0x30,
+        &[
+            &der_encode(0x06, &[0x55, 0x1d, 0x1d])[..],
+            &der_encode(0x01, &[0xff]),
+            &der_encode(0x04, &der_encode(0x30, &[])),
+        ]
+        .concat(),
+    );
+    // The same without the critical flag, which may be ignored
+    let noncritical = der_encode(
+        0x30,
+        &[
+            &der_encode(0x06, &[0x55, 0x1d, 0x1b])[..],
+            &der_encode(0x04, &der_encode(0x02, &[1])),
+        ]
+        .concat(),
+    );
+
+    assert_matches!(
+        parse(&sign_crl(&ca, None, &[], &delta)),
+        Err(webpki::Error::UnsupportedCriticalExtension)
+    );
+    assert_matches!(
+        parse(&sign_crl(&ca, None, &[(&unrevoked, &indirect)], &[])),
+        Err(webpki::Error::UnsupportedCriticalExtension)
+    );
+    parse(&sign_crl(
+        &ca,
+        None,
+        &[(&unrevoked, &noncritical)],
+        &noncritical,
+    ))
+    .unwrap();
+
+    let verify = |crl: &[u8]| {
+        let verifier = crypto::rustls::RevocationVerifier::new(
+            Arc::new(
+                crypto::rustls::WebPkiServerVerifier::new(vec![ca_cert.inner.clone()]).unwrap(),
+            ),
+            parse(crl),
+        );
+        rustls::client::ServerCertVerifier::verify_server_cert(
+            &verifier,
+            &leaf.inner,
+            &[],
+            &rustls::ServerName::try_from("localhost").unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    };
+    verify(&sign_crl(&ca, Some("20991231235959Z"), &[], &[])).unwrap();
+    // Once a list is due to be replaced, it no longer vouches for the issuer's certificates
+    verify(&sign_crl(&ca, Some("20210201000000Z"), &[], &[])).unwrap_err();
+}
+
+/// Encode a CRL for `ca`, signed with its ECDSA key, revoking the certificates with the
+/// DER-encoded serial numbers in `revoked`, each with its DER-encoded entry extensions
+fn sign_crl(
+    ca: &rcgen::Certificate,
+    next_update: Option<&str>,
+    revoked: &[(&[u8], &[u8])],
+    extensions: &[u8],
+) -> Vec<u8> {
+    // rcgen can't generate CRLs, so encode one by hand
+    let ca_der = ca.serialize_der().unwrap();
+    let mut fields = der_split(&mut der_split(&mut &ca_der[..]).1).1;
+    let _version = der_split(&mut fields);
+    let _serial = der_split(&mut fields);
+    let _signature = der_split(&mut fields);
+    let _issuer = der_split(&mut fields);
+    let _validity = der_split(&mut fields);
+    let ca_subject = der_split(&mut fields).0;
+
+    const ECDSA_WITH_SHA256: &[u8] = &hex!("2a8648ce3d040302");
+    let this_update = der_encode(0x17, b"210101000000Z");
+    let next_update = next_update.map_or_else(Vec::new, |x| der_encode(0x18, x.as_bytes()));
+    let algorithm = der_encode(0x30, &der_encode(0x06, ECDSA_WITH_SHA256));
+    let revoked = revoked
+        .iter()
+        .map(|&(serial, extensions)| {
+            let extensions = match extensions {
+                [] => Vec::new(),
+                x => der_encode(0x30, x),
+            };
+            der_encode(0x30, &[serial, &this_update[..], &extensions].concat())
+        })
+        .collect::<Vec<_>>()
+        .concat();
+    let revoked = match revoked.len() {
+        0 => Vec::new(),
+        _ => der_encode(0x30, &revoked),
+    };
+    let extensions = match extensions {
+        [] => Vec::new(),
+        x => der_encode(0xa0, &der_encode(0x30, x)),
+    };
+    let tbs = der_encode(
+        0x30,
+        &[
+            &der_encode(0x02, &[1])[..],
+            &algorithm,
+            ca_subject,
+            &this_update,
+            &next_update,
+            &revoked,
+            &extensions,
+        ]
+        .concat(),
+    );
+    let key = ring::signature::EcdsaKeyPair::from_pkcs8(
+        &ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+        &ca.serialize_private_key_der(),
+    )
+    .unwrap();
+    let signature = key.sign(&ring::rand::SystemRandom::new(), &tbs).unwrap();
+    der_encode(
+        0x30,
+        &[
+            &tbs[..],
+            &algorithm,
+            &der_encode(0x03, &[&[0], signature.as_ref()].concat()),
+        ]
+        .concat(),
+    )
+}
+
+#[test]
+fn stapled_scts() {
+    let _guard = subscribe();
+    let (log, logs) = ct_log();
+    let cert = Certificate::from_der(&CERTIFICATE.serialize_der().unwrap()).unwrap();
+    let key = PrivateKey::from_der(&CERTIFICATE.serialize_private_key_der()).unwrap();
+    // entry_type = x509_entry
+    let entry = [&[0, 0][..], &u24(cert.as_der().len()), cert.as_der()].concat();
+    let mut invalid = sign_sct(&log, logs[0].id, &entry);
+    *invalid.last_mut().unwrap() ^= 1;
+
+    for (scts, policy, valid) in &[
+        (
+            Some(sign_sct(&log, logs[0].id, &entry)),
+            SctPolicy::Require,
+            true,
+        ),
+        (None, SctPolicy::Require, false),
+        (None, SctPolicy::BestEffort, true),
+        (Some(invalid), SctPolicy::BestEffort, false),
+    ] {
+        let crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth();
+        let mut crypto = match *scts {
+            Some(ref sct) => crypto.with_single_cert_with_ocsp_and_sct(
+                vec![cert.inner.clone()],
+                key.inner.clone(),
+                Vec::new(),
+                sct_list(&[sct]),
+            ),
+            None => crypto.with_single_cert(vec![cert.inner.clone()], key.inner.clone()),
+        }
+        .unwrap();
+        crypto.max_early_data_size = u32::MAX;
+        let mut pair = Pair::new(
+            Default::default(),
+            ServerConfig::with_crypto(Arc::new(crypto)),
+        );
+
+        let config = ClientConfig::builder(vec![cert.clone()])
+            .unwrap()
+            .ct_logs(logs, *policy)
+            .build();
+        let client_ch = pair.begin_connect(config);
+        pair.drive();
+        assert_certificate_accepted(&mut pair, client_ch, *valid);
+    }
+}
+
+#[test]
+fn embedded_scts() {
+    let (log, logs) = ct_log();
+    let mut root = rcgen::CertificateParams::new(Vec::new());
+    root.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let root = rcgen::Certificate::from_params(root).unwrap();
+    let mut issuer = rcgen::CertificateParams::new(Vec::new());
+    issuer.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    let issuer = rcgen::Certificate::from_params(issuer).unwrap();
+    let issuer_der = issuer.serialize_der_with_signer(&root).unwrap();
+    let key = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)
+        .unwrap()
+        .serialize_der();
+    let leaf = |extensions| {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".into()]);
+        params.key_pair = Some(rcgen::KeyPair::from_der(&key).unwrap());
+        params.use_authority_key_identifier_extension = true;
+        params.custom_extensions = extensions;
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        Certificate::from_der(&cert.serialize_der_with_signer(&issuer).unwrap()).unwrap()
+    };
+
+    // Sign the certificate as it is before SCTs are embedded
+    let precert = leaf(Vec::new());
+    let precert_tbs = der_split(&mut der_split(&mut precert.as_der()).1).0;
+    let issuer = Certificate::from_der(&issuer_der).unwrap();
+    // entry_type = precert_entry
+    let entry = [
+        &[0, 1][..],
+        &issuer.spki_sha256().unwrap(),
+        &u24(precert_tbs.len()),
+        precert_tbs,
+    ]
+    .concat();
+    let sct = sign_sct(&log, logs[0].id, &entry);
+    let cert = leaf(vec![rcgen::CustomExtension::from_oid_content(
+        &[1, 3, 6, 1, 4, 1, 11129, 2, 4, 2],
+        der_encode(0x04, &sct_list(&[&sct])),
+    )]);
+
+    let roots = crypto::rustls::WebPkiServerVerifier::new(vec![rustls::Certificate(
+        root.serialize_der().unwrap(),
+    )])
+    .unwrap();
+    let verifier = crypto::rustls::SctVerifier::new(Arc::new(roots), logs, SctPolicy::Require);
+    let verify = |cert: &Certificate, intermediates: &[rustls::Certificate]| {
+        rustls::client::ServerCertVerifier::verify_server_cert(
+            &verifier,
+            &cert.inner,
+            intermediates,
+            &rustls::ServerName::try_from("localhost").unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    };
+    let intermediates = [issuer.inner.clone()];
+    verify(&cert, &intermediates).unwrap();
+    verify(&precert, &intermediates).unwrap_err();
+
+    // The issuer is found by its key identifier, not its position or its name, which the root
+    // shares
+    let root = rustls::Certificate(root.serialize_der().unwrap());
+    verify(&cert, &[root, issuer.inner]).unwrap();
+}
+
+/// Generate a Certificate Transparency log, returning its signing key and a log list
+fn ct_log() -> (
+    ring::signature::EcdsaKeyPair,
+    &'static [&'static sct::Log<'static>],
+) {
+    let alg = &ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = ring::signature::EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+    let key = ring::signature::EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref()).unwrap();
+    let public = ring::signature::KeyPair::public_key(&key).as_ref().to_vec();
+    let mut id = [0; 32];
+    id.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, &public).as_ref());
+    let log = Box::leak(Box::new(sct::Log {
+        description: "test log",
+        url: "",
+        operated_by: "",
+        key: Box::leak(public.into_boxed_slice()),
+        id,
+        max_merge_delay: 0,
+    }));
+    (key, Box::leak(Box::new([&*log])))
+}
+
+/// Issue an SCT for `entry`, a `LogEntryType` followed by the corresponding entry
+fn sign_sct(log: &ring::signature::EcdsaKeyPair, id: [u8; 32], entry: &[u8]) -> Vec<u8> {
+    let timestamp = (std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+        - 1000)
+        .to_be_bytes();
+    // version, signature_type, timestamp, entry, no extensions
+    let signed = [&[0, 0][..], &timestamp, entry, &[0, 0]].concat();
+    let signature = log.sign(&ring::rand::SystemRandom::new(), &signed).unwrap();
+    let signature = signature.as_ref();
+    [
+        &[0][..],
+        &id,
+        &timestamp,
+        &[0, 0],
+        // ecdsa_secp256r1_sha256
+        &[4, 3],
+        &(signature.len() as u16).to_be_bytes(),
+        signature,
+    ]
+    .concat()
+}
+
+/// Encode a TLS `SignedCertificateTimestampList`
+fn sct_list(scts: &[&[u8]]) -> Vec<u8> {
+    let list = scts
+        .iter()
+        .flat_map(|sct| [&(sct.len() as u16).to_be_bytes()[..], sct].concat())
+        .collect::<Vec<_>>();
+    [&(list.len() as u16).to_be_bytes()[..], &list].concat()
+}
+
+fn u24(x: usize) -> [u8; 3] {
+    let x = (x as u32).to_be_bytes();
+    [x[1], x[2], x[3]]
+}
+
+/// Split the first value off a DER encoding, returning its full encoding and its contents
+fn der_split<'a>(input: &mut &'a [u8]) -> (&'a [u8], &'a [u8]) {
+    let (header, len) = match input[1] {
+        x if x < 0x80 => (2, usize::from(x)),
+        0x81 => (3, usize::from(input[2])),
+        0x82 => (4, usize::from(u16::from_be_bytes([input[2], input[3]]))),
+        _ => unreachable!(),
+    };
+    let (value, rest) = input.split_at(header + len);
+    *input = rest;
+    (value, &value[header..])
+}
+
+fn der_encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut result = vec![tag];
+    match contents.len() {
+        x if x < 0x80 => result.push(x as u8),
+        x if x < 0x100 => result.extend_from_slice(&[0x81, x as u8]),
+        x => {
+            result.push(0x82);
+            result.extend_from_slice(&(x as u16).to_be_bytes());
+        }
+    }
+    result.extend_from_slice(contents);
+    result
+}
+
 /// Generate a big fat certificate that can't fit inside the initial anti-amplification limit
 fn big_cert_and_key() -> (Certificate, PrivateKey) {
     let cert = rcgen::generate_simple_self_signed(
@@ -1858,3 +4501,145 @@ fn big_cert_and_key() -> (Certificate, PrivateKey) {
     let cert = Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
     (cert, key)
 }
+
+#[cfg(feature = "qlog")]
+#[test]
+fn qlog_trace() {
+    use std::io::{self, Write};
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Factory(SharedBuf);
+
+    impl QlogFactory for Factory {
+        fn writer(&self, side: Side, _: ConnectionId) -> Option<Box<dyn Write + Send>> {
+            assert_eq!(side, Side::Server);
+            Some(Box::new(self.0.clone()))
+        }
+    }
+
+    let _guard = subscribe();
+    let buf = SharedBuf::default();
+    let mut transport = TransportConfig::default();
+    transport.qlog(Some(Arc::new(Factory(buf.clone()))));
+    let server = ServerConfig {
+        transport: Arc::new(transport),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, _) = pair.connect();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+
+    let trace = buf.0.lock().unwrap();
+    let records = trace
+        .split(|&b| b == 0x1e)
+        .skip(1)
+        .map(|record| serde_json::from_slice::<serde_json::Value>(record).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(records[0]["qlog_format"], "JSON-SEQ");
+    assert_eq!(records[0]["trace"]["vantage_point"]["type"], "server");
+    let events = |name: &str| {
+        records[1..]
+            .iter()
+            .filter(|x| x["name"] == name)
+            .map(|x| &x["data"])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        events("transport:packet_received")[0]["header"]["packet_type"],
+        "initial"
+    );
+    assert!(!events("transport:packet_sent").is_empty());
+    assert!(!events("recovery:metrics_updated").is_empty());
+    assert!(events("transport:packet_received").iter().any(|x| {
+        x["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|frame| frame["frame_type"] == "stream" && frame["length"] == 5)
+    }));
+}
+
+#[test]
+fn grease_tolerated() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    // Enough packets that some are very likely to begin with greasing PADDING
+    for i in 0..64u8 {
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        pair.client_send(client_ch, s).write(&[i]).unwrap();
+        pair.client_send(client_ch, s).finish().unwrap();
+        pair.drive();
+
+        assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+        let mut recv = pair.server_recv(server_ch, s);
+        let mut chunks = recv.read(false).unwrap();
+        assert_matches!(chunks.next(usize::MAX), Ok(Some(chunk)) if chunk.bytes[..] == [i]);
+        let _ = chunks.finalize();
+    }
+    assert!(!pair.client_conn_mut(client_ch).is_closed());
+    assert!(!pair.server_conn_mut(server_ch).is_closed());
+}
+
+#[test]
+fn careful_resume() {
+    #[derive(Default)]
+    struct Cache(Mutex<HashMap<String, PathParameters>>);
+
+    impl PathCache for Cache {
+        fn put(&self, server_name: &str, params: PathParameters) {
+            self.0.lock().unwrap().insert(server_name.into(), params);
+        }
+
+        fn get(&self, server_name: &str) -> Option<PathParameters> {
+            self.0.lock().unwrap().get(server_name).cloned()
+        }
+    }
+
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(50);
+    let saved = PathParameters {
+        rtt: Duration::from_millis(100),
+        delivery_rate: 10_000_000,
+    };
+    let cache = Arc::new(Cache::default());
+    cache.put("localhost", saved);
+    let client_ch = pair.begin_connect(ClientConfig {
+        path_cache: Some(cache.clone()),
+        ..client_config()
+    });
+    pair.drive();
+    pair.server.assert_accept();
+
+    // The first RTT sample in the data space confirms the path and jumps the window
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 1024]).unwrap();
+    pair.drive();
+    let cwnd = pair.client_conn_mut(client_ch).stats().path.cwnd;
+    assert!(cwnd >= saved.window() / 2, "window {} not resumed", cwnd);
+
+    pair.client
+        .connections
+        .get_mut(&client_ch)
+        .unwrap()
+        .close(pair.time, VarInt(42), Bytes::new());
+    pair.drive();
+    let stored = cache.get("localhost").unwrap();
+    assert_ne!(stored, saved);
+    assert!(stored.rtt >= Duration::from_millis(100));
+}
@@ -285,16 +285,15 @@ impl TestEndpoint {
         }
 
         let mut endpoint_events: Vec<(ConnectionHandle, EndpointEvent)> = vec![];
+        let timeout_expired = self.timeout.map_or(false, |x| x <= now);
+        let mut next_timeout = None;
         for (ch, conn) in self.connections.iter_mut() {
-            if self.timeout.map_or(false, |x| x <= now) {
-                self.timeout = None;
+            if timeout_expired {
                 conn.handle_timeout(now);
             }
 
-            for (_, mut events) in self.conn_events.drain() {
-                for event in events.drain(..) {
-                    conn.handle_event(event);
-                }
+            for event in self.conn_events.remove(ch).into_iter().flatten() {
+                conn.handle_event(event);
             }
 
             while let Some(event) = conn.poll_endpoint_events() {
@@ -304,8 +303,11 @@ impl TestEndpoint {
             while let Some(x) = conn.poll_transmit(now, MAX_DATAGRAMS) {
                 self.outbound.extend(split_transmit(x));
             }
-            self.timeout = conn.poll_timeout();
+            next_timeout = min_opt(next_timeout, conn.poll_timeout());
         }
+        self.timeout = next_timeout;
+        // Discard events for connections that no longer exist
+        self.conn_events.clear();
 
         for (ch, event) in endpoint_events {
             if let Some(event) = self.handle_event(ch, event) {
@@ -397,6 +399,7 @@ pub fn client_config() -> ClientConfig {
     ClientConfig {
         transport: Default::default(),
         crypto: Arc::new(client_crypto()),
+        path_cache: None,
     }
 }
 
@@ -404,6 +407,7 @@ pub fn client_config_with_certs(certs: Vec<Certificate>) -> ClientConfig {
     ClientConfig {
         transport: Default::default(),
         crypto: Arc::new(client_crypto_with_certs(certs)),
+        path_cache: None,
     }
 }
 
@@ -52,6 +52,17 @@ impl Code {
     pub(crate) fn crypto(code: u8) -> Self {
         Code(0x100 | u64::from(code))
     }
+
+    /// TLS alert code carried by a crypto error code, if this is one
+    pub(crate) fn tls_alert(self) -> Option<u8> {
+        match self.0 {
+            0x100..=0x1ff => Some(self.0 as u8),
+            _ => None,
+        }
+    }
+
+    /// the peer's certificate has been revoked by its issuer
+    pub const CERTIFICATE_REVOKED: Self = Code(0x100 | 44);
 }
 
 impl coding::Codec for Code {
@@ -12,16 +12,16 @@ use std::{
 };
 
 use bytes::{Buf, BufMut};
+use rand::Rng;
 use thiserror::Error;
 
 use crate::{
     cid_generator::ConnectionIdGenerator,
-    cid_queue::CidQueue,
     coding::{BufExt, BufMutExt, UnexpectedEnd},
     config::{EndpointConfig, ServerConfig, TransportConfig},
     shared::ConnectionId,
     ResetToken, Side, TransportError, VarInt, LOC_CID_COUNT, MAX_CID_SIZE, MAX_STREAM_COUNT,
-    RESET_TOKEN_SIZE,
+    RESET_TOKEN_SIZE, TIMER_GRANULARITY,
 };
 
 // Apply a given macro to a list of all the transport parameters having integer types, along with
@@ -74,6 +74,9 @@ macro_rules! make_struct {
             pub(crate) disable_active_migration: bool,
             /// Maximum size for datagram frames
             pub(crate) max_datagram_frame_size: Option<VarInt>,
+            /// Minimum amount of time in microseconds by which the endpoint can delay
+            /// acknowledgments, present iff the endpoint supports the ACK frequency extension
+            pub(crate) min_ack_delay: Option<VarInt>,
             /// The value that the endpoint included in the Source Connection ID field of the first
             /// Initial packet it sends for the connection
             pub(crate) initial_src_cid: Option<ConnectionId>,
@@ -89,6 +92,10 @@ macro_rules! make_struct {
             pub(crate) stateless_reset_token: Option<ResetToken>,
             /// The server's preferred address for communication after handshake completion
             pub(crate) preferred_address: Option<PreferredAddress>,
+
+            /// Parameter with a reserved ID, sent to exercise the peer's handling of unknown
+            /// parameters and never received
+            pub(crate) grease_transport_parameter: Option<ReservedTransportParameter>,
         }
 
         impl Default for TransportParameters {
@@ -99,12 +106,15 @@ macro_rules! make_struct {
 
                     disable_active_migration: false,
                     max_datagram_frame_size: None,
+                    min_ack_delay: None,
                     initial_src_cid: None,
 
                     original_dst_cid: None,
                     retry_src_cid: None,
                     stateless_reset_token: None,
                     preferred_address: None,
+
+                    grease_transport_parameter: None,
                 }
             }
         }
@@ -113,6 +123,44 @@ macro_rules! make_struct {
 
 apply_params!(make_struct);
 
+macro_rules! make_accessors {
+    {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
+        impl TransportParameters {
+            $($(#[$doc])* pub fn $name(&self) -> VarInt {
+                self.$name
+            })*
+
+            /// Whether the endpoint forbids active connection migration
+            pub fn disable_active_migration(&self) -> bool {
+                self.disable_active_migration
+            }
+
+            /// Maximum size for datagram frames, or `None` if datagrams are not supported
+            pub fn max_datagram_frame_size(&self) -> Option<VarInt> {
+                self.max_datagram_frame_size
+            }
+
+            /// Minimum ACK delay in microseconds, or `None` if the ACK frequency extension is not
+            /// supported
+            pub fn min_ack_delay(&self) -> Option<VarInt> {
+                self.min_ack_delay
+            }
+
+            /// IPv4 address the server would like clients to migrate to, if any
+            pub fn preferred_address_v4(&self) -> Option<SocketAddrV4> {
+                self.preferred_address.and_then(|x| x.address_v4)
+            }
+
+            /// IPv6 address the server would like clients to migrate to, if any
+            pub fn preferred_address_v6(&self) -> Option<SocketAddrV6> {
+                self.preferred_address.and_then(|x| x.address_v6)
+            }
+        }
+    }
+}
+
+apply_params!(make_accessors);
+
 impl TransportParameters {
     pub(crate) fn new(
         config: &TransportConfig,
@@ -135,12 +183,18 @@ impl TransportParameters {
             active_connection_id_limit: if cid_gen.cid_len() == 0 {
                 2 // i.e. default, i.e. unsent
             } else {
-                CidQueue::LEN as u32
+                config.active_connection_id_limit
             }
             .into(),
             max_datagram_frame_size: config
                 .datagram_receive_buffer_size
                 .map(|x| (x.min(u16::max_value().into()) as u16).into()),
+            min_ack_delay: Some(VarInt::from_u64(TIMER_GRANULARITY.as_micros() as u64).unwrap()),
+            grease_transport_parameter: if config.grease {
+                Some(ReservedTransportParameter::random(&mut rand::thread_rng()))
+            } else {
+                None
+            },
             ..Self::default()
         }
     }
@@ -176,6 +230,40 @@ impl TransportParameters {
     }
 }
 
+/// A transport parameter with an ID reserved for greasing, which the peer must ignore
+///
+/// See RFC 9000 section 18.1.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct ReservedTransportParameter {
+    id: VarInt,
+    payload: [u8; Self::MAX_PAYLOAD_LEN],
+    payload_len: usize,
+}
+
+impl ReservedTransportParameter {
+    const MAX_PAYLOAD_LEN: usize = 16;
+
+    /// A parameter with a random reserved ID and a random payload of up to 16 bytes
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        // Reserved IDs are of the form `31 * N + 27`
+        let n = rng.gen_range(0..=(VarInt::MAX.into_inner() - 27) / 31);
+        let payload_len = rng.gen_range(0..=Self::MAX_PAYLOAD_LEN);
+        let mut payload = [0; Self::MAX_PAYLOAD_LEN];
+        rng.fill(&mut payload[..payload_len]);
+        Self {
+            id: VarInt::from_u64(31 * n + 27).unwrap(),
+            payload,
+            payload_len,
+        }
+    }
+
+    fn write<W: BufMut>(&self, w: &mut W) {
+        w.write(self.id);
+        w.write_var(self.payload_len as u64);
+        w.put_slice(&self.payload[..self.payload_len]);
+    }
+}
+
 /// A server's preferred address
 ///
 /// This is communicated as a transport parameter during TLS session establishment.
@@ -283,9 +371,9 @@ impl TransportParameters {
         }
         apply_params!(write_params);
 
-        // Add a reserved parameter to keep people on their toes
-        w.write_var(31 * 5 + 27);
-        w.write_var(0);
+        if let Some(ref x) = self.grease_transport_parameter {
+            x.write(w);
+        }
 
         if let Some(ref x) = self.stateless_reset_token {
             w.write_var(0x02);
@@ -304,6 +392,12 @@ impl TransportParameters {
             w.write(x);
         }
 
+        if let Some(x) = self.min_ack_delay {
+            w.write_var(0xff04de1b);
+            w.write_var(x.size() as u64);
+            w.write(x);
+        }
+
         if let Some(ref x) = self.preferred_address {
             w.write_var(0x000d);
             w.write_var(x.wire_size() as u64);
@@ -381,6 +475,13 @@ impl TransportParameters {
                     }
                     params.max_datagram_frame_size = Some(r.get().unwrap());
                 }
+                0xff04de1b => {
+                    let value = r.get::<VarInt>()?;
+                    if len != value.size() || params.min_ack_delay.is_some() {
+                        return Err(Error::Malformed);
+                    }
+                    params.min_ack_delay = Some(value);
+                }
                 _ => {
                     macro_rules! parse {
                         {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
@@ -405,6 +506,9 @@ impl TransportParameters {
             || params.max_ack_delay.0 >= 1 << 14
             || params.active_connection_id_limit.0 < 2
             || params.max_udp_payload_size.0 < 1200
+            || params
+                .min_ack_delay
+                .map_or(false, |x| x.0 > params.max_ack_delay.0 * 1000)
             || params.initial_max_streams_bidi.0 > MAX_STREAM_COUNT
             || params.initial_max_streams_uni.0 > MAX_STREAM_COUNT
             || (side.is_server()
@@ -440,6 +544,7 @@ mod test {
             initial_max_streams_uni: 16u32.into(),
             ack_delay_exponent: 2u32.into(),
             max_udp_payload_size: 1200u32.into(),
+            min_ack_delay: Some(2_000u32.into()),
             preferred_address: Some(PreferredAddress {
                 address_v4: Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 42)),
                 address_v6: None,
@@ -455,6 +560,52 @@ mod test {
         );
     }
 
+    #[test]
+    fn malformed_min_ack_delay() {
+        // Empty, as the last parameter
+        let mut buf = Vec::new();
+        buf.write_var(0xff04de1b);
+        buf.write_var(0);
+        assert_eq!(
+            TransportParameters::read(Side::Client, &mut buf.as_slice()),
+            Err(Error::Malformed)
+        );
+
+        // Length not matching the encoded value
+        let mut buf = Vec::new();
+        buf.write_var(0xff04de1b);
+        buf.write_var(4);
+        buf.write_var(2_000);
+        buf.write_var(0x00);
+        buf.write_var(0);
+        assert_eq!(
+            TransportParameters::read(Side::Client, &mut buf.as_slice()),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn grease_ignored() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..64 {
+            let params = TransportParameters {
+                initial_max_data: 42u32.into(),
+                grease_transport_parameter: Some(ReservedTransportParameter::random(&mut rng)),
+                ..TransportParameters::default()
+            };
+            let mut buf = Vec::new();
+            params.write(&mut buf);
+            let decoded = TransportParameters::read(Side::Server, &mut buf.as_slice()).unwrap();
+            assert_eq!(
+                decoded,
+                TransportParameters {
+                    grease_transport_parameter: None,
+                    ..params
+                }
+            );
+        }
+    }
+
     #[test]
     fn resumption_params_validation() {
         let high_limit = TransportParameters {
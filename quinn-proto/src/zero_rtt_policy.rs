@@ -0,0 +1,28 @@
+use std::net::SocketAddr;
+
+/// Governs the 0-RTT data a server accepts from resuming clients
+///
+/// Configured with [`ServerConfig::zero_rtt_policy()`](crate::ServerConfig::zero_rtt_policy).
+/// 0-RTT data isn't protected against replay: an attacker can capture a client's first flight and
+/// send it to the server again, which processes it in a connection whose handshake never
+/// completes. Applications which can't make their early requests idempotent should use this to
+/// admit each session ticket at most once.
+pub trait ZeroRttPolicy: Send + Sync {
+    /// Whether to accept 0-RTT data from the client at `remote` resuming the session with the
+    /// session ticket `ticket`
+    ///
+    /// Called at most once per connection attempt, before the handshake proceeds, and only if
+    /// the client offers 0-RTT data. Returning `false` rejects the data, which the client will
+    /// then retransmit once the handshake completes. To prevent replays, return `false` if `ticket`
+    /// has been seen before, e.g. by recording tickets in a store shared by all servers that may
+    /// receive them until they expire.
+    fn accept_early_data(&self, remote: SocketAddr, ticket: &[u8]) -> bool;
+
+    /// Maximum number of bytes of 0-RTT packet payload accepted per connection
+    ///
+    /// Further 0-RTT packets are dropped, so the client retransmits their contents once the
+    /// handshake completes. Unlimited by default.
+    fn max_early_data_size(&self) -> u64 {
+        u64::MAX
+    }
+}
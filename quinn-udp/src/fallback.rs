@@ -1,59 +1,58 @@
 use std::{
     io::{self, IoSliceMut},
-    net::SocketAddr,
-    task::{Context, Poll},
+    mem::MaybeUninit,
     time::Instant,
 };
 
-use futures_util::ready;
 use proto::Transmit;
-use tokio::io::ReadBuf;
 
-use super::{log_sendmsg_error, RecvMeta, UdpState, IO_ERROR_LOG_INTERVAL};
+use super::{log_sendmsg_error, RecvMeta, UdpSockRef, UdpState, IO_ERROR_LOG_INTERVAL};
 
-/// Tokio-compatible UDP socket with some useful specializations.
+/// Sends and receives datagrams on a UDP socket
 ///
-/// Unlike a standard tokio UDP socket, this allows ECN bits to be read and written on some
-/// platforms.
+/// Independent of any async runtime: the socket must be in non-blocking mode, and operations fail
+/// with [`io::ErrorKind::WouldBlock`] when the caller should wait for the socket to become ready.
+/// ECN bits are neither read nor written on this platform.
 #[derive(Debug)]
-pub struct UdpSocket {
-    io: tokio::net::UdpSocket,
+pub struct UdpSocketState {
     last_send_error: Instant,
 }
 
-impl UdpSocket {
-    pub fn from_std(socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
-        socket.set_nonblocking(true)?;
+impl UdpSocketState {
+    pub fn new() -> Self {
         let now = Instant::now();
-        Ok(UdpSocket {
-            io: tokio::net::UdpSocket::from_std(socket)?,
+        Self {
             last_send_error: now.checked_sub(2 * IO_ERROR_LOG_INTERVAL).unwrap_or(now),
-        })
+        }
+    }
+
+    /// Prepare `socket` for use, making it non-blocking
+    pub fn configure(socket: UdpSockRef<'_>) -> io::Result<()> {
+        socket.0.set_nonblocking(true)
     }
 
-    pub fn poll_send(
+    /// Send as many of `transmits` as possible, returning the number sent
+    pub fn send(
         &mut self,
+        socket: UdpSockRef<'_>,
         _state: &UdpState,
-        cx: &mut Context,
         transmits: &[Transmit],
-    ) -> Poll<Result<usize, io::Error>> {
+    ) -> io::Result<usize> {
         let mut sent = 0;
         for transmit in transmits {
-            match self
-                .io
-                .poll_send_to(cx, &transmit.contents, transmit.destination)
+            match socket
+                .0
+                .send_to(&transmit.contents, &transmit.destination.into())
             {
-                Poll::Ready(Ok(_)) => {
+                Ok(_) => {
                     sent += 1;
                 }
                 // We need to report that some packets were sent in this case, so we rely on
                 // errors being either harmlessly transient (in the case of WouldBlock) or
                 // recurring on the next call.
-                Poll::Ready(Err(_)) | Poll::Pending if sent != 0 => return Poll::Ready(Ok(sent)),
-                Poll::Ready(Err(e)) => {
-                    // WouldBlock is expected to be returned as `Poll::Pending`
-                    debug_assert!(e.kind() != io::ErrorKind::WouldBlock);
-
+                Err(_) if sent != 0 => return Ok(sent),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(e),
+                Err(e) => {
                     // Errors are ignored, since they will ususally be handled
                     // by higher level retransmits and timeouts.
                     // - PermissionDenied errors have been observed due to iptable rules.
@@ -63,32 +62,37 @@ impl UdpSocket {
                     log_sendmsg_error(&mut self.last_send_error, e, transmit);
                     sent += 1;
                 }
-                Poll::Pending => return Poll::Pending,
             }
         }
-        Poll::Ready(Ok(sent))
+        Ok(sent)
     }
 
-    pub fn poll_recv(
+    /// Receive datagrams into `bufs`, returning the number received
+    pub fn recv(
         &self,
-        cx: &mut Context,
+        socket: UdpSockRef<'_>,
         bufs: &mut [IoSliceMut<'_>],
         meta: &mut [RecvMeta],
-    ) -> Poll<io::Result<usize>> {
+    ) -> io::Result<usize> {
         debug_assert!(!bufs.is_empty());
-        let mut buf = ReadBuf::new(&mut bufs[0]);
-        let addr = ready!(self.io.poll_recv_from(cx, &mut buf))?;
+        // SAFETY: `recv_from` only writes initialized bytes to the buffer
+        let buf = unsafe { &mut *(&mut *bufs[0] as *mut [u8] as *mut [MaybeUninit<u8>]) };
+        let (len, addr) = socket.0.recv_from(buf)?;
         meta[0] = RecvMeta {
-            len: buf.filled().len(),
-            addr,
+            len,
+            addr: addr.as_socket().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "received from a non-IP address")
+            })?,
             ecn: None,
             dst_ip: None,
         };
-        Poll::Ready(Ok(1))
+        Ok(1)
     }
+}
 
-    pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.io.local_addr()
+impl Default for UdpSocketState {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
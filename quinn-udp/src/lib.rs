@@ -1,4 +1,8 @@
 //! Uniform interface to send/recv UDP packets with ECN information.
+//!
+//! Sockets are used in non-blocking mode without reference to any particular async runtime; an
+//! operation that can't make progress fails with `WouldBlock`, and the caller is responsible for
+//! waiting until the socket is ready.
 use std::{
     net::{IpAddr, Ipv6Addr, SocketAddr},
     sync::atomic::{AtomicUsize, Ordering},
@@ -14,12 +18,43 @@ mod cmsg;
 #[path = "unix.rs"]
 mod imp;
 
+#[cfg(target_os = "linux")]
+pub mod reuseport;
+
 // No ECN support
 #[cfg(not(unix))]
 #[path = "fallback.rs"]
 mod imp;
 
-pub use imp::UdpSocket;
+pub use imp::UdpSocketState;
+
+/// A borrowed UDP socket
+///
+/// Constructed from a reference to any socket type exposing its file descriptor on Unix, or its
+/// raw socket on Windows, such as `std::net::UdpSocket` or the UDP socket types of async
+/// runtimes.
+#[derive(Debug)]
+pub struct UdpSockRef<'a>(socket2::SockRef<'a>);
+
+#[cfg(unix)]
+impl<'s, S> From<&'s S> for UdpSockRef<'s>
+where
+    S: std::os::unix::io::AsRawFd,
+{
+    fn from(socket: &'s S) -> Self {
+        Self(socket.into())
+    }
+}
+
+#[cfg(windows)]
+impl<'s, S> From<&'s S> for UdpSockRef<'s>
+where
+    S: std::os::windows::io::AsRawSocket,
+{
+    fn from(socket: &'s S) -> Self {
+        Self(socket.into())
+    }
+}
 
 /// Number of UDP packets to send/receive at a time
 pub const BATCH_SIZE: usize = imp::BATCH_SIZE;
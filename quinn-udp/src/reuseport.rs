@@ -0,0 +1,169 @@
+//! Steering of incoming packets across a group of `SO_REUSEPORT` sockets by connection ID
+//!
+//! The kernel normally distributes datagrams among sockets sharing a port by hashing the 4-tuple,
+//! which breaks down as soon as a client's address changes. The helpers here instead select the
+//! socket using a byte of the destination connection ID, so that a connection stays on the same
+//! shard for its whole lifetime as long as the connection IDs issued on shard `i` of `n` carry a
+//! byte that is congruent to `i` modulo `n` at the configured position.
+//!
+//! The steering program is classic BPF, attached with `SO_ATTACH_REUSEPORT_CBPF`, rather than
+//! eBPF. Selecting a shard only needs one byte of the packet, which classic BPF can express, and
+//! unlike eBPF it needs neither `CAP_BPF` nor a program loader. Deployments that want eBPF can
+//! attach their own `SO_ATTACH_REUSEPORT_EBPF` program to a group bound with
+//! [`bind_reuseport_group`]. It should follow the same shard layout.
+
+use std::{io, mem, net::SocketAddr, os::unix::io::AsRawFd};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Bind `shards` UDP sockets to `addr` as a single `SO_REUSEPORT` group
+///
+/// The index of each socket in the returned `Vec` is its index in the group, i.e. the shard that
+/// [`attach_cid_steering`] will deliver packets to. If `addr` has port 0, the port chosen for the
+/// first socket is used for the rest.
+pub fn bind_reuseport_group(
+    addr: &SocketAddr,
+    shards: usize,
+) -> io::Result<Vec<std::net::UdpSocket>> {
+    let mut addr = *addr;
+    let mut sockets = Vec::with_capacity(shards);
+    for _ in 0..shards {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        let on: libc::c_int = 1;
+        let rc = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_REUSEPORT,
+                &on as *const _ as _,
+                mem::size_of_val(&on) as _,
+            )
+        };
+        if rc == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        socket.bind(&addr.into())?;
+        if addr.port() == 0 {
+            addr = socket.local_addr()?.as_socket().unwrap();
+        }
+        sockets.push(socket.into());
+    }
+    Ok(sockets)
+}
+
+/// Install a program steering packets to the socket selected by their destination connection ID
+///
+/// `socket` may be any member of the reuseport group; the program applies to the whole group.
+/// Packets are delivered to the socket whose index in the group is `cid[cid_offset] % shards`,
+/// where `cid` is the destination connection ID of the packet. For long header packets
+/// initiating a connection, the CID was chosen at random by the client, so the first shard to see
+/// a connection is effectively random too. Combine with a connection ID generator that preserves
+/// the shard index at `cid_offset` so later packets keep arriving on the same shard.
+pub fn attach_cid_steering(socket: &impl AsRawFd, shards: u8, cid_offset: u8) -> io::Result<()> {
+    if shards == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "at least one shard is required",
+        ));
+    }
+    let cid_offset = u32::from(cid_offset);
+    let mut program = [
+        // A = first byte of the UDP payload
+        stmt(BPF_LD | BPF_B | BPF_ABS, 0),
+        // Long headers have the high bit set
+        jump(BPF_JMP | BPF_JSET | BPF_K, 0x80, 2, 0),
+        // Short header: the DCID immediately follows the first byte
+        stmt(BPF_LD | BPF_B | BPF_ABS, 1 + cid_offset),
+        stmt(BPF_JMP | BPF_JA, 1),
+        // Long header: first byte, 4 byte version and DCID length precede the DCID
+        stmt(BPF_LD | BPF_B | BPF_ABS, 6 + cid_offset),
+        stmt(BPF_ALU | BPF_MOD | BPF_K, u32::from(shards)),
+        stmt(BPF_RET | BPF_A, 0),
+    ];
+    let prog = SockFprog {
+        len: program.len() as u16,
+        filter: program.as_mut_ptr(),
+    };
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_ATTACH_REUSEPORT_CBPF,
+            &prog as *const _ as _,
+            mem::size_of::<SockFprog>() as _,
+        )
+    };
+    if rc == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn stmt(code: u16, k: u32) -> SockFilter {
+    jump(code, k, 0, 0)
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code, jt, jf, k }
+}
+
+// Defined locally as older versions of `libc` lack them
+#[repr(C)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *mut SockFilter,
+}
+
+const SO_ATTACH_REUSEPORT_CBPF: libc::c_int = 51;
+
+const BPF_LD: u16 = 0x00;
+const BPF_JMP: u16 = 0x05;
+const BPF_ALU: u16 = 0x04;
+const BPF_RET: u16 = 0x06;
+const BPF_B: u16 = 0x10;
+const BPF_ABS: u16 = 0x20;
+const BPF_JA: u16 = 0x00;
+const BPF_JSET: u16 = 0x40;
+const BPF_MOD: u16 = 0x90;
+const BPF_K: u16 = 0x00;
+const BPF_A: u16 = 0x10;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, time::Duration};
+
+    #[test]
+    fn steer_by_cid() {
+        let sockets = bind_reuseport_group(&"127.0.0.1:0".parse().unwrap(), 2).unwrap();
+        attach_cid_steering(&sockets[0], 2, 0).unwrap();
+        for socket in &sockets {
+            socket
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+        }
+        let addr = sockets[0].local_addr().unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = [0; 64];
+        for &(packet, shard) in &[
+            // Short headers: the first CID byte follows the first byte
+            (&[0x40, 0x03, 0xaa][..], 1),
+            (&[0x40, 0x04, 0xaa][..], 0),
+            // Long header: first byte, version and CID length precede the CID
+            (&[0xc0, 0, 0, 0, 1, 8, 0x05][..], 1),
+        ] {
+            client.send_to(packet, addr).unwrap();
+            let (len, _) = sockets[shard].recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..len], packet);
+        }
+    }
+}
@@ -6,81 +6,70 @@ use std::{
     os::unix::io::AsRawFd,
     ptr,
     sync::atomic::AtomicUsize,
-    task::{Context, Poll},
     time::Instant,
 };
 
-use futures_util::ready;
 use proto::{EcnCodepoint, Transmit};
-use tokio::io::unix::AsyncFd;
 
-use super::{cmsg, log_sendmsg_error, RecvMeta, UdpState, IO_ERROR_LOG_INTERVAL};
+use super::{cmsg, log_sendmsg_error, RecvMeta, UdpSockRef, UdpState, IO_ERROR_LOG_INTERVAL};
 
 #[cfg(target_os = "freebsd")]
 type IpTosTy = libc::c_uchar;
 #[cfg(not(target_os = "freebsd"))]
 type IpTosTy = libc::c_int;
 
-/// Tokio-compatible UDP socket with some useful specializations.
+/// Sends and receives datagrams with ECN information on a UDP socket
 ///
-/// Unlike a standard tokio UDP socket, this allows ECN bits to be read and written on some
-/// platforms.
+/// Independent of any async runtime: the socket must be in non-blocking mode, and operations fail
+/// with [`io::ErrorKind::WouldBlock`] when the caller should wait for the socket to become ready.
 #[derive(Debug)]
-pub struct UdpSocket {
-    io: AsyncFd<mio::net::UdpSocket>,
+pub struct UdpSocketState {
     last_send_error: Instant,
 }
 
-impl UdpSocket {
-    pub fn from_std(socket: std::net::UdpSocket) -> io::Result<UdpSocket> {
-        socket.set_nonblocking(true)?;
-        let io = mio::net::UdpSocket::from_std(socket);
-        init(&io)?;
+impl UdpSocketState {
+    pub fn new() -> Self {
         let now = Instant::now();
-        Ok(UdpSocket {
-            io: AsyncFd::new(io)?,
+        Self {
             last_send_error: now.checked_sub(2 * IO_ERROR_LOG_INTERVAL).unwrap_or(now),
-        })
+        }
+    }
+
+    /// Prepare `socket` for use, making it non-blocking and enabling ECN reporting
+    pub fn configure(socket: UdpSockRef<'_>) -> io::Result<()> {
+        socket.0.set_nonblocking(true)?;
+        init(&socket.0)
     }
 
-    pub fn poll_send(
+    /// Send as many of `transmits` as possible, returning the number sent
+    pub fn send(
         &mut self,
+        socket: UdpSockRef<'_>,
         state: &UdpState,
-        cx: &mut Context,
         transmits: &[Transmit],
-    ) -> Poll<Result<usize, io::Error>> {
-        loop {
-            let last_send_error = &mut self.last_send_error;
-            let mut guard = ready!(self.io.poll_write_ready(cx))?;
-            if let Ok(res) =
-                guard.try_io(|io| send(state, io.get_ref(), last_send_error, transmits))
-            {
-                return Poll::Ready(res);
-            }
-        }
+    ) -> io::Result<usize> {
+        send(state, &socket.0, &mut self.last_send_error, transmits)
     }
 
-    pub fn poll_recv(
+    /// Receive datagrams into `bufs`, returning the number received
+    pub fn recv(
         &self,
-        cx: &mut Context,
+        socket: UdpSockRef<'_>,
         bufs: &mut [IoSliceMut<'_>],
         meta: &mut [RecvMeta],
-    ) -> Poll<io::Result<usize>> {
+    ) -> io::Result<usize> {
         debug_assert!(!bufs.is_empty());
-        loop {
-            let mut guard = ready!(self.io.poll_read_ready(cx))?;
-            if let Ok(res) = guard.try_io(|io| recv(io.get_ref(), bufs, meta)) {
-                return Poll::Ready(res);
-            }
-        }
+        recv(&socket.0, bufs, meta)
     }
+}
 
-    pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.io.get_ref().local_addr()
+impl Default for UdpSocketState {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn init(io: &mio::net::UdpSocket) -> io::Result<()> {
+fn init(io: &socket2::Socket) -> io::Result<()> {
     let mut cmsg_platform_space = 0;
     if cfg!(target_os = "linux") {
         cmsg_platform_space +=
@@ -97,7 +86,10 @@ fn init(io: &mio::net::UdpSocket) -> io::Result<()> {
         "control message buffers will be misaligned"
     );
 
-    let addr = io.local_addr()?;
+    let addr = io
+        .local_addr()?
+        .as_socket()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not an IP socket"))?;
 
     // macos and ios do not support IP_RECVTOS on dual-stack sockets :(
     if addr.is_ipv4() || ((!cfg!(any(target_os = "macos", target_os = "ios"))) && !io.only_v6()?) {
@@ -194,7 +186,7 @@ fn init(io: &mio::net::UdpSocket) -> io::Result<()> {
 #[cfg(not(any(target_os = "macos", target_os = "ios")))]
 fn send(
     state: &UdpState,
-    io: &mio::net::UdpSocket,
+    io: &socket2::Socket,
     last_send_error: &mut Instant,
     transmits: &[Transmit],
 ) -> io::Result<usize> {
@@ -277,7 +269,7 @@ fn send(
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 fn send(
     _state: &UdpState,
-    io: &mio::net::UdpSocket,
+    io: &socket2::Socket,
     last_send_error: &mut Instant,
     transmits: &[Transmit],
 ) -> io::Result<usize> {
@@ -317,7 +309,7 @@ fn send(
 
 #[cfg(not(any(target_os = "macos", target_os = "ios")))]
 fn recv(
-    io: &mio::net::UdpSocket,
+    io: &socket2::Socket,
     bufs: &mut [IoSliceMut<'_>],
     meta: &mut [RecvMeta],
 ) -> io::Result<usize> {
@@ -360,7 +352,7 @@ fn recv(
 
 #[cfg(any(target_os = "macos", target_os = "ios"))]
 fn recv(
-    io: &mio::net::UdpSocket,
+    io: &socket2::Socket,
     bufs: &mut [IoSliceMut<'_>],
     meta: &mut [RecvMeta],
 ) -> io::Result<usize> {
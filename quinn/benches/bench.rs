@@ -17,7 +17,8 @@ benchmark_group!(
     large_data_1_stream,
     large_data_10_streams,
     small_data_1_stream,
-    small_data_100_streams
+    small_data_100_streams,
+    echo_large_data_1_stream
 );
 benchmark_main!(benches);
 
@@ -37,6 +38,28 @@ fn small_data_100_streams(bench: &mut Bencher) {
     send_data(bench, SMALL_DATA, 100);
 }
 
+fn echo_large_data_1_stream(bench: &mut Bencher) {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let ctx = Context::new();
+    let (addr, thread) = ctx.spawn_server();
+    let (endpoint, client, runtime) = ctx.make_client(addr);
+
+    bench.bytes = LARGE_DATA.len() as u64;
+    bench.iter(|| {
+        runtime.block_on(async {
+            let (mut send, recv) = client.open_bi().await.unwrap();
+            let reader = tokio::spawn(async move { recv.read_to_end(usize::MAX).await.unwrap() });
+            send.write_all(LARGE_DATA).await.unwrap();
+            send.finish().await.unwrap();
+            assert_eq!(reader.await.unwrap().len(), LARGE_DATA.len());
+        });
+    });
+    drop(client);
+    runtime.block_on(endpoint.wait_idle());
+    thread.join().unwrap()
+}
+
 fn send_data(bench: &mut Bencher, data: &'static [u8], concurrent_streams: usize) {
     let _ = tracing_subscriber::fmt::try_init();
 
@@ -108,7 +131,9 @@ impl Context {
             let handle = runtime.spawn(
                 async move {
                     let quinn::NewConnection {
-                        mut uni_streams, ..
+                        connection,
+                        mut uni_streams,
+                        ..
                     } = incoming
                         .next()
                         .await
@@ -116,6 +141,16 @@ impl Context {
                         .await
                         .expect("connect");
 
+                    // Echo bidirectional streams back to the client
+                    tokio::spawn(async move {
+                        while let Ok((mut send, mut recv)) = connection.accept_bi().await {
+                            tokio::spawn(async move {
+                                quinn::copy(&mut recv, &mut send).await.unwrap();
+                                send.finish().await.unwrap();
+                            });
+                        }
+                    });
+
                     while let Some(Ok(mut stream)) = uni_streams.next().await {
                         tokio::spawn(async move {
                             while stream
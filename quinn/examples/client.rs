@@ -96,6 +96,7 @@ async fn run(options: Opt) -> Result<()> {
     endpoint.default_client_config(quinn::ClientConfig {
         crypto: Arc::new(client_crypto),
         transport: Default::default(),
+        path_cache: None,
     });
 
     let (endpoint, _) = endpoint.bind(&"[::]:0".parse().unwrap())?;
@@ -88,5 +88,6 @@ fn configure_client() -> ClientConfig {
     ClientConfig {
         crypto: Arc::new(crypto),
         transport,
+        path_cache: None,
     }
 }
@@ -127,10 +127,10 @@ async fn run(options: Opt) -> Result<()> {
         bail!("root path does not exist");
     }
 
-    let (endpoint, mut incoming) = endpoint.bind(&options.listen)?;
+    let (endpoint, _) = endpoint.bind(&options.listen)?;
     eprintln!("listening on {}", endpoint.local_addr()?);
 
-    while let Some(conn) = incoming.next().await {
+    while let Some(conn) = endpoint.accept().await {
         info!("connection incoming");
         tokio::spawn(
             handle_connection(root.clone(), conn).unwrap_or_else(move |e| {
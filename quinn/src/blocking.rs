@@ -0,0 +1,169 @@
+//! Synchronous `std::io` adapters for streams
+
+use std::{
+    io,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use crate::{RecvStream, SendStream};
+
+/// A [`SendStream`] usable as a blocking [`std::io::Write`]
+///
+/// Produced by [`SendStream::into_blocking_writer()`]. Each operation blocks the calling thread
+/// until it completes or the configured timeout elapses, in which case it fails with
+/// [`io::ErrorKind::TimedOut`] without having written any data. The connection continues to be
+/// driven by its runtime in the background, so the calling thread must not be one the runtime
+/// depends on to make progress, such as that of a single-threaded runtime.
+#[derive(Debug)]
+pub struct BlockingWriter {
+    stream: SendStream,
+    timeout: Option<Duration>,
+}
+
+impl BlockingWriter {
+    pub(crate) fn new(stream: SendStream) -> Self {
+        Self {
+            stream,
+            timeout: None,
+        }
+    }
+
+    /// Limit how long each operation may block, or remove the limit with `None`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// The current limit on how long each operation may block
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Shut down the stream gracefully, blocking until the peer has acknowledged all data
+    ///
+    /// See [`SendStream::finish()`]. If this times out, calling it again resumes waiting.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let stream = &mut self.stream;
+        block_on(self.timeout, |cx| stream.poll_finish(cx))
+            .ok_or_else(timed_out)?
+            .map_err(Into::into)
+    }
+
+    /// Access the underlying stream
+    pub fn get_mut(&mut self) -> &mut SendStream {
+        &mut self.stream
+    }
+
+    /// Recover the underlying stream
+    pub fn into_inner(self) -> SendStream {
+        self.stream
+    }
+}
+
+impl io::Write for BlockingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stream = &mut self.stream;
+        block_on(self.timeout, |cx| stream.poll_write(cx, buf))
+            .ok_or_else(timed_out)?
+            .map_err(Into::into)
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let stream = &mut self.stream;
+        block_on(self.timeout, |cx| stream.poll_write_vectored(cx, bufs))
+            .ok_or_else(timed_out)?
+            .map_err(Into::into)
+    }
+
+    /// Does nothing, as written data is handed to the connection immediately
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`RecvStream`] usable as a blocking [`std::io::Read`]
+///
+/// Produced by [`RecvStream::into_blocking_reader()`]. Each read blocks the calling thread until
+/// data is available, the stream has finished, or the configured timeout elapses, in which case it
+/// fails with [`io::ErrorKind::TimedOut`] without having consumed any data. The same caveats as
+/// for [`BlockingWriter`] apply.
+#[derive(Debug)]
+pub struct BlockingReader {
+    stream: RecvStream,
+    timeout: Option<Duration>,
+}
+
+impl BlockingReader {
+    pub(crate) fn new(stream: RecvStream) -> Self {
+        Self {
+            stream,
+            timeout: None,
+        }
+    }
+
+    /// Limit how long each read may block, or remove the limit with `None`
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// The current limit on how long each read may block
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Access the underlying stream
+    pub fn get_mut(&mut self) -> &mut RecvStream {
+        &mut self.stream
+    }
+
+    /// Recover the underlying stream
+    pub fn into_inner(self) -> RecvStream {
+        self.stream
+    }
+}
+
+impl io::Read for BlockingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        let stream = &mut self.stream;
+        block_on(self.timeout, |cx| stream.poll_read(cx, &mut buf)).ok_or_else(timed_out)??;
+        Ok(buf.filled().len())
+    }
+}
+
+/// Poll `f` on the current thread until it's ready, or give up after `timeout`
+fn block_on<T>(timeout: Option<Duration>, mut f: impl FnMut(&mut Context) -> Poll<T>) -> Option<T> {
+    let deadline = timeout.map(|x| Instant::now() + x);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(x) = f(&mut cx) {
+            return Some(x);
+        }
+        match deadline {
+            None => thread::park(),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return None;
+                }
+                thread::park_timeout(deadline - now);
+            }
+        }
+    }
+}
+
+/// Wakes a thread blocked in [`block_on()`]
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "stream operation timed out")
+}
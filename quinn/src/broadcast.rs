@@ -58,5 +58,5 @@ impl Broadcast {
 /// State maintained by each interested task
 ///
 /// Stores the generation at which the task previously registered a `Waker`, if any.
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub struct State(Option<u64>);
@@ -1,11 +1,26 @@
-use std::{io, net::SocketAddr, sync::Arc};
+use std::{
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::Arc,
+};
 
-use proto::{ClientConfig, ConnectionIdGenerator, EndpointConfig, ServerConfig};
+use proto::{ClientConfig, ConnectionIdGenerator, EndpointConfig, HandshakeFailure, ServerConfig};
+use rand::Rng;
+use socket2::SockRef;
 use thiserror::Error;
 use tracing::error;
-use udp::UdpSocket;
 
-use crate::endpoint::{Endpoint, EndpointDriver, EndpointRef, Incoming};
+use crate::{
+    endpoint::{
+        AdmissionControl, Endpoint, EndpointDriver, EndpointRef, HandshakeFailureHook, Incoming,
+    },
+    proxy::PacketRelay,
+    resolver::{DefaultResolver, Resolver},
+    runtime::{default_runtime, AsyncUdpSocket, Runtime},
+    scheduler::EgressScheduler,
+};
 
 /// A helper for constructing an [`Endpoint`].
 ///
@@ -15,6 +30,14 @@ pub struct EndpointBuilder {
     server_config: Option<ServerConfig>,
     config: EndpointConfig,
     default_client_config: Option<ClientConfig>,
+    admission: Option<Arc<AdmissionControl>>,
+    handshake_failure_hook: Option<Arc<HandshakeFailureHook>>,
+    source_port: Option<SourcePort>,
+    ttl: Option<u32>,
+    runtime: Option<Arc<dyn Runtime>>,
+    resolver: Option<Arc<dyn Resolver>>,
+    fair_queueing: bool,
+    egress_rate_limit: Option<u64>,
 }
 
 impl EndpointBuilder {
@@ -24,51 +47,180 @@ impl EndpointBuilder {
             server_config: None,
             config,
             default_client_config,
+            admission: None,
+            handshake_failure_hook: None,
+            source_port: None,
+            ttl: None,
+            runtime: None,
+            resolver: None,
+            fair_queueing: false,
+            egress_rate_limit: None,
         }
     }
 
     /// Build an endpoint bound to `addr`
     ///
-    /// Must be called from within a tokio runtime context. To avoid consuming the
-    /// `EndpointBuilder`, call `clone()` first.
+    /// Unless a runtime has been supplied with [`runtime()`](Self::runtime), one is selected by
+    /// [`default_runtime()`](crate::runtime::default_runtime), which requires being called from
+    /// within a tokio runtime context if async-std and smol support are disabled. To avoid
+    /// consuming the `EndpointBuilder`, call `clone()` first.
     ///
     /// Platform defaults for dual-stack sockets vary. For example, any socket bound to a wildcard
     /// IPv6 address on Windows will not by default be able to communicate with IPv4
     /// addresses. Portable applications should bind an address that matches the family they wish to
     /// communicate within.
+    ///
+    /// If a [`source_port()`](Self::source_port) policy has been set, it determines the port
+    /// instead of `addr`.
     pub fn bind(self, addr: &SocketAddr) -> Result<(Endpoint, Incoming), EndpointError> {
-        let socket = std::net::UdpSocket::bind(addr).map_err(EndpointError::Socket)?;
+        let socket = match self.source_port {
+            Some(ref policy) => policy.bind(addr.ip(), None),
+            None => std::net::UdpSocket::bind(addr),
+        }
+        .map_err(EndpointError::Socket)?;
         self.with_socket(socket)
     }
 
     /// Build an endpoint around a pre-configured socket
     ///
-    /// Must be called from within a tokio runtime context. To avoid consuming the
-    /// `EndpointBuilder`, call `clone()` first.
+    /// Unless a runtime has been supplied with [`runtime()`](Self::runtime), one is selected by
+    /// [`default_runtime()`](crate::runtime::default_runtime), which requires being called from
+    /// within a tokio runtime context if async-std and smol support are disabled. To avoid
+    /// consuming the `EndpointBuilder`, call `clone()` first.
     pub fn with_socket(
         self,
         socket: std::net::UdpSocket,
     ) -> Result<(Endpoint, Incoming), EndpointError> {
-        let addr = socket.local_addr().map_err(EndpointError::Socket)?;
-        let socket = UdpSocket::from_std(socket).map_err(EndpointError::Socket)?;
-        let rc = EndpointRef::new(
-            socket,
-            proto::Endpoint::new(Arc::new(self.config), self.server_config.map(Arc::new)),
-            addr.is_ipv6(),
-        );
-        let driver = EndpointDriver(rc.clone());
-        tokio::spawn(async {
-            if let Err(e) = driver.await {
-                error!("I/O error: {}", e);
-            }
-        });
-        Ok((
-            Endpoint {
-                inner: rc.clone(),
-                default_client_config: self.default_client_config,
-            },
-            Incoming::new(rc),
-        ))
+        let (mut endpoints, incoming) = self.with_sockets(vec![socket])?;
+        Ok((endpoints.pop().unwrap(), incoming))
+    }
+
+    /// Build a group of endpoints bound to each of `addrs`, sharing a single [`Incoming`] stream
+    ///
+    /// See [`with_sockets()`](Self::with_sockets) for details.
+    pub fn bind_multiple(
+        self,
+        addrs: &[SocketAddr],
+    ) -> Result<(Vec<Endpoint>, Incoming), EndpointError> {
+        let sockets = addrs
+            .iter()
+            .map(std::net::UdpSocket::bind)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(EndpointError::Socket)?;
+        self.with_sockets(sockets)
+    }
+
+    /// Build a group of endpoints around pre-configured sockets, sharing a single [`Incoming`]
+    /// stream
+    ///
+    /// Useful for serving several ports or addresses from one logical server. Each socket gets
+    /// its own [`Endpoint`], in the same order as `sockets`, but the endpoints share one server
+    /// configuration, including the keys used to validate address validation tokens, so a token
+    /// issued on one socket is accepted on all of them. Connections accepted by any endpoint of
+    /// the group are yielded by the returned [`Incoming`], which ends once every endpoint has
+    /// stopped accepting connections.
+    ///
+    /// Unless a runtime has been supplied with [`runtime()`](Self::runtime), one is selected by
+    /// [`default_runtime()`](crate::runtime::default_runtime), which requires being called from
+    /// within a tokio runtime context if async-std and smol support are disabled. To avoid
+    /// consuming the `EndpointBuilder`, call `clone()` first.
+    ///
+    /// [`Incoming`]: crate::Incoming
+    pub fn with_sockets(
+        self,
+        sockets: Vec<std::net::UdpSocket>,
+    ) -> Result<(Vec<Endpoint>, Incoming), EndpointError> {
+        let runtime = self.runtime_or_default()?;
+        let sockets = sockets
+            .into_iter()
+            .map(|socket| {
+                if let Some(ttl) = self.ttl {
+                    set_ttl(&socket, ttl)?;
+                }
+                let addr = socket.local_addr()?;
+                Ok((runtime.wrap_udp_socket(socket)?, addr))
+            })
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(EndpointError::Socket)?;
+        Ok(self.build(runtime, sockets))
+    }
+
+    /// Build an endpoint whose UDP datagrams are carried by the application instead of a socket
+    ///
+    /// The endpoint sends and receives through the returned [`PacketRelay`], which lets the
+    /// application forward datagrams over another channel such as a CONNECT-UDP proxy or another
+    /// QUIC connection. `local_addr` is reported as the endpoint's local address; only its address
+    /// family matters to the endpoint.
+    ///
+    /// Unless a runtime has been supplied with [`runtime()`](Self::runtime), one is selected by
+    /// [`default_runtime()`](crate::runtime::default_runtime), which is used for the endpoint's
+    /// tasks and timers. To avoid consuming the `EndpointBuilder`, call `clone()` first.
+    pub fn with_packet_relay(
+        self,
+        local_addr: SocketAddr,
+    ) -> Result<(Endpoint, Incoming, PacketRelay), EndpointError> {
+        let runtime = self.runtime_or_default()?;
+        let (relay, socket) = PacketRelay::new(local_addr);
+        let (mut endpoints, incoming) = self.build(runtime, vec![(socket, local_addr)]);
+        Ok((endpoints.pop().unwrap(), incoming, relay))
+    }
+
+    fn runtime_or_default(&self) -> Result<Arc<dyn Runtime>, EndpointError> {
+        match self.runtime {
+            Some(ref runtime) => Ok(runtime.clone()),
+            None => default_runtime().ok_or(EndpointError::NoRuntime),
+        }
+    }
+
+    /// Build a group of endpoints around sockets registered with `runtime`, along with their
+    /// local addresses
+    fn build(
+        self,
+        runtime: Arc<dyn Runtime>,
+        sockets: Vec<(Box<dyn AsyncUdpSocket>, SocketAddr)>,
+    ) -> (Vec<Endpoint>, Incoming) {
+        let config = Arc::new(self.config);
+        let server_config = self.server_config.map(Arc::new);
+        let egress_rate_limit = self.egress_rate_limit;
+        let fair_queueing = self.fair_queueing || egress_rate_limit.is_some();
+        let mut refs = Vec::with_capacity(sockets.len());
+        for (socket, addr) in sockets {
+            refs.push(EndpointRef::new(
+                socket,
+                proto::Endpoint::new(config.clone(), server_config.clone()),
+                addr.is_ipv6(),
+                self.admission.clone(),
+                self.handshake_failure_hook.clone(),
+                runtime.clone(),
+                fair_queueing.then(|| EgressScheduler::new(egress_rate_limit)),
+            ));
+        }
+
+        let default_client_config = self.default_client_config;
+        let source_port = self.source_port.unwrap_or(SourcePort::Ephemeral);
+        let ttl = self.ttl;
+        let resolver = self
+            .resolver
+            .unwrap_or_else(|| Arc::new(DefaultResolver) as Arc<dyn Resolver>);
+        let endpoints = refs
+            .iter()
+            .map(|rc| {
+                let driver = EndpointDriver(rc.clone());
+                runtime.spawn(Box::pin(async {
+                    if let Err(e) = driver.await {
+                        error!("I/O error: {}", e);
+                    }
+                }));
+                Endpoint {
+                    inner: rc.clone(),
+                    default_client_config: default_client_config.clone(),
+                    source_port: source_port.clone(),
+                    ttl,
+                    resolver: resolver.clone(),
+                }
+            })
+            .collect();
+        (endpoints, Incoming::new(refs))
     }
 
     /// Accept incoming connections.
@@ -87,6 +239,113 @@ impl EndpointBuilder {
         self
     }
 
+    /// Consult `hook` before yielding each incoming connection from [`Incoming`]
+    ///
+    /// `hook` is called with the remote address of every new incoming connection, and the
+    /// connection is only yielded once the returned future resolves to `true`. Connections for
+    /// which it resolves to `false` are closed. The handshake continues in the background while a
+    /// decision is pending. If `max_pending` connections are already awaiting a decision, further
+    /// connections are closed immediately without consulting `hook`.
+    ///
+    /// Decisions are driven by polling [`Incoming`].
+    ///
+    /// [`Incoming`]: crate::Incoming
+    pub fn admission_control<F, Fut>(&mut self, max_pending: usize, hook: F) -> &mut Self
+    where
+        F: Fn(SocketAddr) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.admission = Some(Arc::new(AdmissionControl {
+            hook: Box::new(move |addr| Box::pin(hook(addr))),
+            max_pending,
+        }));
+        self
+    }
+
+    /// Call `hook` for every incoming connection attempt that fails before completing the handshake
+    ///
+    /// Each [`HandshakeFailure`] records the client's address and the stage at which the handshake
+    /// failed, such as an invalid address validation token, a TLS alert, or a timeout, so failures
+    /// can be monitored without enabling verbose logging. `hook` is called from the endpoint's
+    /// driver and must not block.
+    pub fn on_handshake_failure<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(HandshakeFailure) + Send + Sync + 'static,
+    {
+        self.handshake_failure_hook = Some(Arc::new(HandshakeFailureHook(Box::new(hook))));
+        self
+    }
+
+    /// Run the endpoint and its connections on `runtime`
+    ///
+    /// Allows async runtimes other than tokio to be used, and endpoints to be constructed outside
+    /// of a tokio runtime context, e.g. by libraries that build an endpoint in a synchronous
+    /// constructor. If unset, [`default_runtime()`](crate::runtime::default_runtime) is used.
+    pub fn runtime(&mut self, runtime: Arc<dyn Runtime>) -> &mut Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Resolve host names passed to [`Endpoint::connect_to()`] with `resolver`
+    ///
+    /// If unset, [`DefaultResolver`](crate::DefaultResolver) is used.
+    ///
+    /// [`Endpoint::connect_to()`]: crate::Endpoint::connect_to
+    pub fn resolver(&mut self, resolver: Arc<dyn Resolver>) -> &mut Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Control how the local port is chosen by [`bind()`](Self::bind) and
+    /// [`Endpoint::rebind_source_port()`]
+    ///
+    /// [`Endpoint::rebind_source_port()`]: crate::Endpoint::rebind_source_port
+    pub fn source_port(&mut self, policy: SourcePort) -> &mut Self {
+        self.source_port = Some(policy);
+        self
+    }
+
+    /// Set the time-to-live, or hop limit, of outgoing packets
+    ///
+    /// Applies `IP_TTL` to IPv4 sockets and `IPV6_UNICAST_HOPS` to IPv6 sockets, including those
+    /// later passed to [`Endpoint::rebind()`] or bound by [`Endpoint::rebind_source_port()`].
+    /// Useful for anycast deployments and for deliberately limiting how far packets travel. As all
+    /// connections of an endpoint share one socket, the limit can't be set per connection; use
+    /// separate endpoints for that. If unset, the operating system default is used.
+    ///
+    /// [`Endpoint::rebind()`]: crate::Endpoint::rebind
+    /// [`Endpoint::rebind_source_port()`]: crate::Endpoint::rebind_source_port
+    pub fn ttl(&mut self, ttl: u32) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Share each endpoint's egress bandwidth fairly among its connections
+    ///
+    /// By default, packets are sent in the order connections produce them, so a connection with a
+    /// lot of data to send can crowd out others on the same endpoint. With fair queueing enabled,
+    /// packets are queued per connection and sent in weighted round robin order, each connection
+    /// getting a share of the socket proportional to the weight set by
+    /// [`Connection::set_weight()`]. Packets that don't belong to a connection, such as stateless
+    /// resets, bypass the queues.
+    ///
+    /// [`Connection::set_weight()`]: crate::Connection::set_weight
+    pub fn fair_queueing(&mut self, enabled: bool) -> &mut Self {
+        self.fair_queueing = enabled;
+        self
+    }
+
+    /// Limit the rate at which each endpoint sends connection data to `bytes_per_second`
+    ///
+    /// Enables [`fair_queueing()`](Self::fair_queueing), through which the available bandwidth is
+    /// shared among connections. Short bursts above the limit are permitted. The limit applies to
+    /// each endpoint separately, including each endpoint of a group built with
+    /// [`with_sockets()`](Self::with_sockets).
+    pub fn egress_rate_limit(&mut self, bytes_per_second: u64) -> &mut Self {
+        self.egress_rate_limit = Some(bytes_per_second);
+        self
+    }
+
     /// Use a customized cid generator factory in the endpoint
     pub fn connection_id_generator<
         F: Fn() -> Box<dyn ConnectionIdGenerator> + Send + Sync + 'static,
@@ -106,14 +365,88 @@ impl Default for EndpointBuilder {
             server_config: None,
             config: EndpointConfig::default(),
             default_client_config: None,
+            admission: None,
+            handshake_failure_hook: None,
+            source_port: None,
+            ttl: None,
+            runtime: None,
+            resolver: None,
+            fair_queueing: false,
+            egress_rate_limit: None,
         }
     }
 }
 
+/// Policy for selecting the local UDP port of an endpoint
+///
+/// Useful for clients that must cooperate with firewall policies, or that want to avoid being
+/// tracked by a long-lived port. For a fresh random port per connection, use a separate endpoint
+/// for each connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourcePort {
+    /// Let the operating system pick an ephemeral port
+    Ephemeral,
+    /// Always use the given port
+    Fixed(u16),
+    /// Use a randomly selected free port from the given range
+    Range(RangeInclusive<u16>),
+}
+
+impl SourcePort {
+    /// Bind a socket on `ip` according to this policy, avoiding `exclude` if possible
+    pub(crate) fn bind(&self, ip: IpAddr, exclude: Option<u16>) -> io::Result<std::net::UdpSocket> {
+        let range = match *self {
+            SourcePort::Ephemeral => return std::net::UdpSocket::bind((ip, 0)),
+            SourcePort::Fixed(port) => return std::net::UdpSocket::bind((ip, port)),
+            SourcePort::Range(ref range) => range,
+        };
+        if range.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty source port range",
+            ));
+        }
+
+        // Probe sequentially from a random starting point so that every port in the range gets
+        // tried exactly once
+        let (start, end) = (u32::from(*range.start()), u32::from(*range.end()));
+        let len = end - start + 1;
+        let offset = rand::thread_rng().gen_range(0..len);
+        let mut last_error = None;
+        for i in 0..len {
+            let port = (start + (offset + i) % len) as u16;
+            if Some(port) == exclude && len > 1 {
+                continue;
+            }
+            match std::net::UdpSocket::bind((ip, port)) {
+                Ok(socket) => return Ok(socket),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+}
+
+/// Set the hop limit of packets sent on `socket`
+pub(crate) fn set_ttl(socket: &std::net::UdpSocket, ttl: u32) -> io::Result<()> {
+    if socket.local_addr()?.is_ipv4() {
+        return socket.set_ttl(ttl);
+    }
+    SockRef::from(socket).set_unicast_hops_v6(ttl)?;
+    // Dual-stack sockets use the IPv4 option for IPv4-mapped destinations; failure just means the
+    // socket is IPv6-only
+    let _ = socket.set_ttl(ttl);
+    Ok(())
+}
+
 /// Errors that can occur during the construction of an `Endpoint`.
 #[derive(Debug, Error)]
 pub enum EndpointError {
     /// An error during setup of the underlying UDP socket.
     #[error("failed to set up UDP socket: {0}")]
     Socket(io::Error),
+    /// No runtime was configured and none could be selected by
+    /// [`default_runtime()`](crate::runtime::default_runtime)
+    #[error("no async runtime available")]
+    NoRuntime,
 }
@@ -14,9 +14,11 @@ use bytes::Bytes;
 use futures_channel::{mpsc, oneshot};
 use futures_util::{FutureExt, StreamExt};
 use fxhash::FxHashMap;
-use proto::{ConnectionError, ConnectionHandle, ConnectionStats, Dir, StreamEvent, StreamId};
+use proto::{
+    ConnectionError, ConnectionHandle, ConnectionStats, Dir, PathParameters, StreamEvent, StreamId,
+    StreamInfo,
+};
 use thiserror::Error;
-use tokio::time::{sleep_until, Instant as TokioInstant, Sleep};
 use tracing::info_span;
 use udp::UdpState;
 
@@ -24,8 +26,9 @@ use crate::{
     broadcast::{self, Broadcast},
     mutex::Mutex,
     recv_stream::RecvStream,
+    runtime::{AsyncTimer, Runtime},
     send_stream::{SendStream, WriteError},
-    ConnectionEvent, EndpointEvent, VarInt,
+    ConnectionEvent, EndpointEvent, TransportParameters, VarInt,
 };
 
 /// In-progress connection attempt future
@@ -33,7 +36,7 @@ use crate::{
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 pub struct Connecting {
     conn: Option<ConnectionRef>,
-    connected: oneshot::Receiver<bool>,
+    connected: oneshot::Receiver<Result<(), ZeroRttRejected>>,
     handshake_data_ready: Option<oneshot::Receiver<()>>,
 }
 
@@ -44,6 +47,7 @@ impl Connecting {
         endpoint_events: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
         conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
         udp_state: Arc<UdpState>,
+        runtime: Arc<dyn Runtime>,
     ) -> Connecting {
         let (on_handshake_data_send, on_handshake_data_recv) = oneshot::channel();
         let (on_connected_send, on_connected_recv) = oneshot::channel();
@@ -55,9 +59,10 @@ impl Connecting {
             on_handshake_data_send,
             on_connected_send,
             udp_state,
+            runtime.clone(),
         );
 
-        tokio::spawn(ConnectionDriver(conn.clone()));
+        runtime.spawn(Box::pin(ConnectionDriver(conn.clone())));
 
         Connecting {
             conn: Some(conn),
@@ -66,6 +71,16 @@ impl Connecting {
         }
     }
 
+    /// The connection's handle on its endpoint, unless the endpoint may already have forgotten it
+    pub(crate) fn live_handle(&self) -> Option<ConnectionHandle> {
+        let conn = self.conn.as_ref()?.lock("live_handle");
+        if conn.inner.is_drained() {
+            None
+        } else {
+            Some(conn.handle)
+        }
+    }
+
     /// Convert into a 0-RTT or 0.5-RTT connection at the cost of weakened security
     ///
     /// Opens up the connection for use before the handshake finishes, allowing the API user to
@@ -73,7 +88,8 @@ impl Connecting {
     /// for reducing start-up latency by beginning transmission of application data without waiting
     /// for the handshake's cryptographic security guarantees to be established.
     ///
-    /// When the `ZeroRttAccepted` future completes, the connection has been fully established.
+    /// When the `ZeroRttAccepted` future completes, the connection has been fully established,
+    /// and on outgoing connections it reports whether the server accepted the 0-RTT data.
     ///
     /// # Security
     ///
@@ -109,9 +125,11 @@ impl Connecting {
 
     /// Parameters negotiated during the handshake
     ///
-    /// The dynamic type returned is determined by the configured
-    /// [`Session`](proto::crypto::Session). For the default `rustls` session, the return value can
-    /// be [`downcast`](Box::downcast) to a
+    /// Resolves before the handshake completes: on incoming connections as soon as the client's
+    /// hello is received, so that servers can route or refuse connections by server name and
+    /// application protocol before accepting them. The dynamic type returned is determined by the
+    /// configured [`Session`](proto::crypto::Session). For the default `rustls` session, the
+    /// return value can be [`downcast`](Box::downcast) to a
     /// [`crypto::rustls::HandshakeData`](crate::crypto::rustls::HandshakeData).
     pub async fn handshake_data(&mut self) -> Result<Box<dyn Any>, ConnectionError> {
         // Taking &mut self allows us to use a single oneshot channel rather than dealing with
@@ -183,22 +201,78 @@ impl Connecting {
         let conn_ref: &ConnectionRef = self.conn.as_ref().expect("used after yielding Ready");
         conn_ref.lock("remote_address").inner.remote_address()
     }
+
+    /// Whether the client's address was validated with a Retry before the connection was accepted
+    ///
+    /// Clients that haven't been validated may be spoofing their address, so servers may wish to
+    /// be more conservative with the resources they commit to them. Retries are sent to every
+    /// client when [`ServerConfig::use_stateless_retry()`] is set, or once too many handshakes
+    /// are in progress. Always `true` for clients.
+    ///
+    /// Will panic if called after `poll` has returned `Ready`.
+    ///
+    /// [`ServerConfig::use_stateless_retry()`]: crate::ServerConfig::use_stateless_retry
+    pub fn remote_address_validated(&self) -> bool {
+        let conn_ref: &ConnectionRef = self.conn.as_ref().expect("used after yielding Ready");
+        conn_ref
+            .lock("remote_address_validated")
+            .inner
+            .remote_address_validated()
+    }
 }
 
 /// Future that completes when a connection is fully established
 ///
-/// For clients, the resulting value indicates if 0-RTT was accepted. For servers, the resulting
-/// value is meaningless.
+/// For clients, the resulting value indicates if 0-RTT was accepted, and if not, why. For
+/// servers, the result is `Ok` unless the connection was lost during the handshake.
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
-pub struct ZeroRttAccepted(oneshot::Receiver<bool>);
+pub struct ZeroRttAccepted(oneshot::Receiver<Result<(), ZeroRttRejected>>);
 
 impl Future for ZeroRttAccepted {
-    type Output = bool;
+    type Output = Result<(), ZeroRttRejected>;
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        self.0.poll_unpin(cx).map(|x| x.unwrap_or(false))
+        self.0.poll_unpin(cx).map(|x| {
+            x.unwrap_or(Err(ZeroRttRejected::ConnectionLost(
+                ConnectionError::LocallyClosed,
+            )))
+        })
     }
 }
 
+/// Reasons why 0-RTT data was not accepted, as reported by [`ZeroRttAccepted`]
+///
+/// 0-RTT being unavailable in the first place, e.g. for lack of keys from a previous connection
+/// to the same server, is reported by [`Connecting::into_0rtt()`] failing instead.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ZeroRttRejected {
+    /// The server completed the handshake but declined the 0-RTT data
+    ///
+    /// This happens e.g. when the server no longer recognizes the session ticket, or when the
+    /// negotiated application protocol changed. Unreliable datagrams sent during 0-RTT are lost
+    /// either way. If `replayed` is `true`, data written to streams during 0-RTT has been
+    /// retransmitted automatically, and the streams remain usable as if 0-RTT had been accepted.
+    /// Otherwise, the server's new limits don't accommodate that data: streams opened during 0-RTT
+    /// have been discarded, operations on them fail with `ZeroRttRejected` errors, and the
+    /// application must open new streams and write their data again.
+    #[error("server rejected 0-RTT data")]
+    RejectedByServer {
+        /// Whether stream data was retransmitted in 1-RTT packets
+        replayed: bool,
+    },
+    /// The server doesn't support the QUIC version 0-RTT data was sent with
+    ///
+    /// If the server offered a version the client also supports, the handshake restarted with it
+    /// and the connection is established without 0-RTT. As when the server rejects 0-RTT data
+    /// without replaying it, streams opened during 0-RTT have been discarded and their data must be
+    /// written again on new streams. Otherwise, the connection has failed with
+    /// [`ConnectionError::VersionMismatch`].
+    #[error("server requires a different QUIC version")]
+    VersionChanged,
+    /// The connection was lost before the handshake completed
+    #[error("connection lost during the handshake: {0}")]
+    ConnectionLost(ConnectionError),
+}
+
 /// Components of a newly established connection
 ///
 /// All fields of this struct, in addition to any other handles constructed later, must be dropped
@@ -278,6 +352,7 @@ impl Future for ConnectionDriver {
 
         if let Err(e) = conn.process_conn_events(cx) {
             conn.terminate(e);
+            conn.notify_drained();
             return Poll::Ready(());
         }
         let mut keep_going = conn.drive_transmit();
@@ -286,6 +361,9 @@ impl Future for ConnectionDriver {
         keep_going |= conn.drive_timer(cx);
         conn.forward_endpoint_events();
         conn.forward_app_events();
+        // After application events, so that e.g. the completion of the last stream is reported
+        keep_going |= conn.close_if_idle();
+        keep_going |= conn.close_if_delivered();
 
         if !conn.inner.is_drained() {
             if keep_going {
@@ -299,6 +377,7 @@ impl Future for ConnectionDriver {
         if conn.error.is_none() {
             unreachable!("drained connections always have an error");
         }
+        conn.notify_drained();
         Poll::Ready(())
     }
 }
@@ -341,6 +420,34 @@ impl Connection {
         }
     }
 
+    /// Accept the next incoming unidirectional stream
+    ///
+    /// Equivalent to taking the next item from [`NewConnection::uni_streams`], but usable from any
+    /// clone of the `Connection`. Resolves to [`ConnectionError::LocallyClosed`] once the
+    /// connection has been closed locally.
+    pub fn accept_uni(&self) -> AcceptUni {
+        AcceptUni {
+            conn: self.0.clone(),
+            state: broadcast::State::default(),
+        }
+    }
+
+    /// Accept the next incoming bidirectional stream
+    ///
+    /// Equivalent to taking the next item from [`NewConnection::bi_streams`], but usable from any
+    /// clone of the `Connection`. Resolves to [`ConnectionError::LocallyClosed`] once the
+    /// connection has been closed locally.
+    ///
+    /// **Important Note**: The peer is only notified of a new stream once data is sent on it, so
+    /// a stream opened by the peer with [`open_bi()`](Self::open_bi) isn't accepted here until
+    /// the peer writes to it.
+    pub fn accept_bi(&self) -> AcceptBi {
+        AcceptBi {
+            conn: self.0.clone(),
+            state: broadcast::State::default(),
+        }
+    }
+
     /// Close the connection immediately.
     ///
     /// Pending operations will fail immediately with [`ConnectionError::LocallyClosed`]. Delivery
@@ -361,6 +468,37 @@ impl Connection {
         conn.close(error_code, Bytes::copy_from_slice(reason));
     }
 
+    /// Close the connection once data written to streams has been delivered, and wait until it's
+    /// fully closed
+    ///
+    /// Unlike [`close()`](Self::close), data already written to [`SendStream`]s isn't lost: the
+    /// connection is only closed once the peer has acknowledged all of it, including the end of
+    /// finished streams. Resolves after the close has been sent and the subsequent draining period
+    /// has passed, or as soon as the connection is lost, e.g. by timing out because the peer
+    /// stopped acknowledging data. Streams that are never finished or reset should not be written
+    /// to while waiting, lest the close be deferred indefinitely.
+    ///
+    /// `error_code` and `reason` are handled as by [`close()`](Self::close). If called again
+    /// before the connection is closed, the most recent `error_code` and `reason` are used.
+    ///
+    /// [`SendStream`]: crate::SendStream
+    pub async fn close_and_wait(&self, error_code: VarInt, reason: &[u8]) {
+        let (send, recv) = oneshot::channel();
+        {
+            let conn = &mut *self.0.lock("close_and_wait");
+            if conn.inner.is_drained() {
+                return;
+            }
+            conn.on_drained.push(send);
+            if conn.error.is_none() {
+                conn.close_when_delivered = Some((error_code, Bytes::copy_from_slice(reason)));
+                conn.wake();
+            }
+        }
+        // Dropped without sending only if the driver was, in which case there's nothing to wait for
+        let _ = recv.await;
+    }
+
     /// Transmit `data` as an unreliable, unordered application datagram
     ///
     /// Application datagrams are a low-level primitive. They may be lost or delivered out of order,
@@ -381,10 +519,44 @@ impl Connection {
                 UnsupportedByPeer => SendDatagramError::UnsupportedByPeer,
                 Disabled => SendDatagramError::Disabled,
                 TooLarge => SendDatagramError::TooLarge,
+                Blocked => SendDatagramError::Blocked,
             }),
         }
     }
 
+    /// Receive the next application datagram sent by the peer
+    ///
+    /// Equivalent to taking the next item from [`NewConnection::datagrams`], but usable from any
+    /// clone of the `Connection`. Resolves to [`ConnectionError::LocallyClosed`] once the
+    /// connection has been closed locally.
+    pub fn read_datagram(&self) -> ReadDatagram {
+        ReadDatagram {
+            conn: self.0.clone(),
+            state: broadcast::State::default(),
+        }
+    }
+
+    /// Probe for bandwidth beyond what the application is currently using
+    ///
+    /// Sends up to `max_bytes` of padding, subject to congestion control and pacing, and resolves
+    /// to the estimated delivery rate in bytes per second once the probe traffic has been
+    /// acknowledged. Useful for real-time applications to find out whether the path can sustain a
+    /// higher rate before committing to it, e.g. by stepping up media quality. Starting a new probe
+    /// replaces any probe already in progress; all pending calls resolve when it completes.
+    pub async fn probe_bandwidth(&self, max_bytes: u64) -> Result<u64, ConnectionError> {
+        let (send, recv) = oneshot::channel();
+        {
+            let conn = &mut *self.0.lock("probe_bandwidth");
+            if let Some(ref e) = conn.error {
+                return Err(e.clone());
+            }
+            conn.inner.probe_bandwidth(max_bytes);
+            conn.bandwidth_probes.push(send);
+            conn.wake();
+        }
+        recv.await.unwrap_or(Err(ConnectionError::LocallyClosed))
+    }
+
     /// Compute the maximum size of datagrams that may be passed to [`send_datagram()`].
     ///
     /// Returns `None` if datagrams are unsupported by the peer or disabled locally.
@@ -404,6 +576,37 @@ impl Connection {
             .max_size()
     }
 
+    /// Wait for outgoing datagrams to be discarded before being sent
+    ///
+    /// Resolves to the number of datagrams dropped since this call, either to make room in the
+    /// send buffer under [`DatagramDropPolicy::DropOldest`] or because they no longer fit in a
+    /// packet. [`stats()`](Self::stats) breaks drops down by cause. Allows real-time applications
+    /// to adapt their encoding when they outpace the connection.
+    ///
+    /// [`DatagramDropPolicy::DropOldest`]: crate::DatagramDropPolicy::DropOldest
+    pub fn datagrams_dropped(&self) -> DatagramsDropped {
+        let conn = self.0.lock("datagrams_dropped");
+        DatagramsDropped {
+            conn: self.0.clone(),
+            baseline: dropped_datagrams(&conn.inner),
+            state: broadcast::State::default(),
+        }
+    }
+
+    /// Number of datagrams next to be yielded by [`NewConnection::datagrams`] that were received
+    /// before the handshake completed
+    ///
+    /// On servers, such datagrams arrived in 0-RTT packets, which are subject to replay attacks
+    /// like data on streams for which [`RecvStream::is_0rtt()`](crate::RecvStream::is_0rtt) is
+    /// `true`.
+    pub fn buffered_0rtt_datagrams(&self) -> usize {
+        self.0
+            .lock("buffered_0rtt_datagrams")
+            .inner
+            .datagrams()
+            .buffered_0rtt()
+    }
+
     /// The peer's UDP address
     ///
     /// If `ServerConfig::migration` is `true`, clients may change addresses at will, e.g. when
@@ -430,16 +633,90 @@ impl Connection {
         self.0.lock("local_ip").inner.local_ip()
     }
 
+    /// Whether the handshake resumed an earlier TLS session rather than authenticating the server
+    /// afresh
+    ///
+    /// Resumption requires the client to have connected to the same server before, and the
+    /// server to have issued it a session ticket. The value is meaningless until the handshake
+    /// completes.
+    pub fn is_resumed(&self) -> bool {
+        self.0.lock("is_resumed").inner.is_resumed()
+    }
+
+    /// Whether 0-RTT data was accepted, by the peer for clients or from the peer for servers
+    ///
+    /// The value is meaningless until the handshake completes.
+    pub fn accepted_0rtt(&self) -> bool {
+        self.0.lock("accepted_0rtt").inner.accepted_0rtt()
+    }
+
+    /// The QUIC version in use on the wire for this connection
+    pub fn version(&self) -> u32 {
+        self.0.lock("version").inner.version()
+    }
+
     /// Current best estimate of this connection's latency (round-trip-time)
     pub fn rtt(&self) -> Duration {
         self.0.lock("rtt").inner.rtt()
     }
 
+    /// Current best estimate of the rate at which data can be delivered, in bytes per second
+    pub fn delivery_rate(&self) -> u64 {
+        self.0.lock("delivery_rate").inner.delivery_rate()
+    }
+
+    /// Characteristics of the current path, as saved in a client's [`PathCache`](crate::PathCache)
+    pub fn path_parameters(&self) -> PathParameters {
+        self.0.lock("path_parameters").inner.path_parameters()
+    }
+
+    /// Wait for the estimated delivery rate or RTT to change significantly
+    ///
+    /// Resolves to the new delivery rate once either estimate has changed by more than
+    /// [`TransportConfig::delivery_rate_threshold`] since it was last reported, allowing adaptive
+    /// applications to react immediately rather than polling [`stats()`](Self::stats). Only
+    /// changes after this call are considered. Never resolves if no threshold is configured,
+    /// unless the connection is lost.
+    ///
+    /// [`TransportConfig::delivery_rate_threshold`]: crate::TransportConfig::delivery_rate_threshold
+    pub fn delivery_rate_changed(&self) -> DeliveryRateChanged {
+        let generation = self
+            .0
+            .lock("delivery_rate_changed")
+            .delivery_rate_generation;
+        DeliveryRateChanged {
+            conn: self.0.clone(),
+            generation,
+            state: broadcast::State::default(),
+        }
+    }
+
+    /// Describe the currently open streams, ordered by ID
+    ///
+    /// Remotely initiated streams are only included once they've been accepted, e.g. yielded by
+    /// [`IncomingBiStreams`]. Useful for debugging, or to wait for outstanding streams to finish
+    /// before closing a connection.
+    pub fn open_streams(&self) -> Vec<StreamInfo> {
+        self.0.lock("open_streams").inner.streams().open_streams()
+    }
+
     /// Returns connection statistics
     pub fn stats(&self) -> ConnectionStats {
         self.0.lock("stats").inner.stats()
     }
 
+    /// Transport parameters sent by the peer
+    ///
+    /// Reflects the limits the peer has committed to, such as its idle timeout, initial flow
+    /// control windows and maximum datagram frame size. Guaranteed to return `Some` on fully
+    /// established connections.
+    pub fn peer_transport_parameters(&self) -> Option<TransportParameters> {
+        self.0
+            .lock("peer_transport_parameters")
+            .inner
+            .peer_transport_parameters()
+    }
+
     /// Parameters negotiated during the handshake
     ///
     /// Guaranteed to return `Some` on fully established connections or after
@@ -460,6 +737,9 @@ impl Connection {
     /// The dynamic type returned is determined by the configured
     /// [`Session`](proto::crypto::Session). For the default `rustls` session, the return value can
     /// be [`downcast`](Box::downcast) to a [`CertificateChain`](crate::CertificateChain).
+    ///
+    /// Returns `None` if the peer didn't authenticate itself, as is the case for clients unless
+    /// the server's crypto configuration requests client certificates.
     pub fn peer_identity(&self) -> Option<Box<dyn Any>> {
         self.0
             .lock("peer_identity")
@@ -476,6 +756,62 @@ impl Connection {
         self.0.stable_id()
     }
 
+    /// Set this connection's share of its endpoint's egress bandwidth
+    ///
+    /// Only has an effect if [`EndpointBuilder::fair_queueing()`] is enabled, in which case a
+    /// connection with data to send is allotted bandwidth in proportion to its weight relative to
+    /// other such connections. Defaults to 1; a weight of 0 is treated as 1.
+    ///
+    /// [`EndpointBuilder::fair_queueing()`]: crate::EndpointBuilder::fair_queueing
+    pub fn set_weight(&self, weight: u32) {
+        let conn = self.0.lock("set_weight");
+        // If the endpoint driver is gone, noop.
+        let _ = conn
+            .endpoint_events
+            .unbounded_send((conn.handle, EndpointEvent::EgressWeight(weight)));
+    }
+
+    /// Set the number of bidirectional streams the peer may have open concurrently
+    ///
+    /// Overrides [`TransportConfig::max_concurrent_bidi_streams()`] for this connection, e.g. to
+    /// shed load. An increase is advertised to the peer promptly. A decrease takes effect
+    /// gradually as the peer's existing streams close, since stream credit already issued can't be
+    /// revoked.
+    ///
+    /// Setting `count` to zero stops the peer from opening new streams once its outstanding credit
+    /// is used up while existing streams run to completion, e.g. for a server entering drain mode.
+    ///
+    /// [`TransportConfig::max_concurrent_bidi_streams()`]: crate::TransportConfig::max_concurrent_bidi_streams
+    pub fn set_max_concurrent_bi_streams(&self, count: VarInt) {
+        let mut conn = self.0.lock("set_max_concurrent_bi_streams");
+        conn.inner.set_max_concurrent_streams(Dir::Bi, count);
+        conn.wake();
+    }
+
+    /// Set the number of unidirectional streams the peer may have open concurrently
+    ///
+    /// See [`set_max_concurrent_bi_streams()`](Self::set_max_concurrent_bi_streams).
+    pub fn set_max_concurrent_uni_streams(&self, count: VarInt) {
+        let mut conn = self.0.lock("set_max_concurrent_uni_streams");
+        conn.inner.set_max_concurrent_streams(Dir::Uni, count);
+        conn.wake();
+    }
+
+    /// Whether to send keep-alives while no streams are open
+    ///
+    /// Only relevant if [`TransportConfig::keep_alive_interval()`] is set. Defaults to `true`. When
+    /// `false`, keep-alive packets are only sent while at least one stream is open, allowing an
+    /// unused connection to go quiet and eventually time out, e.g. so that a mobile device's radio
+    /// may sleep. Set back to `true` to register interest in keeping the connection alive
+    /// regardless.
+    ///
+    /// [`TransportConfig::keep_alive_interval()`]: crate::TransportConfig::keep_alive_interval
+    pub fn set_keep_alive_while_idle(&self, value: bool) {
+        let mut conn = self.0.lock("set_keep_alive_while_idle");
+        conn.inner.set_keep_alive_while_idle(value);
+        conn.wake();
+    }
+
     // Update traffic keys spontaneously for testing purposes.
     #[doc(hidden)]
     pub fn force_key_update(&self) {
@@ -527,19 +863,14 @@ impl futures_util::stream::Stream for IncomingUniStreams {
     type Item = Result<RecvStream, ConnectionError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let mut conn = self.0.lock("IncomingUniStreams::poll_next");
-        if let Some(x) = conn.inner.streams().accept(Dir::Uni) {
-            conn.wake(); // To send additional stream ID credit
-            mem::drop(conn); // Release the lock so clone can take it
-            Poll::Ready(Some(Ok(RecvStream::new(self.0.clone(), x, false))))
-        } else if let Some(ConnectionError::LocallyClosed) = conn.error {
-            Poll::Ready(None)
-        } else if let Some(ref e) = conn.error {
-            Poll::Ready(Some(Err(e.clone())))
-        } else {
+        let accepted = poll_accept(&self.0, Dir::Uni, "IncomingUniStreams::poll_next", |conn| {
             conn.incoming_uni_streams_reader = Some(cx.waker().clone());
-            Poll::Pending
-        }
+        });
+        accepted.map(|x| match x {
+            Ok((id, _)) => Some(Ok(RecvStream::new(self.0.clone(), id, false))),
+            Err(ConnectionError::LocallyClosed) => None,
+            Err(e) => Some(Err(e)),
+        })
     }
 }
 
@@ -553,23 +884,17 @@ impl futures_util::stream::Stream for IncomingBiStreams {
     type Item = Result<(SendStream, RecvStream), ConnectionError>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let mut conn = self.0.lock("IncomingBiStreams::poll_next");
-        if let Some(x) = conn.inner.streams().accept(Dir::Bi) {
-            let is_0rtt = conn.inner.is_handshaking();
-            conn.wake(); // To send additional stream ID credit
-            mem::drop(conn); // Release the lock so clone can take it
-            Poll::Ready(Some(Ok((
-                SendStream::new(self.0.clone(), x, is_0rtt),
-                RecvStream::new(self.0.clone(), x, is_0rtt),
-            ))))
-        } else if let Some(ConnectionError::LocallyClosed) = conn.error {
-            Poll::Ready(None)
-        } else if let Some(ref e) = conn.error {
-            Poll::Ready(Some(Err(e.clone())))
-        } else {
+        let accepted = poll_accept(&self.0, Dir::Bi, "IncomingBiStreams::poll_next", |conn| {
             conn.incoming_bi_streams_reader = Some(cx.waker().clone());
-            Poll::Pending
-        }
+        });
+        accepted.map(|x| match x {
+            Ok((id, is_0rtt)) => Some(Ok((
+                SendStream::new(self.0.clone(), id, is_0rtt),
+                RecvStream::new(self.0.clone(), id, is_0rtt),
+            ))),
+            Err(ConnectionError::LocallyClosed) => None,
+            Err(e) => Some(Err(e)),
+        })
     }
 }
 
@@ -650,18 +975,180 @@ impl Future for OpenBi {
     }
 }
 
+/// A future that will resolve into the next incoming unidirectional stream
+///
+/// See [`Connection::accept_uni()`].
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct AcceptUni {
+    conn: ConnectionRef,
+    state: broadcast::State,
+}
+
+impl Future for AcceptUni {
+    type Output = Result<RecvStream, ConnectionError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let state = &mut this.state;
+        let accepted = poll_accept(&this.conn, Dir::Uni, "AcceptUni::poll", |conn| {
+            conn.uni_accepting.register(cx, state);
+        });
+        accepted.map_ok(|(id, _)| RecvStream::new(this.conn.clone(), id, false))
+    }
+}
+
+/// A future that will resolve into the next incoming bidirectional stream
+///
+/// See [`Connection::accept_bi()`].
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct AcceptBi {
+    conn: ConnectionRef,
+    state: broadcast::State,
+}
+
+impl Future for AcceptBi {
+    type Output = Result<(SendStream, RecvStream), ConnectionError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let state = &mut this.state;
+        let accepted = poll_accept(&this.conn, Dir::Bi, "AcceptBi::poll", |conn| {
+            conn.bi_accepting.register(cx, state);
+        });
+        accepted.map_ok(|(id, is_0rtt)| {
+            (
+                SendStream::new(this.conn.clone(), id, is_0rtt),
+                RecvStream::new(this.conn.clone(), id, is_0rtt),
+            )
+        })
+    }
+}
+
+/// Take the next stream in `dir` opened by the peer, shared by every way of accepting streams
+///
+/// Yields the stream's ID and whether it may carry 0-RTT data, or the error that closed the
+/// connection. Otherwise calls `register` with the connection still locked, so the caller can
+/// arrange to be woken when a stream arrives.
+fn poll_accept(
+    conn: &ConnectionRef,
+    dir: Dir,
+    purpose: &'static str,
+    register: impl FnOnce(&mut ConnectionInner),
+) -> Poll<Result<(StreamId, bool), ConnectionError>> {
+    let mut conn = conn.lock(purpose);
+    if let Some(id) = conn.inner.streams().accept(dir) {
+        let is_0rtt = conn.inner.is_handshaking();
+        conn.wake(); // To send additional stream ID credit
+        return Poll::Ready(Ok((id, is_0rtt)));
+    }
+    if let Some(ref e) = conn.error {
+        return Poll::Ready(Err(e.clone()));
+    }
+    register(&mut conn);
+    Poll::Pending
+}
+
+/// A future that will resolve into the next application datagram sent by the peer
+///
+/// See [`Connection::read_datagram()`].
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct ReadDatagram {
+    conn: ConnectionRef,
+    state: broadcast::State,
+}
+
+impl Future for ReadDatagram {
+    type Output = Result<Bytes, ConnectionError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut conn = this.conn.lock("ReadDatagram::poll");
+        if let Some(x) = conn.inner.datagrams().recv() {
+            return Poll::Ready(Ok(x));
+        }
+        if let Some(ref e) = conn.error {
+            return Poll::Ready(Err(e.clone()));
+        }
+        conn.datagram_reading.register(cx, &mut this.state);
+        Poll::Pending
+    }
+}
+
+/// A future that resolves when outgoing datagrams are discarded
+///
+/// See [`Connection::datagrams_dropped()`].
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct DatagramsDropped {
+    conn: ConnectionRef,
+    baseline: u64,
+    state: broadcast::State,
+}
+
+impl Future for DatagramsDropped {
+    type Output = Result<u64, ConnectionError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut conn = this.conn.lock("DatagramsDropped::poll");
+        let dropped = dropped_datagrams(&conn.inner) - this.baseline;
+        if dropped > 0 {
+            return Poll::Ready(Ok(dropped));
+        }
+        if let Some(ref e) = conn.error {
+            return Poll::Ready(Err(e.clone()));
+        }
+        conn.datagrams_dropped.register(cx, &mut this.state);
+        Poll::Pending
+    }
+}
+
+/// Total number of outgoing datagrams discarded by `conn`
+fn dropped_datagrams(conn: &proto::Connection) -> u64 {
+    let drops = conn.stats().datagram_drops;
+    drops.send_buffer + drops.unsendable
+}
+
+/// A future that resolves when the delivery rate or RTT of a connection changes
+///
+/// See [`Connection::delivery_rate_changed()`].
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct DeliveryRateChanged {
+    conn: ConnectionRef,
+    generation: u64,
+    state: broadcast::State,
+}
+
+impl Future for DeliveryRateChanged {
+    type Output = Result<u64, ConnectionError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut conn = this.conn.lock("DeliveryRateChanged::poll");
+        if conn.delivery_rate_generation != this.generation {
+            return Poll::Ready(Ok(conn.inner.delivery_rate()));
+        }
+        if let Some(ref e) = conn.error {
+            return Poll::Ready(Err(e.clone()));
+        }
+        conn.delivery_rate_changed.register(cx, &mut this.state);
+        Poll::Pending
+    }
+}
+
 #[derive(Debug)]
 pub struct ConnectionRef(Arc<Mutex<ConnectionInner>>);
 
 impl ConnectionRef {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         handle: ConnectionHandle,
         conn: proto::Connection,
         endpoint_events: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
         conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
         on_handshake_data: oneshot::Sender<()>,
-        on_connected: oneshot::Sender<bool>,
+        on_connected: oneshot::Sender<Result<(), ZeroRttRejected>>,
         udp_state: Arc<UdpState>,
+        runtime: Arc<dyn Runtime>,
     ) -> Self {
         Self(Arc::new(Mutex::new(ConnectionInner {
             inner: conn,
@@ -678,14 +1165,26 @@ impl ConnectionRef {
             blocked_readers: FxHashMap::default(),
             uni_opening: Broadcast::new(),
             bi_opening: Broadcast::new(),
+            uni_accepting: Broadcast::new(),
+            bi_accepting: Broadcast::new(),
+            datagram_reading: Broadcast::new(),
+            delivery_rate_changed: Broadcast::new(),
+            datagrams_dropped: Broadcast::new(),
+            delivery_rate_generation: 0,
+            bandwidth_probes: Vec::new(),
             incoming_uni_streams_reader: None,
             incoming_bi_streams_reader: None,
             datagram_reader: None,
             finishing: FxHashMap::default(),
             stopped: FxHashMap::default(),
             error: None,
+            close_when_idle: None,
+            close_when_delivered: None,
+            on_drained: Vec::new(),
+            moved_from: Vec::new(),
             ref_count: 0,
             udp_state,
+            runtime,
         })))
     }
 
@@ -694,6 +1193,12 @@ impl ConnectionRef {
     }
 }
 
+impl Connection {
+    pub(crate) fn conn_ref(&self) -> &ConnectionRef {
+        &self.0
+    }
+}
+
 impl Clone for ConnectionRef {
     fn clone(&self) -> Self {
         self.lock("clone").ref_count += 1;
@@ -727,18 +1232,31 @@ impl std::ops::Deref for ConnectionRef {
 pub struct ConnectionInner {
     pub(crate) inner: proto::Connection,
     driver: Option<Waker>,
-    handle: ConnectionHandle,
+    pub(crate) handle: ConnectionHandle,
     on_handshake_data: Option<oneshot::Sender<()>>,
-    on_connected: Option<oneshot::Sender<bool>>,
+    on_connected: Option<oneshot::Sender<Result<(), ZeroRttRejected>>>,
     connected: bool,
-    timer: Option<Pin<Box<Sleep>>>,
-    timer_deadline: Option<TokioInstant>,
+    timer: Option<Pin<Box<dyn AsyncTimer>>>,
+    timer_deadline: Option<Instant>,
     conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
     endpoint_events: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
+    /// Handles on endpoints the connection moved away from, still forwarding its datagrams
+    moved_from: Vec<(
+        mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
+        ConnectionHandle,
+    )>,
     pub(crate) blocked_writers: FxHashMap<StreamId, Waker>,
     pub(crate) blocked_readers: FxHashMap<StreamId, Waker>,
     uni_opening: Broadcast,
     bi_opening: Broadcast,
+    uni_accepting: Broadcast,
+    bi_accepting: Broadcast,
+    datagram_reading: Broadcast,
+    delivery_rate_changed: Broadcast,
+    datagrams_dropped: Broadcast,
+    /// Number of `DeliveryRateChanged` events received
+    delivery_rate_generation: u64,
+    bandwidth_probes: Vec<oneshot::Sender<Result<u64, ConnectionError>>>,
     incoming_uni_streams_reader: Option<Waker>,
     incoming_bi_streams_reader: Option<Waker>,
     datagram_reader: Option<Waker>,
@@ -746,9 +1264,16 @@ pub struct ConnectionInner {
     pub(crate) stopped: FxHashMap<StreamId, Waker>,
     /// Always set to Some before the connection becomes drained
     pub(crate) error: Option<ConnectionError>,
+    /// Error code and reason to close with once no streams remain open
+    close_when_idle: Option<(VarInt, Bytes)>,
+    /// Error code and reason to close with once all stream data has been acknowledged
+    close_when_delivered: Option<(VarInt, Bytes)>,
+    /// Notified when the driver stops, after the connection has drained
+    on_drained: Vec<oneshot::Sender<()>>,
     /// Number of live handles that can be used to initiate or handle I/O; excludes the driver
     ref_count: usize,
     udp_state: Arc<UdpState>,
+    pub(crate) runtime: Arc<dyn Runtime>,
 }
 
 impl ConnectionInner {
@@ -782,6 +1307,9 @@ impl ConnectionInner {
 
     fn forward_endpoint_events(&mut self) {
         while let Some(event) = self.inner.poll_endpoint_events() {
+            if event.is_drained() {
+                self.release_moved_from();
+            }
             // If the endpoint driver is gone, noop.
             let _ = self
                 .endpoint_events
@@ -794,11 +1322,27 @@ impl ConnectionInner {
         loop {
             match self.conn_events.poll_next_unpin(cx) {
                 Poll::Ready(Some(ConnectionEvent::Proto(event))) => {
+                    if event.is_datagram() {
+                        // The peer has found the connection's current endpoint
+                        self.release_moved_from();
+                    }
+                    self.inner.handle_event(event);
+                }
+                Poll::Ready(Some(ConnectionEvent::Forwarded(event))) => {
                     self.inner.handle_event(event);
                 }
                 Poll::Ready(Some(ConnectionEvent::Close { reason, error_code })) => {
                     self.close(error_code, reason);
                 }
+                Poll::Ready(Some(ConnectionEvent::CloseWhenIdle { error_code, reason })) => {
+                    // Stop the peer from opening streams beyond the credit already issued
+                    self.inner.set_max_concurrent_streams(Dir::Bi, 0u32.into());
+                    self.inner.set_max_concurrent_streams(Dir::Uni, 0u32.into());
+                    self.close_when_idle = Some((error_code, reason));
+                }
+                Poll::Ready(Some(ConnectionEvent::LocalAddressChanged)) => {
+                    self.inner.local_address_changed();
+                }
                 Poll::Ready(None) => {
                     return Err(ConnectionError::TransportError(proto::TransportError {
                         code: proto::TransportErrorCode::INTERNAL_ERROR,
@@ -825,8 +1369,19 @@ impl ConnectionInner {
                 Connected => {
                     self.connected = true;
                     if let Some(x) = self.on_connected.take() {
+                        let result = if self.inner.side().is_server() {
+                            Ok(())
+                        } else if self.inner.version_changed_0rtt() {
+                            Err(ZeroRttRejected::VersionChanged)
+                        } else if !self.inner.has_0rtt() || self.inner.accepted_0rtt() {
+                            Ok(())
+                        } else {
+                            Err(ZeroRttRejected::RejectedByServer {
+                                replayed: self.inner.replayed_0rtt(),
+                            })
+                        };
                         // We don't care if the on-connected future was dropped
-                        let _ = x.send(self.inner.accepted_0rtt());
+                        let _ = x.send(result);
                     }
                 }
                 ConnectionLost { reason } => {
@@ -841,16 +1396,29 @@ impl ConnectionInner {
                     if let Some(x) = self.incoming_uni_streams_reader.take() {
                         x.wake();
                     }
+                    self.uni_accepting.wake();
                 }
                 Stream(StreamEvent::Opened { dir: Dir::Bi }) => {
                     if let Some(x) = self.incoming_bi_streams_reader.take() {
                         x.wake();
                     }
+                    self.bi_accepting.wake();
                 }
                 DatagramReceived => {
                     if let Some(x) = self.datagram_reader.take() {
                         x.wake();
                     }
+                    self.datagram_reading.wake();
+                }
+                DatagramsDropped => self.datagrams_dropped.wake(),
+                DeliveryRateChanged => {
+                    self.delivery_rate_generation = self.delivery_rate_generation.wrapping_add(1);
+                    self.delivery_rate_changed.wake();
+                }
+                BandwidthProbed { delivery_rate } => {
+                    for x in self.bandwidth_probes.drain(..) {
+                        let _ = x.send(Ok(delivery_rate));
+                    }
                 }
                 Stream(StreamEvent::Readable { id }) => {
                     if let Some(reader) = self.blocked_readers.remove(&id) {
@@ -864,12 +1432,25 @@ impl ConnectionInner {
                     };
                     tasks.wake();
                 }
+                Stream(StreamEvent::Acked { id }) => {
+                    if let Some(writer) = self.blocked_writers.remove(&id) {
+                        writer.wake();
+                    }
+                }
                 Stream(StreamEvent::Finished { id }) => {
                     if let Some(finishing) = self.finishing.remove(&id) {
                         // If the finishing stream was already dropped, there's nothing more to do.
                         let _ = finishing.send(None);
                     }
                 }
+                Stream(StreamEvent::Expired { id }) => {
+                    if let Some(finishing) = self.finishing.remove(&id) {
+                        let _ = finishing.send(Some(WriteError::Expired));
+                    }
+                    if let Some(writer) = self.blocked_writers.remove(&id) {
+                        writer.wake();
+                    }
+                }
                 Stream(StreamEvent::Stopped { id, error_code }) => {
                     if let Some(stopped) = self.stopped.remove(&id) {
                         stopped.wake();
@@ -889,10 +1470,10 @@ impl ConnectionInner {
         // Check whether we need to (re)set the timer. If so, we must poll again to ensure the
         // timer is registered with the runtime (and check whether it's already
         // expired).
-        match self.inner.poll_timeout().map(TokioInstant::from_std) {
+        match self.inner.poll_timeout() {
             Some(deadline) => {
                 if let Some(delay) = &mut self.timer {
-                    // There is no need to reset the tokio timer if the deadline
+                    // There is no need to reset the timer if the deadline
                     // did not change
                     if self
                         .timer_deadline
@@ -902,7 +1483,7 @@ impl ConnectionInner {
                         delay.as_mut().reset(deadline);
                     }
                 } else {
-                    self.timer = Some(Box::pin(sleep_until(deadline)));
+                    self.timer = Some(self.runtime.new_timer(deadline));
                 }
                 // Store the actual expiration time of the timer
                 self.timer_deadline = Some(deadline);
@@ -956,6 +1537,14 @@ impl ConnectionInner {
         }
         self.uni_opening.wake();
         self.bi_opening.wake();
+        self.uni_accepting.wake();
+        self.bi_accepting.wake();
+        self.datagram_reading.wake();
+        self.datagrams_dropped.wake();
+        self.delivery_rate_changed.wake();
+        for x in self.bandwidth_probes.drain(..) {
+            let _ = x.send(Err(reason.clone()));
+        }
         if let Some(x) = self.incoming_uni_streams_reader.take() {
             x.wake();
         }
@@ -969,7 +1558,10 @@ impl ConnectionInner {
             let _ = x.send(Some(WriteError::ConnectionClosed(reason.clone())));
         }
         if let Some(x) = self.on_connected.take() {
-            let _ = x.send(false);
+            let _ = x.send(Err(match reason {
+                ConnectionError::VersionMismatch => ZeroRttRejected::VersionChanged,
+                _ => ZeroRttRejected::ConnectionLost(reason.clone()),
+            }));
         }
         for (_, waker) in self.stopped.drain() {
             waker.wake();
@@ -982,14 +1574,105 @@ impl ConnectionInner {
         self.wake();
     }
 
+    /// Close if a graceful close was requested and the last stream has been closed
+    ///
+    /// Returns whether the connection was closed.
+    fn close_if_idle(&mut self) -> bool {
+        if self.error.is_some() || self.inner.has_open_streams() {
+            return false;
+        }
+        match self.close_when_idle.take() {
+            Some((error_code, reason)) => {
+                self.close(error_code, reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Close if [`Connection::close_and_wait()`] was called and the peer has acknowledged all
+    /// stream data
+    ///
+    /// Returns whether the connection was closed.
+    fn close_if_delivered(&mut self) -> bool {
+        if self.error.is_some() || self.inner.has_undelivered_data() {
+            return false;
+        }
+        match self.close_when_delivered.take() {
+            Some((error_code, reason)) => {
+                self.close(error_code, reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Release the handles of endpoints the connection moved away from
+    ///
+    /// The drained event follows every event sent to those endpoints under the old handle, so
+    /// it can safely be released.
+    fn release_moved_from(&mut self) {
+        for (endpoint, handle) in self.moved_from.drain(..) {
+            let _ = endpoint.unbounded_send((
+                handle,
+                EndpointEvent::Proto(proto::EndpointEvent::drained()),
+            ));
+        }
+    }
+
+    fn notify_drained(&mut self) {
+        for x in self.on_drained.drain(..) {
+            let _ = x.send(());
+        }
+    }
+
     /// Close for a reason other than the application's explicit request
     pub fn implicit_close(&mut self) {
         self.close(0u32.into(), Bytes::new());
     }
 
+    /// Whether the connection is driven by the endpoint receiving on the other end of `events`
+    pub(crate) fn is_bound_to(
+        &self,
+        events: &mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
+    ) -> bool {
+        self.endpoint_events.same_receiver(events)
+    }
+
+    /// Start exchanging events with another endpoint, which knows the connection as `handle`
+    ///
+    /// Events queued by the previous endpoint are forwarded to `forward`, the sender for
+    /// `conn_events`, so that no datagrams are lost. They are marked as forwarded, since they
+    /// don't show that the peer has found the new endpoint.
+    pub(crate) fn switch_endpoint(
+        &mut self,
+        handle: ConnectionHandle,
+        endpoint_events: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
+        conn_events: mpsc::UnboundedReceiver<ConnectionEvent>,
+        forward: &mpsc::UnboundedSender<ConnectionEvent>,
+        udp_state: Arc<UdpState>,
+    ) {
+        let mut old_events = mem::replace(&mut self.conn_events, conn_events);
+        while let Some(Some(event)) = old_events.next().now_or_never() {
+            let event = match event {
+                ConnectionEvent::Proto(event) => ConnectionEvent::Forwarded(event),
+                event => event,
+            };
+            let _ = forward.unbounded_send(event);
+        }
+        let old_endpoint = mem::replace(&mut self.endpoint_events, endpoint_events);
+        // Keep the old handle until datagrams arrive through the new endpoint, so that packets the
+        // peer sent to the previous one in the meantime aren't answered with stateless resets
+        self.moved_from.push((old_endpoint, self.handle));
+        self.handle = handle;
+        self.udp_state = udp_state;
+        self.wake();
+    }
+
     pub(crate) fn check_0rtt(&self) -> Result<(), ()> {
         if self.inner.is_handshaking()
             || self.inner.accepted_0rtt()
+            || self.inner.replayed_0rtt()
             || self.inner.side().is_server()
         {
             Ok(())
@@ -1001,6 +1684,7 @@ impl ConnectionInner {
 
 impl Drop for ConnectionInner {
     fn drop(&mut self) {
+        self.release_moved_from();
         if !self.inner.is_drained() {
             // Ensure the endpoint can tidy up
             let _ = self.endpoint_events.unbounded_send((
@@ -1034,6 +1718,11 @@ pub enum SendDatagramError {
     /// exceeded.
     #[error("datagram too large")]
     TooLarge,
+    /// The send buffer is full
+    ///
+    /// Only returned under [`DatagramDropPolicy::RejectNew`](crate::DatagramDropPolicy::RejectNew).
+    #[error("datagram send buffer full")]
+    Blocked,
     /// The connection was closed
     #[error("connection closed: {0}")]
     ConnectionClosed(#[source] ConnectionError),
@@ -1,32 +1,40 @@
 use std::{
     collections::VecDeque,
+    fmt,
     future::Future,
     io,
     io::IoSliceMut,
-    mem::MaybeUninit,
+    mem::{self, MaybeUninit},
     net::{SocketAddr, SocketAddrV6},
     pin::Pin,
     str,
     sync::{Arc, Mutex},
     task::{Context, Poll, Waker},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
 use futures_channel::mpsc;
-use futures_util::StreamExt;
+use futures_util::{stream::FuturesUnordered, StreamExt};
 use fxhash::FxHashMap;
 use proto::{
-    self as proto, ClientConfig, ConnectError, ConnectionHandle, DatagramEvent, ServerConfig,
+    self as proto, ClientConfig, ConnectError, ConnectionHandle, DatagramEvent, EndpointStats,
+    HandshakeFailure, ServerConfig,
 };
-use udp::{RecvMeta, UdpSocket, UdpState, BATCH_SIZE};
+use thiserror::Error;
+use tracing::debug;
+use udp::{RecvMeta, UdpState, BATCH_SIZE};
 
 use crate::{
     broadcast::{self, Broadcast},
-    builders::EndpointBuilder,
-    connection::Connecting,
+    builders::{set_ttl, EndpointBuilder, SourcePort},
+    connection::{Connecting, Connection, NewConnection},
+    resolver::Resolver,
+    runtime::{AsyncTimer, AsyncUdpSocket, Runtime},
+    scheduler::EgressScheduler,
     work_limiter::WorkLimiter,
-    ConnectionEvent, EndpointEvent, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND, SEND_TIME_BOUND,
+    ConnectionError, ConnectionEvent, EndpointEvent, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND,
+    SEND_TIME_BOUND,
 };
 
 /// A QUIC endpoint.
@@ -39,6 +47,9 @@ use crate::{
 pub struct Endpoint {
     pub(crate) inner: EndpointRef,
     pub(crate) default_client_config: Option<ClientConfig>,
+    pub(crate) source_port: SourcePort,
+    pub(crate) ttl: Option<u32>,
+    pub(crate) resolver: Arc<dyn Resolver>,
 }
 
 impl Endpoint {
@@ -51,7 +62,8 @@ impl Endpoint {
     ///
     /// `server_name` must be covered by the certificate presented by the server. This prevents a
     /// connection from being intercepted by an attacker with a valid certificate for some other
-    /// server.
+    /// server. It may also be an IP address, optionally in brackets for IPv6, for servers without a
+    /// DNS name; the certificate must then carry a matching IP address subject alternative name.
     ///
     /// May fail immediately due to configuration errors, or in the future if the connection could
     /// not be established.
@@ -93,21 +105,282 @@ impl Endpoint {
         };
         let (ch, conn) = endpoint.inner.connect(config, addr, server_name)?;
         let udp_state = endpoint.udp_state.clone();
-        Ok(endpoint.connections.insert(ch, conn, udp_state))
+        let runtime = endpoint.runtime.clone();
+        Ok(endpoint.connections.insert(ch, conn, udp_state, runtime))
+    }
+
+    /// Connect to `port` on `host`, resolving it with the endpoint's [`Resolver`]
+    ///
+    /// `host` is also used as the server name; see [`connect()`](Self::connect). If `host` has
+    /// several addresses, connections are attempted in turn, alternating between IPv6 and IPv4,
+    /// with a new attempt started whenever the previous one fails or has been outstanding for
+    /// 250ms without completing, and the first to complete its handshake is returned. The other
+    /// attempts are abandoned. IPv6 addresses are skipped if the endpoint isn't bound to an IPv6
+    /// socket.
+    ///
+    /// Uses the default client configuration, and the error of the last attempt is returned if
+    /// none succeed.
+    ///
+    /// [`Resolver`]: crate::Resolver
+    pub async fn connect_to(&self, host: &str, port: u16) -> Result<NewConnection, ConnectToError> {
+        let ipv6 = self.inner.lock().unwrap().ipv6;
+        let addrs = self
+            .resolver
+            .resolve(host, port)
+            .await
+            .map_err(ConnectToError::Resolve)?;
+        let (v6, v4): (Vec<_>, Vec<_>) = addrs
+            .into_iter()
+            .filter(|addr| ipv6 || addr.is_ipv4())
+            .partition(|addr| addr.is_ipv6());
+        let mut addrs = Vec::with_capacity(v6.len() + v4.len());
+        let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+        loop {
+            let (a, b) = (v6.next(), v4.next());
+            if a.is_none() && b.is_none() {
+                break;
+            }
+            addrs.extend(a.into_iter().chain(b));
+        }
+
+        let mut addrs = addrs.into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut timer = self.inner.lock().unwrap().runtime.new_timer(Instant::now());
+        let mut last_error = ConnectToError::NoAddresses;
+        let mut failed = false;
+        futures_util::future::poll_fn(|cx| loop {
+            if attempts.is_empty() || mem::take(&mut failed) || timer.as_mut().poll(cx).is_ready() {
+                match addrs.next() {
+                    Some(addr) => {
+                        match self.connect(&addr, host) {
+                            Ok(connecting) => attempts.push(connecting),
+                            Err(e) => {
+                                last_error = ConnectToError::Connect(e);
+                                failed = true;
+                                continue;
+                            }
+                        }
+                        timer
+                            .as_mut()
+                            .reset(Instant::now() + CONNECTION_ATTEMPT_DELAY);
+                        continue;
+                    }
+                    None if attempts.is_empty() => {
+                        return Poll::Ready(Err(mem::replace(
+                            &mut last_error,
+                            ConnectToError::NoAddresses,
+                        )));
+                    }
+                    None => {}
+                }
+            }
+            match attempts.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(conn))) => return Poll::Ready(Ok(conn)),
+                Poll::Ready(Some(Err(e))) => {
+                    last_error = ConnectToError::Connection(e);
+                    failed = true;
+                }
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Wait for the next incoming connection
+    ///
+    /// An alternative to the [`Incoming`] stream that can be called from any clone of the
+    /// `Endpoint`, allowing servers to accept in a plain loop and bound concurrency with ordinary
+    /// tools such as semaphores. Connections accepted here are not yielded by [`Incoming`], and
+    /// vice versa. Yields `None` once the endpoint has been closed or its driver has stopped.
+    ///
+    /// The [`Incoming`] stream need not be kept alive: new connections are only refused once both
+    /// it and every clone of the `Endpoint` have been dropped. To stop accepting without dropping
+    /// the endpoint, call [`set_server_config(None)`](Self::set_server_config).
+    ///
+    /// [`Incoming`]: crate::Incoming
+    pub async fn accept(&self) -> Option<Connecting> {
+        let mut state = broadcast::State::default();
+        futures_util::future::poll_fn(|cx| poll_incoming(&self.inner, cx, &mut state)).await
     }
 
     /// Switch to a new UDP socket
     ///
-    /// Allows the endpoint's address to be updated live, affecting all active connections. Incoming
-    /// connections and connections to servers unreachable from the new address will be lost.
+    /// Allows the endpoint's address to be updated live, affecting all active connections, e.g.
+    /// when a mobile device moves from one network to another. Every connection promptly sends a
+    /// packet from the new socket so that its peer learns of the new address and validates the new
+    /// path, after which the connection carries on as before. Incoming connections and connections
+    /// to servers unreachable from the new address will be lost.
+    ///
+    /// The time-to-live set with [`EndpointBuilder::ttl()`], if any, is applied to the new socket.
     ///
     /// On error, the old UDP socket is retained.
     pub fn rebind(&self, socket: std::net::UdpSocket) -> io::Result<()> {
         let addr = socket.local_addr()?;
-        let socket = UdpSocket::from_std(socket)?;
+        if let Some(ttl) = self.ttl {
+            set_ttl(&socket, ttl)?;
+        }
         let mut inner = self.inner.lock().unwrap();
+        let socket = inner.runtime.wrap_udp_socket(socket)?;
         inner.socket = socket;
         inner.ipv6 = addr.is_ipv6();
+
+        for sender in inner.connections.senders.values() {
+            // Ignoring errors from dropped connections
+            let _ = sender.unbounded_send(ConnectionEvent::LocalAddressChanged);
+        }
+        // The driver must poll the new socket to register for its I/O readiness
+        if let Some(driver) = inner.driver.take() {
+            driver.wake();
+        }
+        Ok(())
+    }
+
+    /// Switch to a new UDP socket on the same IP address, with a port selected by the endpoint's
+    /// [`SourcePort`] policy
+    ///
+    /// Policies that can choose between several ports will pick one other than the current port.
+    /// Returns the new local address. See [`rebind()`](Self::rebind) for details. If the policy is
+    /// [`SourcePort::Fixed`] and the endpoint already uses that port, the current socket is kept
+    /// and its address returned.
+    pub fn rebind_source_port(&self) -> io::Result<SocketAddr> {
+        let current = self.local_addr()?;
+        if self.source_port == SourcePort::Fixed(current.port()) {
+            return Ok(current);
+        }
+        let socket = self.source_port.bind(current.ip(), Some(current.port()))?;
+        let addr = socket.local_addr()?;
+        self.rebind(socket)?;
+        Ok(addr)
+    }
+
+    /// Move `connection` from this endpoint to `to`
+    ///
+    /// The connection keeps its connection IDs and cryptographic state, and from then on sends
+    /// and receives datagrams through `to`'s socket. This allows applications using one endpoint
+    /// per network interface to keep connections alive when an interface goes away. The peer sees
+    /// the connection migrate to a new address, so moving connections whose peer is the client
+    /// only works if the client tolerates servers changing addresses. Both endpoints must use the
+    /// same address family and connection ID length, and should be configured with the same
+    /// [`reset_key`](proto::EndpointConfig::reset_key) so that stateless resets for the
+    /// connection's existing IDs remain valid. Any egress weight set with
+    /// [`Connection::set_weight()`] must be set again.
+    ///
+    /// On error, the connection remains on this endpoint.
+    pub fn move_connection(
+        &self,
+        connection: &Connection,
+        to: &Endpoint,
+    ) -> Result<(), MoveConnectionError> {
+        self.transfer(connection, to, false)
+    }
+
+    /// Deliberately migrate `connection`, for which this endpoint is the client, to `to`
+    ///
+    /// Like [`move_connection()`](Self::move_connection), but as prescribed by RFC 9000 §9 for
+    /// clients changing networks on purpose, e.g. to move to a preferred interface: the
+    /// connection switches to a connection ID the server hasn't seen before, so that observers
+    /// can't link the old and new paths, validates the new path, and restarts congestion control
+    /// unless both endpoints are bound to the same IP address. To migrate to a new local address,
+    /// bind a client endpoint to it and pass that as `to`.
+    ///
+    /// Fails with [`MoveConnectionError::Migration`], leaving the connection where it is, if the
+    /// server disabled migration, the handshake hasn't been confirmed yet, or the server hasn't
+    /// issued a spare connection ID.
+    pub fn migrate(
+        &self,
+        connection: &Connection,
+        to: &Endpoint,
+    ) -> Result<(), MoveConnectionError> {
+        self.transfer(connection, to, true)
+    }
+
+    fn transfer(
+        &self,
+        connection: &Connection,
+        to: &Endpoint,
+        migrate: bool,
+    ) -> Result<(), MoveConnectionError> {
+        if Arc::ptr_eq(&self.inner.0, &to.inner.0) {
+            return Ok(());
+        }
+        // Lock the endpoints in a consistent order, so that concurrent moves in opposite directions
+        // can't deadlock
+        let (mut from, mut to) = if Arc::as_ptr(&self.inner.0) < Arc::as_ptr(&to.inner.0) {
+            let from = self.inner.lock().unwrap();
+            (from, to.inner.lock().unwrap())
+        } else {
+            let to = to.inner.lock().unwrap();
+            (self.inner.lock().unwrap(), to)
+        };
+        let (from, to) = (&mut *from, &mut *to);
+        if to.driver_lost {
+            return Err(MoveConnectionError::EndpointStopping);
+        }
+        if from.ipv6 != to.ipv6 {
+            return Err(MoveConnectionError::AddressFamily);
+        }
+        let conn = &mut *connection.conn_ref().lock("move_connection");
+        if conn.error.is_some() || !conn.is_bound_to(&from.connections.sender) {
+            return Err(MoveConnectionError::UnknownConnection);
+        }
+        // Apply the routing changes the connection has already requested, such as new or retired
+        // CIDs, before its routing state is handed over. Responses reach it through the old
+        // channel, which is forwarded below.
+        from.flush_events();
+        let old = conn.handle;
+        let detached = from.inner.detach(old);
+        // Only migrate once the new endpoint can route the connection's packets, and undo the
+        // move if the connection can't migrate
+        let result = match to.inner.attach(&detached) {
+            Ok(handle) if migrate => {
+                let ip_changed = match (from.socket.local_addr(), to.socket.local_addr()) {
+                    (Ok(from), Ok(to)) => from.ip() != to.ip(),
+                    _ => true,
+                };
+                match conn.inner.initiate_migration(Instant::now(), ip_changed) {
+                    Ok(()) => Ok(handle),
+                    Err(e) => {
+                        // The connection never ran on this handle, so release it right away
+                        to.inner.detach(handle);
+                        to.inner
+                            .handle_event(handle, proto::EndpointEvent::drained());
+                        Err(e.into())
+                    }
+                }
+            }
+            result => result.map_err(MoveConnectionError::from),
+        };
+        let handle = match result {
+            Ok(handle) => handle,
+            Err(e) => {
+                from.inner.restore(old, detached);
+                return Err(e);
+            }
+        };
+        from.connections.senders.remove(&old);
+        from.idle.wake();
+        let (send, recv) = mpsc::unbounded();
+        if let Some((error_code, ref reason)) = to.connections.close {
+            let _ = send.unbounded_send(ConnectionEvent::Close {
+                error_code,
+                reason: reason.clone(),
+            });
+        }
+        // The peer may keep sending to this endpoint until it learns of the move, so its
+        // datagrams are forwarded until the connection releases its old handle
+        from.connections.forwards.insert(old, send.clone());
+        conn.switch_endpoint(
+            handle,
+            to.connections.sender.clone(),
+            recv,
+            &send,
+            to.udp_state.clone(),
+        );
+        to.connections.senders.insert(handle, send);
+        // Migration sends a PATH_CHALLENGE anyway
+        if !migrate {
+            conn.inner.local_address_changed();
+        }
         Ok(())
     }
 
@@ -143,16 +416,74 @@ impl Endpoint {
                 reason: reason.clone(),
             });
         }
-        if let Some(task) = endpoint.incoming_reader.take() {
-            task.wake();
-        }
+        endpoint.incoming_readers.wake();
+    }
+
+    /// Shut down gracefully, giving connections up to `grace` to finish their streams
+    ///
+    /// Ceases accepting new connections and refuses connections that haven't been accepted yet.
+    /// Each existing connection stops granting its peer credit for new streams and is closed with
+    /// `error_code` and `reason` as soon as all of its streams have been closed, letting the peer
+    /// know that the closure was deliberate, much like an HTTP/2 GOAWAY. Connections which still
+    /// have open streams once `grace` has elapsed are closed as if by [`close()`](Self::close).
+    ///
+    /// Resolves once all connections have been shut down, as with
+    /// [`wait_idle()`](Self::wait_idle).
+    pub async fn shutdown(&self, grace: Duration, error_code: VarInt, reason: &[u8]) {
+        let reason = Bytes::copy_from_slice(reason);
+        let (unaccepted, mut timer) = {
+            let endpoint = &mut *self.inner.lock().unwrap();
+            endpoint.inner.reject_new_connections();
+            endpoint.connections.close = Some((error_code, reason.clone()));
+            for sender in endpoint.connections.senders.values() {
+                // Ignoring errors from dropped connections
+                let _ = sender.unbounded_send(ConnectionEvent::CloseWhenIdle {
+                    error_code,
+                    reason: reason.clone(),
+                });
+            }
+            endpoint.incoming_readers.wake();
+            (
+                mem::take(&mut endpoint.incoming),
+                endpoint.runtime.new_timer(Instant::now() + grace),
+            )
+        };
+        // Closing connections mustn't happen under the endpoint lock
+        drop(unaccepted);
+
+        let mut state = broadcast::State::default();
+        let mut expired = false;
+        futures_util::future::poll_fn(|cx| {
+            if !expired && timer.as_mut().poll(cx).is_ready() {
+                expired = true;
+                self.close(error_code, &reason);
+            }
+            let endpoint = &mut *self.inner.lock().unwrap();
+            if endpoint.connections.is_empty() {
+                return Poll::Ready(());
+            }
+            endpoint.idle.register(cx, &mut state);
+            Poll::Pending
+        })
+        .await;
+    }
+
+    /// Number of connections on this endpoint that have not yet been fully shut down
+    pub fn open_connections(&self) -> usize {
+        self.inner.lock().unwrap().connections.senders.len()
+    }
+
+    /// Counters for the responses this endpoint sent to packets not belonging to any connection
+    pub fn stats(&self) -> EndpointStats {
+        self.inner.lock().unwrap().inner.stats()
     }
 
     /// Wait for all connections on the endpoint to be cleanly shut down
     ///
     /// Waiting for this condition before exiting ensures that a good-faith effort is made to notify
     /// peers of recent connection closes, whereas exiting immediately could force them to wait out
-    /// the idle timeout period.
+    /// the idle timeout period. How long closed connections linger can be tuned with
+    /// [`TransportConfig::closing_period()`].
     ///
     /// Does not proactively close existing connections or cause incoming connections to be
     /// rejected. Consider calling [`close()`] and dropping the [`Incoming`] stream if
@@ -160,14 +491,32 @@ impl Endpoint {
     ///
     /// [`close()`]: Endpoint::close
     /// [`Incoming`]: crate::Incoming
+    /// [`TransportConfig::closing_period()`]: crate::TransportConfig::closing_period
     pub async fn wait_idle(&self) {
+        self.wait_idle_with_progress(|_| {}).await;
+    }
+
+    /// Like [`wait_idle()`](Self::wait_idle), but calls `progress` with the number of connections
+    /// still open whenever it changes
+    ///
+    /// Useful to report shutdown progress to users. `progress` is not called once all connections
+    /// have been shut down.
+    pub async fn wait_idle_with_progress(&self, mut progress: impl FnMut(usize)) {
         let mut state = broadcast::State::default();
+        let mut last = None;
         futures_util::future::poll_fn(|cx| {
-            let endpoint = &mut *self.inner.lock().unwrap();
-            if endpoint.connections.is_empty() {
-                return Poll::Ready(());
+            let open = {
+                let endpoint = &mut *self.inner.lock().unwrap();
+                if endpoint.connections.is_empty() {
+                    return Poll::Ready(());
+                }
+                endpoint.idle.register(cx, &mut state);
+                endpoint.connections.senders.len()
+            };
+            if last != Some(open) {
+                last = Some(open);
+                progress(open);
             }
-            endpoint.idle.register(cx, &mut state);
             Poll::Pending
         })
         .await;
@@ -183,7 +532,7 @@ impl Endpoint {
 /// running this task is necessary to keep the endpoint's connections running.
 ///
 /// `EndpointDriver` futures terminate when the `Incoming` stream and all clones of the `Endpoint`
-/// have been dropped, or when an I/O error occurs.
+/// have been dropped and all connections have closed, or when an I/O error occurs.
 #[must_use = "endpoint drivers must be spawned for I/O to occur"]
 #[derive(Debug)]
 pub(crate) struct EndpointDriver(pub(crate) EndpointRef);
@@ -203,11 +552,10 @@ impl Future for EndpointDriver {
         keep_going |= endpoint.drive_recv(cx, now)?;
         keep_going |= endpoint.handle_events(cx);
         keep_going |= endpoint.drive_send(cx)?;
+        endpoint.report_handshake_failures();
 
         if !endpoint.incoming.is_empty() {
-            if let Some(task) = endpoint.incoming_reader.take() {
-                task.wake();
-            }
+            endpoint.incoming_readers.wake();
         }
 
         if endpoint.ref_count == 0 && endpoint.connections.is_empty() {
@@ -229,23 +577,22 @@ impl Drop for EndpointDriver {
     fn drop(&mut self) {
         let mut endpoint = self.0.lock().unwrap();
         endpoint.driver_lost = true;
-        if let Some(task) = endpoint.incoming_reader.take() {
-            task.wake();
-        }
+        endpoint.incoming_readers.wake();
         // Drop all outgoing channels, signaling the termination of the endpoint to the associated
         // connections.
         endpoint.connections.senders.clear();
+        endpoint.connections.forwards.clear();
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct EndpointInner {
-    socket: UdpSocket,
+    socket: Box<dyn AsyncUdpSocket>,
     udp_state: Arc<UdpState>,
     inner: proto::Endpoint,
     outgoing: VecDeque<proto::Transmit>,
     incoming: VecDeque<Connecting>,
-    incoming_reader: Option<Waker>,
+    incoming_readers: Broadcast,
     driver: Option<Waker>,
     ipv6: bool,
     connections: ConnectionSet,
@@ -257,6 +604,17 @@ pub(crate) struct EndpointInner {
     recv_buf: Box<[u8]>,
     send_limiter: WorkLimiter,
     idle: Broadcast,
+    admission: Option<Arc<AdmissionControl>>,
+    /// Incoming connections awaiting a decision from `admission`
+    pending_admission: Vec<PendingAdmission>,
+    handshake_failure_hook: Option<Arc<HandshakeFailureHook>>,
+    /// Runtime on which the endpoint's tasks are run
+    runtime: Arc<dyn Runtime>,
+    /// Set if fair queueing is enabled, in which case connections' transmits are queued here
+    /// before being moved to `outgoing`
+    scheduler: Option<EgressScheduler>,
+    /// Fires when the egress rate limit allows more transmits to be sent
+    scheduler_timer: Option<Pin<Box<dyn AsyncTimer>>>,
 }
 
 impl EndpointInner {
@@ -285,19 +643,31 @@ impl EndpointInner {
                             .handle(now, meta.addr, meta.dst_ip, meta.ecn, data)
                         {
                             Some((handle, DatagramEvent::NewConnection(conn))) => {
-                                let conn =
-                                    self.connections
-                                        .insert(handle, conn, self.udp_state.clone());
-                                self.incoming.push_back(conn);
+                                let conn = self.connections.insert(
+                                    handle,
+                                    conn,
+                                    self.udp_state.clone(),
+                                    self.runtime.clone(),
+                                );
+                                admit(
+                                    self.admission.as_deref(),
+                                    &mut self.incoming,
+                                    &mut self.pending_admission,
+                                    &mut self.incoming_readers,
+                                    conn,
+                                );
                             }
                             Some((handle, DatagramEvent::ConnectionEvent(event))) => {
+                                let connections = &self.connections;
+                                let (sender, event) = match connections.senders.get(&handle) {
+                                    Some(sender) => (sender, ConnectionEvent::Proto(event)),
+                                    None => (
+                                        &connections.forwards[&handle],
+                                        ConnectionEvent::Forwarded(event),
+                                    ),
+                                };
                                 // Ignoring errors from dropped connections that haven't yet been cleaned up
-                                let _ = self
-                                    .connections
-                                    .senders
-                                    .get_mut(&handle)
-                                    .unwrap()
-                                    .unbounded_send(ConnectionEvent::Proto(event));
+                                let _ = sender.unbounded_send(event);
                             }
                             None => {}
                         }
@@ -335,6 +705,7 @@ impl EndpointInner {
                     None => break,
                 }
             }
+            self.poll_scheduler(cx);
 
             if self.outgoing.is_empty() {
                 break Ok(false);
@@ -367,31 +738,51 @@ impl EndpointInner {
         result
     }
 
-    fn handle_events(&mut self, cx: &mut Context) -> bool {
-        use EndpointEvent::*;
+    /// Move transmits released by the scheduler, if any, to `outgoing`
+    fn poll_scheduler(&mut self, cx: &mut Context) {
+        let scheduler = match self.scheduler {
+            Some(ref mut x) => x,
+            None => return,
+        };
+        loop {
+            match scheduler.dequeue(Instant::now(), &mut self.outgoing, BATCH_SIZE) {
+                None => {
+                    self.scheduler_timer = None;
+                    return;
+                }
+                Some(wakeup) => {
+                    match self.scheduler_timer {
+                        Some(ref mut timer) => timer.as_mut().reset(wakeup),
+                        None => self.scheduler_timer = Some(self.runtime.new_timer(wakeup)),
+                    }
+                    if self
+                        .scheduler_timer
+                        .as_mut()
+                        .unwrap()
+                        .as_mut()
+                        .poll(cx)
+                        .is_pending()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pass failed incoming handshakes to the hook, if any
+    fn report_handshake_failures(&mut self) {
+        while let Some(failure) = self.inner.poll_handshake_failure() {
+            if let Some(ref hook) = self.handshake_failure_hook {
+                (hook.0)(failure);
+            }
+        }
+    }
 
+    fn handle_events(&mut self, cx: &mut Context) -> bool {
         for _ in 0..IO_LOOP_BOUND {
             match self.events.poll_next_unpin(cx) {
-                Poll::Ready(Some((ch, event))) => match event {
-                    Proto(e) => {
-                        if e.is_drained() {
-                            self.connections.senders.remove(&ch);
-                            if self.connections.is_empty() {
-                                self.idle.wake();
-                            }
-                        }
-                        if let Some(event) = self.inner.handle_event(ch, e) {
-                            // Ignoring errors from dropped connections that haven't yet been cleaned up
-                            let _ = self
-                                .connections
-                                .senders
-                                .get_mut(&ch)
-                                .unwrap()
-                                .unbounded_send(ConnectionEvent::Proto(event));
-                        }
-                    }
-                    Transmit(t) => self.outgoing.push_back(t),
-                },
+                Poll::Ready(Some((ch, event))) => self.handle_event(ch, event),
                 Poll::Ready(None) => unreachable!("EndpointInner owns one sender"),
                 Poll::Pending => {
                     return false;
@@ -401,12 +792,59 @@ impl EndpointInner {
 
         true
     }
+
+    /// Process the events connections have queued so far, without waiting for the driver
+    // The replacement, `try_recv`, needs a futures-channel release newer than the MSRV supports
+    #[allow(deprecated)]
+    fn flush_events(&mut self) {
+        while let Ok(Some((ch, event))) = self.events.try_next() {
+            self.handle_event(ch, event);
+        }
+    }
+
+    fn handle_event(&mut self, ch: ConnectionHandle, event: EndpointEvent) {
+        use EndpointEvent::*;
+
+        match event {
+            Proto(e) => {
+                if e.is_drained() {
+                    self.connections.senders.remove(&ch);
+                    self.connections.forwards.remove(&ch);
+                    self.idle.wake();
+                    if let Some(ref mut scheduler) = self.scheduler {
+                        scheduler.remove(ch);
+                    }
+                }
+                if let Some(event) = self.inner.handle_event(ch, e) {
+                    // Ignoring errors from dropped connections that haven't yet been cleaned up
+                    let _ = self
+                        .connections
+                        .senders
+                        .get_mut(&ch)
+                        .unwrap()
+                        .unbounded_send(ConnectionEvent::Proto(event));
+                }
+            }
+            Transmit(t) => match self.scheduler {
+                Some(ref mut scheduler) => scheduler.enqueue(ch, t),
+                None => self.outgoing.push_back(t),
+            },
+            EgressWeight(weight) => {
+                if let Some(ref mut scheduler) = self.scheduler {
+                    scheduler.set_weight(ch, weight);
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ConnectionSet {
     /// Senders for communicating with the endpoint's connections
     senders: FxHashMap<ConnectionHandle, mpsc::UnboundedSender<ConnectionEvent>>,
+    /// Senders for connections moved to other endpoints, for datagrams the peer sent here before
+    /// learning of the move
+    forwards: FxHashMap<ConnectionHandle, mpsc::UnboundedSender<ConnectionEvent>>,
     /// Stored to give out clones to new ConnectionInners
     sender: mpsc::UnboundedSender<(ConnectionHandle, EndpointEvent)>,
     /// Set if the endpoint has been manually closed
@@ -419,6 +857,7 @@ impl ConnectionSet {
         handle: ConnectionHandle,
         conn: proto::Connection,
         udp_state: Arc<UdpState>,
+        runtime: Arc<dyn Runtime>,
     ) -> Connecting {
         let (send, recv) = mpsc::unbounded();
         if let Some((error_code, ref reason)) = self.close {
@@ -429,7 +868,7 @@ impl ConnectionSet {
             .unwrap();
         }
         self.senders.insert(handle, send);
-        Connecting::new(handle, conn, self.sender.clone(), recv, udp_state)
+        Connecting::new(handle, conn, self.sender.clone(), recv, udp_state, runtime)
     }
 
     fn is_empty(&self) -> bool {
@@ -437,6 +876,74 @@ impl ConnectionSet {
     }
 }
 
+/// Errors that prevent [`Endpoint::connect_to()`] from establishing a connection
+#[derive(Debug, Error)]
+pub enum ConnectToError {
+    /// The host name couldn't be resolved
+    #[error("failed to resolve host: {0}")]
+    Resolve(io::Error),
+    /// The host name resolved to no addresses reachable from this endpoint
+    #[error("no usable addresses")]
+    NoAddresses,
+    /// The last connection attempt couldn't be started
+    #[error("{0}")]
+    Connect(ConnectError),
+    /// The last connection attempt failed
+    #[error("{0}")]
+    Connection(ConnectionError),
+}
+
+/// Delay before racing another address in [`Endpoint::connect_to()`], as recommended by RFC 8305
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Errors that prevent a connection from being moved to another endpoint
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum MoveConnectionError {
+    /// The connection isn't driven by the endpoint it was to be moved from, or has been closed
+    #[error("unknown connection")]
+    UnknownConnection,
+    /// The destination endpoint's driver has stopped
+    #[error("endpoint stopping")]
+    EndpointStopping,
+    /// The endpoints' sockets belong to different address families
+    #[error("incompatible address family")]
+    AddressFamily,
+    /// The destination endpoint can't route the connection's identifiers
+    #[error("{0}")]
+    Attach(#[from] proto::AttachError),
+    /// The connection can't be migrated
+    #[error("{0}")]
+    Migration(#[from] proto::MigrateError),
+}
+
+/// Queue a new incoming connection, consulting the admission control hook if there is one
+fn admit(
+    admission: Option<&AdmissionControl>,
+    incoming: &mut VecDeque<Connecting>,
+    pending: &mut Vec<PendingAdmission>,
+    incoming_readers: &mut Broadcast,
+    conn: Connecting,
+) {
+    let admission = match admission {
+        Some(x) => x,
+        None => {
+            incoming.push_back(conn);
+            return;
+        }
+    };
+    if pending.len() >= admission.max_pending {
+        debug!(
+            remote = %conn.remote_address(),
+            "refusing connection: too many awaiting admission"
+        );
+        // Dropping the only handle closes the connection
+        return;
+    }
+    let decision = (admission.hook)(conn.remote_address());
+    pending.push(PendingAdmission { conn, decision });
+    incoming_readers.wake();
+}
+
 fn ensure_ipv6(x: SocketAddr) -> SocketAddrV6 {
     match x {
         SocketAddr::V6(x) => x,
@@ -445,12 +952,31 @@ fn ensure_ipv6(x: SocketAddr) -> SocketAddrV6 {
 }
 
 /// Stream of incoming connections.
+///
+/// When an endpoint group was built with [`EndpointBuilder::with_sockets()`], connections from
+/// every endpoint in the group are yielded by a single `Incoming`.
+///
+/// Servers may instead accept connections with [`Endpoint::accept()`], in which case this stream
+/// can simply be dropped. Dropping it refuses new connections only if no `Endpoint` remains to
+/// accept them.
 #[derive(Debug)]
-pub struct Incoming(EndpointRef);
+pub struct Incoming {
+    endpoints: Vec<EndpointRef>,
+    states: Vec<broadcast::State>,
+    /// Index of the endpoint to poll first, rotated for fairness
+    next: usize,
+}
 
 impl Incoming {
-    pub(crate) fn new(inner: EndpointRef) -> Self {
-        Self(inner)
+    pub(crate) fn new(endpoints: Vec<EndpointRef>) -> Self {
+        Self {
+            states: endpoints
+                .iter()
+                .map(|_| broadcast::State::default())
+                .collect(),
+            endpoints,
+            next: 0,
+        }
     }
 }
 
@@ -459,25 +985,121 @@ impl futures_util::stream::Stream for Incoming {
 
     #[allow(unused_mut)] // MSRV
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let endpoint = &mut *self.0.lock().unwrap();
-        if endpoint.driver_lost {
-            Poll::Ready(None)
-        } else if let Some(conn) = endpoint.incoming.pop_front() {
-            Poll::Ready(Some(conn))
-        } else if endpoint.connections.close.is_some() {
-            Poll::Ready(None)
-        } else {
-            endpoint.incoming_reader = Some(cx.waker().clone());
+        let this = &mut *self;
+        let n = this.endpoints.len();
+        let mut live = false;
+        for i in 0..n {
+            let index = (this.next + i) % n;
+            match poll_incoming(&this.endpoints[index], cx, &mut this.states[index]) {
+                Poll::Ready(Some(conn)) => {
+                    this.next = (index + 1) % n;
+                    return Poll::Ready(Some(conn));
+                }
+                Poll::Ready(None) => {}
+                Poll::Pending => live = true,
+            }
+        }
+        if live {
             Poll::Pending
+        } else {
+            Poll::Ready(None)
+        }
+    }
+}
+
+fn poll_incoming(
+    endpoint: &EndpointRef,
+    cx: &mut Context,
+    state: &mut broadcast::State,
+) -> Poll<Option<Connecting>> {
+    // Decisions are polled without holding the endpoint lock, so hooks are free to use the
+    // `Endpoint`
+    let mut pending = mem::take(&mut endpoint.lock().unwrap().pending_admission);
+    let mut admitted = Vec::new();
+    let mut i = 0;
+    while i < pending.len() {
+        match pending[i].decision.as_mut().poll(cx) {
+            Poll::Ready(accept) => {
+                let PendingAdmission { conn, .. } = pending.swap_remove(i);
+                if accept {
+                    admitted.push(conn);
+                } else {
+                    debug!(remote = %conn.remote_address(), "connection denied admission");
+                }
+            }
+            Poll::Pending => i += 1,
+        }
+    }
+
+    let endpoint = &mut *endpoint.lock().unwrap();
+    endpoint.incoming.extend(admitted);
+    pending.append(&mut endpoint.pending_admission);
+    endpoint.pending_admission = pending;
+    if endpoint.driver_lost {
+        Poll::Ready(None)
+    } else if let Some(conn) = endpoint.incoming.pop_front() {
+        // Drained connections are forgotten by the endpoint, which frees their buffer space itself
+        if let Some(handle) = conn.live_handle() {
+            endpoint.inner.accept_connection(handle);
         }
+        Poll::Ready(Some(conn))
+    } else if endpoint.connections.close.is_some() {
+        Poll::Ready(None)
+    } else {
+        endpoint.incoming_readers.register(cx, state);
+        Poll::Pending
     }
 }
 
 impl Drop for Incoming {
     fn drop(&mut self) {
-        let endpoint = &mut *self.0.lock().unwrap();
-        endpoint.inner.reject_new_connections();
-        endpoint.incoming_reader = None;
+        for endpoint in &self.endpoints {
+            let endpoint = &mut *endpoint.lock().unwrap();
+            // Our own reference is the last one, so nobody is left to call `Endpoint::accept`
+            if endpoint.ref_count <= 1 {
+                endpoint.inner.reject_new_connections();
+            }
+        }
+    }
+}
+
+/// Future resolving to whether an incoming connection should be accepted
+pub(crate) type AdmissionFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+/// Asynchronous approval of incoming connections, configured through the [`EndpointBuilder`]
+pub(crate) struct AdmissionControl {
+    pub(crate) hook: Box<dyn Fn(SocketAddr) -> AdmissionFuture + Send + Sync>,
+    /// Maximum number of connections awaiting a decision before new ones are refused outright
+    pub(crate) max_pending: usize,
+}
+
+impl fmt::Debug for AdmissionControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AdmissionControl")
+            .field("max_pending", &self.max_pending)
+            .finish()
+    }
+}
+
+/// Observer of failed incoming handshakes, configured through the [`EndpointBuilder`]
+pub(crate) struct HandshakeFailureHook(pub(crate) Box<dyn Fn(HandshakeFailure) + Send + Sync>);
+
+impl fmt::Debug for HandshakeFailureHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HandshakeFailureHook").finish()
+    }
+}
+
+struct PendingAdmission {
+    conn: Connecting,
+    decision: AdmissionFuture,
+}
+
+impl fmt::Debug for PendingAdmission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PendingAdmission")
+            .field("conn", &self.conn)
+            .finish()
     }
 }
 
@@ -485,7 +1107,15 @@ impl Drop for Incoming {
 pub(crate) struct EndpointRef(Arc<Mutex<EndpointInner>>);
 
 impl EndpointRef {
-    pub(crate) fn new(socket: UdpSocket, inner: proto::Endpoint, ipv6: bool) -> Self {
+    pub(crate) fn new(
+        socket: Box<dyn AsyncUdpSocket>,
+        inner: proto::Endpoint,
+        ipv6: bool,
+        admission: Option<Arc<AdmissionControl>>,
+        handshake_failure_hook: Option<Arc<HandshakeFailureHook>>,
+        runtime: Arc<dyn Runtime>,
+        scheduler: Option<EgressScheduler>,
+    ) -> Self {
         let recv_buf =
             vec![0; inner.config().get_max_udp_payload_size().min(64 * 1024) as usize * BATCH_SIZE];
         let (sender, events) = mpsc::unbounded();
@@ -497,10 +1127,11 @@ impl EndpointRef {
             events,
             outgoing: VecDeque::new(),
             incoming: VecDeque::new(),
-            incoming_reader: None,
+            incoming_readers: Broadcast::new(),
             driver: None,
             connections: ConnectionSet {
                 senders: FxHashMap::default(),
+                forwards: FxHashMap::default(),
                 sender,
                 close: None,
             },
@@ -510,6 +1141,12 @@ impl EndpointRef {
             recv_limiter: WorkLimiter::new(RECV_TIME_BOUND),
             send_limiter: WorkLimiter::new(SEND_TIME_BOUND),
             idle: Broadcast::new(),
+            admission,
+            pending_admission: Vec::new(),
+            handshake_failure_hook,
+            runtime,
+            scheduler,
+            scheduler_timer: None,
         })))
     }
 }
@@ -523,15 +1160,22 @@ impl Clone for EndpointRef {
 
 impl Drop for EndpointRef {
     fn drop(&mut self) {
-        let endpoint = &mut *self.0.lock().unwrap();
+        let mut endpoint = self.0.lock().unwrap();
         if let Some(x) = endpoint.ref_count.checked_sub(1) {
             endpoint.ref_count = x;
             if x == 0 {
+                // Nothing can accept connections anymore, so refuse new ones and abandon any that
+                // were never accepted
+                endpoint.inner.reject_new_connections();
+                let unaccepted = mem::take(&mut endpoint.incoming);
                 // If the driver is about to be on its own, ensure it can shut down if the last
                 // connection is gone.
                 if let Some(task) = endpoint.driver.take() {
                     task.wake();
                 }
+                // Closing connections mustn't happen under the endpoint lock
+                drop(endpoint);
+                drop(unaccepted);
             }
         }
     }
@@ -52,32 +52,57 @@ let (endpoint, _) = builder.bind(&\"[::]:0\".parse().unwrap()).unwrap();
 
 use std::time::Duration;
 
+mod blocking;
 mod broadcast;
 mod builders;
 mod connection;
 mod endpoint;
 mod mutex;
+pub mod proxy;
 mod recv_stream;
+mod resolver;
+pub mod runtime;
+mod scheduler;
 mod send_stream;
+#[cfg(feature = "tower")]
+pub mod service;
 mod work_limiter;
 
+pub use proto::transport_parameters::TransportParameters;
 pub use proto::{
-    crypto, ApplicationClose, Certificate, CertificateChain, Chunk, ClientConfig, ConfigError,
-    ConnectError, ConnectionClose, ConnectionError, IdleTimeout, ParseError, PrivateKey,
-    ServerConfig, StreamId, Transmit, TransportConfig, VarInt,
+    crypto, AckFrequencyConfig, ApplicationClose, Certificate, CertificateChain, Chunk,
+    ClientConfig, ConfigError, ConnectError, ConnectionClose, ConnectionError, DatagramDropPolicy,
+    Dir, HandshakeFailure, HandshakeFailureReason, IdleTimeout, MigrateError, ParseError,
+    PathCache, PathParameters, PrivateKey, RecvStreamState, RecvStreamStats, RetryPolicy,
+    RetryTokenCodec, SchedulerKind, SendStreamState, SendStreamStats, ServerConfig, SessionCache,
+    Side, StreamId, StreamInfo, Transmit, TransportConfig, VarInt, ZeroRttPolicy,
 };
+#[cfg(feature = "qlog")]
+pub use proto::{QlogDir, QlogFactory};
+/// Runtime-independent UDP I/O, for implementing [`runtime::AsyncUdpSocket`]
+pub use udp;
 
-pub use crate::builders::{EndpointBuilder, EndpointError};
+#[cfg(feature = "rustls")]
+pub use proto::ClientConfigBuilder;
+
+pub use crate::blocking::{BlockingReader, BlockingWriter};
+pub use crate::builders::{EndpointBuilder, EndpointError, SourcePort};
 pub use crate::connection::{
-    Connecting, Connection, Datagrams, IncomingBiStreams, IncomingUniStreams, NewConnection,
-    OpenBi, OpenUni, SendDatagramError, ZeroRttAccepted,
+    AcceptBi, AcceptUni, Connecting, Connection, Datagrams, DatagramsDropped, DeliveryRateChanged,
+    IncomingBiStreams, IncomingUniStreams, NewConnection, OpenBi, OpenUni, ReadDatagram,
+    SendDatagramError, ZeroRttAccepted, ZeroRttRejected,
 };
-pub use crate::endpoint::{Endpoint, Incoming};
+pub use crate::endpoint::{ConnectToError, Endpoint, Incoming, MoveConnectionError};
+pub use crate::proxy::{copy, CopyError, CopyStreams};
 pub use crate::recv_stream::{
-    Read, ReadChunk, ReadChunks, ReadError, ReadExact, ReadExactError, ReadToEnd, ReadToEndError,
-    RecvStream,
+    CopyTo, CopyToError, Read, ReadBuf, ReadChunk, ReadChunks, ReadError, ReadExact,
+    ReadExactError, ReadToEnd, ReadToEndError, RecvStream,
+};
+pub use crate::resolver::{DefaultResolver, ResolveFuture, Resolver};
+pub use crate::send_stream::{
+    Acked, SendFrom, SendStream, StoppedError, WriteAllAcked, WriteAllBuf, WriteError,
+    WriteVectored,
 };
-pub use crate::send_stream::{SendStream, StoppedError, WriteError};
 
 #[cfg(test)]
 mod tests;
@@ -88,13 +113,24 @@ enum ConnectionEvent {
         error_code: VarInt,
         reason: bytes::Bytes,
     },
+    /// Close once no streams remain open, refusing new ones in the meantime
+    CloseWhenIdle {
+        error_code: VarInt,
+        reason: bytes::Bytes,
+    },
+    /// The endpoint switched to a different socket
+    LocalAddressChanged,
     Proto(proto::ConnectionEvent),
+    /// Received by an endpoint the connection has since moved away from
+    Forwarded(proto::ConnectionEvent),
 }
 
 #[derive(Debug)]
 enum EndpointEvent {
     Proto(proto::EndpointEvent),
     Transmit(proto::Transmit),
+    /// Set the connection's share of egress bandwidth under fair queueing
+    EgressWeight(u32),
 }
 
 /// Maximum number of datagrams processed in send/recv calls to make before moving on to other processing
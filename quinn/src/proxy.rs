@@ -0,0 +1,442 @@
+//! Helpers for relaying data between streams, and for carrying connections over other channels
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use bytes::Bytes;
+use futures_util::ready;
+use proto::Transmit;
+use thiserror::Error;
+use udp::{RecvMeta, UdpState};
+
+use crate::{
+    broadcast::{self, Broadcast},
+    mutex::Mutex,
+    runtime::AsyncUdpSocket,
+    ReadError, RecvStream, SendStream, VarInt, WriteError,
+};
+
+/// Relay all data received on `recv` to `send`
+///
+/// Data is forwarded chunk by chunk as it arrives, without being copied. Flow control applies
+/// end to end: at most a small, fixed number of chunks are held at a time, so a slow reader on
+/// the `send` side eventually blocks the peer writing to `recv`. The state of each stream is
+/// propagated to the other:
+///
+/// - When `recv` is finished, `send` is finished, and the future completes once the peer has
+///   acknowledged all data, yielding the number of bytes relayed.
+/// - When `recv` is reset by its peer, `send` is reset with the same error code.
+/// - When `send` is stopped by its peer, `recv` is stopped with the same error code.
+/// - If either connection is lost, the surviving stream is reset or stopped with error code 0.
+///
+/// To relay a bidirectional stream, splice each direction separately.
+///
+/// Not cancel-safe: dropping the future drops both streams, which finishes `send` even if not
+/// all data from `recv` has been relayed. Streams with 0-RTT data are relayed like any other, so
+/// the relayed data is subject to the same replay risks.
+pub fn splice(recv: RecvStream, send: SendStream) -> Splice {
+    Splice {
+        recv,
+        send,
+        relay: Relay::new(),
+        finishing: false,
+    }
+}
+
+/// Copy all data received on `recv` to `send`
+///
+/// Like [`splice()`], data is forwarded in batches of chunks as they arrive, without being
+/// copied, and `recv` is only read from once everything previously read has been accepted by
+/// `send`, so flow control applies end to end. Unlike [`splice()`], the streams are borrowed and
+/// left as they are: `send` is not finished once `recv` is, and neither stream is reset or
+/// stopped on failure, leaving that to the caller. Yields the number of bytes copied once `recv`
+/// is finished.
+///
+/// Not cancel-safe: if the future is dropped before completing, data may have been read from
+/// `recv` but not written to `send`.
+pub fn copy<'a>(recv: &'a mut RecvStream, send: &'a mut SendStream) -> CopyStreams<'a> {
+    CopyStreams {
+        recv,
+        send,
+        relay: Relay::new(),
+    }
+}
+
+/// Future produced by [`copy()`]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct CopyStreams<'a> {
+    recv: &'a mut RecvStream,
+    send: &'a mut SendStream,
+    relay: Relay,
+}
+
+impl Future for CopyStreams<'_> {
+    type Output = Result<u64, CopyError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.relay.poll(cx, this.recv, this.send)
+    }
+}
+
+/// Error from the [`CopyStreams`] future
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum CopyError {
+    /// Reading from the source stream failed
+    #[error("read error: {0}")]
+    Read(ReadError),
+    /// Writing to the destination stream failed
+    #[error("write error: {0}")]
+    Write(WriteError),
+}
+
+/// Forwarding state shared by [`Splice`] and [`CopyStreams`]
+struct Relay {
+    /// Chunks read from the source, of which those in `start..end` have yet to be written
+    bufs: Box<[Bytes]>,
+    start: usize,
+    end: usize,
+    relayed: u64,
+}
+
+impl Relay {
+    fn new() -> Self {
+        Self {
+            bufs: vec![Bytes::new(); RELAY_CHUNKS].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            relayed: 0,
+        }
+    }
+
+    /// Forward data until `recv` is finished, yielding the total number of bytes relayed
+    fn poll(
+        &mut self,
+        cx: &mut Context,
+        recv: &mut RecvStream,
+        send: &mut SendStream,
+    ) -> Poll<Result<u64, CopyError>> {
+        loop {
+            while self.start < self.end {
+                let bufs = &mut self.bufs[self.start..self.end];
+                let written = ready!(Pin::new(&mut send.write_chunks(bufs)).poll(cx))
+                    .map_err(CopyError::Write)?;
+                self.start += written.chunks;
+                self.relayed += written.bytes as u64;
+            }
+
+            match ready!(Pin::new(&mut recv.read_chunks(&mut self.bufs)).poll(cx))
+                .map_err(CopyError::Read)?
+            {
+                Some(n) => {
+                    self.start = 0;
+                    self.end = n;
+                }
+                None => return Poll::Ready(Ok(self.relayed)),
+            }
+        }
+    }
+}
+
+/// Future produced by [`splice()`]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct Splice {
+    recv: RecvStream,
+    send: SendStream,
+    relay: Relay,
+    finishing: bool,
+}
+
+impl Splice {
+    /// Propagate a write failure to `recv`
+    fn write_failed(&mut self, e: WriteError) -> SpliceError {
+        let error_code = match e {
+            WriteError::Stopped(error_code) => error_code,
+            _ => VarInt::from_u32(0),
+        };
+        // `recv` may already have been reset or finished, which is fine
+        let _ = self.recv.stop(error_code);
+        SpliceError::Write(e)
+    }
+
+    /// Propagate a read failure to `send`
+    fn read_failed(&mut self, e: ReadError) -> SpliceError {
+        let error_code = match e {
+            ReadError::Reset(error_code) => error_code,
+            _ => VarInt::from_u32(0),
+        };
+        // Resetting rather than letting `send` be finished on drop keeps the peer from mistaking
+        // a truncated stream for a complete one
+        let _ = self.send.reset(error_code);
+        SpliceError::Read(e)
+    }
+}
+
+impl Future for Splice {
+    type Output = Result<u64, SpliceError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if !this.finishing {
+            // Notice the `send` side being stopped even while there's nothing to write
+            if let Poll::Ready(Ok(error_code)) = this.send.poll_stopped(cx) {
+                return Poll::Ready(Err(this.write_failed(WriteError::Stopped(error_code))));
+            }
+
+            match ready!(this.relay.poll(cx, &mut this.recv, &mut this.send)) {
+                Ok(_) => this.finishing = true,
+                Err(CopyError::Read(e)) => return Poll::Ready(Err(this.read_failed(e))),
+                Err(CopyError::Write(e)) => return Poll::Ready(Err(this.write_failed(e))),
+            }
+        }
+
+        match ready!(this.send.poll_finish(cx)) {
+            Ok(()) => Poll::Ready(Ok(this.relay.relayed)),
+            Err(e) => Poll::Ready(Err(this.write_failed(e))),
+        }
+    }
+}
+
+/// Maximum number of chunks buffered by [`Splice`] and [`CopyStreams`] at a time
+const RELAY_CHUNKS: usize = 32;
+
+/// Error from the [`Splice`] future
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum SpliceError {
+    /// Reading from the source stream failed
+    ///
+    /// The destination stream has been reset.
+    #[error("read error: {0}")]
+    Read(ReadError),
+    /// Writing to the destination stream failed
+    ///
+    /// The source stream has been stopped.
+    #[error("write error: {0}")]
+    Write(WriteError),
+}
+
+/// Carries the UDP payloads of an endpoint through a channel provided by the application
+///
+/// Produced by [`EndpointBuilder::with_packet_relay()`](crate::EndpointBuilder::with_packet_relay),
+/// which builds an endpoint without a UDP socket of its own. Every datagram the endpoint would
+/// send is instead [`extract`](Self::extract)ed by the application, which forwards it however it
+/// sees fit, e.g. as a CONNECT-UDP (RFC 9298) datagram or over a stream or datagrams of another
+/// QUIC connection, and datagrams arriving from the other side are handed to the endpoint with
+/// [`inject()`](Self::inject). This allows QUIC connections to be tunneled through proxies,
+/// including through other QUIC connections.
+///
+/// Like a UDP socket, the relay buffers a bounded number of datagrams in each direction and
+/// drops injected datagrams when the endpoint falls behind. The relay should preserve the
+/// boundaries of datagrams, and the path through it must carry payloads of the endpoint's
+/// `max_udp_payload_size`, or of at least 1200 bytes if the relay can't carry larger ones and
+/// the endpoint's configuration is adjusted to match.
+#[derive(Clone)]
+pub struct PacketRelay {
+    shared: Arc<Mutex<RelayState>>,
+}
+
+impl PacketRelay {
+    /// Create a relay, and the socket through which an endpoint uses it
+    pub(crate) fn new(local_addr: SocketAddr) -> (Self, Box<dyn AsyncUdpSocket>) {
+        let shared = Arc::new(Mutex::new(RelayState {
+            incoming: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            recv_waker: None,
+            send_waker: None,
+            extracting: Broadcast::new(),
+            socket_dropped: false,
+        }));
+        let socket = RelaySocket {
+            shared: shared.clone(),
+            local_addr,
+        };
+        (Self { shared }, Box::new(socket))
+    }
+
+    /// Deliver `payload` to the endpoint as a UDP datagram received from `remote`
+    ///
+    /// The datagram is dropped if the endpoint has too many datagrams waiting to be received, or
+    /// has been dropped.
+    pub fn inject(&self, remote: SocketAddr, payload: Bytes) {
+        let mut state = self.shared.lock("PacketRelay::inject");
+        if state.socket_dropped || state.incoming.len() >= RELAY_QUEUE_LEN {
+            return;
+        }
+        state.incoming.push_back(RelayedPacket { remote, payload });
+        if let Some(waker) = state.recv_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Take the next UDP datagram sent by the endpoint
+    ///
+    /// Yields `None` once the endpoint has been dropped.
+    pub fn extract(&self) -> ExtractPacket {
+        ExtractPacket {
+            shared: self.shared.clone(),
+            state: broadcast::State::default(),
+        }
+    }
+}
+
+impl fmt::Debug for PacketRelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketRelay").finish()
+    }
+}
+
+/// A UDP datagram passing through a [`PacketRelay`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayedPacket {
+    /// The peer the datagram is sent to, or was received from
+    pub remote: SocketAddr,
+    /// The UDP payload, i.e. one or more coalesced QUIC packets
+    pub payload: Bytes,
+}
+
+/// Future produced by [`PacketRelay::extract()`]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct ExtractPacket {
+    shared: Arc<Mutex<RelayState>>,
+    state: broadcast::State,
+}
+
+impl Future for ExtractPacket {
+    type Output = Option<RelayedPacket>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.shared.lock("ExtractPacket::poll");
+        if let Some(packet) = state.outgoing.pop_front() {
+            if let Some(waker) = state.send_waker.take() {
+                waker.wake();
+            }
+            return Poll::Ready(Some(packet));
+        }
+        if state.socket_dropped {
+            return Poll::Ready(None);
+        }
+        state.extracting.register(cx, &mut this.state);
+        Poll::Pending
+    }
+}
+
+/// State shared between a [`PacketRelay`] and its [`RelaySocket`]
+struct RelayState {
+    /// Datagrams injected by the application, yet to be received by the endpoint
+    incoming: VecDeque<RelayedPacket>,
+    /// Datagrams sent by the endpoint, yet to be extracted by the application
+    outgoing: VecDeque<RelayedPacket>,
+    /// Endpoint waiting for `incoming` to be non-empty
+    recv_waker: Option<Waker>,
+    /// Endpoint waiting for room in `outgoing`
+    send_waker: Option<Waker>,
+    /// Tasks waiting for `outgoing` to be non-empty
+    extracting: Broadcast,
+    socket_dropped: bool,
+}
+
+/// Stands in for the UDP socket of an endpoint built around a [`PacketRelay`]
+struct RelaySocket {
+    shared: Arc<Mutex<RelayState>>,
+    local_addr: SocketAddr,
+}
+
+impl AsyncUdpSocket for RelaySocket {
+    fn poll_send(
+        &mut self,
+        _state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.lock("RelaySocket::poll_send");
+        let mut sent = 0;
+        for transmit in transmits {
+            if state.outgoing.len() >= RELAY_QUEUE_LEN {
+                break;
+            }
+            // Split segmentation offload batches back into their datagrams
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            let contents = Bytes::copy_from_slice(&transmit.contents);
+            for start in (0..contents.len()).step_by(segment_size.max(1)) {
+                let end = (start + segment_size).min(contents.len());
+                state.outgoing.push_back(RelayedPacket {
+                    remote: transmit.destination,
+                    payload: contents.slice(start..end),
+                });
+            }
+            sent += 1;
+        }
+        if sent > 0 {
+            state.extracting.wake();
+            return Poll::Ready(Ok(sent));
+        }
+        state.send_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.lock("RelaySocket::poll_recv");
+        let mut received = 0;
+        while received < bufs.len().min(meta.len()) {
+            let packet = match state.incoming.pop_front() {
+                Some(x) => x,
+                None => break,
+            };
+            let buf = &mut bufs[received];
+            if packet.payload.len() > buf.len() {
+                // Larger than any datagram the endpoint accepts, just like it would be truncated
+                // by a real socket
+                continue;
+            }
+            buf[..packet.payload.len()].copy_from_slice(&packet.payload);
+            meta[received] = RecvMeta {
+                addr: packet.remote,
+                len: packet.payload.len(),
+                ecn: None,
+                dst_ip: None,
+            };
+            received += 1;
+        }
+        if received > 0 {
+            return Poll::Ready(Ok(received));
+        }
+        state.recv_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for RelaySocket {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock("RelaySocket::drop");
+        state.socket_dropped = true;
+        state.incoming.clear();
+        state.extracting.wake();
+    }
+}
+
+impl fmt::Debug for RelaySocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelaySocket")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+/// Maximum number of datagrams buffered by a [`PacketRelay`] in each direction
+const RELAY_QUEUE_LEN: usize = 256;
@@ -1,20 +1,26 @@
 use std::{
+    collections::VecDeque,
     future::Future,
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use bytes::Bytes;
+use bytes::{Buf, BufMut, Bytes};
 use futures_util::{io::AsyncRead, ready};
-use proto::{Chunk, Chunks, ConnectionError, ReadableError, StreamId};
+use proto::{Chunk, Chunks, ConnectionError, ReadableError, RecvStreamStats, StreamId};
 use thiserror::Error;
-use tokio::io::ReadBuf;
+use tokio::io::AsyncWrite;
 
-use crate::{connection::ConnectionRef, VarInt};
+use crate::{blocking::BlockingReader, connection::ConnectionRef, runtime::AsyncTimer, VarInt};
 
 /// A stream that can only be used to receive data
 ///
+/// Unless noted otherwise, the futures returned by its methods are cancel-safe: dropping one
+/// before it completes, e.g. because another branch of a `select!` completed first, never loses
+/// data. Data is only consumed from the stream when it is yielded to the caller.
+///
 /// `stop(0)` is implicitly called on drop unless:
 /// - A variant of [`ReadError`] has been yielded by a read call
 /// - [`stop()`] was called explicitly
@@ -28,6 +34,13 @@ pub struct RecvStream {
     is_0rtt: bool,
     all_data_read: bool,
     reset: Option<VarInt>,
+    /// Error code of a reset that has been yielded, or is waiting to be, by a read
+    reset_code: Option<VarInt>,
+    /// Data consumed from the connection by an incomplete `read_exact()`, to be yielded by the
+    /// next read
+    unread: VecDeque<Chunk>,
+    /// Total length of `unread`
+    unread_len: usize,
 }
 
 impl RecvStream {
@@ -38,40 +51,88 @@ impl RecvStream {
             is_0rtt,
             all_data_read: false,
             reset: None,
+            reset_code: None,
+            unread: VecDeque::new(),
+            unread_len: 0,
         }
     }
 
     /// Read data contiguously from the stream.
     ///
     /// Yields the number of bytes read into `buf` on success, or `None` if the stream was finished.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was read.
     pub fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Read<'a> {
         Read {
             stream: self,
-            buf: ReadBuf::new(buf),
+            buf: tokio::io::ReadBuf::new(buf),
         }
     }
 
     /// Read an exact number of bytes contiguously from the stream.
     ///
-    /// See [`read()`] for details.
+    /// Fails with [`ReadExactError::FinishedEarly`] if the stream finishes before `buf` could be
+    /// filled. See [`read()`] for details.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, `buf` is left untouched, and any
+    /// data already received is yielded by the next read from the stream.
     ///
     /// [`read()`]: RecvStream::read
     pub fn read_exact<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadExact<'a> {
-        ReadExact {
-            stream: self,
-            buf: ReadBuf::new(buf),
-        }
+        ReadExact { stream: self, buf }
     }
 
-    fn poll_read(
+    /// Read data contiguously from the stream into a [`BufMut`]
+    ///
+    /// Reads up to `buf.remaining_mut()` bytes, advancing `buf` past them. Yields the number of
+    /// bytes read on success, or `None` if the stream was finished.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was read.
+    pub fn read_buf<'a, B: BufMut + ?Sized>(&'a mut self, buf: &'a mut B) -> ReadBuf<'a, B> {
+        ReadBuf { stream: self, buf }
+    }
+
+    /// Take up to `max_length` bytes of data left over from an incomplete `read_exact()`
+    fn take_unread(&mut self, max_length: usize) -> Option<Chunk> {
+        let front = self.unread.front_mut()?;
+        let chunk = if front.bytes.len() > max_length {
+            let chunk = Chunk {
+                offset: front.offset,
+                bytes: front.bytes.split_to(max_length),
+            };
+            front.offset += max_length as u64;
+            chunk
+        } else {
+            self.unread.pop_front().unwrap()
+        };
+        self.unread_len -= chunk.bytes.len();
+        Some(chunk)
+    }
+
+    /// Foundation of [`read()`](Self::read), for implementing futures and protocol layers by hand
+    ///
+    /// Fills as much of `buf` as possible with data immediately following the data read so far.
+    /// Leaves `buf` unchanged if the stream was finished. Cancel-safe in the same way as `read()`:
+    /// data is only consumed when it is placed in `buf`.
+    pub fn poll_read(
         &mut self,
         cx: &mut Context,
-        buf: &mut ReadBuf<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<Result<(), ReadError>> {
         if buf.remaining() == 0 {
             return Poll::Ready(Ok(()));
         }
 
+        if !self.unread.is_empty() {
+            while buf.remaining() > 0 {
+                match self.take_unread(buf.remaining()) {
+                    Some(chunk) => buf.put_slice(&chunk.bytes),
+                    None => break,
+                }
+            }
+            return Poll::Ready(Ok(()));
+        }
+
         self.poll_read_generic(cx, true, |chunks| {
             let mut read = false;
             loop {
@@ -103,6 +164,8 @@ impl RecvStream {
     ///
     /// Slightly more efficient than `read` due to not copying. Chunk boundaries do not correspond
     /// to peer writes, and hence cannot be used as framing.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was read.
     pub fn read_chunk(&mut self, max_length: usize, ordered: bool) -> ReadChunk<'_> {
         ReadChunk {
             stream: self,
@@ -111,13 +174,33 @@ impl RecvStream {
         }
     }
 
-    /// Foundation of [`read_chunk()`]: RecvStream::read_chunk
-    fn poll_read_chunk(
+    /// Read the next segment of data as soon as it arrives, regardless of gaps before it
+    ///
+    /// Shorthand for [`read_chunk(max_length, false)`](Self::read_chunk). Each [`Chunk`] carries
+    /// its offset in the stream, so the application can reassemble the data itself, e.g. by
+    /// writing each chunk at the corresponding position of a file. Data is handed over as soon as
+    /// it's received rather than being held until any preceding gaps are filled, so less of the
+    /// stream is buffered by the connection. Once data has been read out of order, ordered reads
+    /// from the same stream are no longer possible.
+    ///
+    /// Yields `None` once the stream has finished and all data has been read.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was read.
+    pub fn read_chunk_unordered(&mut self, max_length: usize) -> ReadChunk<'_> {
+        self.read_chunk(max_length, false)
+    }
+
+    /// Foundation of [`read_chunk()`](Self::read_chunk), for implementing futures and protocol
+    /// layers by hand
+    pub fn poll_read_chunk(
         &mut self,
         cx: &mut Context,
         max_length: usize,
         ordered: bool,
     ) -> Poll<Result<Option<Chunk>, ReadError>> {
+        if let Some(chunk) = self.take_unread(max_length) {
+            return Poll::Ready(Ok(Some(chunk)));
+        }
         self.poll_read_generic(cx, ordered, |chunks| match chunks.next(max_length) {
             Ok(Some(chunk)) => ReadStatus::Readable(chunk),
             res => (None, res.err()).into(),
@@ -132,12 +215,15 @@ impl RecvStream {
     ///
     /// Slightly more efficient than `read` due to not copying. Chunk boundaries
     /// do not correspond to peer writes, and hence cannot be used as framing.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was read.
     pub fn read_chunks<'a>(&'a mut self, bufs: &'a mut [Bytes]) -> ReadChunks<'a> {
         ReadChunks { stream: self, bufs }
     }
 
-    /// Foundation of [`read_chunks()`]: RecvStream::read_chunks
-    fn poll_read_chunks(
+    /// Foundation of [`read_chunks()`](Self::read_chunks), for implementing futures and protocol
+    /// layers by hand
+    pub fn poll_read_chunks(
         &mut self,
         cx: &mut Context,
         bufs: &mut [Bytes],
@@ -146,6 +232,18 @@ impl RecvStream {
             return Poll::Ready(Ok(Some(0)));
         }
 
+        if !self.unread.is_empty() {
+            let mut read = 0;
+            while read < bufs.len() {
+                match self.take_unread(usize::MAX) {
+                    Some(chunk) => bufs[read] = chunk.bytes,
+                    None => break,
+                }
+                read += 1;
+            }
+            return Poll::Ready(Ok(Some(read)));
+        }
+
         self.poll_read_generic(cx, true, |chunks| {
             let mut read = 0;
             loop {
@@ -165,6 +263,85 @@ impl RecvStream {
         })
     }
 
+    /// Foundation of [`read_buf()`]: RecvStream::read_buf
+    fn poll_read_buf<B: BufMut + ?Sized>(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut B,
+    ) -> Poll<Result<Option<usize>, ReadError>> {
+        if !buf.has_remaining_mut() {
+            return Poll::Ready(Ok(Some(0)));
+        }
+
+        if !self.unread.is_empty() {
+            let mut read = 0;
+            while buf.has_remaining_mut() {
+                match self.take_unread(buf.remaining_mut()) {
+                    Some(chunk) => {
+                        read += chunk.bytes.len();
+                        buf.put_slice(&chunk.bytes);
+                    }
+                    None => break,
+                }
+            }
+            return Poll::Ready(Ok(Some(read)));
+        }
+
+        self.poll_read_generic(cx, true, |chunks| {
+            let mut read = 0;
+            loop {
+                if !buf.has_remaining_mut() {
+                    // We know `read > 0` because `buf` had space before
+                    return ReadStatus::Readable(read);
+                }
+
+                match chunks.next(buf.remaining_mut()) {
+                    Ok(Some(chunk)) => {
+                        read += chunk.bytes.len();
+                        buf.put_slice(&chunk.bytes);
+                    }
+                    res => return (if read == 0 { None } else { Some(read) }, res.err()).into(),
+                }
+            }
+        })
+    }
+
+    /// Foundation of [`read_exact()`]: RecvStream::read_exact
+    ///
+    /// Data is set aside in `unread` until `buf` can be filled in one go, so that nothing is lost
+    /// if the caller gives up.
+    fn poll_read_exact(
+        &mut self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<(), ReadExactError>> {
+        while self.unread_len < buf.len() {
+            let wanted = buf.len() - self.unread_len;
+            let chunk =
+                ready!(
+                    self.poll_read_generic(cx, true, |chunks| match chunks.next(wanted) {
+                        Ok(Some(chunk)) => ReadStatus::Readable(chunk),
+                        res => (None, res.err()).into(),
+                    })
+                )?;
+            match chunk {
+                Some(chunk) => {
+                    self.unread_len += chunk.bytes.len();
+                    self.unread.push_back(chunk);
+                }
+                None => return Poll::Ready(Err(ReadExactError::FinishedEarly)),
+            }
+        }
+
+        let mut filled = 0;
+        while filled < buf.len() {
+            let chunk = self.take_unread(buf.len() - filled).unwrap();
+            buf[filled..filled + chunk.bytes.len()].copy_from_slice(&chunk.bytes);
+            filled += chunk.bytes.len();
+        }
+        Poll::Ready(Ok(()))
+    }
+
     /// Convenience method to read all remaining data into a buffer
     ///
     /// The returned future fails with [`ReadToEndError::TooLong`] if it's longer than `size_limit`
@@ -174,6 +351,12 @@ impl RecvStream {
     /// If unordered reads have already been made, the resulting buffer may have gaps containing
     /// arbitrary data.
     ///
+    /// Not cancel-safe: the stream and any data read so far are lost if the future is dropped.
+    ///
+    /// A peer that stops sending would leave the future pending indefinitely; see
+    /// [`ReadToEnd::deadline()`], [`ReadToEnd::inactivity_timeout()`] and
+    /// [`ReadToEnd::on_progress()`] for ways to bound that.
+    ///
     /// [`ReadToEndError::TooLong`]: crate::ReadToEndError::TooLong
     pub fn read_to_end(self, size_limit: usize) -> ReadToEnd {
         ReadToEnd {
@@ -182,9 +365,47 @@ impl RecvStream {
             read: Vec::new(),
             start: u64::max_value(),
             end: 0,
+            received: 0,
+            deadline: None,
+            inactivity_timeout: None,
+            last_activity: None,
+            on_progress: None,
+            timer: None,
+        }
+    }
+
+    /// Copy all remaining data into `writer`
+    ///
+    /// Yields the number of bytes copied once the stream has finished and `writer` has been
+    /// flushed. Fails with [`CopyToError::TooLong`] if the stream holds more than `size_limit`
+    /// bytes. Unlike [`read_to_end()`](Self::read_to_end), at most one chunk of data is buffered at
+    /// a time, so memory use is independent of the size of the stream.
+    ///
+    /// Not cancel-safe: if the future is dropped before completing, data may have been read from
+    /// the stream but not written to `writer`.
+    ///
+    /// [`CopyToError::TooLong`]: crate::CopyToError::TooLong
+    pub fn copy_to<'a, W>(&'a mut self, writer: &'a mut W, size_limit: u64) -> CopyTo<'a, W>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        CopyTo {
+            stream: self,
+            writer,
+            size_limit,
+            copied: 0,
+            pending: Bytes::new(),
         }
     }
 
+    /// Convert into a blocking [`std::io::Read`] implementation
+    ///
+    /// Intended for synchronous code running outside the async runtime that drives the
+    /// connection; see [`BlockingReader`](crate::BlockingReader) for details.
+    pub fn into_blocking_reader(self) -> BlockingReader {
+        BlockingReader::new(self)
+    }
+
     /// Stop accepting data
     ///
     /// Discards unread data and notifies the peer to stop transmitting. Once stopped, further
@@ -197,6 +418,8 @@ impl RecvStream {
         conn.inner.recv_stream(self.stream).stop(error_code)?;
         conn.wake();
         self.all_data_read = true;
+        self.unread.clear();
+        self.unread_len = 0;
         Ok(())
     }
 
@@ -209,10 +432,70 @@ impl RecvStream {
     }
 
     /// Get the identity of this stream
+    ///
+    /// The numeric QUIC stream ID is available via `u64::from`, alongside the stream's
+    /// [`initiator()`](StreamId::initiator) and [`dir()`](StreamId::dir).
     pub fn id(&self) -> StreamId {
         self.stream
     }
 
+    /// Set the amount of flow control credit to maintain ahead of the data read from this stream
+    ///
+    /// Overrides [`TransportConfig::stream_receive_window()`] for this stream, e.g. to let a bulk
+    /// transfer use a larger window than control streams on the same connection. The stream is
+    /// no longer subject to auto-tuning. An increase is advertised to the peer promptly, while a
+    /// decrease takes effect as data is read.
+    ///
+    /// [`TransportConfig::stream_receive_window()`]: crate::TransportConfig::stream_receive_window
+    pub fn set_receive_window(&self, window: VarInt) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("RecvStream::set_receive_window");
+        conn.inner
+            .recv_stream(self.stream)
+            .set_receive_window(window)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Stop issuing flow control credit for data beyond offset `max_data`
+    ///
+    /// Suited to request/response protocols where the length of a message becomes known partway
+    /// through the stream: limiting credit to the end of the message keeps a malicious peer from
+    /// making the connection buffer data that will never be read. Credit already issued can't be
+    /// revoked, so this only limits future credit.
+    pub fn set_max_data(&self, max_data: VarInt) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("RecvStream::set_max_data");
+        conn.inner.recv_stream(self.stream).set_max_data(max_data)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// The application error code the peer reset the stream with, if it has done so
+    ///
+    /// Available as soon as the reset arrives, even before reads have consumed preceding data, and
+    /// afterwards matches the code carried by [`ReadError::Reset`]. The peer's response to
+    /// [`stop()`](Self::stop) is not reported here.
+    pub fn reset_code(&self) -> Option<VarInt> {
+        if self.reset_code.is_some() {
+            return self.reset_code;
+        }
+        let mut conn = self.conn.lock("RecvStream::reset_code");
+        conn.inner
+            .recv_stream(self.stream)
+            .reset_code()
+            .ok()
+            .flatten()
+    }
+
+    /// Statistics about the data received on this stream so far
+    ///
+    /// Data buffered by an incomplete [`read_exact()`](Self::read_exact) is not counted as read.
+    pub fn stats(&self) -> Result<RecvStreamStats, UnknownStream> {
+        let mut conn = self.conn.lock("RecvStream::stats");
+        let mut stats = conn.inner.recv_stream(self.stream).stats()?;
+        stats.bytes_read -= self.unread_len as u64;
+        Ok(stats)
+    }
+
     /// Handle common logic related to reading out of a receive stream
     ///
     /// This takes an `FnMut` closure that takes care of the actual reading process, matching
@@ -269,16 +552,19 @@ impl RecvStream {
                     Poll::Pending
                 }
             },
-            ReadStatus::Failed(read, Reset(error_code)) => match read {
-                None => {
-                    self.all_data_read = true;
-                    Poll::Ready(Err(ReadError::Reset(error_code)))
-                }
-                done => {
-                    self.reset = Some(error_code);
-                    Poll::Ready(Ok(done))
+            ReadStatus::Failed(read, Reset(error_code)) => {
+                self.reset_code = Some(error_code);
+                match read {
+                    None => {
+                        self.all_data_read = true;
+                        Poll::Ready(Err(ReadError::Reset(error_code)))
+                    }
+                    done => {
+                        self.reset = Some(error_code);
+                        Poll::Ready(Ok(done))
+                    }
                 }
-            },
+            }
         }
     }
 }
@@ -308,13 +594,88 @@ pub struct ReadToEnd {
     start: u64,
     end: u64,
     size_limit: usize,
+    /// Number of bytes received so far
+    received: u64,
+    deadline: Option<Instant>,
+    inactivity_timeout: Option<Duration>,
+    /// When data was last received, or the future first polled
+    last_activity: Option<Instant>,
+    on_progress: Option<Box<dyn FnMut(u64) -> bool + Send>>,
+    timer: Option<Pin<Box<dyn AsyncTimer>>>,
+}
+
+impl ReadToEnd {
+    /// Fail with [`ReadToEndError::TimedOut`] if the stream hasn't been read in full by `deadline`
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fail with [`ReadToEndError::TimedOut`] if no data is received for `timeout`
+    pub fn inactivity_timeout(mut self, timeout: Duration) -> Self {
+        self.inactivity_timeout = Some(timeout);
+        self
+    }
+
+    /// Call `f` with the total number of bytes received so far whenever more data arrives
+    ///
+    /// If `f` returns `false`, the future fails with [`ReadToEndError::Aborted`]. Combined with an
+    /// [inactivity timeout](Self::inactivity_timeout), this allows enforcing a minimum transfer
+    /// rate, so peers can't tie up resources by trickling data.
+    pub fn on_progress<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(u64) -> bool + Send + 'static,
+    {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    /// When the future times out, if ever
+    fn timeout(&self) -> Option<Instant> {
+        let inactive = self
+            .inactivity_timeout
+            .and_then(|x| Some(self.last_activity? + x));
+        match (self.deadline, inactive) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Check whether the future has timed out, registering for a wakeup if it hasn't
+    fn poll_timeout(&mut self, cx: &mut Context) -> Poll<()> {
+        let timeout = match self.timeout() {
+            Some(x) => x,
+            None => return Poll::Pending,
+        };
+        if timeout <= Instant::now() {
+            return Poll::Ready(());
+        }
+        match self.timer {
+            Some(ref mut timer) => timer.as_mut().reset(timeout),
+            None => {
+                let conn = self.stream.conn.lock("ReadToEnd::poll_timeout");
+                self.timer = Some(conn.runtime.new_timer(timeout));
+            }
+        }
+        self.timer.as_mut().unwrap().as_mut().poll(cx)
+    }
 }
 
 impl Future for ReadToEnd {
     type Output = Result<Vec<u8>, ReadToEndError>;
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.last_activity.is_none() && self.inactivity_timeout.is_some() {
+            self.last_activity = Some(Instant::now());
+        }
         loop {
-            match ready!(self.stream.poll_read_chunk(cx, usize::MAX, false))? {
+            let chunk = match self.stream.poll_read_chunk(cx, usize::MAX, false)? {
+                Poll::Ready(x) => x,
+                Poll::Pending => {
+                    ready!(self.poll_timeout(cx));
+                    return Poll::Ready(Err(ReadToEndError::TimedOut));
+                }
+            };
+            match chunk {
                 Some(chunk) => {
                     self.start = self.start.min(chunk.offset);
                     let end = chunk.bytes.len() as u64 + chunk.offset;
@@ -322,7 +683,17 @@ impl Future for ReadToEnd {
                         return Poll::Ready(Err(ReadToEndError::TooLong));
                     }
                     self.end = self.end.max(end);
+                    self.received += chunk.bytes.len() as u64;
                     self.read.push((chunk.bytes, chunk.offset));
+                    if self.inactivity_timeout.is_some() {
+                        self.last_activity = Some(Instant::now());
+                    }
+                    let received = self.received;
+                    if let Some(ref mut f) = self.on_progress {
+                        if !f(received) {
+                            return Poll::Ready(Err(ReadToEndError::Aborted));
+                        }
+                    }
                 }
                 None => {
                     if self.end == 0 {
@@ -353,6 +724,83 @@ pub enum ReadToEndError {
     /// The stream is larger than the user-supplied limit
     #[error("stream too long")]
     TooLong,
+    /// The deadline or inactivity timeout elapsed before the stream was read in full
+    #[error("timed out")]
+    TimedOut,
+    /// The progress callback requested that reading be aborted
+    #[error("aborted")]
+    Aborted,
+}
+
+/// Future produced by [`RecvStream::copy_to()`].
+///
+/// [`RecvStream::copy_to()`]: crate::RecvStream::copy_to
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct CopyTo<'a, W: ?Sized> {
+    stream: &'a mut RecvStream,
+    writer: &'a mut W,
+    size_limit: u64,
+    copied: u64,
+    /// Data read from the stream but not yet written
+    pending: Bytes,
+}
+
+impl<'a, W> Future for CopyTo<'a, W>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    type Output = Result<u64, CopyToError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            while !this.pending.is_empty() {
+                let n = ready!(Pin::new(&mut *this.writer).poll_write(cx, &this.pending))
+                    .map_err(CopyToError::Write)?;
+                if n == 0 {
+                    return Poll::Ready(Err(CopyToError::Write(io::ErrorKind::WriteZero.into())));
+                }
+                this.pending.advance(n);
+            }
+
+            // Read one byte past the limit, if possible, to detect overlong streams
+            let max_length = (this.size_limit - this.copied)
+                .saturating_add(1)
+                .min(COPY_CHUNK_SIZE as u64) as usize;
+            match ready!(this.stream.poll_read_chunk(cx, max_length, true))? {
+                Some(chunk) => {
+                    this.copied += chunk.bytes.len() as u64;
+                    if this.copied > this.size_limit {
+                        return Poll::Ready(Err(CopyToError::TooLong));
+                    }
+                    this.pending = chunk.bytes;
+                }
+                None => {
+                    ready!(Pin::new(&mut *this.writer).poll_flush(cx))
+                        .map_err(CopyToError::Write)?;
+                    return Poll::Ready(Ok(this.copied));
+                }
+            }
+        }
+    }
+}
+
+/// Maximum amount of data buffered by [`CopyTo`] at a time
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Error from the [`CopyTo`] future.
+///
+/// [`CopyTo`]: crate::CopyTo
+#[derive(Debug, Error)]
+pub enum CopyToError {
+    /// An error occurred during reading
+    #[error("read error: {0}")]
+    Read(#[from] ReadError),
+    /// An error occurred during writing
+    #[error("write error: {0}")]
+    Write(io::Error),
+    /// The stream is larger than the user-supplied limit
+    #[error("stream too long")]
+    TooLong,
 }
 
 impl AsyncRead for RecvStream {
@@ -361,7 +809,7 @@ impl AsyncRead for RecvStream {
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        let mut buf = ReadBuf::new(buf);
+        let mut buf = tokio::io::ReadBuf::new(buf);
         ready!(RecvStream::poll_read(self.get_mut(), cx, &mut buf))?;
         Poll::Ready(Ok(buf.filled().len()))
     }
@@ -371,7 +819,7 @@ impl tokio::io::AsyncRead for RecvStream {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         ready!(RecvStream::poll_read(self.get_mut(), cx, buf))?;
         Poll::Ready(Ok(()))
@@ -449,7 +897,7 @@ impl From<ReadError> for io::Error {
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 pub struct Read<'a> {
     stream: &'a mut RecvStream,
-    buf: ReadBuf<'a>,
+    buf: tokio::io::ReadBuf<'a>,
 }
 
 impl<'a> Future for Read<'a> {
@@ -471,23 +919,14 @@ impl<'a> Future for Read<'a> {
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 pub struct ReadExact<'a> {
     stream: &'a mut RecvStream,
-    buf: ReadBuf<'a>,
+    buf: &'a mut [u8],
 }
 
 impl<'a> Future for ReadExact<'a> {
     type Output = Result<(), ReadExactError>;
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let this = self.get_mut();
-        let mut remaining = this.buf.remaining();
-        while remaining > 0 {
-            ready!(this.stream.poll_read(cx, &mut this.buf))?;
-            let new = this.buf.remaining();
-            if new == remaining {
-                return Poll::Ready(Err(ReadExactError::FinishedEarly));
-            }
-            remaining = new;
-        }
-        Poll::Ready(Ok(()))
+        this.stream.poll_read_exact(cx, this.buf)
     }
 }
 
@@ -502,6 +941,23 @@ pub enum ReadExactError {
     ReadError(#[from] ReadError),
 }
 
+/// Future produced by [`RecvStream::read_buf()`].
+///
+/// [`RecvStream::read_buf()`]: crate::RecvStream::read_buf
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct ReadBuf<'a, B: ?Sized> {
+    stream: &'a mut RecvStream,
+    buf: &'a mut B,
+}
+
+impl<'a, B: BufMut + ?Sized> Future for ReadBuf<'a, B> {
+    type Output = Result<Option<usize>, ReadError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.stream.poll_read_buf(cx, this.buf)
+    }
+}
+
 /// Future produced by [`RecvStream::read_chunk()`].
 ///
 /// [`RecvStream::read_chunk()`]: crate::RecvStream::read_chunk
@@ -0,0 +1,61 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    pin::Pin,
+};
+
+use futures_channel::oneshot;
+
+/// Future resolving to the addresses of a host
+pub type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Resolves host names for [`Endpoint::connect_to()`](crate::Endpoint::connect_to)
+///
+/// Configured with [`EndpointBuilder::resolver()`](crate::EndpointBuilder::resolver), e.g. to use
+/// an asynchronous DNS client or a fixed mapping in tests.
+pub trait Resolver: Send + Sync + Debug + 'static {
+    /// Look up the addresses of `host`, combined with `port`
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture;
+}
+
+/// Resolves host names using the operating system's resolver
+///
+/// Uses [`tokio::net::lookup_host`] from within a tokio runtime, and a background thread
+/// otherwise.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultResolver;
+
+impl Resolver for DefaultResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        let host = host.to_owned();
+
+        #[cfg(feature = "runtime-tokio")]
+        {
+            if tokio::runtime::Handle::try_current().is_ok() {
+                return Box::pin(async move {
+                    Ok(tokio::net::lookup_host((&host[..], port)).await?.collect())
+                });
+            }
+        }
+
+        // The system resolver blocks, so keep it off the async runtime's threads
+        let (send, recv) = oneshot::channel();
+        let spawned = std::thread::Builder::new()
+            .name("quinn-resolver".into())
+            .spawn(move || {
+                let _ = send.send(
+                    (&host[..], port)
+                        .to_socket_addrs()
+                        .map(|addrs| addrs.collect()),
+                );
+            });
+        Box::pin(async move {
+            spawned?;
+            // The resolver thread only drops the sender without sending if it panicked
+            recv.await
+                .unwrap_or_else(|_| Err(io::ErrorKind::Other.into()))
+        })
+    }
+}
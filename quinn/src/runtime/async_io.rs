@@ -0,0 +1,117 @@
+use std::{
+    future::Future,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use async_io::{Async, Timer};
+use futures_util::ready;
+use proto::Transmit;
+use udp::{RecvMeta, UdpSocketState, UdpState};
+
+use super::{AsyncTimer, AsyncUdpSocket, Runtime};
+
+/// An async-std runtime
+#[cfg(feature = "runtime-async-std")]
+#[derive(Debug, Clone, Copy)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "runtime-async-std")]
+impl Runtime for AsyncStdRuntime {
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        Box::pin(Timer::at(deadline))
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        async_std::task::spawn(future);
+    }
+
+    fn wrap_udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
+        UdpSocket::wrap(socket)
+    }
+}
+
+/// A smol runtime
+#[cfg(feature = "runtime-smol")]
+#[derive(Debug, Clone, Copy)]
+pub struct SmolRuntime;
+
+#[cfg(feature = "runtime-smol")]
+impl Runtime for SmolRuntime {
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        Box::pin(Timer::at(deadline))
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        smol::spawn(future).detach();
+    }
+
+    fn wrap_udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
+        UdpSocket::wrap(socket)
+    }
+}
+
+impl AsyncTimer for Timer {
+    fn reset(mut self: Pin<&mut Self>, deadline: Instant) {
+        self.set_at(deadline)
+    }
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Future::poll(self.as_mut(), cx).map(|_| ())
+    }
+}
+
+#[derive(Debug)]
+struct UdpSocket {
+    io: Async<std::net::UdpSocket>,
+    inner: UdpSocketState,
+}
+
+impl UdpSocket {
+    fn wrap(socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
+        UdpSocketState::configure((&socket).into())?;
+        Ok(Box::new(Self {
+            io: Async::new(socket)?,
+            inner: UdpSocketState::new(),
+        }))
+    }
+}
+
+impl AsyncUdpSocket for UdpSocket {
+    fn poll_send(
+        &mut self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_writable(cx))?;
+            match self.inner.send(self.io.get_ref().into(), state, transmits) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_readable(cx))?;
+            match self.inner.recv(self.io.get_ref().into(), bufs, meta) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+}
@@ -0,0 +1,104 @@
+//! Abstractions over the async runtime driving endpoints and connections
+//!
+//! All of an endpoint's background tasks, timers and sockets are obtained from a [`Runtime`].
+//! Implementations for tokio, async-std and smol are provided by the `runtime-tokio`,
+//! `runtime-async-std` and `runtime-smol` features respectively. Other runtimes can be supported
+//! by implementing the traits in this module and passing the result to
+//! [`EndpointBuilder::runtime()`](crate::EndpointBuilder::runtime).
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use proto::Transmit;
+use udp::{RecvMeta, UdpState};
+
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+mod async_io;
+#[cfg(feature = "runtime-tokio")]
+mod tokio;
+
+#[cfg(feature = "runtime-async-std")]
+pub use self::async_io::AsyncStdRuntime;
+#[cfg(feature = "runtime-smol")]
+pub use self::async_io::SmolRuntime;
+#[cfg(feature = "runtime-tokio")]
+pub use self::tokio::TokioRuntime;
+
+/// An async runtime capable of driving endpoints and connections
+pub trait Runtime: Send + Sync + Debug + 'static {
+    /// Construct a timer that expires at `deadline`
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>>;
+    /// Drive `future` to completion in the background
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>);
+    /// Register `socket` with the runtime's reactor
+    ///
+    /// The socket must be prepared with [`UdpSocketState::configure()`](udp::UdpSocketState::configure).
+    fn wrap_udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>>;
+}
+
+/// A timer which can be rescheduled
+pub trait AsyncTimer: Send + Debug + 'static {
+    /// Reschedule the timer to expire at `deadline`
+    fn reset(self: Pin<&mut Self>, deadline: Instant);
+    /// Check whether the timer has expired, registering for a wakeup if it hasn't
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()>;
+}
+
+/// A UDP socket registered with an async runtime's reactor
+///
+/// Implementations are expected to send and receive through a [`udp::UdpSocketState`], which
+/// takes care of ECN and segmentation offload.
+pub trait AsyncUdpSocket: Send + Debug + 'static {
+    /// Send as many of `transmits` as possible, returning the number sent
+    fn poll_send(
+        &mut self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>>;
+
+    /// Receive datagrams into `bufs`, returning the number received
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>>;
+
+    /// The local address the socket is bound to
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// Select a runtime based on the calling context and the enabled features
+///
+/// The tokio runtime of the calling context is preferred, if there is one. Otherwise async-std is
+/// used if enabled, then smol. Returns `None` if no runtime is available.
+#[allow(unreachable_code)]
+pub fn default_runtime() -> Option<Arc<dyn Runtime>> {
+    #[cfg(feature = "runtime-tokio")]
+    {
+        if let Ok(handle) = ::tokio::runtime::Handle::try_current() {
+            return Some(Arc::new(TokioRuntime::new(handle)));
+        }
+    }
+
+    #[cfg(feature = "runtime-async-std")]
+    {
+        return Some(Arc::new(AsyncStdRuntime));
+    }
+
+    #[cfg(feature = "runtime-smol")]
+    {
+        return Some(Arc::new(SmolRuntime));
+    }
+
+    None
+}
@@ -0,0 +1,113 @@
+use std::{
+    future::Future,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use ::tokio::{
+    io::Interest,
+    runtime::Handle,
+    time::{sleep_until, Sleep},
+};
+use futures_util::ready;
+use proto::Transmit;
+use udp::{RecvMeta, UdpSocketState, UdpState};
+
+use super::{AsyncTimer, AsyncUdpSocket, Runtime};
+
+/// A tokio runtime
+///
+/// Holding a [`Handle`] allows endpoints to be built outside of the runtime's context, e.g. by
+/// libraries that construct an endpoint in a synchronous constructor.
+#[derive(Debug, Clone)]
+pub struct TokioRuntime(Handle);
+
+impl TokioRuntime {
+    /// Run endpoints and connections on the runtime referred to by `handle`
+    pub fn new(handle: Handle) -> Self {
+        Self(handle)
+    }
+}
+
+impl Runtime for TokioRuntime {
+    fn new_timer(&self, deadline: Instant) -> Pin<Box<dyn AsyncTimer>> {
+        // Timers are bound to the time driver of the context they're constructed in
+        let _guard = self.0.enter();
+        Box::pin(sleep_until(deadline.into()))
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.0.spawn(future);
+    }
+
+    fn wrap_udp_socket(&self, socket: std::net::UdpSocket) -> io::Result<Box<dyn AsyncUdpSocket>> {
+        UdpSocketState::configure((&socket).into())?;
+        // Registering sockets with the reactor requires entering the runtime
+        let _guard = self.0.enter();
+        Ok(Box::new(UdpSocket {
+            io: ::tokio::net::UdpSocket::from_std(socket)?,
+            inner: UdpSocketState::new(),
+        }))
+    }
+}
+
+impl AsyncTimer for Sleep {
+    fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        Sleep::reset(self, deadline.into())
+    }
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        Future::poll(self, cx)
+    }
+}
+
+#[derive(Debug)]
+struct UdpSocket {
+    io: ::tokio::net::UdpSocket,
+    inner: UdpSocketState,
+}
+
+impl AsyncUdpSocket for UdpSocket {
+    fn poll_send(
+        &mut self,
+        state: &UdpState,
+        cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        let inner = &mut self.inner;
+        let socket = &self.io;
+        loop {
+            ready!(socket.poll_send_ready(cx))?;
+            match socket.try_io(Interest::WRITABLE, || {
+                inner.send(socket.into(), state, transmits)
+            }) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.io.poll_recv_ready(cx))?;
+            match self.io.try_io(Interest::READABLE, || {
+                self.inner.recv((&self.io).into(), bufs, meta)
+            }) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use fxhash::FxHashMap;
+use proto::{ConnectionHandle, Transmit};
+
+/// Shares an endpoint's egress bandwidth among its connections
+///
+/// Transmits are queued per connection and released by deficit round robin, so that each
+/// connection with data to send gets a share of the socket proportional to its weight no matter
+/// how much data other connections have queued. Optionally, the total rate at which data is
+/// released is capped by a token bucket.
+#[derive(Debug)]
+pub struct EgressScheduler {
+    queues: FxHashMap<ConnectionHandle, Queue>,
+    /// Connections with queued transmits, in round robin order
+    active: VecDeque<ConnectionHandle>,
+    limit: Option<RateLimit>,
+}
+
+impl EgressScheduler {
+    pub fn new(rate_limit: Option<u64>) -> Self {
+        Self {
+            queues: FxHashMap::default(),
+            active: VecDeque::new(),
+            limit: rate_limit.map(|rate| RateLimit {
+                rate: rate.max(1),
+                // Allow bursts of a few milliseconds worth of data, and at least one full batch
+                burst: (rate / 200).max(64 * 1024),
+                tokens: 0,
+                last_refill: None,
+            }),
+        }
+    }
+
+    /// Queue a transmit of the connection `ch`
+    pub fn enqueue(&mut self, ch: ConnectionHandle, transmit: Transmit) {
+        let queue = self.queues.entry(ch).or_insert_with(Queue::new);
+        if queue.transmits.is_empty() {
+            self.active.push_back(ch);
+        }
+        queue.transmits.push_back(transmit);
+    }
+
+    /// Set the relative share of bandwidth of the connection `ch`
+    pub fn set_weight(&mut self, ch: ConnectionHandle, weight: u32) {
+        self.queues.entry(ch).or_insert_with(Queue::new).weight = weight.max(1);
+    }
+
+    /// Forget about the connection `ch` once its queued transmits have been released
+    pub fn remove(&mut self, ch: ConnectionHandle) {
+        if let Some(queue) = self.queues.get_mut(&ch) {
+            if queue.transmits.is_empty() {
+                self.queues.remove(&ch);
+            } else {
+                queue.drained = true;
+            }
+        }
+    }
+
+    /// Move transmits to `out` until it holds `max` transmits
+    ///
+    /// Returns the time at which more transmits may be released if blocked by the rate limit.
+    pub fn dequeue(
+        &mut self,
+        now: Instant,
+        out: &mut VecDeque<Transmit>,
+        max: usize,
+    ) -> Option<Instant> {
+        while out.len() < max {
+            let ch = *self.active.front()?;
+            if let Some(ref mut limit) = self.limit {
+                if let Some(wakeup) = limit.blocked(now) {
+                    return Some(wakeup);
+                }
+            }
+
+            let queue = self.queues.get_mut(&ch).unwrap();
+            let size = queue.transmits.front().unwrap().contents.len() as u64;
+            if queue.deficit < size {
+                // Out of credit for this round; move on to the next connection
+                queue.deficit += QUANTUM * u64::from(queue.weight);
+                self.active.rotate_left(1);
+                continue;
+            }
+
+            queue.deficit -= size;
+            out.push_back(queue.transmits.pop_front().unwrap());
+            if let Some(ref mut limit) = self.limit {
+                limit.tokens -= size as i64;
+            }
+            if queue.transmits.is_empty() {
+                queue.deficit = 0;
+                self.active.pop_front();
+                if queue.drained {
+                    self.queues.remove(&ch);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    transmits: VecDeque<Transmit>,
+    weight: u32,
+    /// Number of bytes the connection may send in the current round
+    deficit: u64,
+    /// Whether the connection has been drained, so the queue can be dropped once empty
+    drained: bool,
+}
+
+impl Queue {
+    fn new() -> Self {
+        Self {
+            transmits: VecDeque::new(),
+            weight: 1,
+            deficit: 0,
+            drained: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RateLimit {
+    /// Bytes per second
+    rate: u64,
+    /// Maximum number of tokens that can be accumulated
+    burst: u64,
+    /// Bytes that may be sent immediately; negative when more than that has been sent
+    tokens: i64,
+    last_refill: Option<Instant>,
+}
+
+impl RateLimit {
+    /// Refill the bucket, returning when sending may resume if it's empty
+    fn blocked(&mut self, now: Instant) -> Option<Instant> {
+        match self.last_refill {
+            Some(last) => {
+                let elapsed = now.saturating_duration_since(last);
+                let new = (elapsed.as_nanos() * u128::from(self.rate) / 1_000_000_000) as i64;
+                if new > 0 {
+                    self.tokens = (self.tokens + new).min(self.burst as i64);
+                    self.last_refill = Some(now);
+                }
+            }
+            None => {
+                self.tokens = self.burst as i64;
+                self.last_refill = Some(now);
+            }
+        }
+        if self.tokens > 0 {
+            return None;
+        }
+        let deficit = (1 - self.tokens) as u128;
+        let delay = deficit * 1_000_000_000 / u128::from(self.rate);
+        Some(now + Duration::from_nanos(delay as u64))
+    }
+}
+
+/// Number of bytes credited to a connection of weight 1 per round
+const QUANTUM: u64 = 1500;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transmit(len: usize) -> Transmit {
+        Transmit {
+            destination: "[::1]:4433".parse().unwrap(),
+            ecn: None,
+            contents: vec![0; len],
+            segment_size: None,
+            src_ip: None,
+        }
+    }
+
+    #[test]
+    fn weighted_fairness() {
+        let (a, b) = (ConnectionHandle(0), ConnectionHandle(1));
+        let mut scheduler = EgressScheduler::new(None);
+        scheduler.set_weight(a, 3);
+        for _ in 0..100 {
+            scheduler.enqueue(a, transmit(1200));
+            scheduler.enqueue(b, transmit(1200));
+        }
+
+        let mut out = VecDeque::new();
+        assert_eq!(scheduler.dequeue(Instant::now(), &mut out, 40), None);
+        assert_eq!(out.len(), 40);
+        // Transmits don't identify their connection, so tell them apart by queue length
+        let remaining_a = scheduler.queues[&a].transmits.len();
+        let remaining_b = scheduler.queues[&b].transmits.len();
+        let (sent_a, sent_b) = (100 - remaining_a, 100 - remaining_b);
+        assert!((28..=32).contains(&sent_a), "{} {}", sent_a, sent_b);
+
+        // A queue that empties leaves the remaining bandwidth to the others
+        out.clear();
+        scheduler.dequeue(Instant::now(), &mut out, usize::MAX);
+        assert_eq!(out.len(), 160);
+        assert!(scheduler.active.is_empty());
+    }
+
+    #[test]
+    fn rate_limit() {
+        let ch = ConnectionHandle(0);
+        let mut scheduler = EgressScheduler::new(Some(1_000_000));
+        for _ in 0..200 {
+            scheduler.enqueue(ch, transmit(1000));
+        }
+
+        // The initial burst is released immediately
+        let now = Instant::now();
+        let mut out = VecDeque::new();
+        let wakeup = scheduler.dequeue(now, &mut out, usize::MAX).unwrap();
+        let burst = out.len();
+        assert!((64..200).contains(&burst));
+        assert!(wakeup > now && wakeup <= now + Duration::from_millis(1));
+
+        // Then one transmit per millisecond
+        out.clear();
+        let later = now + Duration::from_millis(10);
+        scheduler.dequeue(later, &mut out, usize::MAX).unwrap();
+        assert!((9..=11).contains(&out.len()), "{}", out.len());
+    }
+
+    #[test]
+    fn rate_limited_transfer() {
+        let ch = ConnectionHandle(0);
+        let mut scheduler = EgressScheduler::new(Some(200_000));
+        for _ in 0..250 {
+            scheduler.enqueue(ch, transmit(1200));
+        }
+
+        // Drain the queue as an endpoint driver would, sleeping until each wakeup
+        let start = Instant::now();
+        let mut now = start;
+        let mut out = VecDeque::new();
+        while !scheduler.active.is_empty() {
+            if let Some(wakeup) = scheduler.dequeue(now, &mut out, 10) {
+                assert!(wakeup > now);
+                now = wakeup;
+            }
+            out.clear();
+        }
+
+        // Everything beyond the initial 64 KiB burst is sent at the limited rate
+        let expected = Duration::from_secs_f64((250.0 * 1200.0 - 65536.0) / 200_000.0);
+        let elapsed = now - start;
+        assert!(
+            elapsed >= expected - Duration::from_millis(10)
+                && elapsed <= expected + Duration::from_millis(10),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[test]
+    fn remove_after_flush() {
+        let ch = ConnectionHandle(0);
+        let mut scheduler = EgressScheduler::new(None);
+        scheduler.enqueue(ch, transmit(100));
+        scheduler.remove(ch);
+        assert!(scheduler.queues.contains_key(&ch));
+        scheduler.dequeue(Instant::now(), &mut VecDeque::new(), usize::MAX);
+        assert!(!scheduler.queues.contains_key(&ch));
+    }
+}
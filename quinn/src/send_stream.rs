@@ -1,20 +1,31 @@
 use std::{
     future::Future,
-    io,
+    io::{self, IoSlice},
     pin::Pin,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use bytes::Bytes;
+use bytes::{Buf, Bytes, BytesMut};
 use futures_channel::oneshot;
 use futures_util::{io::AsyncWrite, ready, FutureExt};
-use proto::{ConnectionError, FinishError, StreamId, Written};
+use proto::{ConnectionError, FinishError, SendStreamStats, StreamId, Written};
 use thiserror::Error;
 
-use crate::{connection::ConnectionRef, recv_stream::UnknownStream, VarInt};
+use crate::{
+    blocking::BlockingWriter,
+    connection::{ConnectionInner, ConnectionRef},
+    recv_stream::UnknownStream,
+    VarInt,
+};
 
 /// A stream that can only be used to send data
 ///
+/// Unless noted otherwise, the futures returned by its methods are cancel-safe: dropping one
+/// before it completes, e.g. because another branch of a `select!` completed first, never loses
+/// or duplicates data. Any data it reports as written has been written, and any data it hasn't
+/// reported has not.
+///
 /// If dropped, streams that haven't been explicitly [`reset()`] will continue to (re)transmit
 /// previously written data until it has been fully acknowledged or the connection is closed.
 ///
@@ -41,25 +52,56 @@ impl SendStream {
     ///
     /// Yields the number of bytes written on success. Congestion and flow control may cause this to
     /// be shorter than `buf.len()`, indicating that only a prefix of `buf` was written.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was written.
     pub fn write<'a>(&'a mut self, buf: &'a [u8]) -> Write<'a> {
         Write { stream: self, buf }
     }
 
+    /// Write bytes from a sequence of buffers to the stream
+    ///
+    /// Like [`write()`](Self::write), but takes data from each of `bufs` in order, so scattered
+    /// buffers such as a header and a body can be submitted without concatenating them first.
+    /// Yields the total number of bytes written, which may be less than the combined length of
+    /// `bufs`.
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was written.
+    pub fn write_vectored<'a>(&'a mut self, bufs: &'a [IoSlice<'a>]) -> WriteVectored<'a> {
+        WriteVectored { stream: self, bufs }
+    }
+
     /// Convenience method to write an entire buffer to the stream
+    ///
+    /// Not cancel-safe: if the future is dropped before completing, an unknown prefix of `buf` may
+    /// have been written. Use [`write_all_buf()`](Self::write_all_buf) where that matters.
     pub fn write_all<'a>(&'a mut self, buf: &'a [u8]) -> WriteAll<'a> {
         WriteAll { stream: self, buf }
     }
 
+    /// Convenience method to write an entire buffer to the stream, advancing it as data is
+    /// written
+    ///
+    /// Cancel-safe: if the future is dropped before completing, exactly the data that has been
+    /// written has been consumed from `buf`, so the write can be resumed by calling this again.
+    pub fn write_all_buf<'a, B: Buf>(&'a mut self, buf: &'a mut B) -> WriteAllBuf<'a, B> {
+        WriteAllBuf { stream: self, buf }
+    }
+
     /// Write chunks to the stream
     ///
     /// Yields the number of bytes and chunks written on success.
     /// Congestion and flow control may cause this to be shorter than `buf.len()`,
     /// indicating that only a prefix of `bufs` was written
+    ///
+    /// Cancel-safe: if the future is dropped before completing, no data was written.
     pub fn write_chunks<'a>(&'a mut self, bufs: &'a mut [Bytes]) -> WriteChunks<'a> {
         WriteChunks { stream: self, bufs }
     }
 
     /// Convenience method to write a single chunk in its entirety to the stream
+    ///
+    /// Not cancel-safe: if the future is dropped before completing, an unknown prefix of `buf` may
+    /// have been written. Use [`write_all_chunks()`](Self::write_all_chunks) where that matters.
     pub fn write_chunk(&mut self, buf: Bytes) -> WriteChunk<'_> {
         WriteChunk {
             stream: self,
@@ -68,6 +110,10 @@ impl SendStream {
     }
 
     /// Convenience method to write an entire list of chunks to the stream
+    ///
+    /// Cancel-safe: written data is removed from `bufs` as it is written, so if the future is
+    /// dropped before completing, the write can be resumed by calling this again with the same
+    /// `bufs`.
     pub fn write_all_chunks<'a>(&'a mut self, bufs: &'a mut [Bytes]) -> WriteAllChunks<'a> {
         WriteAllChunks {
             stream: self,
@@ -76,6 +122,129 @@ impl SendStream {
         }
     }
 
+    /// Convenience method to write everything `reader` yields to the stream, until it reaches
+    /// end-of-file
+    ///
+    /// Yields the number of bytes copied. Data is read into reusable buffers of up to 64 KiB
+    /// that are handed to the stream without further copies, and
+    /// `reader` is only polled again once the previous chunk has been accepted, so memory use is
+    /// bounded and reads are paced by flow and congestion control. Suitable for serving files,
+    /// e.g. a `tokio::fs::File`. The stream is not finished afterwards.
+    ///
+    /// Not cancel-safe: if the future is dropped before completing, data that was read from
+    /// `reader` may not have been written.
+    pub fn send_from<'a, R>(&'a mut self, reader: &'a mut R) -> SendFrom<'a, R>
+    where
+        R: tokio::io::AsyncRead + Unpin + ?Sized,
+    {
+        SendFrom {
+            stream: self,
+            reader,
+            buf: BytesMut::new(),
+            pending: [Bytes::new()],
+            copied: 0,
+        }
+    }
+
+    /// Convenience method to write an entire buffer to the stream and wait for the peer to
+    /// acknowledge it
+    ///
+    /// Completes once `buf` and all data written before it have been acknowledged, so the data is
+    /// guaranteed to have reached the peer's QUIC stack even if the stream or connection is later
+    /// lost. Acknowledgement does not imply that the peer application has read the data.
+    ///
+    /// Not cancel-safe: if the future is dropped before `buf` has been written, an unknown prefix
+    /// of it may have been written. Use [`write_all_buf()`](Self::write_all_buf) followed by
+    /// [`acked()`](Self::acked) where that matters.
+    pub fn write_all_acked<'a>(&'a mut self, buf: &'a [u8]) -> WriteAllAcked<'a> {
+        WriteAllAcked {
+            stream: self,
+            buf,
+            offset: None,
+        }
+    }
+
+    /// Wait for the peer to acknowledge all data written to the stream so far
+    ///
+    /// Data written after this is first polled is not waited for. Fails if the peer stops the
+    /// stream or the connection is lost first. Streams that have already been finished should use
+    /// [`finish()`](Self::finish) instead, which also waits for the end of the stream to be
+    /// acknowledged.
+    ///
+    /// Cancel-safe.
+    pub fn acked(&mut self) -> Acked<'_> {
+        Acked {
+            stream: self,
+            offset: None,
+        }
+    }
+
+    /// Poll for acknowledgement of the first `offset` bytes, or of all data written so far if
+    /// `offset` is unset
+    fn poll_acked(
+        &mut self,
+        cx: &mut Context,
+        offset: &mut Option<u64>,
+    ) -> Poll<Result<(), WriteError>> {
+        let mut conn = self.conn.lock("SendStream::poll_acked");
+        if self.is_0rtt {
+            conn.check_0rtt()
+                .map_err(|()| WriteError::ZeroRttRejected)?;
+        }
+        let mut stream = conn.inner.send_stream(self.stream);
+        let target = match *offset {
+            Some(x) => x,
+            None => {
+                let written = stream.written().map_err(|_| WriteError::UnknownStream)?;
+                *offset = Some(written);
+                written
+            }
+        };
+        let acked = match stream.acked() {
+            Ok(x) => x,
+            // The stream was finished and has been fully acknowledged. Locally reset streams are
+            // also forgotten, but can't be reset while we hold `&mut self`.
+            Err(_) => return Poll::Ready(Ok(())),
+        };
+        if acked >= target {
+            return Poll::Ready(Ok(()));
+        }
+        if let Ok(Some(error_code)) = stream.stopped() {
+            return Poll::Ready(Err(WriteError::Stopped(error_code)));
+        }
+        if let Ok(true) = stream.is_expired() {
+            return Poll::Ready(Err(WriteError::Expired));
+        }
+        if let Some(ref x) = conn.error {
+            return Poll::Ready(Err(WriteError::ConnectionClosed(x.clone())));
+        }
+        let _ = conn.inner.send_stream(self.stream).notify_acked(target);
+        conn.blocked_writers.insert(self.stream, cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Foundation of [`write()`](Self::write), for implementing futures and protocol layers by
+    /// hand
+    ///
+    /// Yields the number of bytes written. Cancel-safe: data is only written when this returns
+    /// `Poll::Ready(Ok(_))`.
+    pub fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize, WriteError>> {
+        self.execute_poll(cx, |stream| stream.write(buf))
+    }
+
+    /// Foundation of [`write_vectored()`](Self::write_vectored), for implementing futures and
+    /// protocol layers by hand
+    ///
+    /// Yields the number of bytes written. Cancel-safe: data is only written when this returns
+    /// `Poll::Ready(Ok(_))`.
+    pub fn poll_write_vectored(
+        &mut self,
+        cx: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<Result<usize, WriteError>> {
+        self.execute_poll(cx, |stream| stream.write_vectored(bufs))
+    }
+
     fn execute_poll<F, R>(&mut self, cx: &mut Context, write_fn: F) -> Poll<Result<R, WriteError>>
     where
         F: FnOnce(&mut proto::SendStream) -> Result<R, proto::WriteError>,
@@ -99,6 +268,9 @@ impl SendStream {
             Err(Stopped(error_code)) => {
                 return Poll::Ready(Err(WriteError::Stopped(error_code)));
             }
+            Err(Expired) => {
+                return Poll::Ready(Err(WriteError::Expired));
+            }
             Err(UnknownStream) => {
                 return Poll::Ready(Err(WriteError::UnknownStream));
             }
@@ -112,30 +284,22 @@ impl SendStream {
     ///
     /// No new data may be written after calling this method. Completes when the peer has
     /// acknowledged all sent data, retransmitting data as needed.
+    ///
+    /// Cancel-safe: the stream remains finished if the future is dropped, and calling this again
+    /// waits for the same acknowledgement.
     pub fn finish(&mut self) -> Finish<'_> {
         Finish { stream: self }
     }
 
-    #[doc(hidden)]
+    /// Foundation of [`finish()`](Self::finish), for implementing futures and protocol layers by
+    /// hand
     pub fn poll_finish(&mut self, cx: &mut Context) -> Poll<Result<(), WriteError>> {
         let mut conn = self.conn.lock("poll_finish");
         if self.is_0rtt {
             conn.check_0rtt()
                 .map_err(|()| WriteError::ZeroRttRejected)?;
         }
-        if self.finishing.is_none() {
-            conn.inner
-                .send_stream(self.stream)
-                .finish()
-                .map_err(|e| match e {
-                    FinishError::UnknownStream => WriteError::UnknownStream,
-                    FinishError::Stopped(error_code) => WriteError::Stopped(error_code),
-                })?;
-            let (send, recv) = oneshot::channel();
-            self.finishing = Some(recv);
-            conn.finishing.insert(self.stream, send);
-            conn.wake();
-        }
+        start_finish(&mut conn, self.stream, &mut self.finishing)?;
         match self
             .finishing
             .as_mut()
@@ -159,6 +323,29 @@ impl SendStream {
         }
     }
 
+    /// Shut down the send stream gracefully without waiting for the peer's acknowledgement
+    ///
+    /// Like [`finish()`](Self::finish), but returns immediately, so the caller needn't stay around
+    /// until the end of the stream is acknowledged. Written data continues to be retransmitted as
+    /// needed for as long as the connection is open. Awaiting `finish()` afterwards waits for the
+    /// acknowledgement after all.
+    pub fn finish_detach(&mut self) -> Result<(), WriteError> {
+        let mut conn = self.conn.lock("SendStream::finish_detach");
+        if self.is_0rtt {
+            conn.check_0rtt()
+                .map_err(|()| WriteError::ZeroRttRejected)?;
+        }
+        start_finish(&mut conn, self.stream, &mut self.finishing)
+    }
+
+    /// Convert into a blocking [`std::io::Write`] implementation
+    ///
+    /// Intended for synchronous code running outside the async runtime that drives the
+    /// connection; see [`BlockingWriter`](crate::BlockingWriter) for details.
+    pub fn into_blocking_writer(self) -> BlockingWriter {
+        BlockingWriter::new(self)
+    }
+
     /// Close the send stream immediately.
     ///
     /// No new data can be written after calling this method. Locally buffered data is dropped, and
@@ -177,10 +364,13 @@ impl SendStream {
     /// Set the priority of the send stream
     ///
     /// Every send stream has an initial priority of 0. Locally buffered data from streams with
-    /// higher priority will be transmitted before data from streams with lower priority. Changing
-    /// the priority of a stream with pending data may only take effect after that data has been
-    /// transmitted. Using many different priority levels per connection may have a negative
-    /// impact on performance.
+    /// higher priority will be transmitted before data from streams with lower priority. Streams
+    /// sharing a priority take turns, one frame at a time, unless another policy is selected with
+    /// [`TransportConfig::stream_scheduler()`]. Changing the priority of a stream with pending data
+    /// may only take effect after that data has been transmitted. Using many different priority
+    /// levels per connection may have a negative impact on performance.
+    ///
+    /// [`TransportConfig::stream_scheduler()`]: crate::TransportConfig::stream_scheduler
     pub fn set_priority(&self, priority: i32) -> Result<(), UnknownStream> {
         let mut conn = self.conn.lock("SendStream::set_priority");
         conn.inner.send_stream(self.stream).set_priority(priority)?;
@@ -193,12 +383,91 @@ impl SendStream {
         Ok(conn.inner.send_stream(self.stream).priority()?)
     }
 
+    /// Statistics about the data sent on this stream so far
+    pub fn stats(&self) -> Result<SendStreamStats, UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::stats");
+        Ok(conn.inner.send_stream(self.stream).stats()?)
+    }
+
+    /// Limit the rate at which data from the send stream is transmitted, in bytes per second
+    ///
+    /// Streams have no individual limit by default. Capping a bulk transfer leaves the remaining
+    /// capacity of the connection to other streams, such as latency-sensitive requests sharing
+    /// the same connection. `None` removes the limit; a rate of zero suspends transmission of the
+    /// stream's data until the limit is changed.
+    pub fn set_max_rate(&self, rate: Option<u64>) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::set_max_rate");
+        conn.inner.send_stream(self.stream).set_max_rate(rate)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Get the maximum transmission rate of the send stream, in bytes per second
+    pub fn max_rate(&self) -> Result<Option<u64>, UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::max_rate");
+        Ok(conn.inner.send_stream(self.stream).max_rate()?)
+    }
+
+    /// Reset the stream with `error_code` if it hasn't been finished and fully acknowledged by
+    /// `deadline`
+    ///
+    /// Suited to data that is worthless once stale, such as live media or telemetry, where
+    /// retransmitting old data would only delay newer data. When the deadline passes, the stream
+    /// is reset as if by [`reset()`](Self::reset), and pending writes or calls to
+    /// [`finish()`](Self::finish) fail with [`WriteError::Expired`]. Replaces any earlier deadline.
+    pub fn set_deadline(&self, deadline: Instant, error_code: VarInt) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::set_deadline");
+        conn.inner
+            .send_stream(self.stream)
+            .set_deadline(deadline, error_code)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Remove the deadline set by [`set_deadline()`](Self::set_deadline), if any
+    pub fn clear_deadline(&self) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::clear_deadline");
+        conn.inner.send_stream(self.stream).clear_deadline()?;
+        Ok(())
+    }
+
+    /// Reset the stream with `error_code` if it waits on the peer for `timeout` without making
+    /// progress
+    ///
+    /// The stream waits on the peer while it has unacknowledged data or is blocked by flow
+    /// control, and makes progress whenever data is acknowledged or the peer grants more flow
+    /// control credit. Protects servers from peers that open streams but never read from them,
+    /// which would otherwise hold on to buffered data indefinitely. A stalled stream is reset as if
+    /// its [deadline](Self::set_deadline) passed. Replaces any earlier stall timeout.
+    pub fn set_stall_timeout(
+        &self,
+        timeout: Duration,
+        error_code: VarInt,
+    ) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::set_stall_timeout");
+        conn.inner
+            .send_stream(self.stream)
+            .set_stall_timeout(timeout, error_code)?;
+        conn.wake();
+        Ok(())
+    }
+
+    /// Remove the stall timeout set by [`set_stall_timeout()`](Self::set_stall_timeout), if any
+    pub fn clear_stall_timeout(&self) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.lock("SendStream::clear_stall_timeout");
+        conn.inner.send_stream(self.stream).clear_stall_timeout()?;
+        Ok(())
+    }
+
     /// Completes if/when the peer stops the stream, yielding the error code
+    ///
+    /// Cancel-safe.
     pub fn stopped(&mut self) -> Stopped<'_> {
         Stopped { stream: self }
     }
 
-    #[doc(hidden)]
+    /// Foundation of [`stopped()`](Self::stopped), for implementing futures and protocol layers by
+    /// hand
     pub fn poll_stopped(&mut self, cx: &mut Context) -> Poll<Result<VarInt, StoppedError>> {
         let mut conn = self.conn.lock("SendStream::poll_stopped");
 
@@ -218,6 +487,9 @@ impl SendStream {
     }
 
     /// Get the identity of this stream
+    ///
+    /// The numeric QUIC stream ID is available via `u64::from`, alongside the stream's
+    /// [`initiator()`](StreamId::initiator) and [`dir()`](StreamId::dir).
     pub fn id(&self) -> StreamId {
         self.stream
     }
@@ -225,7 +497,15 @@ impl SendStream {
 
 impl AsyncWrite for SendStream {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
-        SendStream::execute_poll(self.get_mut(), cx, |stream| stream.write(buf)).map_err(Into::into)
+        SendStream::poll_write(self.get_mut(), cx, buf).map_err(Into::into)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[IoSlice],
+    ) -> Poll<io::Result<usize>> {
+        SendStream::poll_write_vectored(self.get_mut(), cx, bufs).map_err(Into::into)
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
@@ -246,6 +526,18 @@ impl tokio::io::AsyncWrite for SendStream {
         AsyncWrite::poll_write(self, cx, buf)
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write_vectored(self, cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
         Poll::Ready(Ok(()))
     }
@@ -270,12 +562,36 @@ impl Drop for SendStream {
                     }
                 }
                 // Already finished or reset, which is fine.
-                Err(FinishError::UnknownStream) => {}
+                Err(FinishError::UnknownStream) | Err(FinishError::Expired) => {}
             }
         }
     }
 }
 
+/// Finish `stream` and register for the outcome in `finishing`, unless that was already done
+fn start_finish(
+    conn: &mut ConnectionInner,
+    stream: StreamId,
+    finishing: &mut Option<oneshot::Receiver<Option<WriteError>>>,
+) -> Result<(), WriteError> {
+    if finishing.is_some() {
+        return Ok(());
+    }
+    conn.inner
+        .send_stream(stream)
+        .finish()
+        .map_err(|e| match e {
+            FinishError::UnknownStream => WriteError::UnknownStream,
+            FinishError::Stopped(error_code) => WriteError::Stopped(error_code),
+            FinishError::Expired => WriteError::Expired,
+        })?;
+    let (send, recv) = oneshot::channel();
+    *finishing = Some(recv);
+    conn.finishing.insert(stream, send);
+    conn.wake();
+    Ok(())
+}
+
 /// Future produced by `SendStream::finish`
 #[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
 pub struct Finish<'a> {
@@ -317,8 +633,24 @@ impl<'a> Future for Write<'a> {
     type Output = Result<usize, WriteError>;
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let this = self.get_mut();
-        let buf = this.buf;
-        this.stream.execute_poll(cx, |s| s.write(buf))
+        this.stream.poll_write(cx, this.buf)
+    }
+}
+
+/// Future produced by [`SendStream::write_vectored()`].
+///
+/// [`SendStream::write_vectored()`]: crate::SendStream::write_vectored
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct WriteVectored<'a> {
+    stream: &'a mut SendStream,
+    bufs: &'a [IoSlice<'a>],
+}
+
+impl<'a> Future for WriteVectored<'a> {
+    type Output = Result<usize, WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.stream.poll_write_vectored(cx, this.bufs)
     }
 }
 
@@ -346,6 +678,114 @@ impl<'a> Future for WriteAll<'a> {
     }
 }
 
+/// Largest amount of data [`SendStream::send_from()`] reads from its source at once
+const SEND_FROM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Future produced by [`SendStream::send_from()`].
+///
+/// [`SendStream::send_from()`]: crate::SendStream::send_from
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct SendFrom<'a, R: ?Sized> {
+    stream: &'a mut SendStream,
+    reader: &'a mut R,
+    buf: BytesMut,
+    pending: [Bytes; 1],
+    copied: u64,
+}
+
+impl<'a, R> Future for SendFrom<'a, R>
+where
+    R: tokio::io::AsyncRead + Unpin + ?Sized,
+{
+    type Output = io::Result<u64>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            if !this.pending[0].is_empty() {
+                let bufs = &mut this.pending[..];
+                ready!(this.stream.execute_poll(cx, |s| s.write_chunks(bufs)))?;
+                continue;
+            }
+            if this.buf.len() < SEND_FROM_CHUNK_SIZE / 4 {
+                // Reclaims the previous allocation if the stream has released every chunk split
+                // off from it
+                this.buf.clear();
+                this.buf.resize(SEND_FROM_CHUNK_SIZE, 0);
+            }
+            let mut read_buf = tokio::io::ReadBuf::new(&mut this.buf[..]);
+            ready!(Pin::new(&mut *this.reader).poll_read(cx, &mut read_buf))?;
+            let n = read_buf.filled().len();
+            if n == 0 {
+                return Poll::Ready(Ok(this.copied));
+            }
+            this.pending[0] = this.buf.split_to(n).freeze();
+            this.copied += n as u64;
+        }
+    }
+}
+
+/// Future produced by [`SendStream::write_all_acked()`].
+///
+/// [`SendStream::write_all_acked()`]: crate::SendStream::write_all_acked
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct WriteAllAcked<'a> {
+    stream: &'a mut SendStream,
+    buf: &'a [u8],
+    offset: Option<u64>,
+}
+
+impl<'a> Future for WriteAllAcked<'a> {
+    type Output = Result<(), WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while !this.buf.is_empty() {
+            let buf = this.buf;
+            let n = ready!(this.stream.execute_poll(cx, |s| s.write(buf)))?;
+            this.buf = &this.buf[n..];
+        }
+        this.stream.poll_acked(cx, &mut this.offset)
+    }
+}
+
+/// Future produced by [`SendStream::acked()`].
+///
+/// [`SendStream::acked()`]: crate::SendStream::acked
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct Acked<'a> {
+    stream: &'a mut SendStream,
+    offset: Option<u64>,
+}
+
+impl<'a> Future for Acked<'a> {
+    type Output = Result<(), WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.stream.poll_acked(cx, &mut this.offset)
+    }
+}
+
+/// Future produced by [`SendStream::write_all_buf()`].
+///
+/// [`SendStream::write_all_buf()`]: crate::SendStream::write_all_buf
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct WriteAllBuf<'a, B> {
+    stream: &'a mut SendStream,
+    buf: &'a mut B,
+}
+
+impl<'a, B: Buf> Future for WriteAllBuf<'a, B> {
+    type Output = Result<(), WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while this.buf.has_remaining() {
+            let chunk = this.buf.chunk();
+            let n = ready!(this.stream.execute_poll(cx, |s| s.write(chunk)))?;
+            this.buf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
 /// Future produced by [`SendStream::write_chunks()`].
 ///
 /// [`SendStream::write_chunks()`]: crate::SendStream::write_chunks
@@ -423,6 +863,12 @@ pub enum WriteError {
     /// The connection was closed.
     #[error("connection closed: {0}")]
     ConnectionClosed(#[source] ConnectionError),
+    /// The stream's delivery deadline passed before all of its data was acknowledged, or it stalled,
+    /// and it has been reset
+    ///
+    /// See [`SendStream::set_deadline()`] and [`SendStream::set_stall_timeout()`].
+    #[error("stream expired")]
+    Expired,
     /// The stream has already been finished or reset
     #[error("unknown stream")]
     UnknownStream,
@@ -460,6 +906,7 @@ impl From<WriteError> for io::Error {
         use self::WriteError::*;
         let kind = match x {
             Stopped(_) | ZeroRttRejected => io::ErrorKind::ConnectionReset,
+            Expired => io::ErrorKind::TimedOut,
             ConnectionClosed(_) | UnknownStream => io::ErrorKind::NotConnected,
         };
         io::Error::new(kind, x)
@@ -0,0 +1,142 @@
+//! Request/response over streams as a [`tower_service::Service`]
+//!
+//! Available with the `tower` feature. Each request is carried on its own bidirectional stream, so
+//! concurrent requests don't block each other and middleware layers (timeouts, retries, load
+//! balancing, ...) can be composed with a connection directly.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures_util::future;
+use thiserror::Error;
+
+use crate::{Connection, ConnectionError, ReadToEndError, WriteError};
+
+/// Converts between typed requests and responses and their encoding on a stream
+pub trait Codec: Send + Sync + 'static {
+    /// Requests accepted by the service
+    type Request;
+    /// Responses produced by the service
+    type Response: Send + 'static;
+    /// Errors that may arise from encoding or decoding
+    type Error: std::error::Error + Send + 'static;
+
+    /// Encode `request` as the complete contents of a request stream
+    fn encode(&self, request: Self::Request) -> Result<Bytes, Self::Error>;
+
+    /// Decode the complete contents of a response stream
+    fn decode(&self, response: Vec<u8>) -> Result<Self::Response, Self::Error>;
+}
+
+/// A [`tower_service::Service`] issuing each request on a new bidirectional stream
+///
+/// A call opens a stream, writes the encoded request, finishes the stream, and decodes everything
+/// the peer writes on its half of the stream before finishing it as the response. The peer is
+/// expected to accept incoming bidirectional streams and answer each one likewise.
+///
+/// The service is always ready: a call waits for the peer to allow a new stream if necessary, as
+/// in [`Connection::open_bi()`]. Cloning the service is cheap, and clones share the connection.
+#[derive(Debug)]
+pub struct StreamService<C> {
+    connection: Connection,
+    codec: Arc<C>,
+    max_response_size: usize,
+}
+
+impl<C: Codec> StreamService<C> {
+    /// Construct a service issuing requests on `connection`
+    ///
+    /// Responses longer than `max_response_size` bytes are rejected with
+    /// [`ReadToEndError::TooLong`].
+    pub fn new(connection: Connection, codec: C, max_response_size: usize) -> Self {
+        Self {
+            connection,
+            codec: Arc::new(codec),
+            max_response_size,
+        }
+    }
+
+    /// The connection requests are issued on
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl<C> Clone for StreamService<C> {
+    fn clone(&self) -> Self {
+        Self {
+            connection: self.connection.clone(),
+            codec: self.codec.clone(),
+            max_response_size: self.max_response_size,
+        }
+    }
+}
+
+impl<C: Codec> tower_service::Service<C::Request> for StreamService<C> {
+    type Response = C::Response;
+    type Error = ServiceError<C::Error>;
+    type Future = ResponseFuture<C::Response, C::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: C::Request) -> Self::Future {
+        // Encode eagerly so the request need not be `Send`
+        let request = self.codec.encode(request);
+        let connection = self.connection.clone();
+        let codec = self.codec.clone();
+        let max_response_size = self.max_response_size;
+        ResponseFuture(Box::pin(async move {
+            let request = request.map_err(ServiceError::Codec)?;
+            let (mut send, recv) = connection.open_bi().await?;
+            send.write_all(&request).await?;
+            // The response may be read while the peer acknowledges the request
+            let (_, response) = future::try_join(
+                async { send.finish().await.map_err(ServiceError::from) },
+                async {
+                    recv.read_to_end(max_response_size)
+                        .await
+                        .map_err(ServiceError::from)
+                },
+            )
+            .await?;
+            codec.decode(response).map_err(ServiceError::Codec)
+        }))
+    }
+}
+
+/// Future produced by [`StreamService`]
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+pub struct ResponseFuture<T, E>(
+    Pin<Box<dyn Future<Output = Result<T, ServiceError<E>>> + Send + 'static>>,
+);
+
+impl<T, E> Future for ResponseFuture<T, E> {
+    type Output = Result<T, ServiceError<E>>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+/// Errors that arise from a [`StreamService`] call
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ServiceError<E> {
+    /// The connection was lost before a stream could be opened
+    #[error("opening stream failed: {0}")]
+    Connection(#[from] ConnectionError),
+    /// The request could not be sent
+    #[error("sending request failed: {0}")]
+    Write(#[from] WriteError),
+    /// The response could not be received
+    #[error("receiving response failed: {0}")]
+    Read(#[from] ReadToEndError),
+    /// The codec failed to encode the request or decode the response
+    #[error("codec error: {0}")]
+    Codec(E),
+}
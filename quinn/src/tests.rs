@@ -2,15 +2,21 @@
 
 use std::{
     convert::TryInto,
+    future::Future,
     io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    pin::Pin,
     str,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
 };
 
 use bytes::Bytes;
 use futures_util::future;
-use futures_util::StreamExt;
+use futures_util::{FutureExt, StreamExt};
 use rand::{rngs::StdRng, RngCore, SeedableRng};
 use tokio::{
     runtime::{Builder, Runtime},
@@ -21,8 +27,8 @@ use tracing_futures::Instrument as _;
 use tracing_subscriber::EnvFilter;
 
 use super::{
-    crypto, ClientConfig, Endpoint, Incoming, NewConnection, RecvStream, SendStream,
-    TransportConfig,
+    crypto, ClientConfig, Endpoint, EndpointBuilder, Incoming, NewConnection, RecvStream,
+    SendStream, TransportConfig,
 };
 
 #[test]
@@ -106,6 +112,200 @@ fn local_addr() {
     );
 }
 
+#[tokio::test]
+async fn source_port_range() {
+    let _guard = subscribe();
+    let mut builder = endpoint_builder();
+    builder.source_port(crate::SourcePort::Range(41000..=41031));
+    let (endpoint, _) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+    let first = endpoint.local_addr().unwrap();
+    assert!((41000..=41031).contains(&first.port()));
+
+    let second = endpoint.rebind_source_port().unwrap();
+    assert_eq!(endpoint.local_addr().unwrap(), second);
+    assert!((41000..=41031).contains(&second.port()));
+    assert_ne!(first.port(), second.port());
+}
+
+#[tokio::test]
+async fn source_port_fixed() {
+    let _guard = subscribe();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let port = UdpSocket::bind(localhost)
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut builder = endpoint_builder();
+    builder.source_port(crate::SourcePort::Fixed(port));
+    let (endpoint, _) = builder.bind(&localhost).unwrap();
+    assert_eq!(endpoint.local_addr().unwrap().port(), port);
+
+    // There is no other port to move to, so the current socket is kept
+    let current = endpoint.local_addr().unwrap();
+    assert_eq!(endpoint.rebind_source_port().unwrap(), current);
+    assert_eq!(endpoint.local_addr().unwrap(), current);
+}
+
+#[tokio::test]
+async fn rebind() {
+    let _guard = subscribe();
+    // Endpoints built from the same builder trust each other's certificate
+    let builder = endpoint_builder();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let (server, mut incoming) = builder.clone().bind(&localhost).unwrap();
+    let (client, _) = builder.bind(&localhost).unwrap();
+
+    let server_addr = server.local_addr().unwrap();
+    let server_task = tokio::spawn(async move {
+        let mut new_conn = incoming.next().await.unwrap().await.unwrap();
+        let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+        let data = stream.read_to_end(usize::MAX).await.unwrap();
+        (data, new_conn.connection.remote_address(), new_conn)
+    });
+
+    let new_conn = client
+        .connect(&server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    client.rebind(UdpSocket::bind(localhost).unwrap()).unwrap();
+    let new_addr = client.local_addr().unwrap();
+
+    // The connection survives the switch, and the server learns the client's new address
+    let mut stream = new_conn.connection.open_uni().await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.finish().await.unwrap();
+    let (data, remote, _server_conn) = server_task.await.unwrap();
+    assert_eq!(data, b"hello");
+    assert_eq!(remote, new_addr);
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let _guard = subscribe();
+    let builder = endpoint_builder();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let (server, mut incoming) = builder.clone().bind(&localhost).unwrap();
+    let (client, _) = builder.bind(&localhost).unwrap();
+
+    let mut new_conn = client
+        .connect(&server.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let (mut send, recv) = new_conn.connection.open_bi().await.unwrap();
+    send.write_all(b"ping").await.unwrap();
+
+    let mut server_conn = incoming.next().await.unwrap().await.unwrap();
+    let (mut server_send, server_recv) = server_conn.bi_streams.next().await.unwrap().unwrap();
+    let start = Instant::now();
+    let shutdown = tokio::spawn({
+        let server = server.clone();
+        async move {
+            server
+                .shutdown(Duration::from_secs(10), 42u32.into(), b"bye")
+                .await
+        }
+    });
+
+    // The stream in flight completes normally
+    send.finish().await.unwrap();
+    assert_eq!(server_recv.read_to_end(usize::MAX).await.unwrap(), b"ping");
+    server_send.write_all(b"pong").await.unwrap();
+    server_send.finish().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"pong");
+
+    // Then the connection is closed with the requested code, well within the grace period
+    match new_conn.uni_streams.next().await {
+        Some(Err(crate::ConnectionError::ApplicationClosed(close))) => {
+            assert_eq!(close.error_code, 42u32.into());
+            assert_eq!(&close.reason[..], b"bye");
+        }
+        x => panic!("unexpected result: {:?}", x.map(|x| x.map(|_| ()))),
+    }
+    shutdown.await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(server.accept().await.is_none());
+}
+
+#[test]
+fn build_outside_runtime() {
+    let _guard = subscribe();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    #[cfg(not(any(feature = "runtime-async-std", feature = "runtime-smol")))]
+    match endpoint_builder().bind(&localhost) {
+        Err(crate::EndpointError::NoRuntime) => {}
+        x => panic!("unexpected result: {:?}", x.map(|_| ())),
+    }
+
+    let runtime = rt_basic();
+    let mut builder = endpoint_builder();
+    builder.runtime(Arc::new(crate::runtime::TokioRuntime::new(
+        runtime.handle().clone(),
+    )));
+    let (endpoint, mut incoming) = builder.bind(&localhost).unwrap();
+    // Connections may also be initiated outside the runtime
+    let connecting = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap();
+    runtime.block_on(async move {
+        let (client, server) =
+            tokio::join!(connecting, async { incoming.next().await.unwrap().await });
+        client.unwrap();
+        server.unwrap();
+    });
+}
+
+#[cfg(feature = "runtime-async-std")]
+#[test]
+fn async_std_runtime() {
+    let _guard = subscribe();
+    let mut builder = endpoint_builder();
+    builder.runtime(Arc::new(crate::runtime::AsyncStdRuntime));
+    async_std::task::block_on(echo_on_runtime(builder));
+}
+
+#[cfg(feature = "runtime-smol")]
+#[test]
+fn smol_runtime() {
+    let _guard = subscribe();
+    let mut builder = endpoint_builder();
+    builder.runtime(Arc::new(crate::runtime::SmolRuntime));
+    smol::block_on(echo_on_runtime(builder));
+}
+
+/// Echo a message over an endpoint built by `builder`, without depending on a tokio context
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+async fn echo_on_runtime(builder: EndpointBuilder) {
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let (endpoint, mut incoming) = builder.bind(&localhost).unwrap();
+    let server = async {
+        let mut new_conn = incoming.next().await.unwrap().await.unwrap();
+        let (mut send, recv) = new_conn.bi_streams.next().await.unwrap().unwrap();
+        let data = recv.read_to_end(usize::MAX).await.unwrap();
+        send.write_all(&data).await.unwrap();
+        send.finish().await.unwrap();
+        // Keep the connection open until the client is done
+        new_conn
+    };
+    let client = async {
+        let new_conn = endpoint
+            .connect(&endpoint.local_addr().unwrap(), "localhost")
+            .unwrap()
+            .await
+            .unwrap();
+        let (mut send, recv) = new_conn.connection.open_bi().await.unwrap();
+        send.write_all(b"hello").await.unwrap();
+        send.finish().await.unwrap();
+        assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"hello");
+        new_conn
+    };
+    let (_server, _client) = future::join(server, client).await;
+}
+
 #[test]
 fn read_after_close() {
     let _guard = subscribe();
@@ -148,105 +348,1702 @@ fn read_after_close() {
     });
 }
 
-#[test]
-fn export_keying_material() {
+#[tokio::test]
+async fn copy_to() {
     let _guard = subscribe();
-    let runtime = rt_basic();
-    let (endpoint, mut incoming) = {
-        let _guard = runtime.enter();
-        endpoint()
-    };
+    let (endpoint, mut incoming) = endpoint();
 
-    runtime.block_on(async move {
-        let outgoing_conn = endpoint
-            .connect(&endpoint.local_addr().unwrap(), "localhost")
-            .unwrap()
-            .await
-            .expect("connect");
-        let incoming_conn = incoming
-            .next()
-            .await
-            .expect("endpoint")
-            .await
-            .expect("connection");
-        let mut i_buf = [0u8; 64];
-        incoming_conn
-            .connection
-            .export_keying_material(&mut i_buf, b"asdf", b"qwer")
-            .unwrap();
-        let mut o_buf = [0u8; 64];
-        outgoing_conn
-            .connection
-            .export_keying_material(&mut o_buf, b"asdf", b"qwer")
-            .unwrap();
-        assert_eq!(&i_buf[..], &o_buf[..]);
+    let data = gen_data(200 * 1024, 42);
+    let expected = data.clone();
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        for _ in 0..2 {
+            let mut s = conn.connection.open_uni().await.unwrap();
+            s.write_all(&data).await.unwrap();
+            s.finish().await.unwrap();
+        }
+        // Keep the connection open until the client is done
+        let _ = conn.bi_streams.next().await;
     });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+
+    let mut stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    let mut buf = Vec::new();
+    let n = stream
+        .copy_to(&mut buf, expected.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(n, expected.len() as u64);
+    assert_eq!(buf, expected);
+
+    let mut stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    match stream
+        .copy_to(&mut Vec::new(), expected.len() as u64 - 1)
+        .await
+    {
+        Err(crate::CopyToError::TooLong) => {}
+        x => panic!("unexpected result: {:?}", x),
+    }
+    new_conn.connection.close(0u32.into(), b"done");
 }
 
 #[tokio::test]
-async fn accept_after_close() {
+async fn send_from_reader() {
     let _guard = subscribe();
     let (endpoint, mut incoming) = endpoint();
 
-    const MSG: &[u8] = b"goodbye!";
+    const SIZE: usize = 300 * 1024;
+    let data = gen_data(SIZE, 42);
+    let expected = data.clone();
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        let mut s = conn.connection.open_uni().await.unwrap();
+        assert_eq!(s.send_from(&mut &data[..]).await.unwrap(), SIZE as u64);
+        s.finish().await.unwrap();
+    });
 
-    let sender = endpoint
+    let mut new_conn = endpoint
         .connect(&endpoint.local_addr().unwrap(), "localhost")
         .unwrap()
         .await
-        .expect("connect")
-        .connection;
-    let mut s = sender.open_uni().await.unwrap();
-    s.write_all(MSG).await.unwrap();
-    s.finish().await.unwrap();
-    sender.close(0u32.into(), b"");
+        .unwrap();
+    let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    assert_eq!(stream.id().initiator(), crate::Side::Server);
+    assert_eq!(stream.id().dir(), crate::Dir::Uni);
+    assert_eq!(u64::from(stream.id()), 3);
+    assert_eq!(stream.read_to_end(usize::MAX).await.unwrap(), expected);
+    new_conn.connection.close(0u32.into(), b"done");
+}
 
-    // Allow some time for the close to be sent and processed
-    tokio::time::sleep(Duration::from_millis(100)).await;
+#[tokio::test]
+async fn manual_poll() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
 
-    // Despite the connection having closed, we should be able to accept it...
-    let mut receiver = incoming
-        .next()
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let (mut send, mut recv) = conn.connection.open_bi().await.unwrap();
+        let n = future::poll_fn(|cx| send.poll_write(cx, b"hello"))
+            .await
+            .unwrap();
+        assert_eq!(n, 5);
+        future::poll_fn(|cx| send.poll_finish(cx)).await.unwrap();
+        let mut buf = [0; 8];
+        let mut buf = tokio::io::ReadBuf::new(&mut buf);
+        while buf.filled().len() < 5 {
+            future::poll_fn(|cx| recv.poll_read(cx, &mut buf))
+                .await
+                .unwrap();
+        }
+        assert_eq!(buf.filled(), b"olleh");
+        recv.stop(42u32.into()).unwrap();
+        // Keep the connection open until the client is done
+        let _ = conn.bi_streams.next().await;
+    });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
         .await
-        .expect("endpoint")
+        .unwrap();
+    let (mut send, mut recv) = new_conn.bi_streams.next().await.unwrap().unwrap();
+    let mut data = Vec::new();
+    while let Some(chunk) = future::poll_fn(|cx| recv.poll_read_chunk(cx, usize::MAX, true))
         .await
-        .expect("connection");
+        .unwrap()
+    {
+        data.extend_from_slice(&chunk.bytes);
+    }
+    data.reverse();
+    send.write_all(&data).await.unwrap();
+    assert_eq!(
+        future::poll_fn(|cx| send.poll_stopped(cx)).await.unwrap(),
+        crate::VarInt::from_u32(42)
+    );
+    new_conn.connection.close(0u32.into(), b"done");
+}
 
-    // ...and read what was sent.
-    let stream = receiver
-        .uni_streams
-        .next()
+#[tokio::test]
+async fn finish_detach() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        for _ in 0..2 {
+            let stream = conn.uni_streams.next().await.unwrap().unwrap();
+            assert_eq!(stream.read_to_end(usize::MAX).await.unwrap(), b"hello");
+        }
+        // Keep the connection open until the client is done
+        let _ = conn.bi_streams.next().await;
+    });
+
+    let new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
         .await
-        .expect("incoming streams")
-        .expect("missing stream");
-    let msg = stream
-        .read_to_end(usize::max_value())
+        .unwrap();
+    let conn = &new_conn.connection;
+
+    // The stream is delivered in full even if the handle is dropped right away
+    let mut send = conn.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish_detach().unwrap();
+    drop(send);
+
+    // Waiting for acknowledgement remains possible
+    let mut send = conn.open_uni().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish_detach().unwrap();
+    send.finish().await.unwrap();
+
+    conn.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn accept_streams_from_connection() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        let (mut send, recv) = conn.accept_bi().await.unwrap();
+        let mut data = recv.read_to_end(usize::MAX).await.unwrap();
+        data.reverse();
+        send.write_all(&data).await.unwrap();
+        send.finish().await.unwrap();
+        let recv = conn.accept_uni().await.unwrap();
+        assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"bye");
+        // Keep the connection open until the client is done
+        let _ = conn.accept_bi().await;
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
         .await
-        .expect("read_to_end");
-    assert_eq!(msg, MSG);
+        .unwrap()
+        .connection;
+    let (mut send, recv) = conn.open_bi().await.unwrap();
+    send.write_all(b"hello").await.unwrap();
+    send.finish().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"olleh");
+    let mut send = conn.open_uni().await.unwrap();
+    send.write_all(b"bye").await.unwrap();
+    send.finish().await.unwrap();
+    conn.close(0u32.into(), b"done");
+    assert!(matches!(
+        conn.accept_uni().await,
+        Err(crate::ConnectionError::LocallyClosed)
+    ));
+}
 
-    // But it's still definitely closed.
-    assert!(receiver.connection.open_uni().await.is_err());
+#[tokio::test]
+async fn write_vectored() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        let mut send = conn.open_uni().await.unwrap();
+        let bufs = [
+            io::IoSlice::new(b"head"),
+            io::IoSlice::new(b""),
+            io::IoSlice::new(b"er:body"),
+        ];
+        assert_eq!(send.write_vectored(&bufs).await.unwrap(), 11);
+        send.finish().await.unwrap();
+        // Keep the connection open until the client is done
+        let _ = conn.accept_bi().await;
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let recv = conn.accept_uni().await.unwrap();
+    assert_eq!(recv.read_to_end(usize::MAX).await.unwrap(), b"header:body");
+    conn.close(0u32.into(), b"done");
 }
 
-/// Construct an endpoint suitable for connecting to itself
-fn endpoint() -> (Endpoint, Incoming) {
-    let mut endpoint = Endpoint::builder();
+#[test]
+fn blocking_adapters() {
+    use std::io::{Read, Write};
 
-    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
-    let key = crate::PrivateKey::from_der(&cert.serialize_private_key_der()).unwrap();
-    let cert = crate::Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
-    let cert_chain = crate::CertificateChain::from_certs(vec![cert.clone()]);
-    let server_config = crate::ServerConfig::with_single_cert(cert_chain, key).unwrap();
-    endpoint.listen(server_config);
+    let _guard = subscribe();
+    let runtime = rt_threaded();
+    let (endpoint, mut incoming) = {
+        let _guard = runtime.enter();
+        endpoint()
+    };
 
-    let client_config = ClientConfig::with_root_certificates(vec![cert]).unwrap();
-    endpoint.default_client_config(client_config);
+    runtime.spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        let (mut send, recv) = conn.accept_bi().await.unwrap();
+        // Reply only once the request is complete, so the client's read times out first
+        let mut data = recv.read_to_end(usize::MAX).await.unwrap();
+        data.reverse();
+        send.write_all(&data).await.unwrap();
+        send.finish().await.unwrap();
+        // Keep the connection open until the client is done
+        let _ = conn.accept_bi().await;
+    });
 
-    let (x, y) = endpoint
-        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
-        .unwrap();
-    (x, y)
+    let conn = runtime
+        .block_on(
+            endpoint
+                .connect(&endpoint.local_addr().unwrap(), "localhost")
+                .unwrap(),
+        )
+        .unwrap()
+        .connection;
+    let (send, recv) = runtime.block_on(conn.open_bi()).unwrap();
+    let mut writer = send.into_blocking_writer();
+    let mut reader = recv.into_blocking_reader();
+
+    writer.write_all(b"hello").unwrap();
+    reader.set_timeout(Some(Duration::from_millis(50)));
+    let mut buf = Vec::new();
+    assert_eq!(
+        reader.read_to_end(&mut buf).unwrap_err().kind(),
+        io::ErrorKind::TimedOut
+    );
+    assert!(buf.is_empty());
+
+    writer.finish().unwrap();
+    reader.set_timeout(None);
+    reader.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"olleh");
+    conn.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn read_to_end_limits() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    // The server starts two streams but never finishes them
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        let mut first = conn.open_uni().await.unwrap();
+        first.write_all(b"hello").await.unwrap();
+        let mut second = conn.open_uni().await.unwrap();
+        second.write_all(b"world").await.unwrap();
+        // Keep the connection open until the client is done
+        let _ = conn.accept_bi().await;
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let recv = conn.accept_uni().await.unwrap();
+    let start = Instant::now();
+    assert_eq!(
+        recv.read_to_end(usize::MAX)
+            .inactivity_timeout(Duration::from_millis(100))
+            .await,
+        Err(crate::ReadToEndError::TimedOut)
+    );
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    let recv = conn.accept_uni().await.unwrap();
+    assert_eq!(
+        recv.read_to_end(usize::MAX)
+            .on_progress(|received| received < 5)
+            .await,
+        Err(crate::ReadToEndError::Aborted)
+    );
+    conn.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn datagrams() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    // The server echoes datagrams back to the client
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        while let Ok(data) = conn.read_datagram().await {
+            conn.send_datagram(data).unwrap();
+        }
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    assert!(conn.max_datagram_size().is_some());
+    conn.send_datagram(Bytes::from_static(b"ping")).unwrap();
+    assert_eq!(conn.read_datagram().await.unwrap(), &b"ping"[..]);
+    conn.close(0u32.into(), b"done");
+    assert!(matches!(
+        conn.read_datagram().await,
+        Err(crate::ConnectionError::LocallyClosed)
+    ));
+}
+
+#[tokio::test]
+async fn datagrams_dropped_after_close() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        conn.close(42u32.into(), b"bye");
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let result = tokio::time::timeout(Duration::from_secs(10), conn.datagrams_dropped())
+        .await
+        .expect("not woken by close");
+    assert!(matches!(
+        result,
+        Err(crate::ConnectionError::ApplicationClosed(ref close))
+            if close.error_code == 42u32.into()
+    ));
+}
+
+#[tokio::test]
+async fn read_exact_and_buf() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    let (release, released) = futures_channel::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let mut s = conn.connection.open_uni().await.unwrap();
+        s.write_all(b"abcd").await.unwrap();
+        released.await.unwrap();
+        s.write_all(b"efghijkl").await.unwrap();
+        s.finish().await.unwrap();
+        // Keep the connection open until the client is done
+        let _ = conn.bi_streams.next().await;
+    });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let mut stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+
+    // Abandoning an incomplete read loses nothing
+    let mut buf = [0; 8];
+    tokio::time::timeout(Duration::from_millis(50), stream.read_exact(&mut buf))
+        .await
+        .unwrap_err();
+    assert_eq!(buf, [0; 8]);
+    release.send(()).unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"abcdefgh");
+
+    let mut rest = Vec::new();
+    while let Some(n) = stream.read_buf(&mut rest).await.unwrap() {
+        assert_ne!(n, 0);
+    }
+    assert_eq!(rest, b"ijkl");
+    assert_eq!(
+        stream.read_exact(&mut [0]).await,
+        Err(crate::ReadExactError::FinishedEarly)
+    );
+    new_conn.connection.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn read_chunk_unordered() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    let data = gen_data(64 * 1024, 7);
+    let expected = data.clone();
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let mut s = conn.connection.open_uni().await.unwrap();
+        s.write_all(&data).await.unwrap();
+        s.finish().await.unwrap();
+        // Keep the connection open until the client is done
+        let _ = conn.bi_streams.next().await;
+    });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let mut stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    let mut buf = vec![0; expected.len()];
+    let mut received = 0;
+    while let Some(chunk) = stream.read_chunk_unordered(4096).await.unwrap() {
+        assert!(chunk.bytes.len() <= 4096);
+        let offset = chunk.offset as usize;
+        buf[offset..offset + chunk.bytes.len()].copy_from_slice(&chunk.bytes);
+        received += chunk.bytes.len();
+    }
+    assert_eq!(received, expected.len());
+    assert_eq!(buf, expected);
+    new_conn.connection.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn splice() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    // The server relays each incoming uni stream to a new one of its own
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            let recv = conn.uni_streams.next().await.unwrap().unwrap();
+            let send = conn.connection.open_uni().await.unwrap();
+            results.push(tokio::spawn(crate::proxy::splice(recv, send)));
+        }
+        let mut outcomes = Vec::new();
+        for result in results {
+            outcomes.push(result.await.unwrap());
+        }
+        // Keep the connection open until the client is done
+        let _ = conn.bi_streams.next().await;
+        outcomes
+    });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let conn = &new_conn.connection;
+
+    // Data and FIN are relayed
+    let data = gen_data(300 * 1024, 3);
+    let mut send = conn.open_uni().await.unwrap();
+    send.write_all(&data).await.unwrap();
+    send.finish().await.unwrap();
+    let relayed = new_conn.uni_streams.next().await.unwrap().unwrap();
+    assert_eq!(relayed.read_to_end(usize::MAX).await.unwrap(), data);
+
+    // Resets are relayed
+    let mut send = conn.open_uni().await.unwrap();
+    send.write_all(b"partial").await.unwrap();
+    let mut relayed = new_conn.uni_streams.next().await.unwrap().unwrap();
+    send.reset(7u32.into()).unwrap();
+    let mut buf = [0; 16];
+    loop {
+        match relayed.read(&mut buf).await {
+            Ok(Some(_)) => {}
+            Err(crate::ReadError::Reset(code)) => {
+                assert_eq!(code, 7u32.into());
+                assert_eq!(relayed.reset_code(), Some(code));
+                break;
+            }
+            x => panic!("unexpected result: {:?}", x),
+        }
+    }
+
+    // Stops are relayed back to the source
+    let mut send = conn.open_uni().await.unwrap();
+    send.write_all(b"unwanted").await.unwrap();
+    let mut relayed = new_conn.uni_streams.next().await.unwrap().unwrap();
+    relayed.stop(9u32.into()).unwrap();
+    assert_eq!(send.stopped().await.unwrap(), 9u32.into());
+
+    conn.close(0u32.into(), b"done");
+    let outcomes = server.await.unwrap();
+    assert_eq!(outcomes[0], Ok(data.len() as u64));
+    assert_eq!(
+        outcomes[1],
+        Err(crate::proxy::SpliceError::Read(crate::ReadError::Reset(
+            7u32.into()
+        )))
+    );
+    assert_eq!(
+        outcomes[2],
+        Err(crate::proxy::SpliceError::Write(
+            crate::WriteError::Stopped(9u32.into())
+        ))
+    );
+}
+
+#[tokio::test]
+async fn copy_streams() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    // The server echoes each bidirectional stream back to the client
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap().connection;
+        while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+            let n = crate::copy(&mut recv, &mut send).await.unwrap();
+            // The streams are left to the caller
+            send.write_all(&n.to_be_bytes()).await.unwrap();
+            send.finish().await.unwrap();
+        }
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let data = gen_data(300 * 1024, 5);
+    let (mut send, recv) = conn.open_bi().await.unwrap();
+    let reader = tokio::spawn(async move { recv.read_to_end(usize::MAX).await.unwrap() });
+    send.write_all(&data).await.unwrap();
+    send.finish().await.unwrap();
+    let echoed = reader.await.unwrap();
+    let (echoed, count) = echoed.split_at(echoed.len() - 8);
+    assert_eq!(echoed, &data[..]);
+    assert_eq!(count, &(data.len() as u64).to_be_bytes()[..]);
+    conn.close(0u32.into(), b"done");
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn stream_service() {
+    use crate::service::{Codec, ServiceError, StreamService};
+    use tower_service::Service;
+
+    struct Utf8;
+
+    impl Codec for Utf8 {
+        type Request = String;
+        type Response = String;
+        type Error = std::string::FromUtf8Error;
+
+        fn encode(&self, request: String) -> Result<Bytes, Self::Error> {
+            Ok(request.into())
+        }
+
+        fn decode(&self, response: Vec<u8>) -> Result<String, Self::Error> {
+            String::from_utf8(response)
+        }
+    }
+
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    // The server answers each request with its reversal
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        while let Some(Ok((mut send, recv))) = conn.bi_streams.next().await {
+            tokio::spawn(async move {
+                let mut data = recv.read_to_end(usize::MAX).await.unwrap();
+                data.reverse();
+                send.write_all(&data).await.unwrap();
+                send.finish().await.unwrap();
+            });
+        }
+    });
+
+    let new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let mut service = StreamService::new(new_conn.connection.clone(), Utf8, 16);
+
+    // Concurrent calls each get their own response
+    let (a, b) = future::join(
+        service.call("hello".into()),
+        service.clone().call("world".into()),
+    )
+    .await;
+    assert_eq!(a.unwrap(), "olleh");
+    assert_eq!(b.unwrap(), "dlrow");
+
+    // Oversized responses are rejected
+    assert_eq!(
+        service.call("x".repeat(17)).await,
+        Err(ServiceError::Read(crate::ReadToEndError::TooLong))
+    );
+
+    new_conn.connection.close(0u32.into(), b"done");
+    assert!(matches!(
+        service.call("late".into()).await,
+        Err(ServiceError::Connection(_))
+    ));
+}
+
+#[tokio::test]
+async fn move_connection() {
+    let _guard = subscribe();
+    let (other, _other_incoming) = endpoint();
+    let (endpoint, mut incoming) = endpoint();
+
+    let server = tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        let mut uni_streams = conn.uni_streams;
+        let stream = uni_streams.next().await.unwrap().unwrap();
+        let data = stream.read_to_end(usize::MAX).await.unwrap();
+        (data, conn.connection.remote_address())
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(b"before ").await.unwrap();
+    endpoint.move_connection(&conn, &other).unwrap();
+    assert_eq!(
+        endpoint.move_connection(&conn, &other),
+        Err(crate::MoveConnectionError::UnknownConnection)
+    );
+    stream.write_all(b"after").await.unwrap();
+    stream.finish().await.unwrap();
+
+    let (data, remote) = server.await.unwrap();
+    assert_eq!(data, b"before after");
+    assert_eq!(remote, other.local_addr().unwrap());
+    conn.close(0u32.into(), b"done");
+    other.wait_idle().await;
+    assert_eq!(endpoint.open_connections(), 0);
+}
+
+#[tokio::test]
+async fn move_connection_with_queued_datagram() {
+    let _guard = subscribe();
+    let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 4433);
+    let old_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)), 5000);
+    let new_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3)), 5000);
+    let builder = endpoint_builder();
+    let (_server, mut incoming, server_relay) =
+        builder.clone().with_packet_relay(server_addr).unwrap();
+    let gate = Arc::new(Gate::default());
+    let mut client_builder = builder;
+    client_builder.runtime(Arc::new(GatedRuntime {
+        inner: crate::runtime::TokioRuntime::new(tokio::runtime::Handle::current()),
+        gate: gate.clone(),
+    }));
+    let (old, _, old_relay) = client_builder.clone().with_packet_relay(old_addr).unwrap();
+    let (new, _, _new_relay) = client_builder.with_packet_relay(new_addr).unwrap();
+
+    // Datagrams sent through the new endpoint are never delivered, so the server doesn't learn of
+    // the move and keeps sending to the old endpoint
+    let (to_server, to_client) = (server_relay.clone(), old_relay.clone());
+    tokio::spawn(async move {
+        while let Some(packet) = old_relay.extract().await {
+            to_server.inject(old_addr, packet.payload);
+        }
+    });
+    tokio::spawn(async move {
+        while let Some(packet) = server_relay.extract().await {
+            to_client.inject(server_addr, packet.payload);
+        }
+    });
+
+    let (send_first, first) = futures_channel::oneshot::channel::<()>();
+    let (send_second, second) = futures_channel::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        let mut stream = conn.connection.open_uni().await.unwrap();
+        first.await.unwrap();
+        stream.write_all(b"first").await.unwrap();
+        second.await.unwrap();
+        // Large enough to be answered with a stateless reset if the old endpoint had already
+        // forgotten the connection
+        stream.write_all(&[0; 1000]).await.unwrap();
+        stream.finish_detach().unwrap();
+        conn
+    });
+
+    gate.capture();
+    let NewConnection {
+        connection: conn,
+        mut uni_streams,
+        ..
+    } = old
+        .connect(&server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    // Suspend the connection's driver, so that the first datagram is still queued for it when it
+    // moves
+    gate.set_closed(true);
+    send_first.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    old.move_connection(&conn, &new).unwrap();
+    gate.set_closed(false);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    send_second.send(()).unwrap();
+
+    let stream = uni_streams.next().await.unwrap().unwrap();
+    let data = tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(usize::MAX))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(data.len(), 5 + 1000);
+    assert_eq!(&data[..5], b"first");
+    let _server_conn = server.await.unwrap();
+}
+
+/// Runs tasks on tokio, suspending those spawned after [`Gate::capture()`] while the gate is closed
+#[derive(Debug)]
+struct GatedRuntime {
+    inner: crate::runtime::TokioRuntime,
+    gate: Arc<Gate>,
+}
+
+impl crate::runtime::Runtime for GatedRuntime {
+    fn new_timer(&self, deadline: std::time::Instant) -> Pin<Box<dyn crate::runtime::AsyncTimer>> {
+        self.inner.new_timer(deadline)
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        if self.gate.capturing.load(Ordering::Relaxed) {
+            self.inner.spawn(Box::pin(Gated {
+                future,
+                gate: self.gate.clone(),
+            }));
+        } else {
+            self.inner.spawn(future);
+        }
+    }
+
+    fn wrap_udp_socket(
+        &self,
+        socket: UdpSocket,
+    ) -> io::Result<Box<dyn crate::runtime::AsyncUdpSocket>> {
+        self.inner.wrap_udp_socket(socket)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Gate {
+    capturing: AtomicBool,
+    state: std::sync::Mutex<GateState>,
+}
+
+impl Gate {
+    /// Subject tasks spawned from now on to the gate
+    fn capture(&self) {
+        self.capturing.store(true, Ordering::Relaxed);
+    }
+
+    fn set_closed(&self, closed: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = closed;
+        if !closed {
+            for waker in state.suspended.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    closed: bool,
+    suspended: Vec<Waker>,
+}
+
+struct Gated {
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    gate: Arc<Gate>,
+}
+
+impl Future for Gated {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        {
+            let mut state = self.gate.state.lock().unwrap();
+            if state.closed {
+                state.suspended.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        self.future.as_mut().poll(cx)
+    }
+}
+
+#[tokio::test]
+async fn migrate() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    let (other, _) = Endpoint::builder()
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+
+    let server = tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        let mut uni_streams = conn.uni_streams;
+        let stream = uni_streams.next().await.unwrap().unwrap();
+        let data = stream.read_to_end(usize::MAX).await.unwrap();
+        (data, conn.connection.remote_address())
+    });
+
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(b"before ").await.unwrap();
+    // The handshake is confirmed once the server's reply to the stream data arrives
+    stream.acked().await.unwrap();
+    // Packets still in flight to the old address would be answered with stateless resets
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    endpoint.migrate(&conn, &other).unwrap();
+    stream.write_all(b"after").await.unwrap();
+    stream.finish().await.unwrap();
+
+    let (data, remote) = server.await.unwrap();
+    assert_eq!(data, b"before after");
+    assert_eq!(remote, other.local_addr().unwrap());
+    assert_eq!(conn.stats().frame_tx.path_challenge, 1);
+    conn.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn packet_relay() {
+    let _guard = subscribe();
+    // Neither address is ever bound; datagrams only ever pass through the relays
+    let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 4433);
+    let client_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)), 5000);
+    let builder = endpoint_builder();
+    let (_server, mut incoming, server_relay) =
+        builder.clone().with_packet_relay(server_addr).unwrap();
+    let (client, _, client_relay) = builder.with_packet_relay(client_addr).unwrap();
+    assert_eq!(client.local_addr().unwrap(), client_addr);
+
+    for (from, to, from_addr) in vec![
+        (client_relay.clone(), server_relay.clone(), client_addr),
+        (server_relay, client_relay, server_addr),
+    ] {
+        tokio::spawn(async move {
+            while let Some(packet) = from.extract().await {
+                to.inject(from_addr, packet.payload);
+            }
+        });
+    }
+
+    let server = tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        assert_eq!(conn.connection.remote_address(), client_addr);
+        let mut uni_streams = conn.uni_streams;
+        let stream = uni_streams.next().await.unwrap().unwrap();
+        stream.read_to_end(usize::MAX).await.unwrap()
+    });
+
+    let conn = client
+        .connect(&server_addr, "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(b"tunneled").await.unwrap();
+    stream.finish().await.unwrap();
+    assert_eq!(server.await.unwrap(), b"tunneled");
+    conn.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn cancel_safe_stream_ops() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    let data = gen_data(500 * 1024, 7);
+    let expected = data.clone();
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        let mut s = conn.connection.open_uni().await.unwrap();
+        let mut buf = &data[..];
+        // Repeatedly abandon partially complete writes, resuming from wherever they left off
+        loop {
+            let write = s.write_all_buf(&mut buf);
+            if let Ok(result) = tokio::time::timeout(Duration::from_micros(50), write).await {
+                result.unwrap();
+                break;
+            }
+        }
+        s.finish().await.unwrap();
+    });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let mut stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    let mut received = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        match tokio::time::timeout(Duration::from_micros(50), stream.read(&mut buf)).await {
+            Ok(Ok(Some(n))) => received.extend_from_slice(&buf[..n]),
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => panic!("read error: {}", e),
+            // Abandoned reads must not consume data
+            Err(_) => {}
+        }
+    }
+    assert_eq!(received, expected);
+}
+
+#[test]
+fn export_keying_material() {
+    let _guard = subscribe();
+    let runtime = rt_basic();
+    let (endpoint, mut incoming) = {
+        let _guard = runtime.enter();
+        endpoint()
+    };
+
+    runtime.block_on(async move {
+        let outgoing_conn = endpoint
+            .connect(&endpoint.local_addr().unwrap(), "localhost")
+            .unwrap()
+            .await
+            .expect("connect");
+        let incoming_conn = incoming
+            .next()
+            .await
+            .expect("endpoint")
+            .await
+            .expect("connection");
+        let mut i_buf = [0u8; 64];
+        incoming_conn
+            .connection
+            .export_keying_material(&mut i_buf, b"asdf", b"qwer")
+            .unwrap();
+        let mut o_buf = [0u8; 64];
+        outgoing_conn
+            .connection
+            .export_keying_material(&mut o_buf, b"asdf", b"qwer")
+            .unwrap();
+        assert_eq!(&i_buf[..], &o_buf[..]);
+    });
+}
+
+#[tokio::test]
+async fn accept_after_close() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    const MSG: &[u8] = b"goodbye!";
+
+    let sender = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .expect("connect")
+        .connection;
+    let mut s = sender.open_uni().await.unwrap();
+    s.write_all(MSG).await.unwrap();
+    s.finish().await.unwrap();
+    sender.close(0u32.into(), b"");
+
+    // Allow some time for the close to be sent and processed
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Despite the connection having closed, we should be able to accept it...
+    let mut receiver = incoming
+        .next()
+        .await
+        .expect("endpoint")
+        .await
+        .expect("connection");
+
+    // ...and read what was sent.
+    let stream = receiver
+        .uni_streams
+        .next()
+        .await
+        .expect("incoming streams")
+        .expect("missing stream");
+    let msg = stream
+        .read_to_end(usize::max_value())
+        .await
+        .expect("read_to_end");
+    assert_eq!(msg, MSG);
+
+    // But it's still definitely closed.
+    assert!(receiver.connection.open_uni().await.is_err());
+}
+
+/// Construct an endpoint suitable for connecting to itself
+fn endpoint() -> (Endpoint, Incoming) {
+    endpoint_builder()
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap()
+}
+
+/// Construct a builder for an endpoint suitable for connecting to itself
+fn endpoint_builder() -> EndpointBuilder {
+    let mut endpoint = Endpoint::builder();
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let key = crate::PrivateKey::from_der(&cert.serialize_private_key_der()).unwrap();
+    let cert = crate::Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
+    let cert_chain = crate::CertificateChain::from_certs(vec![cert.clone()]);
+    let server_config = crate::ServerConfig::with_single_cert(cert_chain, key).unwrap();
+    endpoint.listen(server_config);
+
+    let client_config = ClientConfig::with_root_certificates(vec![cert]).unwrap();
+    endpoint.default_client_config(client_config);
+    endpoint
+}
+
+#[tokio::test]
+async fn peer_identity() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    let server = tokio::spawn(async move {
+        let new_conn = incoming.next().await.unwrap().await.unwrap();
+        new_conn.connection.peer_identity().is_none()
+    });
+
+    let new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .expect("connect");
+    let chain = new_conn
+        .connection
+        .peer_identity()
+        .unwrap()
+        .downcast::<crate::CertificateChain>()
+        .unwrap();
+    assert_eq!(chain.iter().count(), 1);
+    assert!(
+        server.await.unwrap(),
+        "client shouldn't have been authenticated"
+    );
+}
+
+#[tokio::test]
+async fn handshake_data() {
+    let _guard = subscribe();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let key = crate::PrivateKey::from_der(&cert.serialize_private_key_der()).unwrap();
+    let cert_der = cert.serialize_der().unwrap();
+    let cert = crate::Certificate::from_der(&cert_der).unwrap();
+
+    let mut server_crypto =
+        crypto::rustls::server_config(crate::CertificateChain::from_certs(vec![cert]), key)
+            .unwrap();
+    server_crypto.alpn_protocols = vec![b"foo".to_vec(), b"bar".to_vec()];
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert_der)).unwrap();
+    let mut client_crypto = crypto::rustls::client_config(roots);
+    client_crypto.alpn_protocols = vec![b"bar".to_vec()];
+
+    let mut builder = Endpoint::builder();
+    builder.listen(crate::ServerConfig::with_crypto(Arc::new(server_crypto)));
+    let (endpoint, mut incoming) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+
+    let server = tokio::spawn(async move {
+        let mut connecting = incoming.next().await.unwrap();
+        // Available before the handshake completes, so the connection can be routed or refused
+        let hd = connecting
+            .handshake_data()
+            .await
+            .unwrap()
+            .downcast::<crypto::rustls::HandshakeData>()
+            .unwrap();
+        assert_eq!(hd.server_name.as_deref(), Some("localhost"));
+        assert_eq!(hd.protocol.as_deref(), Some(&b"bar"[..]));
+        connecting.await.unwrap();
+    });
+
+    let client_config = ClientConfig {
+        crypto: Arc::new(client_crypto),
+        transport: Default::default(),
+        path_cache: None,
+    };
+    let mut connecting = endpoint
+        .connect_with(client_config, &endpoint.local_addr().unwrap(), "localhost")
+        .unwrap();
+    let hd = connecting
+        .handshake_data()
+        .await
+        .unwrap()
+        .downcast::<crypto::rustls::HandshakeData>()
+        .unwrap();
+    assert_eq!(hd.protocol.as_deref(), Some(&b"bar"[..]));
+    assert_eq!(hd.server_name, None);
+    connecting.await.expect("connect");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn admission_control() {
+    let _guard = subscribe();
+    let mut builder = endpoint_builder();
+    builder.admission_control(16, |_| async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        true
+    });
+    let (endpoint, mut incoming) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+
+    tokio::spawn(async move {
+        let conn = incoming.next().await.expect("endpoint").await.unwrap();
+        conn.connection.close(0u32.into(), b"done");
+    });
+    endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .expect("connect");
+}
+
+#[tokio::test]
+async fn admission_control_deny() {
+    let _guard = subscribe();
+    let mut builder = endpoint_builder();
+    builder.admission_control(16, |_| async { false });
+    let (endpoint, incoming) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+
+    tokio::spawn(
+        incoming.for_each(|_| -> future::Ready<()> { panic!("denied connection was yielded") }),
+    );
+    // The client may complete its side of the handshake before the server's decision arrives, but
+    // the connection is closed either way
+    let result = match endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+    {
+        Ok(mut new_conn) => new_conn.uni_streams.next().await.unwrap().map(|_| ()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(crate::ConnectionError::ConnectionClosed(_))
+        | Err(crate::ConnectionError::ApplicationClosed(_)) => {}
+        Err(e) => panic!("unexpected error: {:?}", e),
+        Ok(()) => panic!("unexpected stream"),
+    }
+}
+
+#[tokio::test]
+async fn handshake_failure_hook() {
+    let _guard = subscribe();
+    let (tx, mut rx) = futures_channel::mpsc::unbounded();
+    let mut builder = endpoint_builder();
+    builder.on_handshake_failure(move |failure| {
+        let _ = tx.unbounded_send(failure);
+    });
+    let (endpoint, _incoming) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+
+    // A client that doesn't trust the server's certificate aborts the handshake with an alert
+    let untrusted = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let untrusted = crate::Certificate::from_der(&untrusted.serialize_der().unwrap()).unwrap();
+    let client_config = ClientConfig::with_root_certificates(vec![untrusted]).unwrap();
+    let client_addr = endpoint.local_addr().unwrap();
+    assert!(endpoint
+        .connect_with(client_config, &client_addr, "localhost")
+        .unwrap()
+        .await
+        .is_err());
+
+    let failure = rx.next().await.expect("handshake failure reported");
+    assert_eq!(failure.remote, client_addr);
+    assert!(matches!(
+        failure.reason,
+        crate::HandshakeFailureReason::Tls { local: false, .. }
+    ));
+    assert_eq!(endpoint.stats().handshake_failures, 1);
+}
+
+#[tokio::test]
+async fn multiple_sockets() {
+    let _guard = subscribe();
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let (endpoints, mut incoming) = endpoint_builder()
+        .bind_multiple(&[localhost, localhost])
+        .unwrap();
+    assert_eq!(endpoints.len(), 2);
+
+    tokio::spawn(async move {
+        for _ in 0..2 {
+            let conn = incoming.next().await.expect("endpoint").await.unwrap();
+            let mut s = conn.connection.open_uni().await.unwrap();
+            s.write_all(b"hello").await.unwrap();
+            s.finish().await.unwrap();
+        }
+    });
+
+    // Connect to each socket from the other
+    for (client, server) in endpoints.iter().zip(endpoints.iter().rev()) {
+        let mut new_conn = client
+            .connect(&server.local_addr().unwrap(), "localhost")
+            .unwrap()
+            .await
+            .expect("connect");
+        let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+        let msg = stream.read_to_end(usize::MAX).await.unwrap();
+        assert_eq!(msg, b"hello");
+    }
+}
+
+#[tokio::test]
+async fn endpoint_accept() {
+    let _guard = subscribe();
+    let (endpoint, _incoming) = endpoint();
+
+    let server = endpoint.clone();
+    tokio::spawn(async move {
+        while let Some(connecting) = server.accept().await {
+            let conn = connecting.await.unwrap();
+            let mut s = conn.connection.open_uni().await.unwrap();
+            s.write_all(b"hello").await.unwrap();
+            s.finish().await.unwrap();
+        }
+    });
+
+    for _ in 0..2 {
+        let mut new_conn = endpoint
+            .connect(&endpoint.local_addr().unwrap(), "localhost")
+            .unwrap()
+            .await
+            .expect("connect");
+        let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+        let msg = stream.read_to_end(usize::MAX).await.unwrap();
+        assert_eq!(msg, b"hello");
+    }
+}
+
+#[tokio::test]
+async fn accept_without_incoming() {
+    let _guard = subscribe();
+    let (endpoint, incoming) = endpoint();
+    drop(incoming);
+
+    let server = endpoint.clone();
+    tokio::spawn(async move {
+        let conn = server.accept().await.unwrap().await.unwrap();
+        let mut s = conn.connection.open_uni().await.unwrap();
+        s.write_all(b"hello").await.unwrap();
+        s.finish().await.unwrap();
+    });
+
+    let mut new_conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .expect("connect");
+    let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    let msg = stream.read_to_end(usize::MAX).await.unwrap();
+    assert_eq!(msg, b"hello");
+}
+
+#[derive(Debug)]
+struct FixedResolver(Vec<SocketAddr>);
+
+impl crate::Resolver for FixedResolver {
+    fn resolve(&self, host: &str, _port: u16) -> crate::ResolveFuture {
+        assert_eq!(host, "localhost");
+        Box::pin(future::ready(Ok(self.0.clone())))
+    }
+}
+
+#[tokio::test]
+async fn connect_to() {
+    let _guard = subscribe();
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+    let addr = socket.local_addr().unwrap();
+    // Never answers, so the real address is only tried once the first attempt has been delayed
+    let silent = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+    let mut builder = endpoint_builder();
+    builder.resolver(Arc::new(FixedResolver(vec![
+        // Unusable from an IPv4 endpoint
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port()),
+        silent.local_addr().unwrap(),
+        addr,
+    ])));
+    let (endpoint, mut incoming) = builder.with_socket(socket).unwrap();
+
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().await.unwrap();
+        let mut s = conn.connection.open_uni().await.unwrap();
+        s.write_all(b"hello").await.unwrap();
+        s.finish().await.unwrap();
+    });
+
+    let mut new_conn = endpoint
+        .connect_to("localhost", addr.port())
+        .await
+        .expect("connect");
+    assert_eq!(new_conn.connection.remote_address(), addr);
+    let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+    let msg = stream.read_to_end(usize::MAX).await.unwrap();
+    assert_eq!(msg, b"hello");
+
+    let mut builder = endpoint_builder();
+    builder.resolver(Arc::new(FixedResolver(vec![SocketAddr::new(
+        IpAddr::V6(Ipv6Addr::LOCALHOST),
+        addr.port(),
+    )])));
+    let (endpoint, _) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+    assert!(matches!(
+        endpoint.connect_to("localhost", addr.port()).await,
+        Err(crate::ConnectToError::NoAddresses)
+    ));
+}
+
+#[tokio::test]
+async fn delivery_rate_changed() {
+    let _guard = subscribe();
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let key = crate::PrivateKey::from_der(&cert.serialize_private_key_der()).unwrap();
+    let cert = crate::Certificate::from_der(&cert.serialize_der().unwrap()).unwrap();
+    let cert_chain = crate::CertificateChain::from_certs(vec![cert.clone()]);
+    let mut builder = Endpoint::builder();
+    builder.listen(crate::ServerConfig::with_single_cert(cert_chain, key).unwrap());
+    let (endpoint, mut incoming) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let stream = conn.uni_streams.next().await.unwrap().unwrap();
+        stream.read_to_end(usize::MAX).await.unwrap();
+    });
+
+    let mut client_config = ClientConfig::with_root_certificates(vec![cert]).unwrap();
+    let mut transport = TransportConfig::default();
+    transport.delivery_rate_threshold(Some(0.1));
+    client_config.transport = Arc::new(transport);
+    let conn = endpoint
+        .connect_with(client_config, &endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+
+    let changed = conn.delivery_rate_changed();
+    let mut stream = conn.open_uni().await.unwrap();
+    tokio::spawn(async move {
+        stream.write_all(&[0xAB; 1024 * 1024]).await.unwrap();
+        stream.finish().await.unwrap();
+    });
+    let rate = tokio::time::timeout(Duration::from_secs(10), changed)
+        .await
+        .expect("no change reported")
+        .unwrap();
+    assert!(rate > 0);
+}
+
+#[tokio::test]
+async fn probe_bandwidth() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        // Hold the connection open until the client closes it
+        while let Some(Ok(_)) = conn.uni_streams.next().await {}
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+
+    let sent = conn.stats().udp_tx.bytes;
+    let rate = conn.probe_bandwidth(100_000).await.unwrap();
+    assert!(rate > 0);
+    assert!(conn.stats().udp_tx.bytes - sent >= 100_000);
+
+    // Closing the connection fails a probe in progress
+    let probe = conn.probe_bandwidth(u64::MAX);
+    tokio::pin!(probe);
+    assert!(probe.as_mut().now_or_never().is_none());
+    conn.close(0u32.into(), b"done");
+    assert!(matches!(
+        probe.await,
+        Err(crate::ConnectionError::LocallyClosed)
+    ));
+    assert!(conn.probe_bandwidth(100_000).await.is_err());
+}
+
+#[tokio::test]
+async fn egress_rate_limit() {
+    let _guard = subscribe();
+    let mut builder = endpoint_builder();
+    builder.egress_rate_limit(200_000);
+    let (endpoint, mut incoming) = builder
+        .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+        .unwrap();
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let stream = conn.uni_streams.next().await.unwrap().unwrap();
+        stream.read_to_end(usize::MAX).await.unwrap()
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+    conn.set_weight(4);
+
+    // Pacing itself is covered by the scheduler's tests, which don't depend on the wall clock
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(&[0xAB; 256 * 1024]).await.unwrap();
+    stream.finish().await.unwrap();
+    assert_eq!(server.await.unwrap(), vec![0xAB; 256 * 1024]);
+}
+
+#[tokio::test]
+async fn stream_rate_limit() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        while let Some(Ok(stream)) = conn.uni_streams.next().await {
+            tokio::spawn(async move { stream.read_to_end(usize::MAX).await.unwrap() });
+        }
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+
+    let start = Instant::now();
+    let mut bulk = conn.open_uni().await.unwrap();
+    bulk.set_max_rate(Some(100_000)).unwrap();
+    let bulk = tokio::spawn(async move {
+        bulk.write_all(&[0xAB; 64 * 1024]).await.unwrap();
+        bulk.finish().await.unwrap();
+    });
+
+    // Other streams aren't held back by the limited one
+    let mut rpc = conn.open_uni().await.unwrap();
+    rpc.write_all(&[0xCD; 16 * 1024]).await.unwrap();
+    rpc.finish().await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(400));
+
+    bulk.await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn stream_deadline() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    let (release, released) = futures_channel::oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        // Hold the stream open without reading, so the sender is blocked by flow control
+        let _stream = conn.uni_streams.next().await.unwrap().unwrap();
+        let _ = released.await;
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+
+    let mut stream = conn.open_uni().await.unwrap();
+    stream
+        .set_deadline(
+            std::time::Instant::now() + Duration::from_millis(200),
+            42u32.into(),
+        )
+        .unwrap();
+    assert_eq!(
+        stream.write_all(&vec![0; 16 * 1024 * 1024]).await,
+        Err(crate::WriteError::Expired)
+    );
+    drop(release);
+}
+
+#[tokio::test]
+async fn write_all_acked() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        let stream = conn.uni_streams.next().await.unwrap().unwrap();
+        let data = stream.read_to_end(usize::MAX).await.unwrap();
+        assert_eq!(data.len(), 256 * 1024 + 3);
+        let mut stream = conn.uni_streams.next().await.unwrap().unwrap();
+        stream.stop(42u32.into()).unwrap();
+        // Hold the connection open until the client closes it
+        while let Some(Ok(_)) = conn.uni_streams.next().await {}
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all_acked(&[0xAB; 256 * 1024]).await.unwrap();
+    stream.write_all(b"abc").await.unwrap();
+    stream.acked().await.unwrap();
+    // Nothing left to wait for
+    stream.acked().await.unwrap();
+    stream.finish().await.unwrap();
+
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let result = stream.write_all_acked(&[0xAB; 1024 * 1024]).await;
+    assert!(matches!(result, Err(crate::WriteError::Stopped(x)) if x == 42u32.into()));
+    conn.close(0u32.into(), b"done");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn close_and_wait() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+    let server = tokio::spawn(async move {
+        let mut new_conn = incoming.next().await.unwrap().await.unwrap();
+        let stream = new_conn.uni_streams.next().await.unwrap().unwrap();
+        let data = stream.read_to_end(usize::MAX).await.unwrap();
+        assert_eq!(data.len(), 1024 * 1024);
+        match new_conn.uni_streams.next().await {
+            Some(Err(crate::ConnectionError::ApplicationClosed(close))) => {
+                assert_eq!(close.error_code, 42u32.into());
+                assert_eq!(&close.reason[..], b"done");
+            }
+            x => panic!("unexpected result: {:?}", x.map(|x| x.map(|_| ()))),
+        }
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+        .connection;
+
+    let mut stream = conn.open_uni().await.unwrap();
+    stream.write_all(&[0xAB; 1024 * 1024]).await.unwrap();
+    // Finishes the stream without waiting for the peer's acknowledgement
+    drop(stream);
+    conn.close_and_wait(42u32.into(), b"done").await;
+    server.await.unwrap();
+    // Already closed
+    conn.close_and_wait(0u32.into(), b"").await;
+}
+
+#[tokio::test]
+async fn wait_idle_progress() {
+    let _guard = subscribe();
+    let (endpoint, mut incoming) = endpoint();
+
+    tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().await.unwrap();
+        // Hold the connection open until the client closes it
+        while let Some(Ok(_)) = conn.uni_streams.next().await {}
+    });
+    let conn = endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    assert_eq!(endpoint.open_connections(), 2);
+
+    conn.connection.close(0u32.into(), b"done");
+    drop(conn);
+    let mut progress = Vec::new();
+    endpoint
+        .wait_idle_with_progress(|open| progress.push(open))
+        .await;
+    assert!(!progress.is_empty());
+    assert!(progress.windows(2).all(|w| w[0] > w[1]));
+    assert_eq!(endpoint.open_connections(), 0);
+}
+
+#[test]
+fn socket_ttl() {
+    let v4 = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    crate::builders::set_ttl(&v4, 7).unwrap();
+    assert_eq!(v4.ttl().unwrap(), 7);
+
+    if let Ok(v6) = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)) {
+        crate::builders::set_ttl(&v6, 9).unwrap();
+        assert_eq!(socket2::SockRef::from(&v6).unicast_hops_v6().unwrap(), 9);
+    }
+}
+
+#[tokio::test]
+async fn endpoint_ttl() {
+    let _guard = subscribe();
+    let ttls = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut builder = endpoint_builder();
+    builder.ttl(16).runtime(Arc::new(TtlRecordingRuntime {
+        inner: crate::runtime::TokioRuntime::new(tokio::runtime::Handle::current()),
+        ttls: ttls.clone(),
+    }));
+    let localhost = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    let (endpoint, mut incoming) = builder.bind(&localhost).unwrap();
+    assert_eq!(*ttls.lock().unwrap(), [16]);
+    endpoint
+        .rebind(UdpSocket::bind(localhost).unwrap())
+        .unwrap();
+    assert_eq!(*ttls.lock().unwrap(), [16, 16]);
+
+    tokio::spawn(async move { incoming.next().await.unwrap().await.unwrap() });
+    endpoint
+        .connect(&endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+}
+
+/// Runs tasks on tokio, recording the TTL of each socket handed to it
+#[derive(Debug)]
+struct TtlRecordingRuntime {
+    inner: crate::runtime::TokioRuntime,
+    ttls: Arc<std::sync::Mutex<Vec<u32>>>,
+}
+
+impl crate::runtime::Runtime for TtlRecordingRuntime {
+    fn new_timer(&self, deadline: std::time::Instant) -> Pin<Box<dyn crate::runtime::AsyncTimer>> {
+        self.inner.new_timer(deadline)
+    }
+
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send>>) {
+        self.inner.spawn(future);
+    }
+
+    fn wrap_udp_socket(
+        &self,
+        socket: UdpSocket,
+    ) -> io::Result<Box<dyn crate::runtime::AsyncUdpSocket>> {
+        self.ttls.lock().unwrap().push(socket.ttl()?);
+        self.inner.wrap_udp_socket(socket)
+    }
 }
 
 #[tokio::test]
@@ -330,7 +2127,7 @@ async fn zero_rtt() {
         .await
         .expect("read_to_end");
     assert_eq!(msg, MSG);
-    assert!(zero_rtt.await);
+    zero_rtt.await.unwrap();
 
     drop(uni_streams);
 
@@ -456,6 +2253,7 @@ fn run_echo(args: EchoArgs) {
         client.default_client_config(ClientConfig {
             crypto: Arc::new(client_crypto),
             transport: transport_config,
+            path_cache: None,
         });
         let (client, _) = {
             let _guard = runtime.enter();